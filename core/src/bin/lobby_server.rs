@@ -0,0 +1,23 @@
+//! Runs a `LobbyServer` on its own. The address to listen on can be passed as the first argument.
+//! By default, it will listen on all interfaces, on `DEFAULT_LOBBY_PORT`.
+
+use std::net::SocketAddr;
+
+use fishfight_core::network::{LobbyServer, DEFAULT_LOBBY_PORT};
+
+fn main() -> fishfight_core::Result<()> {
+    let addr = std::env::args()
+        .nth(1)
+        .map(|arg| arg.parse::<SocketAddr>())
+        .transpose()
+        .map_err(|err| fishfight_core::Error::new(fishfight_core::error::ErrorKind::Config, err))?
+        .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], DEFAULT_LOBBY_PORT)));
+
+    let server = LobbyServer::bind(addr)?;
+
+    println!("Lobby server listening on {}", server.local_addr()?);
+
+    server.run();
+
+    Ok(())
+}
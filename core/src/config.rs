@@ -4,6 +4,9 @@ use std::path::Path;
 use serde::{Deserialize, Serialize};
 
 use crate::input::mapping::InputMapping;
+use crate::network::{
+    DEFAULT_INPUT_DELAY, DEFAULT_LOBBY_PORT, DEFAULT_MAX_INPUT_DELAY, DEFAULT_MAX_ROLLBACK,
+};
 use crate::Result;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -12,6 +15,10 @@ pub struct Config {
     pub window: WindowConfig,
     #[serde(default)]
     pub input: InputMapping,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub replays: ReplayConfig,
 }
 
 impl Config {
@@ -51,3 +58,98 @@ impl Default for WindowConfig {
         }
     }
 }
+
+/// The netcode used to synchronize the simulation between peers in network games
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetcodeKind {
+    #[default]
+    DelayedLockstep,
+    Rollback,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    #[serde(default)]
+    pub netcode: NetcodeKind,
+    /// The amount of frames local input is delayed by, when using delayed lockstep
+    #[serde(default = "NetworkConfig::default_input_delay", rename = "input-delay")]
+    pub input_delay: u64,
+    /// If this is `true`, the input delay will be adjusted to the latency of the connection, with
+    /// `input-delay` as the initial value
+    #[serde(
+        default = "NetworkConfig::default_is_input_delay_adaptive",
+        rename = "adaptive-input-delay"
+    )]
+    pub is_input_delay_adaptive: bool,
+    /// The maximum amount of frames local input is delayed by, when the delay is adaptive
+    #[serde(
+        default = "NetworkConfig::default_max_input_delay",
+        rename = "max-input-delay"
+    )]
+    pub max_input_delay: u64,
+    /// The maximum amount of frames that will be rolled back, when using rollback
+    #[serde(
+        default = "NetworkConfig::default_max_rollback",
+        rename = "max-rollback"
+    )]
+    pub max_rollback: u64,
+    /// The address of the lobby server. When hosting a game, a lobby server will be started on
+    /// this port, unless one is already running.
+    #[serde(
+        default = "NetworkConfig::default_lobby_server",
+        rename = "lobby-server"
+    )]
+    pub lobby_server: String,
+    /// The name shown to other players in lobbies
+    #[serde(default = "NetworkConfig::default_username")]
+    pub username: String,
+}
+
+impl NetworkConfig {
+    fn default_input_delay() -> u64 {
+        DEFAULT_INPUT_DELAY
+    }
+
+    fn default_is_input_delay_adaptive() -> bool {
+        true
+    }
+
+    fn default_max_input_delay() -> u64 {
+        DEFAULT_MAX_INPUT_DELAY
+    }
+
+    fn default_max_rollback() -> u64 {
+        DEFAULT_MAX_ROLLBACK
+    }
+
+    fn default_lobby_server() -> String {
+        format!("127.0.0.1:{}", DEFAULT_LOBBY_PORT)
+    }
+
+    fn default_username() -> String {
+        "Player".to_string()
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            netcode: NetcodeKind::default(),
+            input_delay: DEFAULT_INPUT_DELAY,
+            is_input_delay_adaptive: Self::default_is_input_delay_adaptive(),
+            max_input_delay: DEFAULT_MAX_INPUT_DELAY,
+            max_rollback: DEFAULT_MAX_ROLLBACK,
+            lobby_server: Self::default_lobby_server(),
+            username: Self::default_username(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayConfig {
+    /// If this is `true`, the input of every local match is recorded to a replay file, which can be
+    /// played back from the replays menu. Recorded matches are always run in deterministic mode.
+    #[serde(default, rename = "record")]
+    pub is_recording: bool,
+}
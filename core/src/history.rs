@@ -0,0 +1,248 @@
+//! A local history of finished matches, kept in a JSON file, and a leaderboard aggregated from it.
+//! Every match is appended to the file when it ends, so the history of a tournament, played over
+//! many sessions, can be inspected, or processed by other tools, afterwards.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// The file name of the match history, which is kept in the same directory as the config file
+pub const MATCH_HISTORY_FILE_NAME: &str = "match_history.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchRecord {
+    /// The time that the match ended, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// The duration of the match, in seconds
+    pub duration: f32,
+    pub map: String,
+    /// A description of the win condition, like `"Stocks (3)"`
+    pub win_condition: String,
+    #[serde(default)]
+    pub friendly_fire: bool,
+    /// The label of the winning side, or `None` if the match ended in a draw
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub winner: Option<String>,
+    pub players: Vec<PlayerRecord>,
+}
+
+impl MatchRecord {
+    /// The current time, in seconds since the Unix epoch, to use as `timestamp`
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlayerRecord {
+    pub index: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<u8>,
+    /// The id of the character that the player played as
+    pub character_id: String,
+    pub character_name: String,
+    /// This is `true` if the player was on the winning side
+    #[serde(default)]
+    pub is_winner: bool,
+    pub kills: u32,
+    pub deaths: u32,
+    pub suicides: u32,
+    pub items_picked_up: u32,
+    pub shots_fired: u32,
+    pub hits: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MatchHistory {
+    pub matches: Vec<MatchRecord>,
+}
+
+impl MatchHistory {
+    /// Load the history from a file. An empty history is returned if the file does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        if path.exists() {
+            let bytes = fs::read(path)?;
+            let res = serde_json::from_slice(&bytes)?;
+            Ok(res)
+        } else {
+            Ok(MatchHistory::default())
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let bytes = serde_json::to_vec_pretty(self)?;
+        fs::write(path, bytes)?;
+
+        Ok(())
+    }
+
+    /// Append a record to the history file. If the existing file can not be parsed, it is left as
+    /// it is, and an error is returned, so that no history is lost.
+    pub fn append<P: AsRef<Path>>(path: P, record: MatchRecord) -> Result<()> {
+        let path = path.as_ref();
+
+        let mut history = MatchHistory::load(path)?;
+        history.matches.push(record);
+        history.save(path)
+    }
+
+    /// Aggregate the stats of all matches by character, ordered by wins, and then by kills
+    pub fn leaderboard(&self) -> Vec<LeaderboardEntry> {
+        let mut entries: HashMap<&str, LeaderboardEntry> = HashMap::new();
+
+        for player in self.matches.iter().flat_map(|record| &record.players) {
+            let entry = entries
+                .entry(&player.character_id)
+                .or_insert_with(|| LeaderboardEntry {
+                    character_id: player.character_id.clone(),
+                    character_name: player.character_name.clone(),
+                    ..Default::default()
+                });
+
+            entry.matches += 1;
+
+            if player.is_winner {
+                entry.wins += 1;
+            }
+
+            entry.kills += player.kills;
+            entry.deaths += player.deaths;
+            entry.suicides += player.suicides;
+            entry.shots_fired += player.shots_fired;
+            entry.hits += player.hits;
+        }
+
+        let mut res = entries.into_values().collect::<Vec<_>>();
+
+        res.sort_by(|a, b| {
+            b.wins
+                .cmp(&a.wins)
+                .then(b.kills.cmp(&a.kills))
+                .then(a.character_name.cmp(&b.character_name))
+        });
+
+        res
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LeaderboardEntry {
+    pub character_id: String,
+    pub character_name: String,
+    pub matches: u32,
+    pub wins: u32,
+    pub kills: u32,
+    pub deaths: u32,
+    pub suicides: u32,
+    pub shots_fired: u32,
+    pub hits: u32,
+}
+
+impl LeaderboardEntry {
+    /// The share of matches that were won
+    pub fn win_rate(&self) -> f32 {
+        if self.matches > 0 {
+            self.wins as f32 / self.matches as f32
+        } else {
+            0.0
+        }
+    }
+
+    /// Kills per death, or the kills if there have been no deaths
+    pub fn kill_death_ratio(&self) -> f32 {
+        self.kills as f32 / self.deaths.max(1) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(character_id: &str, is_winner: bool, kills: u32, deaths: u32) -> PlayerRecord {
+        PlayerRecord {
+            character_id: character_id.to_string(),
+            character_name: character_id.to_uppercase(),
+            is_winner,
+            kills,
+            deaths,
+            ..Default::default()
+        }
+    }
+
+    fn record(players: Vec<PlayerRecord>) -> MatchRecord {
+        MatchRecord {
+            timestamp: 0,
+            duration: 60.0,
+            map: "Test".to_string(),
+            win_condition: "Kills (10)".to_string(),
+            friendly_fire: false,
+            winner: None,
+            players,
+        }
+    }
+
+    #[test]
+    fn test_leaderboard_aggregates_by_character() {
+        let history = MatchHistory {
+            matches: vec![
+                record(vec![
+                    player("sharky", true, 10, 2),
+                    player("pescy", false, 2, 10),
+                ]),
+                record(vec![
+                    player("sharky", false, 4, 10),
+                    player("pescy", true, 10, 4),
+                ]),
+                record(vec![
+                    player("pescy", true, 10, 0),
+                    player("lionfishy", false, 0, 10),
+                ]),
+            ],
+        };
+
+        let leaderboard = history.leaderboard();
+
+        let ids = leaderboard
+            .iter()
+            .map(|entry| entry.character_id.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, ["pescy", "sharky", "lionfishy"]);
+
+        assert_eq!(leaderboard[0].matches, 3);
+        assert_eq!(leaderboard[0].wins, 2);
+        assert_eq!(leaderboard[0].kills, 22);
+        assert_eq!(leaderboard[0].deaths, 14);
+        assert_eq!(leaderboard[1].character_name, "SHARKY");
+    }
+
+    #[test]
+    fn test_append_keeps_previous_records() {
+        let path = std::env::temp_dir().join(format!(
+            "fishfight_match_history_test_{}.json",
+            std::process::id()
+        ));
+
+        let _ = fs::remove_file(&path);
+
+        MatchHistory::append(&path, record(vec![player("sharky", true, 10, 0)])).unwrap();
+        MatchHistory::append(&path, record(vec![player("pescy", true, 10, 0)])).unwrap();
+
+        let history = MatchHistory::load(&path).unwrap();
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(history.matches.len(), 2);
+        assert_eq!(history.matches[1].players[0].character_id, "pescy");
+    }
+}
@@ -1,6 +1,8 @@
 pub mod mapping;
+mod track;
 
 pub use mapping::{Button, KeyCode};
+pub use track::{InputRun, InputTrack};
 
 use fishsticks::Axis;
 
@@ -13,7 +15,7 @@ pub use fishsticks::GamepadContext;
 
 use crate::{Config, Result};
 
-#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PlayerInput {
     pub left: bool,
     pub right: bool,
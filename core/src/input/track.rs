@@ -0,0 +1,146 @@
+//! This implements `InputTrack`, which holds the input of a single player, for every frame of a
+//! match, so that the match can be played back. Input rarely changes from one frame to the next,
+//! so it is stored as runs of identical input, which keeps recordings of long matches small.
+
+use serde::{Deserialize, Serialize};
+
+use super::PlayerInput;
+
+/// A run of frames with identical input
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputRun {
+    pub input: PlayerInput,
+    /// The amount of consecutive frames that the input was held for
+    pub frames: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputTrack {
+    runs: Vec<InputRun>,
+}
+
+impl InputTrack {
+    pub fn new() -> Self {
+        InputTrack::default()
+    }
+
+    /// Add the input of the next frame
+    pub fn push(&mut self, input: PlayerInput) {
+        match self.runs.last_mut() {
+            Some(run) if run.input == input => run.frames += 1,
+            _ => self.runs.push(InputRun { input, frames: 1 }),
+        }
+    }
+
+    /// Add the input of the next `frames` frames, like a held button, when scripting input
+    pub fn push_for(&mut self, input: PlayerInput, frames: u64) {
+        if frames == 0 {
+            return;
+        }
+
+        match self.runs.last_mut() {
+            Some(run) if run.input == input => run.frames += frames,
+            _ => self.runs.push(InputRun { input, frames }),
+        }
+    }
+
+    /// The amount of frames that the track holds input for
+    pub fn len(&self) -> u64 {
+        self.runs.iter().map(|run| run.frames).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// The input of a frame, or `None` if the frame is past the end of the track
+    pub fn get(&self, frame: u64) -> Option<PlayerInput> {
+        let mut start = 0;
+
+        for run in &self.runs {
+            if frame < start + run.frames {
+                return Some(run.input);
+            }
+
+            start += run.frames;
+        }
+
+        None
+    }
+
+    pub fn runs(&self) -> &[InputRun] {
+        &self.runs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(left: bool, fire: bool) -> PlayerInput {
+        PlayerInput {
+            left,
+            fire,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_identical_input_is_merged_into_runs() {
+        let mut track = InputTrack::new();
+
+        for _ in 0..10 {
+            track.push(input(true, false));
+        }
+
+        track.push(input(true, true));
+        track.push(input(false, false));
+        track.push(input(false, false));
+
+        assert_eq!(track.runs().len(), 3);
+        assert_eq!(track.runs()[0].frames, 10);
+        assert_eq!(track.len(), 13);
+
+        track.push_for(input(false, false), 5);
+        track.push_for(input(true, true), 0);
+
+        assert_eq!(track.runs().len(), 3);
+        assert_eq!(track.len(), 18);
+    }
+
+    #[test]
+    fn test_input_is_returned_by_frame() {
+        let mut track = InputTrack::new();
+
+        let frames = [
+            input(false, false),
+            input(true, false),
+            input(true, false),
+            input(true, true),
+            input(false, false),
+        ];
+
+        for input in frames {
+            track.push(input);
+        }
+
+        for (frame, input) in frames.iter().enumerate() {
+            assert_eq!(track.get(frame as u64), Some(*input));
+        }
+
+        assert_eq!(track.get(frames.len() as u64), None);
+    }
+
+    #[test]
+    fn test_track_survives_serialization() {
+        let mut track = InputTrack::new();
+
+        track.push(input(true, false));
+        track.push(input(true, true));
+
+        let json = serde_json::to_string(&track).unwrap();
+        let res: InputTrack = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(res, track);
+    }
+}
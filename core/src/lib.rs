@@ -2,18 +2,20 @@
 pub mod error;
 pub mod config;
 pub mod data;
+pub mod history;
 pub mod input;
 pub mod json;
 pub mod math;
 pub mod network;
 pub mod noise;
+pub mod random;
 pub mod text;
 
 mod channel;
 mod transform;
 
 pub use channel::Channel;
-pub use config::{Config, WindowConfig};
+pub use config::{Config, NetworkConfig, ReplayConfig, WindowConfig};
 pub use error::{Error, Result};
 pub use transform::Transform;
 
@@ -1,10 +1,11 @@
 use async_trait::async_trait;
 
+use crate::error::{Error, ErrorKind};
 use crate::network::message::NetworkMessage;
 
 use crate::Result;
 
-use super::NetworkEvent;
+use super::{LobbyId, LobbyPrivacy, NetworkEvent, PlayerId};
 
 static mut API_INSTANCE: Option<Api> = None;
 
@@ -17,21 +18,21 @@ impl Api {
         unsafe { API_INSTANCE.take() }
     }
 
-    #[allow(dead_code)]
     fn try_get_instance() -> Option<&'static mut Api> {
         unsafe { API_INSTANCE.as_mut() }
     }
 
-    #[allow(dead_code)]
     fn get_instance() -> &'static mut Api {
         Self::try_get_instance()
             .unwrap_or_else(|| panic!("Api::get_instance was called before Api::init"))
     }
 
-    pub async fn init<T: 'static + ApiBackend + ApiBackendConstructor>() -> Result<()> {
+    pub async fn init<T: 'static + ApiBackend + ApiBackendConstructor>(
+        params: T::Params,
+    ) -> Result<()> {
         unsafe {
             if API_INSTANCE.is_none() {
-                let backend = Box::new(T::init().await?);
+                let backend = Box::new(T::init(params).await?);
 
                 API_INSTANCE = Some(Api { backend });
             } else {
@@ -52,14 +53,116 @@ impl Api {
 
         Ok(())
     }
+
+    /// Returns `true` if the api has been initialized
+    pub fn is_initialized() -> bool {
+        Self::try_get_instance().is_some()
+    }
+
+    /// Returns the id of the local player, as known by the backend
+    pub fn local_player_id() -> PlayerId {
+        Self::get_instance().backend.local_player_id()
+    }
+
+    /// Dispatch a network message through the backend
+    pub fn dispatch_message(message: NetworkMessage) -> Result<()> {
+        Self::get_instance().backend.dispatch_message(message)
+    }
+
+    /// Get the next event from the backends queue
+    pub fn next_event() -> Option<NetworkEvent> {
+        Self::get_instance().backend.next_event()
+    }
+
+    /// Send the data of a resync to a rejoining player. `NetworkEvent::Resync` will be emitted
+    /// for the player, once all of it has been received.
+    pub fn resync_player(player_id: &PlayerId, frame: u64, data: &[u8]) -> Result<()> {
+        Self::get_instance()
+            .backend
+            .resync_player(player_id, frame, data)
+    }
+
+    /// Request a new lobby, with the local player as admin.
+    /// `NetworkEvent::LobbyCreated` will be emitted on success.
+    pub fn create_lobby(name: &str, capacity: i32, privacy: LobbyPrivacy) -> Result<()> {
+        Self::get_instance()
+            .backend
+            .create_lobby(name, capacity, privacy)
+    }
+
+    /// Request the list of public lobbies that have not started yet, or that are running and can
+    /// be spectated.
+    /// `NetworkEvent::LobbyList` will be emitted on success.
+    pub fn request_lobby_list() -> Result<()> {
+        Self::get_instance().backend.request_lobby_list()
+    }
+
+    /// Request to join a lobby. `NetworkEvent::LobbyChanged` will be emitted on success.
+    pub fn join_lobby(lobby_id: &LobbyId) -> Result<()> {
+        Self::get_instance().backend.join_lobby(lobby_id)
+    }
+
+    /// Request to spectate the running game of a lobby. `NetworkEvent::LobbyChanged`, followed by
+    /// `NetworkEvent::GameStarted`, will be emitted on success.
+    pub fn spectate_lobby(lobby_id: &LobbyId) -> Result<()> {
+        Self::get_instance().backend.spectate_lobby(lobby_id)
+    }
+
+    /// Leave the current lobby, if any
+    pub fn leave_lobby() -> Result<()> {
+        Self::get_instance().backend.leave_lobby()
+    }
+
+    /// Mark the local player as ready, or not ready, in the current lobby
+    pub fn set_ready(is_ready: bool) -> Result<()> {
+        Self::get_instance().backend.set_ready(is_ready)
+    }
+
+    /// Select the character of the local player in the current lobby
+    pub fn set_character(character: &str) -> Result<()> {
+        Self::get_instance().backend.set_character(character)
+    }
+
+    /// Select the map of the current lobby. This is only allowed for the lobby admin.
+    pub fn set_lobby_map(map: &str) -> Result<()> {
+        Self::get_instance().backend.set_lobby_map(map)
+    }
+
+    /// Select the team of the local player in the current lobby, or leave the team, if `team` is
+    /// `None`
+    pub fn set_team(team: Option<u8>) -> Result<()> {
+        Self::get_instance().backend.set_team(team)
+    }
+
+    /// Allow or disallow damage between players of the same team, in the current lobby. This is
+    /// only allowed for the lobby admin.
+    pub fn set_friendly_fire(is_enabled: bool) -> Result<()> {
+        Self::get_instance().backend.set_friendly_fire(is_enabled)
+    }
+
+    /// Request that the game is started. This is only allowed for the lobby admin, when all
+    /// players are ready. `NetworkEvent::GameStarted` will be emitted on success.
+    pub fn start_game() -> Result<()> {
+        Self::get_instance().backend.start_game()
+    }
+}
+
+fn lobbies_not_supported() -> Error {
+    Error::new_const(ErrorKind::Api, &"Lobbies are not supported by this backend")
+}
+
+fn resync_not_supported() -> Error {
+    Error::new_const(ErrorKind::Api, &"Resync is not supported by this backend")
 }
 
 /// Constructor for backend (needs to be separate from `ApiBackend` so that `ApiBackend` can be
 /// object safe
 #[async_trait]
 pub trait ApiBackendConstructor: Sized {
+    /// The parameters required to initialize the backend
+    type Params: Send;
     /// Init backend
-    async fn init() -> Result<Self>;
+    async fn init(params: Self::Params) -> Result<Self>;
 }
 
 /// This trait should be implemented by all backend implementations
@@ -67,8 +170,59 @@ pub trait ApiBackendConstructor: Sized {
 pub trait ApiBackend {
     /// Close API connection
     async fn close(&mut self) -> Result<()>;
+    /// Get the id of the local player
+    fn local_player_id(&self) -> PlayerId;
     /// Dispatch a network message
     fn dispatch_message(&mut self, message: NetworkMessage) -> Result<()>;
     /// Get next event from the queue
     fn next_event(&mut self) -> Option<NetworkEvent>;
+    /// Send the data of a resync to a rejoining player. Backends that do not support rejoining
+    /// will return an error.
+    fn resync_player(&mut self, _player_id: &PlayerId, _frame: u64, _data: &[u8]) -> Result<()> {
+        Err(resync_not_supported())
+    }
+    /// Request a new lobby. Backends that do not support lobbies will return an error.
+    fn create_lobby(&mut self, _name: &str, _capacity: i32, _privacy: LobbyPrivacy) -> Result<()> {
+        Err(lobbies_not_supported())
+    }
+    /// Request the list of open lobbies
+    fn request_lobby_list(&mut self) -> Result<()> {
+        Err(lobbies_not_supported())
+    }
+    /// Request to join a lobby
+    fn join_lobby(&mut self, _lobby_id: &LobbyId) -> Result<()> {
+        Err(lobbies_not_supported())
+    }
+    /// Request to spectate the running game of a lobby
+    fn spectate_lobby(&mut self, _lobby_id: &LobbyId) -> Result<()> {
+        Err(lobbies_not_supported())
+    }
+    /// Leave the current lobby
+    fn leave_lobby(&mut self) -> Result<()> {
+        Err(lobbies_not_supported())
+    }
+    /// Mark the local player as ready, or not ready
+    fn set_ready(&mut self, _is_ready: bool) -> Result<()> {
+        Err(lobbies_not_supported())
+    }
+    /// Select the character of the local player
+    fn set_character(&mut self, _character: &str) -> Result<()> {
+        Err(lobbies_not_supported())
+    }
+    /// Select the map of the current lobby
+    fn set_lobby_map(&mut self, _map: &str) -> Result<()> {
+        Err(lobbies_not_supported())
+    }
+    /// Select the team of the local player
+    fn set_team(&mut self, _team: Option<u8>) -> Result<()> {
+        Err(lobbies_not_supported())
+    }
+    /// Allow or disallow damage between players of the same team
+    fn set_friendly_fire(&mut self, _is_enabled: bool) -> Result<()> {
+        Err(lobbies_not_supported())
+    }
+    /// Request that the game is started
+    fn start_game(&mut self) -> Result<()> {
+        Err(lobbies_not_supported())
+    }
 }
@@ -0,0 +1,651 @@
+//! The binary wire format of `NetworkMessage`, used by the UDP backend, to keep packets as small as
+//! the netcode chapter of the book promises. A `PlayerInput` is packed into a single byte, with one
+//! bit per button, and frames and other integers, that are mostly small, are encoded as LEB128
+//! varints. Every packet starts with a header that holds `PROTOCOL_VERSION`, so that peers running
+//! incompatible versions of the game will reject each other's packets, instead of misreading them.
+
+use crate::error::{Error, ErrorKind};
+use crate::input::PlayerInput;
+use crate::network::NetworkMessage;
+use crate::Result;
+
+/// Identifies a packet as a FishFight game packet
+const MAGIC: [u8; 2] = *b"FF";
+
+/// This must be incremented whenever the encoding of a message changes
+pub const PROTOCOL_VERSION: u8 = 2;
+
+const UPDATE_PLAYER_INPUT_TAG: u8 = 0;
+const PLAYER_INPUTS_TAG: u8 = 1;
+const STATE_CHECKSUM_TAG: u8 = 2;
+const PING_TAG: u8 = 3;
+const PONG_TAG: u8 = 4;
+const SPECTATOR_INPUTS_TAG: u8 = 5;
+const SPECTATOR_ACK_TAG: u8 = 6;
+const RESYNC_REQUEST_TAG: u8 = 7;
+const RESYNC_CHUNK_TAG: u8 = 8;
+const RESYNC_ACK_TAG: u8 = 9;
+const PLAYER_IDLE_TAG: u8 = 10;
+const STATE_DESCRIPTION_REQUEST_TAG: u8 = 11;
+const STATE_DESCRIPTION_CHUNK_TAG: u8 = 12;
+
+/// Pack a `PlayerInput` into a single byte, with one bit per button, in declaration order
+pub fn pack_input(input: &PlayerInput) -> u8 {
+    [
+        input.left,
+        input.right,
+        input.fire,
+        input.jump,
+        input.pickup,
+        input.float,
+        input.crouch,
+        input.slide,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |bits, (i, is_pressed)| bits | ((*is_pressed as u8) << i))
+}
+
+/// Unpack a `PlayerInput` from a byte produced by `pack_input`
+pub fn unpack_input(bits: u8) -> PlayerInput {
+    let is_set = |i: u8| bits & (1 << i) != 0;
+
+    PlayerInput {
+        left: is_set(0),
+        right: is_set(1),
+        fire: is_set(2),
+        jump: is_set(3),
+        pickup: is_set(4),
+        float: is_set(5),
+        crouch: is_set(6),
+        slide: is_set(7),
+    }
+}
+
+/// Encode a message, including the header
+pub fn encode_message(message: &NetworkMessage) -> Vec<u8> {
+    let mut writer = Writer::default();
+
+    writer.bytes(&MAGIC);
+    writer.u8(PROTOCOL_VERSION);
+
+    match message {
+        NetworkMessage::UpdatePlayerInput {
+            player_id,
+            frame,
+            input,
+        } => {
+            writer.u8(UPDATE_PLAYER_INPUT_TAG);
+            writer.string(player_id);
+            writer.varint(*frame);
+            writer.u8(pack_input(input));
+        }
+        NetworkMessage::PlayerInputs {
+            player_id,
+            start_frame,
+            inputs,
+            ack,
+        } => {
+            writer.u8(PLAYER_INPUTS_TAG);
+            writer.string(player_id);
+            writer.varint(*start_frame);
+            writer.varint(inputs.len() as u64);
+
+            for input in inputs {
+                writer.u8(pack_input(input));
+            }
+
+            // Zero means no ack, so that the common case still fits in a small varint
+            writer.varint(ack.map_or(0, |ack| ack + 1));
+        }
+        NetworkMessage::StateChecksum {
+            player_id,
+            frame,
+            checksum,
+        } => {
+            writer.u8(STATE_CHECKSUM_TAG);
+            writer.string(player_id);
+            writer.varint(*frame);
+            // Hashes use all their bits, so a varint would only make them longer
+            writer.bytes(&checksum.to_le_bytes());
+        }
+        NetworkMessage::Ping {
+            player_id,
+            timestamp,
+        } => {
+            writer.u8(PING_TAG);
+            writer.string(player_id);
+            writer.varint(*timestamp);
+        }
+        NetworkMessage::Pong {
+            player_id,
+            timestamp,
+        } => {
+            writer.u8(PONG_TAG);
+            writer.string(player_id);
+            writer.varint(*timestamp);
+        }
+        NetworkMessage::SpectatorInputs {
+            player_id,
+            start_frame,
+            player_ids,
+            inputs,
+        } => {
+            writer.u8(SPECTATOR_INPUTS_TAG);
+            writer.string(player_id);
+            writer.varint(*start_frame);
+            writer.varint(player_ids.len() as u64);
+
+            for player_id in player_ids {
+                writer.string(player_id);
+            }
+
+            writer.varint(inputs.len() as u64);
+
+            for input in inputs {
+                writer.u8(pack_input(input));
+            }
+        }
+        NetworkMessage::SpectatorAck { player_id, frame } => {
+            writer.u8(SPECTATOR_ACK_TAG);
+            writer.string(player_id);
+            writer.varint(*frame);
+        }
+        NetworkMessage::ResyncRequest {
+            player_id,
+            checkpoint_frames,
+        } => {
+            writer.u8(RESYNC_REQUEST_TAG);
+            writer.string(player_id);
+            writer.varint(checkpoint_frames.len() as u64);
+
+            for frame in checkpoint_frames {
+                writer.varint(*frame);
+            }
+        }
+        NetworkMessage::ResyncChunk {
+            player_id,
+            frame,
+            index,
+            chunk_cnt,
+            data,
+        } => {
+            writer.u8(RESYNC_CHUNK_TAG);
+            writer.string(player_id);
+            writer.varint(*frame);
+            writer.varint(*index);
+            writer.varint(*chunk_cnt);
+            writer.varint(data.len() as u64);
+            writer.bytes(data);
+        }
+        NetworkMessage::ResyncAck {
+            player_id,
+            frame,
+            index,
+        } => {
+            writer.u8(RESYNC_ACK_TAG);
+            writer.string(player_id);
+            writer.varint(*frame);
+            writer.varint(*index);
+        }
+        NetworkMessage::PlayerIdle {
+            player_id,
+            start_frame,
+            end_frame,
+        } => {
+            writer.u8(PLAYER_IDLE_TAG);
+            writer.string(player_id);
+            writer.varint(*start_frame);
+            // Zero means until further notice, like an empty ack
+            writer.varint(end_frame.map_or(0, |frame| frame + 1));
+        }
+        NetworkMessage::StateDescriptionRequest { player_id, frame } => {
+            writer.u8(STATE_DESCRIPTION_REQUEST_TAG);
+            writer.string(player_id);
+            writer.varint(*frame);
+        }
+        NetworkMessage::StateDescriptionChunk {
+            player_id,
+            frame,
+            index,
+            chunk_cnt,
+            data,
+        } => {
+            writer.u8(STATE_DESCRIPTION_CHUNK_TAG);
+            writer.string(player_id);
+            writer.varint(*frame);
+            writer.varint(*index);
+            writer.varint(*chunk_cnt);
+            writer.varint(data.len() as u64);
+            writer.bytes(data);
+        }
+    }
+
+    writer.into_bytes()
+}
+
+/// Decode a message produced by `encode_message`. This will fail if the header does not match, or
+/// if the packet is malformed, in any way, including if it holds trailing bytes.
+pub fn decode_message(bytes: &[u8]) -> Result<NetworkMessage> {
+    let mut reader = Reader::new(bytes);
+
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return Err(Error::new_const(
+            ErrorKind::Parsing,
+            &"Packet is not a game packet",
+        ));
+    }
+
+    let version = reader.u8()?;
+    if version != PROTOCOL_VERSION {
+        return Err(Error::new_message(
+            ErrorKind::Parsing,
+            &format!(
+                "Packet has protocol version {}, expected {}",
+                version, PROTOCOL_VERSION
+            ),
+        ));
+    }
+
+    let message = match reader.u8()? {
+        UPDATE_PLAYER_INPUT_TAG => NetworkMessage::UpdatePlayerInput {
+            player_id: reader.string()?,
+            frame: reader.varint()?,
+            input: unpack_input(reader.u8()?),
+        },
+        PLAYER_INPUTS_TAG => {
+            let player_id = reader.string()?;
+            let start_frame = reader.varint()?;
+            let input_cnt = reader.varint()? as usize;
+
+            let inputs = reader
+                .bytes(input_cnt)?
+                .iter()
+                .map(|bits| unpack_input(*bits))
+                .collect();
+
+            let ack = reader.varint()?.checked_sub(1);
+
+            NetworkMessage::PlayerInputs {
+                player_id,
+                start_frame,
+                inputs,
+                ack,
+            }
+        }
+        STATE_CHECKSUM_TAG => NetworkMessage::StateChecksum {
+            player_id: reader.string()?,
+            frame: reader.varint()?,
+            checksum: {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(reader.bytes(8)?);
+                u64::from_le_bytes(bytes)
+            },
+        },
+        PING_TAG => NetworkMessage::Ping {
+            player_id: reader.string()?,
+            timestamp: reader.varint()?,
+        },
+        PONG_TAG => NetworkMessage::Pong {
+            player_id: reader.string()?,
+            timestamp: reader.varint()?,
+        },
+        SPECTATOR_INPUTS_TAG => {
+            let player_id = reader.string()?;
+            let start_frame = reader.varint()?;
+
+            // The count is not trusted for allocation, as every id takes at least one byte
+            let player_cnt = reader.varint()?;
+            let mut player_ids = Vec::new();
+
+            for _ in 0..player_cnt {
+                player_ids.push(reader.string()?);
+            }
+
+            let input_cnt = reader.varint()? as usize;
+
+            let inputs = reader
+                .bytes(input_cnt)?
+                .iter()
+                .map(|bits| unpack_input(*bits))
+                .collect();
+
+            NetworkMessage::SpectatorInputs {
+                player_id,
+                start_frame,
+                player_ids,
+                inputs,
+            }
+        }
+        SPECTATOR_ACK_TAG => NetworkMessage::SpectatorAck {
+            player_id: reader.string()?,
+            frame: reader.varint()?,
+        },
+        RESYNC_REQUEST_TAG => {
+            let player_id = reader.string()?;
+
+            let frame_cnt = reader.varint()?;
+            let mut checkpoint_frames = Vec::new();
+
+            for _ in 0..frame_cnt {
+                checkpoint_frames.push(reader.varint()?);
+            }
+
+            NetworkMessage::ResyncRequest {
+                player_id,
+                checkpoint_frames,
+            }
+        }
+        RESYNC_CHUNK_TAG => NetworkMessage::ResyncChunk {
+            player_id: reader.string()?,
+            frame: reader.varint()?,
+            index: reader.varint()?,
+            chunk_cnt: reader.varint()?,
+            data: {
+                let len = reader.varint()? as usize;
+                reader.bytes(len)?.to_vec()
+            },
+        },
+        RESYNC_ACK_TAG => NetworkMessage::ResyncAck {
+            player_id: reader.string()?,
+            frame: reader.varint()?,
+            index: reader.varint()?,
+        },
+        PLAYER_IDLE_TAG => NetworkMessage::PlayerIdle {
+            player_id: reader.string()?,
+            start_frame: reader.varint()?,
+            end_frame: reader.varint()?.checked_sub(1),
+        },
+        STATE_DESCRIPTION_REQUEST_TAG => NetworkMessage::StateDescriptionRequest {
+            player_id: reader.string()?,
+            frame: reader.varint()?,
+        },
+        STATE_DESCRIPTION_CHUNK_TAG => NetworkMessage::StateDescriptionChunk {
+            player_id: reader.string()?,
+            frame: reader.varint()?,
+            index: reader.varint()?,
+            chunk_cnt: reader.varint()?,
+            data: {
+                let len = reader.varint()? as usize;
+                reader.bytes(len)?.to_vec()
+            },
+        },
+        tag => {
+            return Err(Error::new_message(
+                ErrorKind::Parsing,
+                &format!("Packet has invalid message tag {}", tag),
+            ))
+        }
+    };
+
+    if !reader.is_at_end() {
+        return Err(Error::new_const(
+            ErrorKind::Parsing,
+            &"Packet has trailing bytes",
+        ));
+    }
+
+    Ok(message)
+}
+
+#[derive(Default)]
+pub(crate) struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Write an unsigned LEB128 varint, seven bits at a time, least significant first
+    pub(crate) fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+
+        self.buf.push(value as u8);
+    }
+
+    pub(crate) fn string(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.bytes(value.as_bytes());
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    pub(crate) fn is_at_end(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.bytes.len() - self.pos {
+            return Err(Error::new_const(ErrorKind::Parsing, &"Packet is truncated"));
+        }
+
+        let res = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+
+        Ok(res)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            let bits = (byte & 0x7f) as u64;
+
+            // The tenth byte may only hold the last bit of a 64-bit value
+            if shift == 63 && bits > 1 {
+                break;
+            }
+
+            value |= bits << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(Error::new_const(
+            ErrorKind::Parsing,
+            &"Packet has invalid varint",
+        ))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String> {
+        let len = self.varint()? as usize;
+        let bytes = self.bytes(len)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|err| Error::new(ErrorKind::Parsing, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    const ITERATION_CNT: usize = 10_000;
+
+    fn random_u64(rng: &mut Rng) -> u64 {
+        // Shift by a random amount so that values of all sizes, including the edge cases, are
+        // tested, instead of mostly huge ones
+        match rng.gen_range(0, 4) {
+            0 => 0,
+            1 => u64::MAX,
+            _ => rng.next_u64() >> rng.gen_range(0u32, 64),
+        }
+    }
+
+    fn random_input(rng: &mut Rng) -> PlayerInput {
+        unpack_input(rng.next_u64() as u8)
+    }
+
+    fn random_player_id(rng: &mut Rng) -> String {
+        let len = rng.gen_range(0, 12);
+        (0..len)
+            .map(|_| ['a', '7', 'é', '🐟'][rng.gen_range(0, 4)])
+            .collect()
+    }
+
+    fn random_message(rng: &mut Rng) -> NetworkMessage {
+        let player_id = random_player_id(rng);
+
+        match rng.gen_range(0, 13) {
+            0 => NetworkMessage::UpdatePlayerInput {
+                player_id,
+                frame: random_u64(rng),
+                input: random_input(rng),
+            },
+            1 => NetworkMessage::PlayerInputs {
+                player_id,
+                start_frame: random_u64(rng),
+                inputs: (0..rng.gen_range(0, 20))
+                    .map(|_| random_input(rng))
+                    .collect(),
+                ack: match rng.gen_range(0, 2) {
+                    0 => None,
+                    // `u64::MAX` can not be acked, as it is encoded as `ack + 1`
+                    _ => Some(random_u64(rng).min(u64::MAX - 1)),
+                },
+            },
+            2 => NetworkMessage::StateChecksum {
+                player_id,
+                frame: random_u64(rng),
+                checksum: rng.next_u64(),
+            },
+            3 => NetworkMessage::Ping {
+                player_id,
+                timestamp: random_u64(rng),
+            },
+            4 => NetworkMessage::Pong {
+                player_id,
+                timestamp: random_u64(rng),
+            },
+            5 => NetworkMessage::SpectatorInputs {
+                player_id,
+                start_frame: random_u64(rng),
+                player_ids: (0..rng.gen_range(0, 8))
+                    .map(|_| random_player_id(rng))
+                    .collect(),
+                inputs: (0..rng.gen_range(0, 64))
+                    .map(|_| random_input(rng))
+                    .collect(),
+            },
+            6 => NetworkMessage::SpectatorAck {
+                player_id,
+                frame: random_u64(rng),
+            },
+            7 => NetworkMessage::ResyncRequest {
+                player_id,
+                checkpoint_frames: (0..rng.gen_range(0, 16)).map(|_| random_u64(rng)).collect(),
+            },
+            8 => NetworkMessage::ResyncChunk {
+                player_id,
+                frame: random_u64(rng),
+                index: random_u64(rng),
+                chunk_cnt: random_u64(rng),
+                data: (0..rng.gen_range(0, 64))
+                    .map(|_| rng.next_u64() as u8)
+                    .collect(),
+            },
+            9 => NetworkMessage::ResyncAck {
+                player_id,
+                frame: random_u64(rng),
+                index: random_u64(rng),
+            },
+            10 => NetworkMessage::PlayerIdle {
+                player_id,
+                start_frame: random_u64(rng),
+                end_frame: match rng.gen_range(0, 2) {
+                    0 => None,
+                    _ => Some(random_u64(rng).min(u64::MAX - 1)),
+                },
+            },
+            11 => NetworkMessage::StateDescriptionRequest {
+                player_id,
+                frame: random_u64(rng),
+            },
+            _ => NetworkMessage::StateDescriptionChunk {
+                player_id,
+                frame: random_u64(rng),
+                index: random_u64(rng),
+                chunk_cnt: random_u64(rng),
+                data: (0..rng.gen_range(0, 64))
+                    .map(|_| rng.next_u64() as u8)
+                    .collect(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_input_round_trip() {
+        for bits in 0..=u8::MAX {
+            assert_eq!(pack_input(&unpack_input(bits)), bits);
+        }
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let mut rng = Rng::new(0);
+
+        for _ in 0..ITERATION_CNT {
+            let message = random_message(&mut rng);
+            let bytes = encode_message(&message);
+
+            assert_eq!(decode_message(&bytes).unwrap(), message);
+
+            // Any truncated packet should be rejected, never decoded into another message
+            let len = rng.gen_range(0, bytes.len());
+            assert!(decode_message(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_packets_are_compact() {
+        let message = NetworkMessage::PlayerInputs {
+            player_id: "1".to_string(),
+            start_frame: 1000,
+            inputs: vec![PlayerInput::default(); 16],
+            ack: Some(1000),
+        };
+
+        // Header and tag, id, frame, count, one byte per input and the ack
+        assert_eq!(encode_message(&message).len(), 4 + 2 + 2 + 1 + 16 + 2);
+    }
+
+    #[test]
+    fn test_other_versions_are_rejected() {
+        let mut bytes = encode_message(&NetworkMessage::Ping {
+            player_id: "1".to_string(),
+            timestamp: 0,
+        });
+
+        bytes[MAGIC.len()] = PROTOCOL_VERSION + 1;
+        assert!(decode_message(&bytes).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+//! A link conditioner simulates an unreliable connection, by dropping and reordering outgoing
+//! packets. It is used to test the netcode, but it can also be enabled on a backend, to try the
+//! game under bad network conditions.
+
+use std::net::SocketAddr;
+
+use crate::random::Rng;
+
+#[derive(Debug, Clone)]
+pub struct LinkConditioner {
+    /// The probability, from `0.0` to `1.0`, that a packet is dropped
+    pub loss: f32,
+    /// The probability, from `0.0` to `1.0`, that a packet is held back and sent after the next
+    pub reorder: f32,
+    rng: Rng,
+    held: Option<(Vec<u8>, SocketAddr)>,
+}
+
+impl LinkConditioner {
+    /// The same seed will always drop and reorder the same packets
+    pub fn new(loss: f32, reorder: f32, seed: u64) -> Self {
+        LinkConditioner {
+            loss,
+            reorder,
+            rng: Rng::new(seed),
+            held: None,
+        }
+    }
+
+    /// Returns the packets that should be sent now, in order, in place of `packet`
+    pub fn process(&mut self, packet: Vec<u8>, addr: SocketAddr) -> Vec<(Vec<u8>, SocketAddr)> {
+        if self.rng.next_f32() < self.loss {
+            return Vec::new();
+        }
+
+        if self.held.is_none() && self.rng.next_f32() < self.reorder {
+            self.held = Some((packet, addr));
+            return Vec::new();
+        }
+
+        let mut res = vec![(packet, addr)];
+        res.extend(self.held.take());
+
+        res
+    }
+}
@@ -0,0 +1,144 @@
+//! This compares state checksums of the local and remote players, in order to detect when the
+//! simulation has diverged. Checksums may arrive before or after the local checksum for the same
+//! frame has been computed, so both are kept for a while, until their counterpart arrives.
+//! When a desync is detected, the players exchange descriptions of the state of the frame, which
+//! are split into chunks, as they are too large for a single packet.
+
+use std::collections::BTreeMap;
+
+use crate::network::{NetworkMessage, PlayerId, RESYNC_CHUNK_SIZE};
+
+/// The amount of frames that checksums are kept for, waiting for their counterpart
+const CHECKSUM_HISTORY_LEN: u64 = 600;
+
+#[derive(Debug, Clone, Default)]
+pub struct DesyncDetector {
+    local: BTreeMap<u64, u64>,
+    remote: BTreeMap<u64, Vec<(PlayerId, u64)>>,
+}
+
+impl DesyncDetector {
+    pub fn new() -> Self {
+        DesyncDetector::default()
+    }
+
+    /// Add the local checksum for a frame. If remote checksums have already been received for
+    /// the frame, and any of them do not match, the ids of the players that sent those are
+    /// returned.
+    pub fn add_local_checksum(&mut self, frame: u64, checksum: u64) -> Option<Vec<PlayerId>> {
+        self.local.insert(frame, checksum);
+
+        let res = self.remote.remove(&frame).and_then(|remote| {
+            let player_ids = remote
+                .into_iter()
+                .filter(|(_, remote)| *remote != checksum)
+                .map(|(player_id, _)| player_id)
+                .collect::<Vec<_>>();
+
+            if player_ids.is_empty() {
+                None
+            } else {
+                Some(player_ids)
+            }
+        });
+
+        self.discard_before(frame.saturating_sub(CHECKSUM_HISTORY_LEN));
+
+        res
+    }
+
+    /// Add a checksum received from a remote player. If the local checksum has already been
+    /// computed for the frame, and it does not match, the id of the remote player is returned.
+    pub fn add_remote_checksum(
+        &mut self,
+        player_id: &PlayerId,
+        frame: u64,
+        checksum: u64,
+    ) -> Option<PlayerId> {
+        if let Some(local) = self.local.get(&frame) {
+            if *local != checksum {
+                return Some(player_id.clone());
+            }
+        } else {
+            self.remote
+                .entry(frame)
+                .or_default()
+                .push((player_id.clone(), checksum));
+        }
+
+        None
+    }
+
+    fn discard_before(&mut self, frame: u64) {
+        self.local = self.local.split_off(&frame);
+        self.remote = self.remote.split_off(&frame);
+    }
+}
+
+/// Split the description of the state of `frame` into `StateDescriptionChunk`s
+pub fn state_description_chunks(
+    player_id: &PlayerId,
+    frame: u64,
+    data: &[u8],
+) -> Vec<NetworkMessage> {
+    let mut chunks = data.chunks(RESYNC_CHUNK_SIZE).collect::<Vec<_>>();
+
+    // Even an empty description is sent as a chunk, so that the receiver knows it is complete
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+
+    let chunk_cnt = chunks.len() as u64;
+
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| NetworkMessage::StateDescriptionChunk {
+            player_id: player_id.clone(),
+            frame,
+            index: index as u64,
+            chunk_cnt,
+            data: data.to_vec(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mismatch_is_detected_in_any_order() {
+        let player_id = "2".to_string();
+        let mut detector = DesyncDetector::new();
+
+        assert_eq!(detector.add_local_checksum(0, 10), None);
+        assert_eq!(detector.add_remote_checksum(&player_id, 0, 10), None);
+
+        assert_eq!(detector.add_remote_checksum(&player_id, 1, 11), None);
+        assert_eq!(detector.add_local_checksum(1, 11), None);
+
+        assert_eq!(detector.add_local_checksum(2, 12), None);
+        assert_eq!(
+            detector.add_remote_checksum(&player_id, 2, 13),
+            Some(player_id.clone())
+        );
+
+        assert_eq!(detector.add_remote_checksum(&player_id, 3, 14), None);
+        assert_eq!(
+            detector.add_local_checksum(3, 15),
+            Some(vec![player_id.clone()])
+        );
+    }
+
+    #[test]
+    fn test_only_mismatching_players_are_returned() {
+        let a = "2".to_string();
+        let b = "3".to_string();
+        let mut detector = DesyncDetector::new();
+
+        assert_eq!(detector.add_remote_checksum(&a, 0, 10), None);
+        assert_eq!(detector.add_remote_checksum(&b, 0, 11), None);
+        assert_eq!(detector.add_local_checksum(0, 10), Some(vec![b]));
+    }
+}
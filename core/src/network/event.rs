@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
 
 use super::PlayerId;
-use crate::network::Lobby;
+use crate::input::PlayerInput;
+use crate::network::{Lobby, RequestStatus};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -10,7 +11,7 @@ pub enum NetworkEvent {
         lobby_id: PlayerId,
     },
     LobbyChanged {
-        lobby: Lobby,
+        lobby: Box<Lobby>,
     },
     PlayerMarkedReady {
         player_id: PlayerId,
@@ -25,13 +26,78 @@ pub enum NetworkEvent {
     PlayerLeft {
         player_id: PlayerId,
     },
+    /// Nothing has been received from a remote player for a while, so it has either dropped, or
+    /// the local player has lost the connection
     PlayerReconnecting {
         player_id: PlayerId,
     },
+    /// A remote player has requested a resync, which should be answered by the host
+    ResyncRequested {
+        player_id: PlayerId,
+        checkpoint_frames: Vec<u64>,
+    },
+    /// The host has made a player idle, from `start_frame` until `end_frame`
+    PlayerIdle {
+        player_id: PlayerId,
+        start_frame: u64,
+        end_frame: Option<u64>,
+    },
+    /// All the data of a resync, sent by the host, has been received
+    Resync {
+        frame: u64,
+        data: Vec<u8>,
+    },
+    /// A spectator joined the running game of the lobby
+    SpectatorJoined {
+        player_id: PlayerId,
+        username: String,
+    },
+    SpectatorLeft {
+        player_id: PlayerId,
+    },
+    /// The response to a request for the list of open lobbies
+    LobbyList {
+        lobbies: Vec<Lobby>,
+    },
+    /// A lobby request was refused by the server
+    RequestFailed {
+        status: RequestStatus,
+    },
     GameStarted {
         lobby_id: PlayerId,
+        /// The seed that all peers should use for the simulation
+        seed: u64,
     },
     GameEnded {
         lobby_id: PlayerId,
     },
+    PlayerInput {
+        player_id: PlayerId,
+        frame: u64,
+        input: PlayerInput,
+    },
+    /// The state checksums of the remote players in `player_ids` did not match the local one for
+    /// `frame`
+    Desync {
+        frame: u64,
+        player_ids: Vec<PlayerId>,
+    },
+    /// A remote player has detected a desync on `frame`, and requests the local description of
+    /// the state of that frame
+    StateDescriptionRequested {
+        player_id: PlayerId,
+        frame: u64,
+    },
+    /// All chunks of the description of the state of `frame`, sent by a remote player, have been
+    /// received
+    StateDescription {
+        player_id: PlayerId,
+        frame: u64,
+        data: Vec<u8>,
+    },
+    /// An answer to a ping, dispatched by the local player at `timestamp`, was received
+    Pong {
+        player_id: PlayerId,
+        timestamp: u64,
+    },
 }
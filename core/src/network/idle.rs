@@ -0,0 +1,62 @@
+//! The periods that players are idle, given default input in place of their own, while they have
+//! dropped from a match and until they rejoin it. This is shared by both kinds of netcode.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::network::PlayerId;
+
+#[derive(Debug, Clone, Default)]
+pub struct IdlePeriods {
+    /// The periods of each player, by their first frame, with the frame they end on, if it is known
+    periods: HashMap<PlayerId, BTreeMap<u64, Option<u64>>>,
+}
+
+impl IdlePeriods {
+    pub fn new() -> Self {
+        IdlePeriods::default()
+    }
+
+    /// Make a player idle from `start_frame` until `end_frame`, or until further notice, if it is
+    /// `None`. Once a period has been given an end, it can not be changed, so that a notice that
+    /// arrives late will not extend it again. Returns `true` if anything changed.
+    pub fn set(&mut self, player_id: &PlayerId, start_frame: u64, end_frame: Option<u64>) -> bool {
+        let periods = self.periods.entry(player_id.clone()).or_default();
+
+        match periods.get_mut(&start_frame) {
+            Some(end) if end.is_none() && end_frame.is_some() => {
+                *end = end_frame;
+                true
+            }
+            Some(_) => false,
+            None => {
+                periods.insert(start_frame, end_frame);
+                true
+            }
+        }
+    }
+
+    /// Returns `true` if a player is idle on the given frame
+    pub fn contains(&self, player_id: &PlayerId, frame: u64) -> bool {
+        self.periods
+            .get(player_id)
+            .map(|periods| {
+                periods.range(..=frame).any(|(_, end)| match end {
+                    Some(end) => frame < *end,
+                    None => true,
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns all periods of all players, as `(player_id, start_frame, end_frame)`
+    pub fn to_vec(&self) -> Vec<(PlayerId, u64, Option<u64>)> {
+        self.periods
+            .iter()
+            .flat_map(|(player_id, periods)| {
+                periods
+                    .iter()
+                    .map(move |(start, end)| (player_id.clone(), *start, *end))
+            })
+            .collect()
+    }
+}
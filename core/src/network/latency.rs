@@ -0,0 +1,196 @@
+//! Latency measurement and adaptive input delay, as described in the netcode chapter of the book.
+//! The round trip time to every peer is measured with ping messages, and the input delay of the
+//! lockstep buffer is continuously adjusted, so that it is only as long as it has to be for
+//! remote input to arrive in time.
+
+/// The default maximum amount of frames that local input is delayed by, when the delay is adaptive
+pub const DEFAULT_MAX_INPUT_DELAY: u64 = 15;
+
+/// The minimum amount of frames that local input is delayed by, when the delay is adaptive
+pub const MIN_INPUT_DELAY: u64 = 1;
+
+/// The amount of simulated frames over which the input slack is measured, before the delay is
+/// shrunk
+pub const DELAY_ADJUSTMENT_WINDOW: u32 = 60;
+
+/// The amount of frames of remote input that should be buffered, beyond the frame that is
+/// simulated, to absorb spikes that were not seen during measurement
+const SLACK_MARGIN: u64 = 1;
+
+/// This estimates the round trip time and jitter to a peer, from samples, smoothing them in the
+/// same way as TCP does (RFC 6298). All times are in seconds.
+#[derive(Debug, Clone, Default)]
+pub struct RttEstimator {
+    rtt: Option<f32>,
+    jitter: f32,
+    last_sample: f32,
+    sample_cnt: u64,
+}
+
+impl RttEstimator {
+    pub fn add_sample(&mut self, rtt: f32) {
+        match self.rtt {
+            Some(smoothed) => {
+                self.jitter = 0.75 * self.jitter + 0.25 * (smoothed - rtt).abs();
+                self.rtt = Some(0.875 * smoothed + 0.125 * rtt);
+            }
+            None => {
+                self.jitter = rtt / 2.0;
+                self.rtt = Some(rtt);
+            }
+        }
+
+        self.last_sample = rtt;
+        self.sample_cnt += 1;
+    }
+
+    /// The smoothed round trip time, or zero if no samples have been added
+    pub fn rtt(&self) -> f32 {
+        self.rtt.unwrap_or_default()
+    }
+
+    /// The mean deviation of the round trip time
+    pub fn jitter(&self) -> f32 {
+        self.jitter
+    }
+
+    pub fn last_sample(&self) -> f32 {
+        self.last_sample
+    }
+
+    pub fn sample_cnt(&self) -> u64 {
+        self.sample_cnt
+    }
+}
+
+/// This adjusts the input delay of the lockstep buffer. It grows the delay by one frame whenever
+/// the simulation has stalled, waiting for remote input, and shrinks it by one frame when remote
+/// input has arrived with frames to spare, for a whole window. The delay will never be shorter
+/// than the latency of the slowest peer.
+#[derive(Debug, Clone)]
+pub struct InputDelayController {
+    delay: u64,
+    min_delay: u64,
+    max_delay: u64,
+    frame_cnt: u32,
+    stall_cnt: u32,
+    min_slack: Option<u64>,
+}
+
+impl InputDelayController {
+    pub fn new(delay: u64, min_delay: u64, max_delay: u64) -> Self {
+        InputDelayController {
+            delay: delay.clamp(min_delay, max_delay),
+            min_delay,
+            max_delay,
+            frame_cnt: 0,
+            stall_cnt: 0,
+            min_slack: None,
+        }
+    }
+
+    pub fn delay(&self) -> u64 {
+        self.delay
+    }
+
+    /// The smallest slack recorded in the current window
+    pub fn min_slack(&self) -> Option<u64> {
+        self.min_slack
+    }
+
+    /// Record a simulated frame. `slack` is the amount of frames of remote input that was
+    /// buffered beyond the frame, for the peer with the least input buffered.
+    pub fn add_frame(&mut self, slack: u64) {
+        self.frame_cnt += 1;
+        self.min_slack = Some(self.min_slack.map_or(slack, |min| min.min(slack)));
+    }
+
+    /// Record a fixed update that was skipped, because remote input had not arrived
+    pub fn add_stall(&mut self) {
+        self.stall_cnt += 1;
+    }
+
+    /// Adjust the delay, if needed, and return it. `latency_frames` is the one-way latency to the
+    /// slowest peer, including jitter, in frames.
+    pub fn update(&mut self, latency_frames: u64) -> u64 {
+        // A long stall should only grow the delay once, so at least one frame must have been
+        // simulated since the last adjustment
+        if self.stall_cnt > 0 && self.frame_cnt > 0 {
+            self.delay += 1;
+            self.reset_window();
+        } else if self.frame_cnt >= DELAY_ADJUSTMENT_WINDOW {
+            if self.stall_cnt == 0 && self.min_slack.unwrap_or_default() > SLACK_MARGIN {
+                self.delay = self.delay.saturating_sub(1);
+            }
+
+            self.reset_window();
+        }
+
+        self.delay = self
+            .delay
+            .max(latency_frames)
+            .clamp(self.min_delay, self.max_delay);
+
+        self.delay
+    }
+
+    fn reset_window(&mut self) {
+        self.frame_cnt = 0;
+        self.stall_cnt = 0;
+        self.min_slack = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtt_converges_and_tracks_jitter() {
+        let mut steady = RttEstimator::default();
+        let mut jittery = RttEstimator::default();
+
+        for i in 0..200 {
+            steady.add_sample(0.1);
+            jittery.add_sample(if i % 2 == 1 { 0.05 } else { 0.15 });
+        }
+
+        assert!((steady.rtt() - 0.1).abs() < 0.001);
+        assert!(steady.jitter() < 0.001);
+
+        assert!((jittery.rtt() - 0.1).abs() < 0.01);
+        assert!(jittery.jitter() > 0.04);
+    }
+
+    #[test]
+    fn test_delay_grows_on_stall_and_shrinks_with_slack() {
+        let mut controller = InputDelayController::new(4, MIN_INPUT_DELAY, 10);
+
+        // A stall before any frame has been simulated should not grow the delay
+        controller.add_stall();
+        assert_eq!(controller.update(0), 4);
+
+        controller.add_frame(0);
+        controller.add_stall();
+        controller.add_stall();
+        assert_eq!(controller.update(0), 5);
+
+        // Plenty of slack for a whole window shrinks the delay by a single frame
+        for _ in 0..DELAY_ADJUSTMENT_WINDOW {
+            controller.add_frame(3);
+            controller.update(0);
+        }
+
+        assert_eq!(controller.delay(), 4);
+
+        // It should never shrink below the latency
+        for _ in 0..DELAY_ADJUSTMENT_WINDOW * 10 {
+            controller.add_frame(3);
+            controller.update(3);
+        }
+
+        assert_eq!(controller.delay(), 3);
+
+        assert_eq!(controller.update(20), 10);
+    }
+}
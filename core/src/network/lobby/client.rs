@@ -0,0 +1,303 @@
+use std::collections::VecDeque;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::error::{Error, ErrorKind};
+use crate::network::{
+    ApiBackend, ApiBackendConstructor, Lobby, LobbyId, LobbyPrivacy, NetworkEvent, NetworkMessage,
+    PlayerId, UdpApiBackend,
+};
+use crate::Result;
+
+use super::{read_lines, write_line, LobbyRequest, LobbyServerMessage};
+
+/// The time to wait for the server to accept a new connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The parameters used to initialize a `LobbyApiBackend` through `Api::init`
+#[derive(Debug, Clone)]
+pub struct LobbyBackendParams {
+    pub server_addr: SocketAddr,
+    pub username: String,
+    /// The local address to bind the game socket to. The IP that other players will send game
+    /// messages to is the one that the server sees the connection come from, unless that is a
+    /// loopback address, in which case the other players are sent the IP that they reached the
+    /// server at.
+    pub game_addr: SocketAddr,
+}
+
+/// An `ApiBackend` that uses a `LobbyServer` to find other players and then plays with them,
+/// peer-to-peer, using an `UdpApiBackend`
+pub struct LobbyApiBackend {
+    player_id: PlayerId,
+    stream: TcpStream,
+    buf: Vec<u8>,
+    is_disconnected: bool,
+    lobby: Option<Lobby>,
+    game: UdpApiBackend,
+    events: VecDeque<NetworkEvent>,
+}
+
+impl LobbyApiBackend {
+    /// Connect to a lobby server and bind the game socket. This will block until the server has
+    /// responded, or the connection times out.
+    pub fn connect(params: LobbyBackendParams) -> Result<Self> {
+        let mut stream = TcpStream::connect_timeout(&params.server_addr, CONNECT_TIMEOUT)
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        stream
+            .set_nonblocking(true)
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        let _ = stream.set_nodelay(true);
+
+        write_line(
+            &mut stream,
+            &LobbyRequest::Connect {
+                username: params.username,
+            },
+        )?;
+
+        let mut buf = Vec::new();
+        let start = Instant::now();
+
+        let player_id = 'welcome: loop {
+            let (messages, is_closed) = read_lines::<LobbyServerMessage>(&mut stream, &mut buf);
+
+            for message in messages {
+                if let LobbyServerMessage::Welcome { player_id } = message {
+                    break 'welcome player_id;
+                }
+            }
+
+            if is_closed {
+                return Err(Error::new_const(
+                    ErrorKind::Network,
+                    &"The lobby server closed the connection",
+                ));
+            }
+
+            if start.elapsed() > CONNECT_TIMEOUT {
+                return Err(Error::new_const(
+                    ErrorKind::Network,
+                    &"Timed out waiting for the lobby server",
+                ));
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        };
+
+        let game = UdpApiBackend::bind(&player_id, params.game_addr)?;
+
+        write_line(
+            &mut stream,
+            &LobbyRequest::SetGamePort {
+                port: game.local_addr()?.port(),
+            },
+        )?;
+
+        Ok(LobbyApiBackend {
+            player_id,
+            stream,
+            buf,
+            is_disconnected: false,
+            lobby: None,
+            game,
+            events: VecDeque::new(),
+        })
+    }
+
+    /// The lobby that the local player is currently in, as last reported by the server
+    pub fn lobby(&self) -> Option<&Lobby> {
+        self.lobby.as_ref()
+    }
+
+    fn send(&mut self, request: LobbyRequest) -> Result<()> {
+        if self.is_disconnected {
+            return Err(Error::new_const(
+                ErrorKind::Network,
+                &"Not connected to a lobby server",
+            ));
+        }
+
+        write_line(&mut self.stream, &request)
+    }
+
+    fn poll_server(&mut self) {
+        if self.is_disconnected {
+            return;
+        }
+
+        let (messages, is_closed) =
+            read_lines::<LobbyServerMessage>(&mut self.stream, &mut self.buf);
+
+        for message in messages {
+            if let LobbyServerMessage::Event(event) = message {
+                self.on_event(*event);
+            }
+        }
+
+        if is_closed {
+            #[cfg(debug_assertions)]
+            println!("WARNING: LobbyApiBackend: Lost connection to the lobby server");
+
+            self.is_disconnected = true;
+        }
+    }
+
+    fn on_event(&mut self, event: NetworkEvent) {
+        match &event {
+            NetworkEvent::LobbyChanged { lobby } => {
+                self.lobby = Some(lobby.as_ref().clone());
+            }
+            NetworkEvent::PlayerLeft { player_id } => {
+                self.game.remove_peer(player_id);
+            }
+            NetworkEvent::GameStarted { .. } => {
+                if let Some(lobby) = &self.lobby {
+                    // A spectator only receives input from the host
+                    let is_spectator = lobby
+                        .spectators
+                        .iter()
+                        .any(|player| player.id == self.player_id);
+
+                    for player in &lobby.players {
+                        if player.id == self.player_id
+                            || (is_spectator && player.id != lobby.admin_player_id)
+                        {
+                            continue;
+                        }
+
+                        if let Some(addr) = player.addr {
+                            self.game.add_peer(&player.id, addr);
+                        }
+                    }
+                }
+            }
+            NetworkEvent::SpectatorJoined { player_id, .. } => {
+                if let Some(lobby) = &self.lobby {
+                    let spectator = lobby
+                        .spectators
+                        .iter()
+                        .find(|player| &player.id == player_id);
+
+                    if lobby.admin_player_id == self.player_id {
+                        if let Some(addr) = spectator.and_then(|player| player.addr) {
+                            self.game.add_spectator(player_id, addr);
+                        }
+                    }
+                }
+            }
+            NetworkEvent::SpectatorLeft { player_id } => {
+                self.game.remove_spectator(player_id);
+            }
+            _ => {}
+        }
+
+        self.events.push_back(event);
+    }
+}
+
+#[async_trait]
+impl ApiBackendConstructor for LobbyApiBackend {
+    type Params = LobbyBackendParams;
+
+    async fn init(params: LobbyBackendParams) -> Result<Self> {
+        LobbyApiBackend::connect(params)
+    }
+}
+
+#[async_trait]
+impl ApiBackend for LobbyApiBackend {
+    async fn close(&mut self) -> Result<()> {
+        if !self.is_disconnected {
+            let _ = self.send(LobbyRequest::LeaveLobby);
+            let _ = self.stream.shutdown(std::net::Shutdown::Both);
+
+            self.is_disconnected = true;
+        }
+
+        self.lobby = None;
+        self.events.clear();
+
+        self.game.close().await
+    }
+
+    fn local_player_id(&self) -> PlayerId {
+        self.player_id.clone()
+    }
+
+    fn dispatch_message(&mut self, message: NetworkMessage) -> Result<()> {
+        self.game.dispatch_message(message)
+    }
+
+    fn resync_player(&mut self, player_id: &PlayerId, frame: u64, data: &[u8]) -> Result<()> {
+        self.game.resync_player(player_id, frame, data)
+    }
+
+    fn next_event(&mut self) -> Option<NetworkEvent> {
+        self.poll_server();
+
+        self.events.pop_front().or_else(|| self.game.next_event())
+    }
+
+    fn create_lobby(&mut self, name: &str, capacity: i32, privacy: LobbyPrivacy) -> Result<()> {
+        self.send(LobbyRequest::CreateLobby {
+            name: name.to_string(),
+            capacity,
+            privacy,
+        })
+    }
+
+    fn request_lobby_list(&mut self) -> Result<()> {
+        self.send(LobbyRequest::ListLobbies)
+    }
+
+    fn join_lobby(&mut self, lobby_id: &LobbyId) -> Result<()> {
+        self.send(LobbyRequest::JoinLobby {
+            lobby_id: lobby_id.clone(),
+        })
+    }
+
+    fn spectate_lobby(&mut self, lobby_id: &LobbyId) -> Result<()> {
+        self.send(LobbyRequest::SpectateLobby {
+            lobby_id: lobby_id.clone(),
+        })
+    }
+
+    fn leave_lobby(&mut self) -> Result<()> {
+        self.lobby = None;
+        self.send(LobbyRequest::LeaveLobby)
+    }
+
+    fn set_ready(&mut self, is_ready: bool) -> Result<()> {
+        self.send(LobbyRequest::SetReady { is_ready })
+    }
+
+    fn set_character(&mut self, character: &str) -> Result<()> {
+        self.send(LobbyRequest::SetCharacter {
+            character: character.to_string(),
+        })
+    }
+
+    fn set_lobby_map(&mut self, map: &str) -> Result<()> {
+        self.send(LobbyRequest::SetMap {
+            map: map.to_string(),
+        })
+    }
+
+    fn set_team(&mut self, team: Option<u8>) -> Result<()> {
+        self.send(LobbyRequest::SetTeam { team })
+    }
+
+    fn set_friendly_fire(&mut self, is_enabled: bool) -> Result<()> {
+        self.send(LobbyRequest::SetFriendlyFire { is_enabled })
+    }
+
+    fn start_game(&mut self) -> Result<()> {
+        self.send(LobbyRequest::StartGame)
+    }
+}
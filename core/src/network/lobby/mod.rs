@@ -0,0 +1,455 @@
+//! A minimal lobby service, used in place of a dedicated matchmaking server, for hosting and
+//! joining games on a local network. The `LobbyServer` keeps track of lobbies and players, and
+//! the `LobbyApiBackend` talks to it over TCP, until the game is started. At that point all
+//! players exchange game messages directly, using an embedded `UdpApiBackend`.
+//!
+//! Messages are JSON encoded and separated by newlines.
+
+mod client;
+mod server;
+
+pub use client::{LobbyApiBackend, LobbyBackendParams};
+pub use server::LobbyServer;
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, ErrorKind};
+use crate::network::{LobbyId, LobbyPrivacy, NetworkEvent, PlayerId};
+use crate::Result;
+
+/// The port that the lobby server listens on, by default
+pub const DEFAULT_LOBBY_PORT: u16 = 9870;
+
+/// The maximum size of a single line that will be buffered before a connection is considered
+/// broken
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// A request sent from a client to the lobby server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LobbyRequest {
+    /// This must be the first request from a new connection. The server will respond with
+    /// `LobbyServerMessage::Welcome`.
+    Connect {
+        username: String,
+    },
+    /// Set the UDP port that the player will receive game messages on. The address of the
+    /// connection is used for the IP.
+    SetGamePort {
+        port: u16,
+    },
+    CreateLobby {
+        name: String,
+        capacity: i32,
+        privacy: LobbyPrivacy,
+    },
+    ListLobbies,
+    JoinLobby {
+        lobby_id: LobbyId,
+    },
+    /// Watch the running game of a lobby. The server will respond with the lobby, followed by
+    /// `NetworkEvent::GameStarted`.
+    SpectateLobby {
+        lobby_id: LobbyId,
+    },
+    LeaveLobby,
+    SetReady {
+        is_ready: bool,
+    },
+    SetCharacter {
+        character: String,
+    },
+    /// Select the map of the current lobby. This is only allowed for the lobby admin.
+    SetMap {
+        map: String,
+    },
+    /// Select the team of the player, or leave the team, if `team` is `None`
+    SetTeam {
+        team: Option<u8>,
+    },
+    /// Allow or disallow damage between players of the same team. This is only allowed for the
+    /// lobby admin.
+    SetFriendlyFire {
+        is_enabled: bool,
+    },
+    StartGame,
+}
+
+/// A message sent from the lobby server to a client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LobbyServerMessage {
+    /// The response to `LobbyRequest::Connect`, holding the id assigned to the player
+    Welcome {
+        player_id: PlayerId,
+    },
+    Event(Box<NetworkEvent>),
+}
+
+/// Serialize a message and write it to the stream, followed by a newline
+fn write_line<T: Serialize>(stream: &mut TcpStream, message: &T) -> Result<()> {
+    let mut bytes = serde_json::to_vec(message)?;
+    bytes.push(b'\n');
+
+    stream
+        .write_all(&bytes)
+        .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+    Ok(())
+}
+
+/// Read everything available on a non-blocking stream into `buf` and return all complete lines,
+/// deserialized. The second value of the returned tuple will be `true` if the connection has
+/// been closed, or is broken.
+fn read_lines<T: DeserializeOwned>(stream: &mut TcpStream, buf: &mut Vec<u8>) -> (Vec<T>, bool) {
+    let mut is_closed = false;
+    let mut chunk = [0u8; 1024];
+
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) => {
+                is_closed = true;
+                break;
+            }
+            Ok(len) => buf.extend_from_slice(&chunk[..len]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => {
+                is_closed = true;
+                break;
+            }
+        }
+    }
+
+    let mut messages = Vec::new();
+
+    while let Some(i) = buf.iter().position(|b| *b == b'\n') {
+        let line = buf.drain(..=i).collect::<Vec<_>>();
+
+        match serde_json::from_slice(&line[..i]) {
+            Ok(message) => messages.push(message),
+            Err(err) => {
+                #[cfg(debug_assertions)]
+                println!("WARNING: Lobby: {}", err);
+            }
+        }
+    }
+
+    if buf.len() > MAX_LINE_LENGTH {
+        is_closed = true;
+    }
+
+    (messages, is_closed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, UdpSocket};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::input::PlayerInput;
+    use crate::network::{ApiBackend, LobbyState, NetworkMessage, RequestStatus};
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// An IP of this machine that is not a loopback address. Connecting a UDP socket sends
+    /// nothing, it only picks the interface that the address would be reached through.
+    fn non_loopback_ip() -> Option<IpAddr> {
+        let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+        socket.connect("192.0.2.1:9").ok()?;
+
+        let ip = socket.local_addr().ok()?.ip();
+        if ip.is_loopback() || ip.is_unspecified() {
+            None
+        } else {
+            Some(ip)
+        }
+    }
+
+    fn connect(server_addr: std::net::SocketAddr, username: &str) -> LobbyApiBackend {
+        LobbyApiBackend::connect(LobbyBackendParams {
+            server_addr,
+            username: username.to_string(),
+            game_addr: "127.0.0.1:0".parse().unwrap(),
+        })
+        .unwrap()
+    }
+
+    /// Poll the backend until an event matching `f` is received, discarding all other events
+    fn wait_for<T>(
+        backend: &mut LobbyApiBackend,
+        mut f: impl FnMut(NetworkEvent) -> Option<T>,
+    ) -> T {
+        let start = Instant::now();
+
+        loop {
+            while let Some(event) = backend.next_event() {
+                if let Some(res) = f(event) {
+                    return res;
+                }
+            }
+
+            assert!(start.elapsed() < TIMEOUT, "Timed out waiting for event");
+
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_create_join_and_start_game() {
+        let (server_addr, _) = LobbyServer::spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let mut host = connect(server_addr, "host");
+        let mut client = connect(server_addr, "client");
+
+        assert_ne!(host.local_player_id(), client.local_player_id());
+
+        host.create_lobby("test", 4, LobbyPrivacy::Public).unwrap();
+
+        let lobby_id = wait_for(&mut host, |event| match event {
+            NetworkEvent::LobbyCreated { lobby_id } => Some(lobby_id),
+            _ => None,
+        });
+
+        client.request_lobby_list().unwrap();
+
+        let lobbies = wait_for(&mut client, |event| match event {
+            NetworkEvent::LobbyList { lobbies } => Some(lobbies),
+            _ => None,
+        });
+
+        assert_eq!(lobbies.len(), 1);
+        assert_eq!(lobbies[0].id, lobby_id);
+
+        client.join_lobby(&lobby_id).unwrap();
+
+        let username = wait_for(&mut host, |event| match event {
+            NetworkEvent::PlayerJoined { username, .. } => Some(username),
+            _ => None,
+        });
+
+        assert_eq!(username, "client");
+
+        // Starting before all players are ready should be refused
+        host.start_game().unwrap();
+
+        let status = wait_for(&mut host, |event| match event {
+            NetworkEvent::RequestFailed { status } => Some(status),
+            _ => None,
+        });
+
+        assert_eq!(status, RequestStatus::Unauthorized);
+
+        host.set_ready(true).unwrap();
+        client.set_ready(true).unwrap();
+
+        wait_for(&mut host, |event| match event {
+            NetworkEvent::LobbyChanged { lobby } if lobby.state == LobbyState::Ready => Some(()),
+            _ => None,
+        });
+
+        host.start_game().unwrap();
+
+        let is_game_started = |event: NetworkEvent| match event {
+            NetworkEvent::GameStarted { seed, .. } => Some(seed),
+            _ => None,
+        };
+
+        let host_seed = wait_for(&mut host, is_game_started);
+        let client_seed = wait_for(&mut client, is_game_started);
+
+        assert_eq!(host_seed, client_seed);
+
+        // Game messages should now be exchanged directly between the players
+        let input = PlayerInput {
+            jump: true,
+            ..Default::default()
+        };
+
+        host.dispatch_message(NetworkMessage::UpdatePlayerInput {
+            player_id: host.local_player_id(),
+            frame: 3,
+            input,
+        })
+        .unwrap();
+
+        let received = wait_for(&mut client, |event| match event {
+            NetworkEvent::PlayerInput { input, .. } => Some(input),
+            _ => None,
+        });
+
+        assert_eq!(received, input);
+
+        // The running game should be listed, and it can be joined by a spectator
+        let mut spectator = connect(server_addr, "spectator");
+
+        spectator.request_lobby_list().unwrap();
+
+        let lobbies = wait_for(&mut spectator, |event| match event {
+            NetworkEvent::LobbyList { lobbies } => Some(lobbies),
+            _ => None,
+        });
+
+        assert_eq!(lobbies[0].state, LobbyState::Running);
+
+        spectator.spectate_lobby(&lobby_id).unwrap();
+
+        let spectator_seed = wait_for(&mut spectator, is_game_started);
+        assert_eq!(spectator_seed, host_seed);
+
+        let username = wait_for(&mut host, |event| match event {
+            NetworkEvent::SpectatorJoined { username, .. } => Some(username),
+            _ => None,
+        });
+
+        assert_eq!(username, "spectator");
+
+        let player_ids = vec![host.local_player_id(), client.local_player_id()];
+
+        host.dispatch_message(NetworkMessage::SpectatorInputs {
+            player_id: host.local_player_id(),
+            start_frame: 0,
+            player_ids: player_ids.clone(),
+            inputs: vec![input, PlayerInput::default()],
+        })
+        .unwrap();
+
+        // Confirmed input is sent to spectators in batches, when the host polls its socket
+        let start = Instant::now();
+
+        let (player_id, frame, received) = 'received: loop {
+            while host.next_event().is_some() {}
+
+            while let Some(event) = spectator.next_event() {
+                if let NetworkEvent::PlayerInput {
+                    player_id,
+                    frame,
+                    input,
+                } = event
+                {
+                    break 'received (player_id, frame, input);
+                }
+            }
+
+            assert!(start.elapsed() < TIMEOUT, "Timed out waiting for input");
+
+            thread::sleep(Duration::from_millis(1));
+        };
+
+        assert_eq!(player_id, player_ids[0]);
+        assert_eq!(frame, 0);
+        assert_eq!(received, input);
+    }
+
+    #[test]
+    fn test_host_on_loopback_is_advertised_by_reachable_addr() {
+        // This can only be tested on a machine with a network interface
+        let ip = match non_loopback_ip() {
+            Some(ip) => ip,
+            None => return,
+        };
+
+        let (server_addr, _) = LobbyServer::spawn("0.0.0.0:0".parse().unwrap()).unwrap();
+
+        // The host runs the server, so it connects through the loopback interface, while the
+        // client reaches the server over the network
+        let mut host = connect(([127, 0, 0, 1], server_addr.port()).into(), "host");
+        let mut client = connect((ip, server_addr.port()).into(), "client");
+
+        host.create_lobby("test", 4, LobbyPrivacy::Public).unwrap();
+
+        let lobby_id = wait_for(&mut host, |event| match event {
+            NetworkEvent::LobbyCreated { lobby_id } => Some(lobby_id),
+            _ => None,
+        });
+
+        client.join_lobby(&lobby_id).unwrap();
+
+        let host_id = host.local_player_id();
+
+        let host_addr = wait_for(&mut client, |event| match event {
+            NetworkEvent::LobbyChanged { lobby } if lobby.players.len() == 2 => lobby
+                .players
+                .into_iter()
+                .find(|player| player.id == host_id)
+                .and_then(|player| player.addr),
+            _ => None,
+        });
+
+        assert!(!host_addr.ip().is_loopback());
+        assert_eq!(host_addr.ip(), ip);
+
+        // The host reached the server through the loopback interface, so it is sent its own
+        // address unchanged
+        let host_addr_for_host = wait_for(&mut host, |event| match event {
+            NetworkEvent::LobbyChanged { lobby } if lobby.players.len() == 2 => lobby
+                .players
+                .into_iter()
+                .find(|player| player.id == host_id)
+                .and_then(|player| player.addr),
+            _ => None,
+        });
+
+        assert!(host_addr_for_host.ip().is_loopback());
+    }
+
+    #[test]
+    fn test_teams_and_friendly_fire() {
+        let (server_addr, _) = LobbyServer::spawn("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let mut host = connect(server_addr, "host");
+        let mut client = connect(server_addr, "client");
+
+        host.create_lobby("test", 4, LobbyPrivacy::Public).unwrap();
+
+        let lobby_id = wait_for(&mut host, |event| match event {
+            NetworkEvent::LobbyCreated { lobby_id } => Some(lobby_id),
+            _ => None,
+        });
+
+        client.join_lobby(&lobby_id).unwrap();
+
+        wait_for(&mut host, |event| match event {
+            NetworkEvent::PlayerJoined { .. } => Some(()),
+            _ => None,
+        });
+
+        // Friendly fire can only be set by the admin
+        client.set_friendly_fire(true).unwrap();
+
+        let status = wait_for(&mut client, |event| match event {
+            NetworkEvent::RequestFailed { status } => Some(status),
+            _ => None,
+        });
+
+        assert_eq!(status, RequestStatus::Unauthorized);
+
+        client.set_team(Some(1)).unwrap();
+        host.set_friendly_fire(true).unwrap();
+
+        let client_id = client.local_player_id();
+
+        wait_for(&mut host, |event| match event {
+            NetworkEvent::LobbyChanged { lobby } => {
+                let client_team = lobby
+                    .players
+                    .iter()
+                    .find(|player| player.id == client_id)
+                    .and_then(|player| player.team);
+
+                if lobby.friendly_fire && client_team == Some(1) {
+                    Some(())
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        });
+    }
+}
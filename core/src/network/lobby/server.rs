@@ -0,0 +1,678 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, ErrorKind};
+use crate::network::{
+    ClientState, Lobby, LobbyId, LobbyPrivacy, LobbyState, NetworkEvent, Player, PlayerId,
+    RequestStatus,
+};
+use crate::Result;
+
+use super::{read_lines, write_line, LobbyRequest, LobbyServerMessage};
+
+/// The time the server will sleep between polls, when run on its own thread
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+struct Connection {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    /// This is `None` until the client has sent `LobbyRequest::Connect`
+    player: Option<Player>,
+    lobby_id: Option<LobbyId>,
+    is_closed: bool,
+}
+
+impl Connection {
+    fn send(&mut self, message: &LobbyServerMessage) {
+        if self.is_closed {
+            return;
+        }
+
+        if let Err(err) = write_line(&mut self.stream, message) {
+            #[cfg(debug_assertions)]
+            println!("WARNING: LobbyServer: {}", err);
+
+            self.is_closed = true;
+        }
+    }
+
+    fn send_event(&mut self, event: NetworkEvent) {
+        self.send(&LobbyServerMessage::Event(Box::new(event)));
+    }
+
+    /// The address that the client of this connection can reach a player at. Players that are
+    /// connected through the loopback interface are on the same machine as the server, like a
+    /// host that runs the server in the background, so their IP is replaced by the one that this
+    /// client reached the server at.
+    fn advertised_addr(&self, addr: SocketAddr) -> SocketAddr {
+        if addr.ip().is_loopback() {
+            if let Ok(local_addr) = self.stream.local_addr() {
+                return SocketAddr::new(local_addr.ip(), addr.port());
+            }
+        }
+
+        addr
+    }
+
+    /// A copy of the lobby, with the addresses of its players as the client of this connection can
+    /// reach them at
+    fn advertised_lobby(&self, lobby: &Lobby) -> Lobby {
+        let mut lobby = lobby.clone();
+
+        for player in lobby.players.iter_mut().chain(lobby.spectators.iter_mut()) {
+            player.addr = player.addr.map(|addr| self.advertised_addr(addr));
+        }
+
+        lobby
+    }
+}
+
+/// A lobby server that keeps all state in memory. It can either be run on its own, with
+/// `LobbyServer::run`, or in the background of a game client, with `LobbyServer::spawn`.
+pub struct LobbyServer {
+    listener: TcpListener,
+    connections: Vec<Connection>,
+    lobbies: Vec<Lobby>,
+    /// The seeds of the games that have been started, kept for spectators that join later
+    seeds: HashMap<LobbyId, u64>,
+    next_id: u64,
+}
+
+impl LobbyServer {
+    pub fn bind(addr: SocketAddr) -> Result<Self> {
+        let listener =
+            TcpListener::bind(addr).map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        listener
+            .set_nonblocking(true)
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        Ok(LobbyServer {
+            listener,
+            connections: Vec::new(),
+            lobbies: Vec::new(),
+            seeds: HashMap::new(),
+            next_id: 1,
+        })
+    }
+
+    /// Bind a new server to `addr` and run it on a separate thread. The returned address is the
+    /// one that the server was actually bound to, which is useful if port `0` was specified.
+    pub fn spawn(addr: SocketAddr) -> Result<(SocketAddr, JoinHandle<()>)> {
+        let server = LobbyServer::bind(addr)?;
+        let local_addr = server.local_addr()?;
+
+        let handle = thread::spawn(move || server.run());
+
+        Ok((local_addr, handle))
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener
+            .local_addr()
+            .map_err(|err| Error::new(ErrorKind::Network, err))
+    }
+
+    pub fn lobbies(&self) -> &[Lobby] {
+        &self.lobbies
+    }
+
+    /// Poll the server until the process ends
+    pub fn run(mut self) {
+        loop {
+            self.poll();
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Accept new connections and handle all pending requests
+    pub fn poll(&mut self) {
+        self.accept_connections();
+
+        for i in 0..self.connections.len() {
+            let (requests, is_closed) = {
+                let connection = &mut self.connections[i];
+                read_lines::<LobbyRequest>(&mut connection.stream, &mut connection.buf)
+            };
+
+            for request in requests {
+                self.handle_request(i, request);
+            }
+
+            if is_closed {
+                self.connections[i].is_closed = true;
+            }
+        }
+
+        for i in 0..self.connections.len() {
+            if self.connections[i].is_closed {
+                self.leave_lobby(i);
+            }
+        }
+
+        self.connections.retain(|connection| !connection.is_closed);
+    }
+
+    fn accept_connections(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(err) = stream.set_nonblocking(true) {
+                        #[cfg(debug_assertions)]
+                        println!("WARNING: LobbyServer: {}", err);
+
+                        continue;
+                    }
+
+                    let _ = stream.set_nodelay(true);
+
+                    self.connections.push(Connection {
+                        stream,
+                        buf: Vec::new(),
+                        player: None,
+                        lobby_id: None,
+                        is_closed: false,
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: LobbyServer: {}", err);
+
+                    break;
+                }
+            }
+        }
+    }
+
+    fn handle_request(&mut self, i: usize, request: LobbyRequest) {
+        if self.connections[i].player.is_none() {
+            if let LobbyRequest::Connect { username } = request {
+                let player_id = self.next_id.to_string();
+                self.next_id += 1;
+
+                let connection = &mut self.connections[i];
+                connection.player = Some(Player::new(&player_id, &username));
+                connection.send(&LobbyServerMessage::Welcome { player_id });
+            } else {
+                self.fail(i, RequestStatus::Unauthorized);
+            }
+
+            return;
+        }
+
+        match request {
+            LobbyRequest::Connect { .. } => self.fail(i, RequestStatus::Unauthorized),
+            LobbyRequest::SetGamePort { port } => {
+                if let Ok(peer_addr) = self.connections[i].stream.peer_addr() {
+                    let player = self.connections[i].player.as_mut().unwrap();
+                    player.addr = Some(SocketAddr::new(peer_addr.ip(), port));
+                }
+            }
+            LobbyRequest::CreateLobby {
+                name,
+                capacity,
+                privacy,
+            } => self.create_lobby(i, &name, capacity, privacy),
+            LobbyRequest::ListLobbies => {
+                let lobbies = self
+                    .lobbies
+                    .iter()
+                    .filter(|lobby| {
+                        lobby.privacy == LobbyPrivacy::Public
+                            && (lobby.state == LobbyState::NotStarted
+                                || lobby.state == LobbyState::Running)
+                    })
+                    .map(|lobby| self.connections[i].advertised_lobby(lobby))
+                    .collect();
+
+                self.connections[i].send_event(NetworkEvent::LobbyList { lobbies });
+            }
+            LobbyRequest::JoinLobby { lobby_id } => self.join_lobby(i, &lobby_id),
+            LobbyRequest::SpectateLobby { lobby_id } => self.spectate_lobby(i, &lobby_id),
+            LobbyRequest::LeaveLobby => self.leave_lobby(i),
+            LobbyRequest::SetReady { is_ready } => self.set_ready(i, is_ready),
+            LobbyRequest::SetCharacter { character } => self.set_character(i, &character),
+            LobbyRequest::SetMap { map } => self.set_map(i, &map),
+            LobbyRequest::SetTeam { team } => self.set_team(i, team),
+            LobbyRequest::SetFriendlyFire { is_enabled } => self.set_friendly_fire(i, is_enabled),
+            LobbyRequest::StartGame => self.start_game(i),
+        }
+    }
+
+    fn fail(&mut self, i: usize, status: RequestStatus) {
+        self.connections[i].send_event(NetworkEvent::RequestFailed { status });
+    }
+
+    fn player_id(&self, i: usize) -> PlayerId {
+        self.connections[i].player.as_ref().unwrap().id.clone()
+    }
+
+    fn lobby_index(&self, lobby_id: &LobbyId) -> Option<usize> {
+        self.lobbies.iter().position(|lobby| &lobby.id == lobby_id)
+    }
+
+    /// The index of the lobby that the connection is in
+    fn current_lobby_index(&self, i: usize) -> Option<usize> {
+        self.connections[i]
+            .lobby_id
+            .as_ref()
+            .and_then(|lobby_id| self.lobby_index(lobby_id))
+    }
+
+    /// Send an event to all connections in a lobby
+    fn broadcast(&mut self, lobby_id: &LobbyId, event: NetworkEvent) {
+        for connection in &mut self.connections {
+            if connection.lobby_id.as_ref() == Some(lobby_id) {
+                connection.send_event(event.clone());
+            }
+        }
+    }
+
+    /// Send the lobby to all connections in it. Each connection is sent the addresses of the
+    /// players as it can reach them at.
+    fn broadcast_lobby(&mut self, lobby_index: usize) {
+        let lobby = &self.lobbies[lobby_index];
+
+        for connection in &mut self.connections {
+            if connection.lobby_id.as_ref() == Some(&lobby.id) {
+                let lobby = Box::new(connection.advertised_lobby(lobby));
+                connection.send_event(NetworkEvent::LobbyChanged { lobby });
+            }
+        }
+    }
+
+    /// Update the players of a lobby with the current state of the connections, and update the
+    /// lobby state accordingly
+    fn sync_lobby_players(&mut self, lobby_index: usize) {
+        let lobby = &mut self.lobbies[lobby_index];
+
+        for player in lobby.players.iter_mut().chain(lobby.spectators.iter_mut()) {
+            if let Some(connection) = self
+                .connections
+                .iter()
+                .find(|connection| connection.player.as_ref().map(|p| &p.id) == Some(&player.id))
+            {
+                player.addr = connection.player.as_ref().unwrap().addr;
+            }
+        }
+
+        lobby.player_count = lobby.players.len() as i32;
+
+        if lobby.state == LobbyState::NotStarted || lobby.state == LobbyState::Ready {
+            let is_ready = lobby.players.len() > 1
+                && lobby
+                    .players
+                    .iter()
+                    .all(|player| player.state == ClientState::Ready);
+
+            lobby.state = if is_ready {
+                LobbyState::Ready
+            } else {
+                LobbyState::NotStarted
+            };
+        }
+    }
+
+    fn create_lobby(&mut self, i: usize, name: &str, capacity: i32, privacy: LobbyPrivacy) {
+        if capacity < 1 {
+            self.fail(i, RequestStatus::Unauthorized);
+            return;
+        }
+
+        self.leave_lobby(i);
+
+        let lobby_id = self.next_id.to_string();
+        self.next_id += 1;
+
+        let player_id = self.player_id(i);
+
+        let mut player = self.connections[i].player.clone().unwrap();
+        player.state = ClientState::Joined;
+
+        self.lobbies.push(Lobby {
+            id: lobby_id.clone(),
+            name: name.to_string(),
+            creator_player_id: player_id.clone(),
+            admin_player_id: player_id,
+            player_count: 1,
+            capacity,
+            server: None,
+            privacy,
+            state: LobbyState::NotStarted,
+            players: vec![player],
+            map: None,
+            spectators: Vec::new(),
+            friendly_fire: false,
+        });
+
+        self.connections[i].lobby_id = Some(lobby_id.clone());
+        self.connections[i].send_event(NetworkEvent::LobbyCreated {
+            lobby_id: lobby_id.clone(),
+        });
+
+        let lobby_index = self.lobbies.len() - 1;
+        self.sync_lobby_players(lobby_index);
+        self.broadcast_lobby(lobby_index);
+    }
+
+    fn join_lobby(&mut self, i: usize, lobby_id: &LobbyId) {
+        let lobby_index = match self.lobby_index(lobby_id) {
+            Some(lobby_index) => lobby_index,
+            None => {
+                self.fail(i, RequestStatus::NotFound);
+                return;
+            }
+        };
+
+        if self.connections[i].lobby_id.as_ref() == Some(lobby_id) {
+            return;
+        }
+
+        {
+            let lobby = &self.lobbies[lobby_index];
+            if lobby.state != LobbyState::NotStarted || lobby.player_count >= lobby.capacity {
+                self.fail(i, RequestStatus::Unauthorized);
+                return;
+            }
+        }
+
+        self.leave_lobby(i);
+
+        // Leaving a lobby might have removed another lobby, so the index must be looked up again
+        let lobby_index = self.lobby_index(lobby_id).unwrap();
+
+        let mut player = self.connections[i].player.clone().unwrap();
+        player.state = ClientState::Joined;
+
+        self.broadcast(
+            lobby_id,
+            NetworkEvent::PlayerJoined {
+                player_id: player.id.clone(),
+                username: player.username.clone(),
+            },
+        );
+
+        self.lobbies[lobby_index].players.push(player);
+        self.connections[i].lobby_id = Some(lobby_id.clone());
+
+        self.sync_lobby_players(lobby_index);
+        self.broadcast_lobby(lobby_index);
+    }
+
+    /// Join the running game of a lobby as a spectator. The spectator is sent the lobby, followed
+    /// by `NetworkEvent::GameStarted`, and the host is notified, so that it can start sending the
+    /// confirmed input of the game to the spectator.
+    fn spectate_lobby(&mut self, i: usize, lobby_id: &LobbyId) {
+        let (lobby_index, seed) = match (self.lobby_index(lobby_id), self.seeds.get(lobby_id)) {
+            (Some(lobby_index), Some(seed)) => (lobby_index, *seed),
+            _ => {
+                self.fail(i, RequestStatus::NotFound);
+                return;
+            }
+        };
+
+        if self.connections[i].lobby_id.as_ref() == Some(lobby_id) {
+            return;
+        }
+
+        let player = self.connections[i].player.clone().unwrap();
+
+        if self.lobbies[lobby_index].state != LobbyState::Running || player.addr.is_none() {
+            self.fail(i, RequestStatus::Unauthorized);
+            return;
+        }
+
+        self.leave_lobby(i);
+
+        let lobby_index = self.lobby_index(lobby_id).unwrap();
+
+        let player = Player {
+            state: ClientState::Spectating,
+            ..player
+        };
+
+        let event = NetworkEvent::SpectatorJoined {
+            player_id: player.id.clone(),
+            username: player.username.clone(),
+        };
+
+        self.lobbies[lobby_index].spectators.push(player);
+        self.connections[i].lobby_id = Some(lobby_id.clone());
+
+        self.sync_lobby_players(lobby_index);
+        self.broadcast_lobby(lobby_index);
+
+        self.broadcast(lobby_id, event);
+
+        self.connections[i].send_event(NetworkEvent::GameStarted {
+            lobby_id: lobby_id.clone(),
+            seed,
+        });
+    }
+
+    fn leave_lobby(&mut self, i: usize) {
+        let lobby_id = match self.connections[i].lobby_id.take() {
+            Some(lobby_id) => lobby_id,
+            None => return,
+        };
+
+        let lobby_index = match self.lobby_index(&lobby_id) {
+            Some(lobby_index) => lobby_index,
+            None => return,
+        };
+
+        let player_id = self.player_id(i);
+
+        let lobby = &mut self.lobbies[lobby_index];
+
+        if lobby.spectators.iter().any(|player| player.id == player_id) {
+            lobby.spectators.retain(|player| player.id != player_id);
+
+            self.broadcast(&lobby_id, NetworkEvent::SpectatorLeft { player_id });
+            self.broadcast_lobby(lobby_index);
+            return;
+        }
+
+        lobby.players.retain(|player| player.id != player_id);
+
+        if lobby.players.is_empty() {
+            self.lobbies.remove(lobby_index);
+            self.seeds.remove(&lobby_id);
+            return;
+        }
+
+        if lobby.admin_player_id == player_id {
+            lobby.admin_player_id = lobby.players[0].id.clone();
+        }
+
+        self.broadcast(&lobby_id, NetworkEvent::PlayerLeft { player_id });
+
+        self.sync_lobby_players(lobby_index);
+        self.broadcast_lobby(lobby_index);
+    }
+
+    fn set_ready(&mut self, i: usize, is_ready: bool) {
+        let lobby_index = match self.current_lobby_index(i) {
+            Some(lobby_index) => lobby_index,
+            None => {
+                self.fail(i, RequestStatus::NotFound);
+                return;
+            }
+        };
+
+        let player_id = self.player_id(i);
+
+        let lobby = &mut self.lobbies[lobby_index];
+        if lobby.state != LobbyState::NotStarted && lobby.state != LobbyState::Ready {
+            self.fail(i, RequestStatus::Unauthorized);
+            return;
+        }
+
+        let (state, event) = if is_ready {
+            (
+                ClientState::Ready,
+                NetworkEvent::PlayerMarkedReady {
+                    player_id: player_id.clone(),
+                },
+            )
+        } else {
+            (
+                ClientState::Joined,
+                NetworkEvent::PlayerMarkedNotReady {
+                    player_id: player_id.clone(),
+                },
+            )
+        };
+
+        if let Some(player) = lobby.players.iter_mut().find(|p| p.id == player_id) {
+            player.state = state;
+        }
+
+        let lobby_id = lobby.id.clone();
+
+        self.broadcast(&lobby_id, event);
+
+        self.sync_lobby_players(lobby_index);
+        self.broadcast_lobby(lobby_index);
+    }
+
+    fn set_character(&mut self, i: usize, character: &str) {
+        let lobby_index = match self.current_lobby_index(i) {
+            Some(lobby_index) => lobby_index,
+            None => {
+                self.fail(i, RequestStatus::NotFound);
+                return;
+            }
+        };
+
+        let player_id = self.player_id(i);
+
+        let lobby = &mut self.lobbies[lobby_index];
+        if let Some(player) = lobby.players.iter_mut().find(|p| p.id == player_id) {
+            player.character = Some(character.to_string());
+        }
+
+        self.broadcast_lobby(lobby_index);
+    }
+
+    fn set_map(&mut self, i: usize, map: &str) {
+        let lobby_index = match self.current_lobby_index(i) {
+            Some(lobby_index) => lobby_index,
+            None => {
+                self.fail(i, RequestStatus::NotFound);
+                return;
+            }
+        };
+
+        let player_id = self.player_id(i);
+
+        let lobby = &mut self.lobbies[lobby_index];
+        if lobby.admin_player_id != player_id || lobby.state == LobbyState::Running {
+            self.fail(i, RequestStatus::Unauthorized);
+            return;
+        }
+
+        lobby.map = Some(map.to_string());
+
+        self.broadcast_lobby(lobby_index);
+    }
+
+    fn set_team(&mut self, i: usize, team: Option<u8>) {
+        let lobby_index = match self.current_lobby_index(i) {
+            Some(lobby_index) => lobby_index,
+            None => {
+                self.fail(i, RequestStatus::NotFound);
+                return;
+            }
+        };
+
+        let player_id = self.player_id(i);
+
+        let lobby = &mut self.lobbies[lobby_index];
+        if lobby.state == LobbyState::Running {
+            self.fail(i, RequestStatus::Unauthorized);
+            return;
+        }
+
+        if let Some(player) = lobby.players.iter_mut().find(|p| p.id == player_id) {
+            player.team = team;
+        }
+
+        self.broadcast_lobby(lobby_index);
+    }
+
+    fn set_friendly_fire(&mut self, i: usize, is_enabled: bool) {
+        let lobby_index = match self.current_lobby_index(i) {
+            Some(lobby_index) => lobby_index,
+            None => {
+                self.fail(i, RequestStatus::NotFound);
+                return;
+            }
+        };
+
+        let player_id = self.player_id(i);
+
+        let lobby = &mut self.lobbies[lobby_index];
+        if lobby.admin_player_id != player_id || lobby.state == LobbyState::Running {
+            self.fail(i, RequestStatus::Unauthorized);
+            return;
+        }
+
+        lobby.friendly_fire = is_enabled;
+
+        self.broadcast_lobby(lobby_index);
+    }
+
+    fn start_game(&mut self, i: usize) {
+        let lobby_index = match self.current_lobby_index(i) {
+            Some(lobby_index) => lobby_index,
+            None => {
+                self.fail(i, RequestStatus::NotFound);
+                return;
+            }
+        };
+
+        self.sync_lobby_players(lobby_index);
+
+        let player_id = self.player_id(i);
+
+        let lobby = &mut self.lobbies[lobby_index];
+        if lobby.admin_player_id != player_id
+            || lobby.state != LobbyState::Ready
+            || lobby.players.iter().any(|player| player.addr.is_none())
+        {
+            self.fail(i, RequestStatus::Unauthorized);
+            return;
+        }
+
+        lobby.state = LobbyState::Running;
+
+        for player in &mut lobby.players {
+            player.state = ClientState::Playing;
+        }
+
+        let lobby_id = lobby.id.clone();
+
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or_default();
+
+        self.seeds.insert(lobby_id.clone(), seed);
+
+        // The lobby is sent first, so that clients have the addresses of all players when the
+        // game starts
+        self.broadcast_lobby(lobby_index);
+
+        let event = NetworkEvent::GameStarted {
+            lobby_id: lobby_id.clone(),
+            seed,
+        };
+
+        self.broadcast(&lobby_id, event);
+    }
+}
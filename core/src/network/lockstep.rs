@@ -0,0 +1,317 @@
+//! This implements the input buffer used for delayed lockstep, as described in the netcode chapter
+//! of the book. Local input is scheduled `delay` frames into the future, giving remote input time
+//! to arrive, and the simulation may only advance once the input of every player is present for
+//! the current frame.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use crate::input::PlayerInput;
+use crate::network::{IdlePeriods, PlayerId};
+
+/// The default amount of frames that local input is delayed by
+pub const DEFAULT_INPUT_DELAY: u64 = 4;
+
+#[derive(Debug, Clone)]
+pub struct InputBuffer {
+    player_ids: Vec<PlayerId>,
+    delay: u64,
+    current_frame: u64,
+    /// The first frame that has not been given local input yet
+    next_local_frame: u64,
+    frames: BTreeMap<u64, HashMap<PlayerId, PlayerInput>>,
+    idle: IdlePeriods,
+}
+
+impl InputBuffer {
+    pub fn new(player_ids: &[PlayerId], delay: u64) -> Self {
+        let mut frames = BTreeMap::new();
+
+        // Nobody can have sent any input for the frames before the first delayed frame, so these
+        // are filled with default input, for all players, to allow the simulation to start.
+        for frame in 0..delay {
+            let inputs = player_ids
+                .iter()
+                .map(|id| (id.clone(), PlayerInput::default()))
+                .collect();
+
+            frames.insert(frame, inputs);
+        }
+
+        InputBuffer {
+            frames,
+            ..InputBuffer::new_at_frame(player_ids, delay, 0)
+        }
+    }
+
+    /// Create a buffer that starts on `frame`, for a player that rejoins a running match. No input
+    /// is filled in, as the input of the frames that are re-simulated comes from the host.
+    pub fn new_at_frame(player_ids: &[PlayerId], delay: u64, frame: u64) -> Self {
+        InputBuffer {
+            player_ids: player_ids.to_vec(),
+            delay,
+            current_frame: frame,
+            next_local_frame: frame + delay,
+            frames: BTreeMap::new(),
+            idle: IdlePeriods::new(),
+        }
+    }
+
+    pub fn player_ids(&self) -> &[PlayerId] {
+        &self.player_ids
+    }
+
+    pub fn delay(&self) -> u64 {
+        self.delay
+    }
+
+    /// Change the input delay. The change is applied gradually, through `insert_local`, as input
+    /// that has already been dispatched to remote players can not be moved to another frame.
+    pub fn set_delay(&mut self, delay: u64) {
+        self.delay = delay;
+    }
+
+    /// This is the frame that will be simulated next
+    pub fn current_frame(&self) -> u64 {
+        self.current_frame
+    }
+
+    /// This is the frame that local input, collected now, should be scheduled for
+    pub fn local_input_frame(&self) -> u64 {
+        self.current_frame + self.delay
+    }
+
+    /// Returns the frames that local input, collected now, should be scheduled for. This is
+    /// normally only `local_input_frame`, but while the delay is shrinking, it will be empty, and
+    /// after the delay has grown, it will also cover the frames that were skipped, so that no
+    /// frame is left without local input, or given local input twice.
+    pub fn local_input_frames(&self) -> Range<u64> {
+        let end = (self.local_input_frame() + 1).max(self.next_local_frame);
+        self.next_local_frame..end
+    }
+
+    /// Insert local input for all the frames returned by `local_input_frames` and return them, so
+    /// that the input can be dispatched to remote players for the same frames.
+    pub fn insert_local(&mut self, player_id: &PlayerId, input: PlayerInput) -> Range<u64> {
+        let frames = self.local_input_frames();
+
+        for frame in frames.clone() {
+            self.insert(player_id, frame, input);
+        }
+
+        self.next_local_frame = frames.end;
+
+        frames
+    }
+
+    /// Schedule local input from `frame` on. This is used when rejoining a match, where the local
+    /// player is idle until `frame`, and the input of the frames before it comes from the host.
+    pub fn resume_local_input(&mut self, frame: u64) {
+        self.next_local_frame = frame;
+    }
+
+    /// Make a player idle from `start_frame` until `end_frame`, or until further notice, if it is
+    /// `None`. Idle players are given default input, in place of any input received for them, so
+    /// that the simulation can go on without them. Once an idle period has been given an end, it
+    /// can not be changed, so that a notice that arrives late will not extend it again.
+    pub fn set_idle(&mut self, player_id: &PlayerId, start_frame: u64, end_frame: Option<u64>) {
+        self.idle.set(player_id, start_frame, end_frame);
+    }
+
+    /// Returns `true` if a player is idle on the given frame
+    pub fn is_idle(&self, player_id: &PlayerId, frame: u64) -> bool {
+        self.idle.contains(player_id, frame)
+    }
+
+    /// Returns all idle periods of all players, as they were passed to `set_idle`
+    pub fn idle_periods(&self) -> Vec<(PlayerId, u64, Option<u64>)> {
+        self.idle.to_vec()
+    }
+
+    /// Returns the input that has been received for a player, for the given frame
+    pub fn input(&self, player_id: &PlayerId, frame: u64) -> Option<PlayerInput> {
+        self.frames
+            .get(&frame)
+            .and_then(|inputs| inputs.get(player_id))
+            .copied()
+    }
+
+    /// Insert input for a player. Input for frames that has already been simulated is ignored.
+    pub fn insert(&mut self, player_id: &PlayerId, frame: u64, input: PlayerInput) {
+        if frame >= self.current_frame {
+            self.frames
+                .entry(frame)
+                .or_default()
+                .insert(player_id.clone(), input);
+        }
+    }
+
+    /// Returns `true` if the input of a player has been received for the given frame, or if the
+    /// player is idle on that frame
+    pub fn has_input(&self, player_id: &PlayerId, frame: u64) -> bool {
+        self.is_idle(player_id, frame)
+            || self
+                .frames
+                .get(&frame)
+                .map(|inputs| inputs.contains_key(player_id))
+                .unwrap_or_default()
+    }
+
+    /// Returns `true` if the input of all players has been received for the current frame
+    pub fn is_frame_ready(&self) -> bool {
+        self.player_ids
+            .iter()
+            .all(|id| self.has_input(id, self.current_frame))
+    }
+
+    /// Returns the amount of consecutive frames, starting with the current frame, that the input
+    /// of a player has been received for
+    pub fn buffered_frames(&self, player_id: &PlayerId) -> u64 {
+        let mut frame = self.current_frame;
+
+        while self.has_input(player_id, frame) {
+            frame += 1;
+        }
+
+        frame - self.current_frame
+    }
+
+    /// Returns the ids of the players whose input is missing for the current frame
+    pub fn missing_players(&self) -> Vec<PlayerId> {
+        self.player_ids
+            .iter()
+            .filter(|id| !self.has_input(id, self.current_frame))
+            .cloned()
+            .collect()
+    }
+
+    /// If the current frame is ready, this will remove its input from the buffer, return it and
+    /// move on to the next frame. If not, `None` is returned, and the simulation should stall.
+    pub fn advance(&mut self) -> Option<HashMap<PlayerId, PlayerInput>> {
+        if self.is_frame_ready() {
+            let frame = self.current_frame;
+            let mut inputs = self.frames.remove(&frame).unwrap_or_default();
+
+            for player_id in &self.player_ids {
+                if self.is_idle(player_id, frame) {
+                    inputs.insert(player_id.clone(), PlayerInput::default());
+                }
+            }
+
+            self.current_frame += 1;
+
+            Some(inputs)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_initial_frames_are_filled() {
+        let player_ids = vec!["1".to_string(), "2".to_string()];
+        let mut buffer = InputBuffer::new(&player_ids, 3);
+
+        for frame in 0..3 {
+            assert_eq!(buffer.current_frame(), frame);
+            assert!(buffer.advance().is_some());
+        }
+
+        assert!(!buffer.is_frame_ready());
+        assert_eq!(buffer.missing_players(), player_ids);
+    }
+
+    #[test]
+    fn test_stale_input_is_ignored() {
+        let player_ids = vec!["1".to_string()];
+        let mut buffer = InputBuffer::new(&player_ids, 1);
+
+        buffer.advance().unwrap();
+
+        let input = PlayerInput {
+            fire: true,
+            ..Default::default()
+        };
+
+        buffer.insert(&player_ids[0], 0, input);
+        assert!(!buffer.is_frame_ready());
+
+        buffer.insert(&player_ids[0], 1, input);
+        assert_eq!(buffer.advance().unwrap()[&player_ids[0]], input);
+    }
+
+    #[test]
+    fn test_changing_delay_never_skips_or_repeats_frames() {
+        let player_id = "1".to_string();
+        let mut buffer = InputBuffer::new(std::slice::from_ref(&player_id), 2);
+
+        let mut scheduled = Vec::new();
+
+        for i in 0..40 {
+            match i {
+                10 => buffer.set_delay(5),
+                20 => buffer.set_delay(1),
+                _ => {}
+            }
+
+            let frames = buffer.insert_local(&player_id, PlayerInput::default());
+            scheduled.extend(frames);
+
+            assert!(buffer.advance().is_some(), "Stalled on iteration {}", i);
+        }
+
+        let expected = (2..scheduled.len() as u64 + 2).collect::<Vec<_>>();
+        assert_eq!(scheduled, expected);
+
+        assert_eq!(buffer.local_input_frame(), buffer.current_frame() + 1);
+        assert_eq!(buffer.buffered_frames(&player_id), 1);
+    }
+
+    #[test]
+    fn test_idle_players_get_default_input() {
+        let player_ids = vec!["1".to_string(), "2".to_string()];
+        let mut buffer = InputBuffer::new(&player_ids, 0);
+
+        let input = PlayerInput {
+            jump: true,
+            ..Default::default()
+        };
+
+        for frame in 0..10 {
+            buffer.insert(&player_ids[0], frame, input);
+        }
+
+        // Input received for an idle player, before the idle period was known, is replaced
+        buffer.insert(&player_ids[1], 2, input);
+
+        buffer.set_idle(&player_ids[1], 2, None);
+        buffer.set_idle(&player_ids[1], 2, Some(5));
+        buffer.set_idle(&player_ids[1], 2, None);
+
+        assert!(!buffer.is_frame_ready());
+        buffer.insert(&player_ids[1], 0, input);
+        buffer.insert(&player_ids[1], 1, input);
+
+        for frame in 0..5 {
+            let inputs = buffer.advance().unwrap();
+            let expected = if frame < 2 {
+                input
+            } else {
+                PlayerInput::default()
+            };
+
+            assert_eq!(inputs[&player_ids[0]], input);
+            assert_eq!(inputs[&player_ids[1]], expected);
+        }
+
+        assert_eq!(buffer.missing_players(), vec![player_ids[1].clone()]);
+        assert_eq!(
+            buffer.idle_periods(),
+            vec![(player_ids[1].clone(), 2, Some(5))]
+        );
+    }
+}
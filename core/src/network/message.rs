@@ -4,11 +4,99 @@ use crate::input::PlayerInput;
 
 use super::PlayerId;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum NetworkMessage {
     UpdatePlayerInput {
         player_id: PlayerId,
+        /// The simulation frame the input should be applied on
+        frame: u64,
         input: PlayerInput,
     },
+    /// Input for a sequence of frames, starting with `start_frame`, as sent by the redundancy
+    /// layer of a backend. `ack` is the last frame up to which all input from the receiver has
+    /// been received by the sender.
+    PlayerInputs {
+        player_id: PlayerId,
+        start_frame: u64,
+        inputs: Vec<PlayerInput>,
+        ack: Option<u64>,
+    },
+    /// A checksum of the simulation state after a frame, used to detect desyncs
+    StateChecksum {
+        player_id: PlayerId,
+        frame: u64,
+        checksum: u64,
+    },
+    /// Sent periodically to measure the round trip time. Backends should answer with a `Pong`,
+    /// holding the same timestamp, directly to the sender.
+    Ping {
+        player_id: PlayerId,
+        /// The local time of the sender, in microseconds
+        timestamp: u64,
+    },
+    Pong {
+        player_id: PlayerId,
+        timestamp: u64,
+    },
+    /// The confirmed input of a sequence of frames, starting with `start_frame`, sent from the host
+    /// to spectators. `inputs` holds the input of every player in `player_ids`, in that order, for
+    /// each frame.
+    SpectatorInputs {
+        player_id: PlayerId,
+        start_frame: u64,
+        player_ids: Vec<PlayerId>,
+        inputs: Vec<PlayerInput>,
+    },
+    /// Sent by a spectator to the host, when all input up to, and including, `frame` has been
+    /// received
+    SpectatorAck {
+        player_id: PlayerId,
+        frame: u64,
+    },
+    /// Sent by a player that has lost the connection to the match, to request a resync from the
+    /// host, which will answer with `ResyncChunk`s. `checkpoint_frames` are the frames that the
+    /// player holds snapshots of its world for, one of which the resync will start from.
+    ResyncRequest {
+        player_id: PlayerId,
+        checkpoint_frames: Vec<u64>,
+    },
+    /// A chunk of the data of a resync, sent from the host to a rejoining player. `frame` is the
+    /// frame that the resync starts the player on, and identifies the transfer.
+    ResyncChunk {
+        player_id: PlayerId,
+        frame: u64,
+        index: u64,
+        chunk_cnt: u64,
+        data: Vec<u8>,
+    },
+    /// Sent by a rejoining player to the host, for every received chunk of a resync
+    ResyncAck {
+        player_id: PlayerId,
+        frame: u64,
+        index: u64,
+    },
+    /// Sent by the host, when a player has dropped, or rejoins. The player is idle, using default
+    /// input, from `start_frame` until `end_frame`, or until further notice, if it is `None`.
+    PlayerIdle {
+        player_id: PlayerId,
+        start_frame: u64,
+        end_frame: Option<u64>,
+    },
+    /// Sent by a player that has detected a desync on `frame`, to request the description of the
+    /// state of that frame from the other players, who answer with `StateDescriptionChunk`s
+    StateDescriptionRequest {
+        player_id: PlayerId,
+        frame: u64,
+    },
+    /// A chunk of the description of the state of `frame`, sent in answer to a
+    /// `StateDescriptionRequest`. Chunks are not acknowledged, so a player will keep requesting
+    /// the description until all of them have arrived.
+    StateDescriptionChunk {
+        player_id: PlayerId,
+        frame: u64,
+        index: u64,
+        chunk_cnt: u64,
+        data: Vec<u8>,
+    },
 }
@@ -1,12 +1,44 @@
 mod api;
+mod codec;
+mod conditioner;
+mod desync;
 mod event;
+mod idle;
+mod latency;
+mod lobby;
+mod lockstep;
 mod message;
+mod redundancy;
+mod resync;
+mod rollback;
+mod spectator;
 mod status;
+mod udp;
 
 pub use api::{Api, ApiBackend, ApiBackendConstructor};
+pub use codec::{decode_message, encode_message, pack_input, unpack_input, PROTOCOL_VERSION};
+pub use conditioner::LinkConditioner;
+pub use desync::{state_description_chunks, DesyncDetector};
 pub use event::NetworkEvent;
+pub use idle::IdlePeriods;
+pub use latency::{
+    InputDelayController, RttEstimator, DEFAULT_MAX_INPUT_DELAY, DELAY_ADJUSTMENT_WINDOW,
+    MIN_INPUT_DELAY,
+};
+pub use lobby::{
+    LobbyApiBackend, LobbyBackendParams, LobbyRequest, LobbyServer, LobbyServerMessage,
+    DEFAULT_LOBBY_PORT,
+};
+pub use lockstep::{InputBuffer, DEFAULT_INPUT_DELAY};
 pub use message::NetworkMessage;
+pub use redundancy::{RedundantInputs, MAX_REDUNDANT_INPUTS};
+pub use resync::{
+    ResyncReceiver, ResyncState, ResyncTransfer, MAX_RESYNC_PACKETS, RESYNC_CHUNK_SIZE,
+};
+pub use rollback::{RollbackSession, RollbackState, DEFAULT_MAX_ROLLBACK};
+pub use spectator::{SpectatorFeed, SpectatorStream, MAX_SPECTATOR_INPUTS, MAX_SPECTATOR_PACKETS};
 pub use status::RequestStatus;
+pub use udp::{UdpApiBackend, UdpBackendParams};
 
 use std::net::SocketAddr;
 
@@ -41,6 +73,15 @@ pub struct Lobby {
     pub privacy: LobbyPrivacy,
     pub state: LobbyState,
     pub players: Vec<Player>,
+    /// The id of the map selected by the admin
+    #[serde(default)]
+    pub map: Option<String>,
+    /// Clients that watch the running game, without taking part in it
+    #[serde(default)]
+    pub spectators: Vec<Player>,
+    /// If this is `true`, players can damage players on their own team
+    #[serde(default)]
+    pub friendly_fire: bool,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -58,6 +99,15 @@ pub struct Player {
     pub id: PlayerId,
     pub username: String,
     pub state: ClientState,
+    /// The address that the player receives game messages on, when playing peer-to-peer
+    #[serde(default)]
+    pub addr: Option<SocketAddr>,
+    /// The id of the character selected by the player
+    #[serde(default)]
+    pub character: Option<String>,
+    /// The team selected by the player, if any
+    #[serde(default)]
+    pub team: Option<u8>,
 }
 
 impl Player {
@@ -66,6 +116,9 @@ impl Player {
             id: id.clone(),
             username: username.to_string(),
             state: ClientState::Unknown,
+            addr: None,
+            character: None,
+            team: None,
         }
     }
 }
@@ -78,4 +131,5 @@ pub enum ClientState {
     Playing,
     Left,
     Done,
+    Spectating,
 }
@@ -0,0 +1,334 @@
+//! The redundancy layer used for input over unreliable transports, as described in the netcode
+//! chapter of the book. Every input packet carries all the frames of local input that the peer has
+//! not acknowledged yet, up to `MAX_REDUNDANT_INPUTS`, and the acknowledgement of the input that
+//! has been received from that peer. This way, a dropped packet is covered by the next one, and
+//! input is never lost, as long as packets keep flowing.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::input::PlayerInput;
+use crate::network::{NetworkMessage, PlayerId};
+
+/// The maximum amount of frames of input sent in a single packet. If more frames are
+/// unacknowledged, the oldest are sent, as the receiver can not advance without them.
+pub const MAX_REDUNDANT_INPUTS: usize = 16;
+
+/// Keeps track of the frames that have been received from a peer
+#[derive(Debug, Clone, Default)]
+struct ReceivedFrames {
+    /// All frames up to, and including, this have been received
+    contiguous: Option<u64>,
+    /// Frames received after a gap
+    ahead: BTreeSet<u64>,
+}
+
+impl ReceivedFrames {
+    /// Mark a frame as received, returning `false` if it already was
+    fn insert(&mut self, frame: u64) -> bool {
+        match self.contiguous {
+            Some(contiguous) if frame <= contiguous => return false,
+            // The first packet from a peer always starts with its first frame of input, as no
+            // frames have been acknowledged yet
+            None if self.ahead.is_empty() => {
+                self.contiguous = Some(frame);
+                return true;
+            }
+            _ => {}
+        }
+
+        if !self.ahead.insert(frame) {
+            return false;
+        }
+
+        self.merge_ahead();
+
+        true
+    }
+
+    /// Mark all frames up to, and including, `frame` as received
+    fn skip_to(&mut self, frame: u64) {
+        self.contiguous = Some(
+            self.contiguous
+                .map_or(frame, |contiguous| contiguous.max(frame)),
+        );
+        self.ahead = self.ahead.split_off(&(frame + 1));
+
+        self.merge_ahead();
+    }
+
+    /// Move the frames that follow the contiguous frames out of `ahead`
+    fn merge_ahead(&mut self) {
+        while let Some(next) = self.contiguous.map(|contiguous| contiguous + 1) {
+            if !self.ahead.remove(&next) {
+                break;
+            }
+
+            self.contiguous = Some(next);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct PeerInputState {
+    /// The peer has acknowledged all local input up to, and including, this frame
+    acked: Option<u64>,
+    received: ReceivedFrames,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RedundantInputs {
+    /// Local input that has not been acknowledged by all peers
+    unacked: BTreeMap<u64, PlayerInput>,
+    peers: HashMap<PlayerId, PeerInputState>,
+}
+
+impl RedundantInputs {
+    pub fn new() -> Self {
+        RedundantInputs::default()
+    }
+
+    pub fn add_peer(&mut self, player_id: &PlayerId) {
+        self.peers.entry(player_id.clone()).or_default();
+    }
+
+    pub fn remove_peer(&mut self, player_id: &PlayerId) {
+        self.peers.remove(player_id);
+        self.prune();
+    }
+
+    /// Returns `true` if any local input has not been acknowledged by all peers
+    pub fn has_unacked(&self) -> bool {
+        !self.unacked.is_empty()
+    }
+
+    /// Add local input, to be sent with the following packets, until acknowledged
+    pub fn push_local(&mut self, frame: u64, input: PlayerInput) {
+        self.unacked.insert(frame, input);
+    }
+
+    /// Build the packet that should be sent to a peer, holding all the input the peer has not
+    /// acknowledged yet, as well as the acknowledgement of the input received from it. The packet
+    /// might hold no input, only the acknowledgement, which must still be sent, periodically, as
+    /// the peer can not send more than `MAX_REDUNDANT_INPUTS` frames past its last acknowledged
+    /// frame. Returns `None` if there is nothing to send.
+    pub fn packet_for(
+        &self,
+        local_player_id: &PlayerId,
+        peer_id: &PlayerId,
+    ) -> Option<NetworkMessage> {
+        let peer = self.peers.get(peer_id)?;
+
+        let mut frames = match peer.acked {
+            Some(acked) => self.unacked.range(acked + 1..),
+            None => self.unacked.range(..),
+        }
+        .take(MAX_REDUNDANT_INPUTS)
+        .peekable();
+
+        let start_frame = match frames.peek() {
+            Some((frame, _)) => **frame,
+            None if peer.received.contiguous.is_some() => 0,
+            None => return None,
+        };
+
+        // Frames are sent as a sequence, so stop at the first gap, if any
+        let inputs = frames
+            .enumerate()
+            .take_while(|(i, (frame, _))| **frame == start_frame + *i as u64)
+            .map(|(_, (_, input))| *input)
+            .collect();
+
+        Some(NetworkMessage::PlayerInputs {
+            player_id: local_player_id.clone(),
+            start_frame,
+            inputs,
+            ack: peer.received.contiguous,
+        })
+    }
+
+    /// Handle a received input packet, returning the frames of input that had not been received
+    /// before
+    pub fn receive(
+        &mut self,
+        peer_id: &PlayerId,
+        start_frame: u64,
+        inputs: &[PlayerInput],
+        ack: Option<u64>,
+    ) -> Vec<(u64, PlayerInput)> {
+        let peer = self.peers.entry(peer_id.clone()).or_default();
+
+        if let Some(ack) = ack {
+            peer.acked = Some(peer.acked.map_or(ack, |acked| acked.max(ack)));
+        }
+
+        let res = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| (start_frame + i as u64, *input))
+            .filter(|(frame, _)| peer.received.insert(*frame))
+            .collect();
+
+        self.prune();
+
+        res
+    }
+
+    /// Mark all input from a peer, up to, and including, `frame`, as received. This is used when a
+    /// player rejoins a match, as the frames before it are covered by the resync, and any input
+    /// the player sent for them, before dropping, is not needed anymore.
+    pub fn skip_received(&mut self, peer_id: &PlayerId, frame: u64) {
+        let peer = self.peers.entry(peer_id.clone()).or_default();
+        peer.received.skip_to(frame);
+    }
+
+    /// Remove input that has been acknowledged by all peers. As `None` is less than any `Some`,
+    /// nothing is removed until every peer has acknowledged something.
+    fn prune(&mut self) {
+        let min_acked = self.peers.values().map(|peer| peer.acked).min().flatten();
+
+        if let Some(min_acked) = min_acked {
+            self.unacked = self.unacked.split_off(&(min_acked + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(frame: u64) -> PlayerInput {
+        PlayerInput {
+            left: frame % 2 == 1,
+            jump: frame % 3 == 1,
+            ..Default::default()
+        }
+    }
+
+    fn receive_packet(
+        receiver: &mut RedundantInputs,
+        packet: NetworkMessage,
+    ) -> Vec<(u64, PlayerInput)> {
+        match packet {
+            NetworkMessage::PlayerInputs {
+                player_id,
+                start_frame,
+                inputs,
+                ack,
+            } => receiver.receive(&player_id, start_frame, &inputs, ack),
+            message => panic!("Unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn test_dropped_packets_are_covered_by_the_next() {
+        let (a_id, b_id) = ("1".to_string(), "2".to_string());
+
+        let mut a = RedundantInputs::new();
+        let mut b = RedundantInputs::new();
+
+        a.add_peer(&b_id);
+        b.add_peer(&a_id);
+
+        let mut received = Vec::new();
+
+        for frame in 4..12 {
+            a.push_local(frame, input(frame));
+
+            let packet = a.packet_for(&a_id, &b_id).unwrap();
+
+            // Drop two out of every three packets
+            if frame % 3 == 2 {
+                received.extend(receive_packet(&mut b, packet));
+            }
+        }
+
+        let expected = (4..12)
+            .map(|frame| (frame, input(frame)))
+            .collect::<Vec<_>>();
+        assert_eq!(received, expected);
+
+        // Acknowledging the received frames should remove them from the packets to `b`
+        b.push_local(0, PlayerInput::default());
+        receive_packet(&mut a, b.packet_for(&b_id, &a_id).unwrap());
+
+        assert!(!a.has_unacked());
+
+        a.push_local(12, input(12));
+
+        match a.packet_for(&a_id, &b_id).unwrap() {
+            NetworkMessage::PlayerInputs {
+                start_frame,
+                inputs,
+                ack,
+                ..
+            } => {
+                assert_eq!(start_frame, 12);
+                assert_eq!(inputs, vec![input(12)]);
+                assert_eq!(ack, Some(0));
+            }
+            message => panic!("Unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn test_reordered_packets_are_only_delivered_once() {
+        let (a_id, b_id) = ("1".to_string(), "2".to_string());
+
+        let mut a = RedundantInputs::new();
+        let mut b = RedundantInputs::new();
+
+        a.add_peer(&b_id);
+
+        let mut packets = Vec::new();
+
+        for frame in 0..3 {
+            a.push_local(frame, input(frame));
+            packets.push(a.packet_for(&a_id, &b_id).unwrap());
+        }
+
+        let mut received = Vec::new();
+
+        for packet in packets.into_iter().rev() {
+            received.extend(receive_packet(&mut b, packet));
+        }
+
+        received.sort_by_key(|(frame, _)| *frame);
+
+        let expected = (0..3)
+            .map(|frame| (frame, input(frame)))
+            .collect::<Vec<_>>();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_skipped_frames_are_acknowledged() {
+        let (a_id, b_id) = ("1".to_string(), "2".to_string());
+
+        let mut a = RedundantInputs::new();
+        let mut b = RedundantInputs::new();
+
+        a.add_peer(&b_id);
+        b.add_peer(&a_id);
+
+        a.push_local(0, input(0));
+        receive_packet(&mut b, a.packet_for(&a_id, &b_id).unwrap());
+
+        // `a` drops, and rejoins with frame 50, after the frames it was idle on
+        let mut a = RedundantInputs::new();
+        a.add_peer(&b_id);
+
+        b.skip_received(&a_id, 49);
+
+        a.push_local(50, input(50));
+        let received = receive_packet(&mut b, a.packet_for(&a_id, &b_id).unwrap());
+
+        assert_eq!(received, vec![(50, input(50))]);
+
+        b.push_local(1, input(1));
+
+        match b.packet_for(&b_id, &a_id).unwrap() {
+            NetworkMessage::PlayerInputs { ack, .. } => assert_eq!(ack, Some(50)),
+            message => panic!("Unexpected message {:?}", message),
+        }
+    }
+}
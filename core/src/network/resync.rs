@@ -0,0 +1,357 @@
+//! Resynchronization of a player that rejoins a running match. The host encodes everything the
+//! player needs to catch up with the match in a `ResyncState`, which is usually too large for a
+//! single packet, so it is split into chunks by a `ResyncTransfer`. Every chunk is acknowledged by the receiver, and the
+//! host keeps sending the chunks that have not been acknowledged, until all of them have been,
+//! while the `ResyncReceiver` puts them back together, in whatever order they arrive.
+
+use crate::error::{Error, ErrorKind};
+use crate::network::codec::{Reader, Writer};
+use crate::network::{NetworkMessage, PlayerId};
+use crate::Result;
+
+/// The maximum amount of bytes of data in a single chunk. This leaves room for the header of the
+/// packet, within the maximum size of a datagram.
+pub const RESYNC_CHUNK_SIZE: usize = 768;
+
+/// The maximum amount of chunks sent to a player at once
+pub const MAX_RESYNC_PACKETS: usize = 16;
+
+/// Transfers with more chunks than this are refused by the receiver, instead of allocating room for
+/// them
+const MAX_RESYNC_CHUNKS: u64 = 16 * 1024;
+
+/// Everything that a rejoining player needs to catch up with the match, starting from a snapshot
+/// of its own world, that it has kept from before it dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResyncState {
+    /// The frame of the snapshot that the world is restored from
+    pub snapshot_frame: u64,
+    /// The world is re-simulated from the snapshot up to this frame, which is the first frame that
+    /// the host has not confirmed the input of yet
+    pub frame: u64,
+    /// The rejoining player is idle until this frame, and sends its input from it on
+    pub rejoin_frame: u64,
+    pub player_ids: Vec<PlayerId>,
+    /// The confirmed input of the frames from `snapshot_frame` up to `frame`, packed with
+    /// `pack_input`. Each frame holds the input of all players, in the order of `player_ids`.
+    pub inputs: Vec<u8>,
+    /// Input of the rejoining player, for frames from `frame` on, that was received by the host
+    /// before the player dropped
+    pub pending_inputs: Vec<(u64, u8)>,
+    /// The idle periods of all players, as `(player_id, start_frame, end_frame)`
+    pub idle_periods: Vec<(PlayerId, u64, Option<u64>)>,
+}
+
+impl ResyncState {
+    /// Encode the state with the varints of the network codec. The input is packed already.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+
+        writer.varint(self.snapshot_frame);
+        writer.varint(self.frame);
+        writer.varint(self.rejoin_frame);
+
+        writer.varint(self.player_ids.len() as u64);
+
+        for player_id in &self.player_ids {
+            writer.string(player_id);
+        }
+
+        writer.varint(self.inputs.len() as u64);
+        writer.bytes(&self.inputs);
+
+        writer.varint(self.pending_inputs.len() as u64);
+
+        for (frame, bits) in &self.pending_inputs {
+            writer.varint(*frame);
+            writer.u8(*bits);
+        }
+
+        writer.varint(self.idle_periods.len() as u64);
+
+        for (player_id, start_frame, end_frame) in &self.idle_periods {
+            writer.string(player_id);
+            writer.varint(*start_frame);
+            // An open period is encoded as zero, and the end frame of any other as `end + 1`
+            writer.varint(end_frame.map(|frame| frame + 1).unwrap_or_default());
+        }
+
+        writer.into_bytes()
+    }
+
+    /// Decode a state encoded with `encode`, returning an error if it is malformed in any way
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+
+        let snapshot_frame = reader.varint()?;
+        let frame = reader.varint()?;
+        let rejoin_frame = reader.varint()?;
+
+        // Counts are not trusted for allocation, as every item takes at least one byte
+        let player_cnt = reader.varint()?;
+        let mut player_ids = Vec::new();
+
+        for _ in 0..player_cnt {
+            player_ids.push(reader.string()?);
+        }
+
+        let input_cnt = reader.varint()? as usize;
+        let inputs = reader.bytes(input_cnt)?.to_vec();
+
+        let pending_cnt = reader.varint()?;
+        let mut pending_inputs = Vec::new();
+
+        for _ in 0..pending_cnt {
+            pending_inputs.push((reader.varint()?, reader.u8()?));
+        }
+
+        let period_cnt = reader.varint()?;
+        let mut idle_periods = Vec::new();
+
+        for _ in 0..period_cnt {
+            idle_periods.push((
+                reader.string()?,
+                reader.varint()?,
+                reader.varint()?.checked_sub(1),
+            ));
+        }
+
+        let frame_cnt = frame.checked_sub(snapshot_frame);
+        let expected_cnt = frame_cnt.and_then(|cnt| cnt.checked_mul(player_ids.len() as u64));
+
+        if expected_cnt != Some(inputs.len() as u64) || !reader.is_at_end() {
+            return Err(Error::new_const(
+                ErrorKind::Parsing,
+                &"Resync does not hold the input of the frames it covers",
+            ));
+        }
+
+        Ok(ResyncState {
+            snapshot_frame,
+            frame,
+            rejoin_frame,
+            player_ids,
+            inputs,
+            pending_inputs,
+            idle_periods,
+        })
+    }
+}
+
+/// Kept by the host, for every player that is being sent a resync
+#[derive(Debug, Clone)]
+pub struct ResyncTransfer {
+    frame: u64,
+    chunks: Vec<Vec<u8>>,
+    is_acked: Vec<bool>,
+}
+
+impl ResyncTransfer {
+    /// Split `data` into chunks. The frame identifies the transfer, so that chunks of an older
+    /// resync, that arrive late, are not mixed up with the current one.
+    pub fn new(frame: u64, data: &[u8]) -> Self {
+        let mut chunks = data
+            .chunks(RESYNC_CHUNK_SIZE)
+            .map(|chunk| chunk.to_vec())
+            .collect::<Vec<_>>();
+
+        // Even empty data is sent as a chunk, so that the receiver knows the transfer is complete
+        if chunks.is_empty() {
+            chunks.push(Vec::new());
+        }
+
+        let is_acked = vec![false; chunks.len()];
+
+        ResyncTransfer {
+            frame,
+            chunks,
+            is_acked,
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn chunk_cnt(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Returns `true` when all chunks have been acknowledged
+    pub fn is_complete(&self) -> bool {
+        self.is_acked.iter().all(|is_acked| *is_acked)
+    }
+
+    /// Mark a chunk as received. Acknowledgements of other transfers are ignored.
+    pub fn ack(&mut self, frame: u64, index: u64) {
+        if frame == self.frame {
+            if let Some(is_acked) = self.is_acked.get_mut(index as usize) {
+                *is_acked = true;
+            }
+        }
+    }
+
+    /// Build the packets of the chunks that have not been acknowledged yet, starting with the
+    /// first. At most `MAX_RESYNC_PACKETS` are returned.
+    pub fn packets(&self, local_player_id: &PlayerId) -> Vec<NetworkMessage> {
+        let chunk_cnt = self.chunks.len() as u64;
+
+        self.chunks
+            .iter()
+            .zip(&self.is_acked)
+            .enumerate()
+            .filter(|(_, (_, is_acked))| !**is_acked)
+            .take(MAX_RESYNC_PACKETS)
+            .map(|(index, (data, _))| NetworkMessage::ResyncChunk {
+                player_id: local_player_id.clone(),
+                frame: self.frame,
+                index: index as u64,
+                chunk_cnt,
+                data: data.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Kept by a player that has requested a resync, to put the received chunks back together
+#[derive(Debug, Clone, Default)]
+pub struct ResyncReceiver {
+    frame: Option<u64>,
+    chunks: Vec<Option<Vec<u8>>>,
+    /// Set when the current transfer has been completed, so that chunks that are sent again,
+    /// because their acknowledgement was lost, do not complete it a second time
+    is_complete: bool,
+}
+
+impl ResyncReceiver {
+    pub fn new() -> Self {
+        ResyncReceiver::default()
+    }
+
+    /// Handle a received chunk, returning the data of the transfer, if this was the last chunk
+    /// that was missing. A chunk of a transfer for a later frame will replace the current one.
+    pub fn receive(
+        &mut self,
+        frame: u64,
+        index: u64,
+        chunk_cnt: u64,
+        data: &[u8],
+    ) -> Option<Vec<u8>> {
+        let is_stale = matches!(self.frame, Some(current) if frame < current);
+
+        if index >= chunk_cnt || chunk_cnt > MAX_RESYNC_CHUNKS || is_stale {
+            return None;
+        }
+
+        if self.frame != Some(frame) {
+            self.frame = Some(frame);
+            self.chunks = vec![None; chunk_cnt as usize];
+            self.is_complete = false;
+        }
+
+        if self.is_complete || self.chunks.len() as u64 != chunk_cnt {
+            return None;
+        }
+
+        self.chunks[index as usize] = Some(data.to_vec());
+
+        if self.chunks.iter().all(Option::is_some) {
+            self.is_complete = true;
+
+            let data = self.chunks.drain(..).flatten().flatten().collect();
+
+            return Some(data);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    #[test]
+    fn test_state_round_trip() {
+        let player_ids = vec!["1".to_string(), "22".to_string(), "333".to_string()];
+
+        let state = ResyncState {
+            snapshot_frame: 240,
+            frame: 301,
+            rejoin_frame: 330,
+            player_ids: player_ids.clone(),
+            inputs: (0..61 * 3).map(|i| (i * 7) as u8).collect(),
+            pending_inputs: vec![(301, 4), (302, 255)],
+            idle_periods: vec![
+                (player_ids[1].clone(), 20, Some(90)),
+                (player_ids[1].clone(), 270, Some(330)),
+                (player_ids[2].clone(), 0, None),
+            ],
+        };
+
+        let bytes = state.encode();
+        assert_eq!(ResyncState::decode(&bytes).unwrap(), state);
+
+        for len in 0..bytes.len() {
+            assert!(ResyncState::decode(&bytes[..len]).is_err());
+        }
+
+        let mut bytes = bytes;
+        bytes.push(0);
+        assert!(ResyncState::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_transfer_completes_through_lost_and_reordered_chunks() {
+        let host_id = "1".to_string();
+
+        let mut rng = Rng::new(0);
+        let data = (0..RESYNC_CHUNK_SIZE * 40 + 17)
+            .map(|_| rng.next_u64() as u8)
+            .collect::<Vec<_>>();
+
+        let mut transfer = ResyncTransfer::new(600, &data);
+        let mut receiver = ResyncReceiver::new();
+
+        assert_eq!(transfer.chunk_cnt(), 41);
+
+        // An earlier resync, which should not get in the way of the next
+        assert!(receiver.receive(300, 0, 1, &[1, 2, 3]).is_some());
+
+        let mut received = None;
+
+        while !transfer.is_complete() {
+            let mut packets = transfer.packets(&host_id);
+            packets.reverse();
+
+            for packet in packets {
+                // Drop some of the chunks, as well as some of the acknowledgements
+                if rng.gen_range(0, 3) == 0 {
+                    continue;
+                }
+
+                if let NetworkMessage::ResyncChunk {
+                    frame,
+                    index,
+                    chunk_cnt,
+                    data,
+                    ..
+                } = packet
+                {
+                    if let Some(data) = receiver.receive(frame, index, chunk_cnt, &data) {
+                        assert!(received.is_none(), "Transfer completed twice");
+                        received = Some(data);
+                    }
+
+                    if rng.gen_range(0, 4) > 0 {
+                        transfer.ack(frame, index);
+                    }
+                }
+            }
+        }
+
+        assert_eq!(received, Some(data));
+        assert!(receiver.receive(300, 0, 1, &[1, 2, 3]).is_none());
+    }
+}
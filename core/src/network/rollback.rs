@@ -0,0 +1,567 @@
+//! This implements a GGPO-style rollback session. Instead of waiting for remote input, like the
+//! delayed lockstep `InputBuffer` does, the session predicts it, by repeating the last input that
+//! was received from each player, and simulates ahead. A snapshot of the simulation is saved
+//! before every frame, so that, if a remote input turns out to differ from the prediction, the
+//! state can be rolled back to the frame of that input and re-simulated with the correct input.
+//!
+//! The session may only run `max_rollback` frames ahead of the last frame for which the input of
+//! all players has been confirmed. When that limit is reached, it will stall until more remote
+//! input arrives, just as a lockstep session would.
+//!
+//! Players that have dropped are made idle, as with delayed lockstep. The input of idle frames is
+//! confirmed as default input, so that the session does not stall on a player that is gone.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::input::PlayerInput;
+use crate::network::{IdlePeriods, PlayerId};
+
+/// The default amount of frames that the simulation may run ahead of confirmed input
+pub const DEFAULT_MAX_ROLLBACK: u64 = 8;
+
+/// This is implemented by the simulation that is driven by a `RollbackSession`
+pub trait RollbackState {
+    type Snapshot;
+
+    /// Capture everything that is required to restore the simulation to its current state
+    fn save_snapshot(&mut self) -> Self::Snapshot;
+
+    /// Restore the simulation to a previously saved state
+    fn load_snapshot(&mut self, snapshot: &Self::Snapshot);
+
+    /// Simulate one frame, using the input of all players
+    fn advance_frame(&mut self, inputs: &HashMap<PlayerId, PlayerInput>);
+}
+
+pub struct RollbackSession<T> {
+    local_player_id: PlayerId,
+    player_ids: Vec<PlayerId>,
+    max_rollback: u64,
+    current_frame: u64,
+    /// All frames before this have had the input of every player confirmed
+    confirmed_frame: u64,
+    /// Input received from players, including the local player, by frame
+    confirmed_inputs: BTreeMap<u64, HashMap<PlayerId, PlayerInput>>,
+    /// The input that unconfirmed frames were simulated with, including predictions
+    simulated_inputs: BTreeMap<u64, HashMap<PlayerId, PlayerInput>>,
+    /// Snapshots of the state before each unconfirmed frame was simulated
+    snapshots: BTreeMap<u64, T>,
+    /// The input of the latest frame received from each player, used for prediction
+    last_inputs: HashMap<PlayerId, (u64, PlayerInput)>,
+    /// The earliest frame that was simulated with a prediction that turned out to be wrong
+    rollback_frame: Option<u64>,
+    rollback_cnt: u64,
+    last_rollback_len: u64,
+    /// The input of frames that have been confirmed, until taken with `take_confirmed_inputs`
+    newly_confirmed: Vec<(u64, HashMap<PlayerId, PlayerInput>)>,
+    idle: IdlePeriods,
+}
+
+impl<T> RollbackSession<T> {
+    pub fn new(local_player_id: &PlayerId, player_ids: &[PlayerId], max_rollback: u64) -> Self {
+        RollbackSession::new_at_frame(local_player_id, player_ids, max_rollback, 0)
+    }
+
+    /// Create a session that starts on `frame`, for a player that rejoins a running match, after
+    /// restoring its state to that frame
+    pub fn new_at_frame(
+        local_player_id: &PlayerId,
+        player_ids: &[PlayerId],
+        max_rollback: u64,
+        frame: u64,
+    ) -> Self {
+        RollbackSession {
+            local_player_id: local_player_id.clone(),
+            player_ids: player_ids.to_vec(),
+            max_rollback,
+            current_frame: frame,
+            confirmed_frame: frame,
+            confirmed_inputs: BTreeMap::new(),
+            simulated_inputs: BTreeMap::new(),
+            snapshots: BTreeMap::new(),
+            last_inputs: HashMap::new(),
+            rollback_frame: None,
+            rollback_cnt: 0,
+            last_rollback_len: 0,
+            newly_confirmed: Vec::new(),
+            idle: IdlePeriods::new(),
+        }
+    }
+
+    pub fn local_player_id(&self) -> &PlayerId {
+        &self.local_player_id
+    }
+
+    pub fn player_ids(&self) -> &[PlayerId] {
+        &self.player_ids
+    }
+
+    pub fn max_rollback(&self) -> u64 {
+        self.max_rollback
+    }
+
+    /// This is the frame that will be simulated next
+    pub fn current_frame(&self) -> u64 {
+        self.current_frame
+    }
+
+    /// All frames before this have been simulated with confirmed input only
+    pub fn confirmed_frame(&self) -> u64 {
+        self.confirmed_frame
+    }
+
+    /// The total amount of rollbacks performed
+    pub fn rollback_cnt(&self) -> u64 {
+        self.rollback_cnt
+    }
+
+    /// The amount of frames that were re-simulated by the last rollback
+    pub fn last_rollback_len(&self) -> u64 {
+        self.last_rollback_len
+    }
+
+    /// Returns the snapshot that was saved before a frame was simulated, if it is still kept. This
+    /// is the case for the frames from the confirmed frame up to the current frame.
+    pub fn snapshot(&self, frame: u64) -> Option<&T> {
+        self.snapshots.get(&frame)
+    }
+
+    /// Returns the input that has been received for a player, for the given frame
+    pub fn input(&self, player_id: &PlayerId, frame: u64) -> Option<PlayerInput> {
+        self.confirmed_inputs
+            .get(&frame)
+            .and_then(|inputs| inputs.get(player_id))
+            .copied()
+    }
+
+    /// Returns the amount of consecutive frames, starting with the confirmed frame, that the input
+    /// of a player has been received for
+    pub fn buffered_frames(&self, player_id: &PlayerId) -> u64 {
+        let mut frame = self.confirmed_frame;
+
+        while self.has_input(player_id, frame) {
+            frame += 1;
+        }
+
+        frame - self.confirmed_frame
+    }
+
+    /// Make a player idle from `start_frame` until `end_frame`, or until further notice, if it is
+    /// `None`, as with `InputBuffer::set_idle`. Frames that have been simulated since the start of
+    /// the period are rolled back, but frames that have already been confirmed are not affected.
+    pub fn set_idle(&mut self, player_id: &PlayerId, start_frame: u64, end_frame: Option<u64>) {
+        if self.idle.set(player_id, start_frame, end_frame) {
+            self.request_rollback(start_frame.max(self.confirmed_frame));
+        }
+    }
+
+    /// Returns `true` if a player is idle on the given frame
+    pub fn is_idle(&self, player_id: &PlayerId, frame: u64) -> bool {
+        self.idle.contains(player_id, frame)
+    }
+
+    /// Returns all idle periods of all players, as they were passed to `set_idle`
+    pub fn idle_periods(&self) -> Vec<(PlayerId, u64, Option<u64>)> {
+        self.idle.to_vec()
+    }
+
+    /// Take the input of all frames that have been confirmed since the last call, in order. This
+    /// should be called regularly, as the input is kept until it is taken.
+    pub fn take_confirmed_inputs(&mut self) -> Vec<(u64, HashMap<PlayerId, PlayerInput>)> {
+        std::mem::take(&mut self.newly_confirmed)
+    }
+
+    /// Returns `true` if the simulation is allowed to run ahead another frame
+    pub fn can_advance(&self) -> bool {
+        self.current_frame < self.confirmed_frame + self.max_rollback
+    }
+
+    /// Add local input for the current frame. The frame is returned, so that the input can be
+    /// dispatched to remote players.
+    pub fn add_local_input(&mut self, input: PlayerInput) -> u64 {
+        let frame = self.current_frame;
+        let player_id = self.local_player_id.clone();
+
+        self.add_input(&player_id, frame, input);
+
+        frame
+    }
+
+    /// Add input received from a remote player. If it differs from what was predicted for a frame
+    /// that has already been simulated, a rollback will be done on the next call to `advance`.
+    pub fn add_remote_input(&mut self, player_id: &PlayerId, frame: u64, input: PlayerInput) {
+        self.add_input(player_id, frame, input);
+    }
+
+    fn add_input(&mut self, player_id: &PlayerId, frame: u64, input: PlayerInput) {
+        if frame < self.confirmed_frame || !self.player_ids.contains(player_id) {
+            return;
+        }
+
+        let inputs = self.confirmed_inputs.entry(frame).or_default();
+
+        if inputs.contains_key(player_id) {
+            return;
+        }
+
+        inputs.insert(player_id.clone(), input);
+
+        if frame < self.current_frame && !self.is_idle(player_id, frame) {
+            let was_predicted = self
+                .simulated_inputs
+                .get(&frame)
+                .and_then(|inputs| inputs.get(player_id))
+                .map(|predicted| *predicted == input)
+                .unwrap_or_default();
+
+            if !was_predicted {
+                self.request_rollback(frame);
+            }
+        }
+
+        let is_latest = self
+            .last_inputs
+            .get(player_id)
+            .map(|(last_frame, _)| frame > *last_frame)
+            .unwrap_or(true);
+
+        if is_latest {
+            self.last_inputs.insert(player_id.clone(), (frame, input));
+        }
+    }
+
+    /// Roll back to `frame` on the next call to `synchronize`, if it has already been simulated
+    fn request_rollback(&mut self, frame: u64) {
+        if frame < self.current_frame {
+            self.rollback_frame = Some(
+                self.rollback_frame
+                    .map(|rollback_frame| rollback_frame.min(frame))
+                    .unwrap_or(frame),
+            );
+        }
+    }
+
+    /// Returns the input that will be used for a frame, predicting input that has not been
+    /// received yet. Idle players are given default input.
+    fn inputs_for(&self, frame: u64) -> HashMap<PlayerId, PlayerInput> {
+        let confirmed = self.confirmed_inputs.get(&frame);
+
+        self.player_ids
+            .iter()
+            .map(|player_id| {
+                if self.is_idle(player_id, frame) {
+                    return (player_id.clone(), PlayerInput::default());
+                }
+
+                let input = confirmed
+                    .and_then(|inputs| inputs.get(player_id))
+                    .copied()
+                    .unwrap_or_else(|| {
+                        self.last_inputs
+                            .get(player_id)
+                            .map(|(_, input)| *input)
+                            .unwrap_or_default()
+                    });
+
+                (player_id.clone(), input)
+            })
+            .collect()
+    }
+
+    /// Returns `true` if the input of a player has been received for the given frame, or if the
+    /// player is idle on that frame
+    pub fn has_input(&self, player_id: &PlayerId, frame: u64) -> bool {
+        self.is_idle(player_id, frame) || self.input(player_id, frame).is_some()
+    }
+
+    /// Returns `true` if the input of all players has been received for the given frame
+    pub fn is_frame_confirmed(&self, frame: u64) -> bool {
+        self.player_ids.iter().all(|id| self.has_input(id, frame))
+    }
+
+    /// If a misprediction has been detected, this will load the snapshot saved before the first
+    /// mispredicted frame and re-simulate up to the current frame.
+    pub fn synchronize<S>(&mut self, state: &mut S)
+    where
+        S: RollbackState<Snapshot = T>,
+    {
+        if let Some(rollback_frame) = self.rollback_frame.take() {
+            let snapshot = self
+                .snapshots
+                .get(&rollback_frame)
+                .expect("RollbackSession: No snapshot saved for rollback frame");
+
+            state.load_snapshot(snapshot);
+
+            for frame in rollback_frame..self.current_frame {
+                if frame != rollback_frame {
+                    self.snapshots.insert(frame, state.save_snapshot());
+                }
+
+                let inputs = self.inputs_for(frame);
+                state.advance_frame(&inputs);
+                self.simulated_inputs.insert(frame, inputs);
+            }
+
+            self.rollback_cnt += 1;
+            self.last_rollback_len = self.current_frame - rollback_frame;
+        }
+
+        self.discard_confirmed_frames();
+    }
+
+    /// Roll back, if required, and then simulate the current frame, if local input has been added
+    /// for it and the session is within the rollback window. Returns `true` if a frame was
+    /// simulated.
+    pub fn advance<S>(&mut self, state: &mut S) -> bool
+    where
+        S: RollbackState<Snapshot = T>,
+    {
+        self.synchronize(state);
+
+        let has_local_input = self.has_input(&self.local_player_id, self.current_frame);
+
+        if !has_local_input || !self.can_advance() {
+            return false;
+        }
+
+        let frame = self.current_frame;
+
+        self.snapshots.insert(frame, state.save_snapshot());
+
+        let inputs = self.inputs_for(frame);
+        state.advance_frame(&inputs);
+        self.simulated_inputs.insert(frame, inputs);
+
+        self.current_frame += 1;
+
+        self.discard_confirmed_frames();
+
+        true
+    }
+
+    /// Move the confirmed frame forward, as far as possible, and drop input and snapshots that
+    /// can no longer be rolled back to
+    fn discard_confirmed_frames(&mut self) {
+        while self.confirmed_frame < self.current_frame
+            && self.is_frame_confirmed(self.confirmed_frame)
+        {
+            let inputs = self.inputs_for(self.confirmed_frame);
+            self.newly_confirmed.push((self.confirmed_frame, inputs));
+
+            self.confirmed_frame += 1;
+        }
+
+        let confirmed_frame = self.confirmed_frame;
+
+        self.confirmed_inputs = self.confirmed_inputs.split_off(&confirmed_frame);
+        self.simulated_inputs = self.simulated_inputs.split_off(&confirmed_frame);
+        self.snapshots = self.snapshots.split_off(&confirmed_frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// A toy simulation, where the outcome depends on the order and timing of all input
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct TestState {
+        positions: BTreeMap<PlayerId, (i64, i64)>,
+        checksum: u64,
+    }
+
+    impl RollbackState for TestState {
+        type Snapshot = TestState;
+
+        fn save_snapshot(&mut self) -> TestState {
+            self.clone()
+        }
+
+        fn load_snapshot(&mut self, snapshot: &TestState) {
+            *self = snapshot.clone();
+        }
+
+        fn advance_frame(&mut self, inputs: &HashMap<PlayerId, PlayerInput>) {
+            let mut player_ids = inputs.keys().collect::<Vec<_>>();
+            player_ids.sort();
+
+            for player_id in player_ids {
+                let input = inputs[player_id];
+                let (position, velocity) = self.positions.entry(player_id.clone()).or_default();
+
+                if input.left {
+                    *velocity -= 1;
+                }
+
+                if input.right {
+                    *velocity += 1;
+                }
+
+                if input.jump {
+                    *velocity *= -2;
+                }
+
+                *position += *velocity;
+
+                self.checksum = self
+                    .checksum
+                    .wrapping_mul(31)
+                    .wrapping_add(*position as u64);
+            }
+        }
+    }
+
+    fn test_input(player: u64, frame: u64) -> PlayerInput {
+        let n = frame + player * 3;
+
+        PlayerInput {
+            left: n % 3 == 2,
+            right: n % 5 == 1,
+            jump: n % 7 == 2,
+            ..Default::default()
+        }
+    }
+
+    /// Runs a session for player "1", where the input of player "2" arrives `latency` frames late
+    fn run_session(frame_cnt: u64, latency: u64) -> (TestState, u64) {
+        let player_ids = vec!["1".to_string(), "2".to_string()];
+
+        let mut session = RollbackSession::new(&player_ids[0], &player_ids, DEFAULT_MAX_ROLLBACK);
+        let mut state = TestState::default();
+
+        let mut in_flight = VecDeque::new();
+
+        for frame in 0..frame_cnt {
+            in_flight.push_back((frame + latency, frame, test_input(2, frame)));
+
+            while let Some((arrival, frame, input)) = in_flight.front().cloned() {
+                if arrival > session.current_frame() {
+                    break;
+                }
+
+                session.add_remote_input(&player_ids[1], frame, input);
+                in_flight.pop_front();
+            }
+
+            session.add_local_input(test_input(1, frame));
+            assert!(session.advance(&mut state));
+        }
+
+        for (_, frame, input) in in_flight {
+            session.add_remote_input(&player_ids[1], frame, input);
+        }
+
+        session.synchronize(&mut state);
+
+        assert_eq!(session.confirmed_frame(), frame_cnt);
+
+        // Every frame should be confirmed once, in order, with the input that was actually sent
+        let confirmed = session.take_confirmed_inputs();
+        assert_eq!(confirmed.len() as u64, frame_cnt);
+
+        for (i, (frame, inputs)) in confirmed.iter().enumerate() {
+            assert_eq!(*frame, i as u64);
+            assert_eq!(inputs[&player_ids[0]], test_input(1, *frame));
+            assert_eq!(inputs[&player_ids[1]], test_input(2, *frame));
+        }
+
+        (state, session.rollback_cnt())
+    }
+
+    #[test]
+    fn test_delayed_input_matches_non_delayed_run() {
+        let (expected, rollback_cnt) = run_session(120, 0);
+        assert_eq!(rollback_cnt, 0);
+
+        for latency in 1..DEFAULT_MAX_ROLLBACK {
+            let (state, rollback_cnt) = run_session(120, latency);
+
+            assert!(rollback_cnt > 0);
+            assert_eq!(state, expected);
+        }
+    }
+
+    #[test]
+    fn test_stalls_when_rollback_window_is_full() {
+        let player_ids = vec!["1".to_string(), "2".to_string()];
+
+        let mut session = RollbackSession::new(&player_ids[0], &player_ids, 2);
+        let mut state = TestState::default();
+
+        for _ in 0..2 {
+            session.add_local_input(PlayerInput::default());
+            assert!(session.advance(&mut state));
+        }
+
+        session.add_local_input(PlayerInput::default());
+        assert!(!session.can_advance());
+        assert!(!session.advance(&mut state));
+
+        session.add_remote_input(&player_ids[1], 0, PlayerInput::default());
+
+        assert!(session.advance(&mut state));
+        assert_eq!(session.current_frame(), 3);
+        assert_eq!(session.confirmed_frame(), 1);
+    }
+
+    #[test]
+    fn test_idle_players_are_confirmed_with_default_input() {
+        let player_ids = vec!["1".to_string(), "2".to_string()];
+
+        let input = PlayerInput {
+            right: true,
+            ..Default::default()
+        };
+
+        let mut session = RollbackSession::new_at_frame(&player_ids[0], &player_ids, 8, 100);
+        let mut state = TestState::default();
+
+        // The remote player drops after frame 101, and its last input is predicted from then on
+        for frame in 100..106 {
+            if frame < 102 {
+                session.add_remote_input(&player_ids[1], frame, input);
+            }
+
+            session.add_local_input(input);
+            assert!(session.advance(&mut state));
+        }
+
+        assert_eq!(session.confirmed_frame(), 102);
+        assert_eq!(session.buffered_frames(&player_ids[1]), 0);
+
+        session.set_idle(&player_ids[1], 102, None);
+        session.synchronize(&mut state);
+
+        assert_eq!(session.rollback_cnt(), 1);
+        assert_eq!(session.confirmed_frame(), 106);
+
+        // Input that arrives late for an idle frame is ignored
+        session.add_remote_input(&player_ids[1], 104, input);
+        session.set_idle(&player_ids[1], 102, Some(108));
+
+        for frame in 106..110 {
+            session.add_remote_input(&player_ids[1], frame, input);
+            session.add_local_input(input);
+            assert!(session.advance(&mut state));
+        }
+
+        assert_eq!(session.confirmed_frame(), 110);
+        assert_eq!(session.rollback_cnt(), 1);
+
+        let confirmed = session.take_confirmed_inputs();
+        assert_eq!(confirmed.len(), 10);
+
+        for (frame, inputs) in confirmed {
+            let expected = if (102..108).contains(&frame) {
+                PlayerInput::default()
+            } else {
+                input
+            };
+
+            assert_eq!(inputs[&player_ids[1]], expected, "Frame {}", frame);
+        }
+    }
+}
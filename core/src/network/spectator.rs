@@ -0,0 +1,262 @@
+//! Spectators watch a network match without taking part in it. The host keeps the confirmed input
+//! of every frame, from the start of the match, in a `SpectatorFeed`, and streams it to all
+//! spectators. As the simulation is deterministic, this is all a spectator needs to run it, so a
+//! spectator can join at any point in the match, and simulate from the first frame to catch up.
+//!
+//! Spectators acknowledge the last frame they have received, in sequence, and the host will keep
+//! sending input from the first frame that has not been acknowledged.
+
+use std::collections::HashMap;
+
+use crate::input::PlayerInput;
+use crate::network::{NetworkMessage, PlayerId};
+
+/// The maximum amount of inputs, for all players combined, sent in a single spectator packet
+pub const MAX_SPECTATOR_INPUTS: usize = 512;
+
+/// The maximum amount of packets sent to a spectator at once, when it is catching up
+pub const MAX_SPECTATOR_PACKETS: usize = 8;
+
+/// Kept by the host, holding the confirmed input of the match and the progress of every spectator
+#[derive(Debug, Clone, Default)]
+pub struct SpectatorFeed {
+    player_ids: Vec<PlayerId>,
+    /// The confirmed input of every frame, with the input of all players, in the order of
+    /// `player_ids`, for each frame
+    inputs: Vec<PlayerInput>,
+    /// The last frame that each spectator has acknowledged
+    spectators: HashMap<PlayerId, Option<u64>>,
+}
+
+impl SpectatorFeed {
+    pub fn new() -> Self {
+        SpectatorFeed::default()
+    }
+
+    /// The amount of frames of confirmed input in the feed
+    pub fn frame_cnt(&self) -> u64 {
+        if self.player_ids.is_empty() {
+            0
+        } else {
+            (self.inputs.len() / self.player_ids.len()) as u64
+        }
+    }
+
+    pub fn has_spectators(&self) -> bool {
+        !self.spectators.is_empty()
+    }
+
+    pub fn add_spectator(&mut self, player_id: &PlayerId) {
+        self.spectators.entry(player_id.clone()).or_default();
+    }
+
+    pub fn remove_spectator(&mut self, player_id: &PlayerId) {
+        self.spectators.remove(player_id);
+    }
+
+    /// Add the confirmed input of a sequence of frames, starting with `start_frame`, holding the
+    /// input of all players in `player_ids`, for each frame. Frames that are already in the feed
+    /// are skipped. Returns `false` if the input does not follow the frames already in the feed,
+    /// or if the players do not match.
+    pub fn push(
+        &mut self,
+        player_ids: &[PlayerId],
+        start_frame: u64,
+        inputs: &[PlayerInput],
+    ) -> bool {
+        if self.player_ids.is_empty() {
+            self.player_ids = player_ids.to_vec();
+        }
+
+        if player_ids.is_empty()
+            || player_ids != self.player_ids
+            || start_frame > self.frame_cnt()
+            || !inputs.chunks_exact(player_ids.len()).remainder().is_empty()
+        {
+            return false;
+        }
+
+        let skip = (self.frame_cnt() - start_frame) as usize * player_ids.len();
+        self.inputs.extend(inputs.iter().skip(skip));
+
+        true
+    }
+
+    /// Mark all frames up to, and including, `frame` as received by a spectator
+    pub fn ack(&mut self, player_id: &PlayerId, frame: u64) {
+        if let Some(acked) = self.spectators.get_mut(player_id) {
+            *acked = Some(acked.map_or(frame, |acked| acked.max(frame)));
+        }
+    }
+
+    /// Build the packets holding the input that a spectator has not acknowledged yet, starting
+    /// with the oldest. At most `MAX_SPECTATOR_PACKETS` are returned, as a spectator that has just
+    /// joined might be missing all of the match.
+    pub fn packets_for(
+        &self,
+        local_player_id: &PlayerId,
+        player_id: &PlayerId,
+    ) -> Vec<NetworkMessage> {
+        let mut packets = Vec::new();
+
+        let acked = match self.spectators.get(player_id) {
+            Some(acked) => *acked,
+            None => return packets,
+        };
+
+        let player_cnt = self.player_ids.len().max(1);
+        let frames_per_packet = (MAX_SPECTATOR_INPUTS / player_cnt).max(1) as u64;
+
+        let mut start_frame = acked.map_or(0, |acked| acked + 1);
+
+        while start_frame < self.frame_cnt() && packets.len() < MAX_SPECTATOR_PACKETS {
+            let end_frame = (start_frame + frames_per_packet).min(self.frame_cnt());
+
+            let inputs = self.inputs
+                [start_frame as usize * player_cnt..end_frame as usize * player_cnt]
+                .to_vec();
+
+            packets.push(NetworkMessage::SpectatorInputs {
+                player_id: local_player_id.clone(),
+                start_frame,
+                player_ids: self.player_ids.clone(),
+                inputs,
+            });
+
+            start_frame = end_frame;
+        }
+
+        packets
+    }
+}
+
+/// Kept by a spectator, to put the input received from the host in sequence
+#[derive(Debug, Clone, Default)]
+pub struct SpectatorStream {
+    /// The first frame that has not been received
+    next_frame: u64,
+}
+
+impl SpectatorStream {
+    pub fn new() -> Self {
+        SpectatorStream::default()
+    }
+
+    /// The frame that should be acknowledged to the host, or `None` if nothing has been received
+    pub fn ack(&self) -> Option<u64> {
+        self.next_frame.checked_sub(1)
+    }
+
+    /// Handle a received packet, returning the input of every player, for all frames that had not
+    /// been received before. Packets that do not continue the received sequence are ignored, as
+    /// the host will send those frames again, after the gap has been filled.
+    pub fn receive(
+        &mut self,
+        start_frame: u64,
+        player_ids: &[PlayerId],
+        inputs: &[PlayerInput],
+    ) -> Vec<(u64, PlayerId, PlayerInput)> {
+        if player_ids.is_empty() || start_frame > self.next_frame {
+            return Vec::new();
+        }
+
+        let res = inputs
+            .chunks_exact(player_ids.len())
+            .zip(start_frame..)
+            .skip_while(|(_, frame)| *frame < self.next_frame)
+            .flat_map(|(inputs, frame)| {
+                player_ids
+                    .iter()
+                    .zip(inputs)
+                    .map(move |(player_id, input)| (frame, player_id.clone(), *input))
+            })
+            .collect::<Vec<_>>();
+
+        if let Some((frame, _, _)) = res.last() {
+            self.next_frame = frame + 1;
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(frame: u64, player: usize) -> PlayerInput {
+        PlayerInput {
+            left: frame % 2 == 1,
+            jump: frame % 3 == 1,
+            fire: player == 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_late_spectator_catches_up_through_lost_packets() {
+        let host_id = "1".to_string();
+        let spectator_id = "3".to_string();
+        let player_ids = vec![host_id.clone(), "2".to_string()];
+
+        let mut feed = SpectatorFeed::new();
+        let mut stream = SpectatorStream::new();
+
+        let frame_inputs = |frame: u64| vec![input(frame, 0), input(frame, 1)];
+
+        // The match is well underway when the spectator joins
+        for frame in 0..2000 {
+            assert!(feed.push(&player_ids, frame, &frame_inputs(frame)));
+        }
+
+        assert!(!feed.push(&player_ids, 2001, &frame_inputs(2001)));
+
+        feed.add_spectator(&spectator_id);
+
+        let mut received = Vec::new();
+
+        for i in 0..100 {
+            let frame = feed.frame_cnt();
+            feed.push(&player_ids, frame, &frame_inputs(frame));
+
+            for (j, packet) in feed
+                .packets_for(&host_id, &spectator_id)
+                .into_iter()
+                .enumerate()
+            {
+                // Drop some packets, which will leave gaps in the sequence, until the end
+                if i < 90 && (i + j) % 4 == 3 {
+                    continue;
+                }
+
+                if let NetworkMessage::SpectatorInputs {
+                    start_frame,
+                    player_ids,
+                    inputs,
+                    ..
+                } = packet
+                {
+                    received.extend(stream.receive(start_frame, &player_ids, &inputs));
+                }
+            }
+
+            if let Some(ack) = stream.ack() {
+                feed.ack(&spectator_id, ack);
+            }
+        }
+
+        assert_eq!(stream.ack(), Some(feed.frame_cnt() - 1));
+
+        let expected = (0..feed.frame_cnt())
+            .flat_map(|frame| {
+                player_ids
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, player_id)| (frame, player_id.clone(), input(frame, i)))
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(received, expected);
+        assert!(feed.packets_for(&host_id, &spectator_id).is_empty());
+    }
+}
@@ -0,0 +1,1123 @@
+//! This implements an `ApiBackend` that sends messages directly between peers, over UDP.
+//! Every peer has to know the addresses of all the other peers in the session, as there is no
+//! server involved in any way.
+//!
+//! Spectators are not peers, as they do not send any input. They are added to the host with
+//! `add_spectator`, and receive the confirmed input dispatched by it, while a spectator only needs
+//! to add the host as a peer.
+//!
+//! If nothing is received from a peer for `PEER_TIMEOUT`, `NetworkEvent::PlayerReconnecting` is
+//! emitted for it. Players that rejoin are resynchronized by the host, through `resync_player`,
+//! which sends the data in chunks, until every chunk has been acknowledged.
+//!
+//! The chunks of state descriptions, that are requested by peers that have detected a desync,
+//! are put back together by a `ResyncReceiver` for every peer.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::error::{Error, ErrorKind};
+use crate::network::{
+    decode_message, encode_message, ApiBackend, ApiBackendConstructor, DesyncDetector,
+    LinkConditioner, NetworkEvent, NetworkMessage, PlayerId, RedundantInputs, ResyncReceiver,
+    ResyncTransfer, SpectatorFeed, SpectatorStream,
+};
+use crate::Result;
+
+/// The maximum size of a datagram that will be read from the socket
+const MAX_PACKET_SIZE: usize = 1024;
+
+/// If no input has been dispatched for this long, the last input packets are sent again. This
+/// also sends acknowledgements when the local simulation is stalled, which would otherwise leave
+/// both peers waiting for each other, if the packet they are waiting for was dropped.
+const INPUT_RESEND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The interval between packets of confirmed input to spectators. Spectators do not need their
+/// input as soon as possible, so it is sent in batches, instead of every time a frame is added.
+const SPECTATOR_SEND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// If nothing is received from a peer for this long, it is considered to have dropped. This is
+/// only checked after the first packet from a peer has arrived, as peers might take a while to
+/// load the game.
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The interval between packets of resync chunks that have not been acknowledged
+const RESYNC_SEND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Notices, like `NetworkMessage::PlayerIdle`, are not acknowledged, so they are sent again at
+/// this interval, until `NOTICE_DURATION` has passed, to make sure that they arrive
+const NOTICE_RESEND_INTERVAL: Duration = Duration::from_millis(100);
+const NOTICE_DURATION: Duration = Duration::from_secs(3);
+
+/// The parameters used to initialize an `UdpApiBackend` through `Api::init`
+#[derive(Debug, Clone)]
+pub struct UdpBackendParams {
+    pub player_id: PlayerId,
+    pub local_addr: SocketAddr,
+    pub peers: Vec<(PlayerId, SocketAddr)>,
+}
+
+pub struct UdpApiBackend {
+    player_id: PlayerId,
+    socket: UdpSocket,
+    peers: Vec<(PlayerId, SocketAddr)>,
+    events: VecDeque<NetworkEvent>,
+    desync_detector: DesyncDetector,
+    inputs: RedundantInputs,
+    last_input_send: Instant,
+    spectators: Vec<(PlayerId, SocketAddr)>,
+    spectator_feed: SpectatorFeed,
+    spectator_stream: SpectatorStream,
+    last_spectator_send: Instant,
+    /// The time that the last packet was received from each peer
+    last_received: HashMap<PlayerId, Instant>,
+    /// Peers that have timed out, and have not been heard from since
+    reconnecting: HashSet<PlayerId>,
+    resync_transfers: HashMap<PlayerId, ResyncTransfer>,
+    resync_receiver: ResyncReceiver,
+    last_resync_send: Instant,
+    description_receivers: HashMap<PlayerId, ResyncReceiver>,
+    /// Notices that are being sent, along with the time they were dispatched
+    notices: Vec<(NetworkMessage, Instant)>,
+    last_notice_send: Instant,
+    conditioner: Option<LinkConditioner>,
+}
+
+impl UdpApiBackend {
+    /// Bind a new, non-blocking, socket to `local_addr`. Peers must be added with `add_peer`
+    /// before any messages can be dispatched.
+    pub fn bind(player_id: &PlayerId, local_addr: SocketAddr) -> Result<Self> {
+        let socket =
+            UdpSocket::bind(local_addr).map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        socket
+            .set_nonblocking(true)
+            .map_err(|err| Error::new(ErrorKind::Network, err))?;
+
+        Ok(UdpApiBackend {
+            player_id: player_id.clone(),
+            socket,
+            peers: Vec::new(),
+            events: VecDeque::new(),
+            desync_detector: DesyncDetector::new(),
+            inputs: RedundantInputs::new(),
+            last_input_send: Instant::now(),
+            spectators: Vec::new(),
+            spectator_feed: SpectatorFeed::new(),
+            spectator_stream: SpectatorStream::new(),
+            last_spectator_send: Instant::now(),
+            last_received: HashMap::new(),
+            reconnecting: HashSet::new(),
+            resync_transfers: HashMap::new(),
+            resync_receiver: ResyncReceiver::new(),
+            last_resync_send: Instant::now(),
+            description_receivers: HashMap::new(),
+            notices: Vec::new(),
+            last_notice_send: Instant::now(),
+            conditioner: None,
+        })
+    }
+
+    /// Pass all outgoing packets through a `LinkConditioner`, to simulate a bad connection
+    pub fn set_link_conditioner(&mut self, conditioner: Option<LinkConditioner>) {
+        self.conditioner = conditioner;
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket
+            .local_addr()
+            .map_err(|err| Error::new(ErrorKind::Network, err))
+    }
+
+    pub fn add_peer(&mut self, player_id: &PlayerId, addr: SocketAddr) {
+        self.peers.retain(|(id, _)| id != player_id);
+        self.peers.push((player_id.clone(), addr));
+
+        self.inputs.add_peer(player_id);
+    }
+
+    pub fn remove_peer(&mut self, player_id: &PlayerId) {
+        self.peers.retain(|(id, _)| id != player_id);
+
+        self.inputs.remove_peer(player_id);
+
+        self.last_received.remove(player_id);
+        self.reconnecting.remove(player_id);
+        self.resync_transfers.remove(player_id);
+    }
+
+    /// Add a spectator, that will be sent all confirmed input dispatched with
+    /// `NetworkMessage::SpectatorInputs`, from the start of the match
+    pub fn add_spectator(&mut self, player_id: &PlayerId, addr: SocketAddr) {
+        self.spectators.retain(|(id, _)| id != player_id);
+        self.spectators.push((player_id.clone(), addr));
+
+        self.spectator_feed.add_spectator(player_id);
+    }
+
+    pub fn remove_spectator(&mut self, player_id: &PlayerId) {
+        self.spectators.retain(|(id, _)| id != player_id);
+
+        self.spectator_feed.remove_spectator(player_id);
+    }
+
+    fn peer_id(&self, addr: SocketAddr) -> Option<&PlayerId> {
+        self.peers
+            .iter()
+            .chain(self.spectators.iter())
+            .find(|(_, peer_addr)| *peer_addr == addr)
+            .map(|(id, _)| id)
+    }
+
+    /// Read all pending datagrams from the socket and queue the resulting events
+    fn poll_socket(&mut self) {
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, addr)) => {
+                    if self.peer_id(addr).is_none() {
+                        #[cfg(debug_assertions)]
+                        println!(
+                            "WARNING: UdpApiBackend: Packet from unknown peer '{}'",
+                            addr
+                        );
+
+                        continue;
+                    }
+
+                    let peer_id = self
+                        .peers
+                        .iter()
+                        .find(|(_, peer_addr)| *peer_addr == addr)
+                        .map(|(id, _)| id.clone());
+
+                    if let Some(peer_id) = peer_id {
+                        self.reconnecting.remove(&peer_id);
+                        self.last_received.insert(peer_id, Instant::now());
+                    }
+
+                    match decode_message(&buf[..len]) {
+                        Ok(message) => self.on_message(message, addr),
+                        Err(err) => {
+                            #[cfg(debug_assertions)]
+                            println!("WARNING: UdpApiBackend: {}", err);
+                        }
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    // On some platforms an ICMP port unreachable, caused by a peer that has not
+                    // bound its socket yet, will surface here. This should not end the session.
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: UdpApiBackend: {}", err);
+
+                    break;
+                }
+            }
+        }
+
+        if self.last_input_send.elapsed() >= INPUT_RESEND_INTERVAL {
+            self.send_inputs();
+        }
+
+        if self.last_spectator_send.elapsed() >= SPECTATOR_SEND_INTERVAL {
+            self.send_spectator_inputs();
+        }
+
+        if self.last_resync_send.elapsed() >= RESYNC_SEND_INTERVAL {
+            self.send_resync_chunks();
+        }
+
+        if self.last_notice_send.elapsed() >= NOTICE_RESEND_INTERVAL {
+            self.send_notices();
+        }
+
+        for (player_id, last_received) in &self.last_received {
+            if last_received.elapsed() >= PEER_TIMEOUT
+                && self.reconnecting.insert(player_id.clone())
+            {
+                self.events.push_back(NetworkEvent::PlayerReconnecting {
+                    player_id: player_id.clone(),
+                });
+            }
+        }
+    }
+
+    /// Send the chunks of every resync that have not been acknowledged
+    fn send_resync_chunks(&mut self) {
+        self.last_resync_send = Instant::now();
+
+        for i in 0..self.peers.len() {
+            let (peer_id, addr) = self.peers[i].clone();
+
+            let packets = self
+                .resync_transfers
+                .get(&peer_id)
+                .map(|transfer| transfer.packets(&self.player_id))
+                .unwrap_or_default();
+
+            for packet in packets {
+                if let Err(err) = self.send_to(&packet, addr) {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: UdpApiBackend: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Send all notices again, dropping the ones that have been sent for `NOTICE_DURATION`
+    fn send_notices(&mut self) {
+        self.last_notice_send = Instant::now();
+
+        self.notices
+            .retain(|(_, dispatched)| dispatched.elapsed() < NOTICE_DURATION);
+
+        for i in 0..self.notices.len() {
+            let notice = self.notices[i].0.clone();
+
+            for j in 0..self.peers.len() {
+                let addr = self.peers[j].1;
+
+                if let Err(err) = self.send_to(&notice, addr) {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: UdpApiBackend: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Start over with the redundancy layer, after a resync that starts on `frame` has been
+    /// received. Input for the frames before it is covered by the resync, so it is marked as
+    /// received, for all peers, and the local input sent before dropping is discarded.
+    fn rejoin(&mut self, frame: u64) {
+        self.inputs = RedundantInputs::new();
+        self.desync_detector = DesyncDetector::new();
+
+        for (peer_id, _) in &self.peers {
+            self.inputs.add_peer(peer_id);
+
+            if let Some(frame) = frame.checked_sub(1) {
+                self.inputs.skip_received(peer_id, frame);
+            }
+        }
+    }
+
+    /// Send the confirmed input that each spectator has not acknowledged
+    fn send_spectator_inputs(&mut self) {
+        self.last_spectator_send = Instant::now();
+
+        for i in 0..self.spectators.len() {
+            let (spectator_id, addr) = self.spectators[i].clone();
+
+            for packet in self
+                .spectator_feed
+                .packets_for(&self.player_id, &spectator_id)
+            {
+                if let Err(err) = self.send_to(&packet, addr) {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: UdpApiBackend: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Send the input that each peer has not acknowledged, along with acknowledgements
+    fn send_inputs(&mut self) {
+        self.last_input_send = Instant::now();
+
+        for i in 0..self.peers.len() {
+            let (peer_id, addr) = self.peers[i].clone();
+
+            if let Some(packet) = self.inputs.packet_for(&self.player_id, &peer_id) {
+                if let Err(err) = self.send_to(&packet, addr) {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: UdpApiBackend: {}", err);
+                }
+            }
+        }
+    }
+
+    fn on_message(&mut self, message: NetworkMessage, addr: SocketAddr) {
+        match message {
+            NetworkMessage::UpdatePlayerInput {
+                player_id,
+                frame,
+                input,
+            } => {
+                self.events.push_back(NetworkEvent::PlayerInput {
+                    player_id,
+                    frame,
+                    input,
+                });
+            }
+            NetworkMessage::PlayerInputs {
+                player_id,
+                start_frame,
+                inputs,
+                ack,
+            } => {
+                for (frame, input) in self.inputs.receive(&player_id, start_frame, &inputs, ack) {
+                    self.events.push_back(NetworkEvent::PlayerInput {
+                        player_id: player_id.clone(),
+                        frame,
+                        input,
+                    });
+                }
+            }
+            NetworkMessage::StateChecksum {
+                player_id,
+                frame,
+                checksum,
+            } => {
+                if let Some(player_id) = self
+                    .desync_detector
+                    .add_remote_checksum(&player_id, frame, checksum)
+                {
+                    self.events.push_back(NetworkEvent::Desync {
+                        frame,
+                        player_ids: vec![player_id],
+                    });
+                }
+            }
+            NetworkMessage::Ping { timestamp, .. } => {
+                let pong = NetworkMessage::Pong {
+                    player_id: self.player_id.clone(),
+                    timestamp,
+                };
+
+                if let Err(err) = self.send_to(&pong, addr) {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: UdpApiBackend: {}", err);
+                }
+            }
+            NetworkMessage::Pong {
+                player_id,
+                timestamp,
+            } => {
+                self.events.push_back(NetworkEvent::Pong {
+                    player_id,
+                    timestamp,
+                });
+            }
+            NetworkMessage::SpectatorInputs {
+                start_frame,
+                player_ids,
+                inputs,
+                ..
+            } => {
+                let received = self
+                    .spectator_stream
+                    .receive(start_frame, &player_ids, &inputs);
+
+                for (frame, player_id, input) in received {
+                    self.events.push_back(NetworkEvent::PlayerInput {
+                        player_id,
+                        frame,
+                        input,
+                    });
+                }
+
+                // Every packet is acknowledged, even if it held nothing new, as the previous
+                // acknowledgement might have been lost
+                if let Some(frame) = self.spectator_stream.ack() {
+                    let ack = NetworkMessage::SpectatorAck {
+                        player_id: self.player_id.clone(),
+                        frame,
+                    };
+
+                    if let Err(err) = self.send_to(&ack, addr) {
+                        #[cfg(debug_assertions)]
+                        println!("WARNING: UdpApiBackend: {}", err);
+                    }
+                }
+            }
+            NetworkMessage::SpectatorAck { player_id, frame } => {
+                self.spectator_feed.ack(&player_id, frame);
+            }
+            NetworkMessage::ResyncRequest {
+                player_id,
+                checkpoint_frames,
+            } => {
+                self.events.push_back(NetworkEvent::ResyncRequested {
+                    player_id,
+                    checkpoint_frames,
+                });
+            }
+            NetworkMessage::ResyncChunk {
+                frame,
+                index,
+                chunk_cnt,
+                data,
+                ..
+            } => {
+                if let Some(data) = self.resync_receiver.receive(frame, index, chunk_cnt, &data) {
+                    self.rejoin(frame);
+                    self.events.push_back(NetworkEvent::Resync { frame, data });
+                }
+
+                // Chunks are acknowledged every time they are received, as the previous
+                // acknowledgement might have been lost
+                let ack = NetworkMessage::ResyncAck {
+                    player_id: self.player_id.clone(),
+                    frame,
+                    index,
+                };
+
+                if let Err(err) = self.send_to(&ack, addr) {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: UdpApiBackend: {}", err);
+                }
+            }
+            NetworkMessage::ResyncAck {
+                player_id,
+                frame,
+                index,
+            } => {
+                if let Some(transfer) = self.resync_transfers.get_mut(&player_id) {
+                    transfer.ack(frame, index);
+
+                    if transfer.is_complete() {
+                        self.resync_transfers.remove(&player_id);
+                    }
+                }
+            }
+            NetworkMessage::PlayerIdle {
+                player_id,
+                start_frame,
+                end_frame,
+            } => {
+                if player_id != self.player_id {
+                    self.skip_idle_frames(&player_id, end_frame);
+                }
+
+                self.events.push_back(NetworkEvent::PlayerIdle {
+                    player_id,
+                    start_frame,
+                    end_frame,
+                });
+            }
+            NetworkMessage::StateDescriptionRequest { player_id, frame } => {
+                self.events
+                    .push_back(NetworkEvent::StateDescriptionRequested { player_id, frame });
+            }
+            NetworkMessage::StateDescriptionChunk {
+                player_id,
+                frame,
+                index,
+                chunk_cnt,
+                data,
+            } => {
+                let data = self
+                    .description_receivers
+                    .entry(player_id.clone())
+                    .or_default()
+                    .receive(frame, index, chunk_cnt, &data);
+
+                if let Some(data) = data {
+                    self.events.push_back(NetworkEvent::StateDescription {
+                        player_id,
+                        frame,
+                        data,
+                    });
+                }
+            }
+        }
+    }
+
+    /// A player that rejoins will send input from the end of its idle period, so the frames before
+    /// it are marked as received, or it would never be acknowledged
+    fn skip_idle_frames(&mut self, player_id: &PlayerId, end_frame: Option<u64>) {
+        if let Some(frame) = end_frame.and_then(|frame| frame.checked_sub(1)) {
+            self.inputs.skip_received(player_id, frame);
+        }
+    }
+
+    fn send_to(&mut self, message: &NetworkMessage, addr: SocketAddr) -> Result<()> {
+        let bytes = encode_message(message);
+
+        let packets = match &mut self.conditioner {
+            Some(conditioner) => conditioner.process(bytes, addr),
+            None => vec![(bytes, addr)],
+        };
+
+        for (bytes, addr) in packets {
+            self.socket
+                .send_to(&bytes, addr)
+                .map_err(|err| Error::new(ErrorKind::Network, err))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ApiBackendConstructor for UdpApiBackend {
+    type Params = UdpBackendParams;
+
+    async fn init(params: UdpBackendParams) -> Result<Self> {
+        let mut backend = UdpApiBackend::bind(&params.player_id, params.local_addr)?;
+
+        for (player_id, addr) in params.peers {
+            backend.add_peer(&player_id, addr);
+        }
+
+        Ok(backend)
+    }
+}
+
+#[async_trait]
+impl ApiBackend for UdpApiBackend {
+    async fn close(&mut self) -> Result<()> {
+        self.peers.clear();
+        self.events.clear();
+        self.desync_detector = DesyncDetector::new();
+        self.inputs = RedundantInputs::new();
+        self.spectators.clear();
+        self.spectator_feed = SpectatorFeed::new();
+        self.spectator_stream = SpectatorStream::new();
+        self.last_received.clear();
+        self.reconnecting.clear();
+        self.resync_transfers.clear();
+        self.resync_receiver = ResyncReceiver::new();
+        self.description_receivers.clear();
+        self.notices.clear();
+
+        Ok(())
+    }
+
+    fn local_player_id(&self) -> PlayerId {
+        self.player_id.clone()
+    }
+
+    fn dispatch_message(&mut self, message: NetworkMessage) -> Result<()> {
+        if let NetworkMessage::StateChecksum {
+            frame, checksum, ..
+        } = &message
+        {
+            if let Some(player_ids) = self.desync_detector.add_local_checksum(*frame, *checksum) {
+                self.events.push_back(NetworkEvent::Desync {
+                    frame: *frame,
+                    player_ids,
+                });
+            }
+        }
+
+        // Input goes through the redundancy layer, which sends it along with all the input that
+        // has not been acknowledged by each peer
+        if let NetworkMessage::UpdatePlayerInput { frame, input, .. } = &message {
+            self.inputs.push_local(*frame, *input);
+            self.send_inputs();
+
+            return Ok(());
+        }
+
+        // Confirmed input is added to the spectator feed, to be sent to spectators in batches
+        if let NetworkMessage::SpectatorInputs {
+            start_frame,
+            player_ids,
+            inputs,
+            ..
+        } = &message
+        {
+            if !self.spectator_feed.push(player_ids, *start_frame, inputs) {
+                return Err(Error::new_message(
+                    ErrorKind::Network,
+                    &format!(
+                        "Confirmed input for frame {} is out of sequence",
+                        start_frame
+                    ),
+                ));
+            }
+
+            return Ok(());
+        }
+
+        // Notices are sent again, for a while, as they are not acknowledged
+        if let NetworkMessage::PlayerIdle {
+            player_id,
+            end_frame,
+            ..
+        } = &message
+        {
+            self.skip_idle_frames(player_id, *end_frame);
+            self.notices.push((message.clone(), Instant::now()));
+        }
+
+        for i in 0..self.peers.len() {
+            let addr = self.peers[i].1;
+            self.send_to(&message, addr)?;
+        }
+
+        Ok(())
+    }
+
+    fn resync_player(&mut self, player_id: &PlayerId, frame: u64, data: &[u8]) -> Result<()> {
+        if !self.peers.iter().any(|(id, _)| id == player_id) {
+            return Err(Error::new_message(
+                ErrorKind::Network,
+                &format!("Unable to resync unknown player '{}'", player_id),
+            ));
+        }
+
+        let transfer = ResyncTransfer::new(frame, data);
+        self.resync_transfers.insert(player_id.clone(), transfer);
+
+        self.send_resync_chunks();
+
+        Ok(())
+    }
+
+    fn next_event(&mut self) -> Option<NetworkEvent> {
+        self.poll_socket();
+        self.events.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::input::PlayerInput;
+    use crate::network::{state_description_chunks, InputBuffer};
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn connected_pair() -> (UdpApiBackend, UdpApiBackend) {
+        let (a_id, b_id) = ("1".to_string(), "2".to_string());
+
+        let mut a = UdpApiBackend::bind(&a_id, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut b = UdpApiBackend::bind(&b_id, "127.0.0.1:0".parse().unwrap()).unwrap();
+
+        a.add_peer(&b_id, b.local_addr().unwrap());
+        b.add_peer(&a_id, a.local_addr().unwrap());
+
+        (a, b)
+    }
+
+    fn wait_for_event(backend: &mut UdpApiBackend) -> NetworkEvent {
+        let start = Instant::now();
+
+        loop {
+            if let Some(event) = backend.next_event() {
+                return event;
+            }
+
+            assert!(start.elapsed() < TIMEOUT, "Timed out waiting for event");
+
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_input_is_received_by_peer() {
+        let (mut a, mut b) = connected_pair();
+
+        let input = PlayerInput {
+            right: true,
+            jump: true,
+            ..Default::default()
+        };
+
+        a.dispatch_message(NetworkMessage::UpdatePlayerInput {
+            player_id: a.local_player_id(),
+            frame: 7,
+            input,
+        })
+        .unwrap();
+
+        match wait_for_event(&mut b) {
+            NetworkEvent::PlayerInput {
+                player_id,
+                frame,
+                input: received,
+            } => {
+                assert_eq!(player_id, "1");
+                assert_eq!(frame, 7);
+                assert_eq!(received, input);
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_ping_is_answered_by_peer() {
+        let (mut a, mut b) = connected_pair();
+
+        a.dispatch_message(NetworkMessage::Ping {
+            player_id: a.local_player_id(),
+            timestamp: 1234,
+        })
+        .unwrap();
+
+        // The pong is sent when the ping is read from the socket, and it does not produce an
+        // event on the receiving end
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(50) {
+            assert!(b.next_event().is_none());
+        }
+
+        match wait_for_event(&mut a) {
+            NetworkEvent::Pong {
+                player_id,
+                timestamp,
+            } => {
+                assert_eq!(player_id, "2");
+                assert_eq!(timestamp, 1234);
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_lockstep_stalls_until_remote_input_arrives() {
+        let (mut a, mut b) = connected_pair();
+
+        let player_ids = vec![a.local_player_id(), b.local_player_id()];
+
+        let mut a_buffer = InputBuffer::new(&player_ids, 2);
+        let mut b_buffer = InputBuffer::new(&player_ids, 2);
+
+        for _ in 0..2 {
+            assert!(a_buffer.advance().is_some());
+            assert!(b_buffer.advance().is_some());
+        }
+
+        let frame = a_buffer.current_frame();
+        assert_eq!(frame, 2);
+
+        let a_input = PlayerInput {
+            left: true,
+            ..Default::default()
+        };
+
+        a_buffer.insert(&a.local_player_id(), frame, a_input);
+
+        // Only local input is available, so the simulation should not be able to advance
+        assert!(!a_buffer.is_frame_ready());
+        assert!(a_buffer.advance().is_none());
+
+        b.dispatch_message(NetworkMessage::UpdatePlayerInput {
+            player_id: b.local_player_id(),
+            frame,
+            input: PlayerInput::default(),
+        })
+        .unwrap();
+
+        if let NetworkEvent::PlayerInput {
+            player_id,
+            frame,
+            input,
+        } = wait_for_event(&mut a)
+        {
+            a_buffer.insert(&player_id, frame, input);
+        }
+
+        let inputs = a_buffer.advance().unwrap();
+
+        assert_eq!(inputs.get(&a.local_player_id()), Some(&a_input));
+        assert_eq!(a_buffer.current_frame(), 3);
+    }
+
+    #[test]
+    fn test_lockstep_survives_packet_loss_and_reordering() {
+        const FRAME_CNT: u64 = 200;
+
+        let (mut a, mut b) = connected_pair();
+
+        a.set_link_conditioner(Some(LinkConditioner::new(0.3, 0.2, 1)));
+        b.set_link_conditioner(Some(LinkConditioner::new(0.3, 0.2, 2)));
+
+        let player_ids = vec![a.local_player_id(), b.local_player_id()];
+
+        let input = |player_id: &PlayerId, frame: u64| PlayerInput {
+            left: frame % 2 == 1,
+            right: frame % 5 == 1,
+            jump: frame % 7 == 1,
+            fire: player_id == "1",
+            ..Default::default()
+        };
+
+        let mut backends = [(a, Vec::new()), (b, Vec::new())];
+        let mut buffers = [
+            InputBuffer::new(&player_ids, 2),
+            InputBuffer::new(&player_ids, 2),
+        ];
+
+        let start = Instant::now();
+
+        while buffers
+            .iter()
+            .any(|buffer| buffer.current_frame() < FRAME_CNT)
+        {
+            assert!(start.elapsed() < TIMEOUT * 5, "Timed out running lockstep");
+
+            for ((backend, simulated), buffer) in backends.iter_mut().zip(buffers.iter_mut()) {
+                let player_id = backend.local_player_id();
+
+                while let Some(event) = backend.next_event() {
+                    if let NetworkEvent::PlayerInput {
+                        player_id,
+                        frame,
+                        input,
+                    } = event
+                    {
+                        buffer.insert(&player_id, frame, input);
+                    }
+                }
+
+                if buffer.current_frame() >= FRAME_CNT {
+                    continue;
+                }
+
+                // The delay is constant, so this is always a single frame
+                let input = input(&player_id, buffer.local_input_frame());
+
+                for frame in buffer.insert_local(&player_id, input) {
+                    backend
+                        .dispatch_message(NetworkMessage::UpdatePlayerInput {
+                            player_id: player_id.clone(),
+                            frame,
+                            input,
+                        })
+                        .unwrap();
+                }
+
+                if let Some(inputs) = buffer.advance() {
+                    simulated.push(inputs);
+                }
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let (a_simulated, b_simulated) = (&backends[0].1, &backends[1].1);
+
+        assert_eq!(a_simulated.len(), FRAME_CNT as usize);
+        assert_eq!(a_simulated, b_simulated);
+
+        for (frame, inputs) in a_simulated.iter().enumerate().skip(2) {
+            for player_id in &player_ids {
+                assert_eq!(inputs[player_id], input(player_id, frame as u64));
+            }
+        }
+    }
+
+    #[test]
+    fn test_spectator_receives_confirmed_input_from_the_start() {
+        const FRAME_CNT: u64 = 300;
+
+        let (host_id, spectator_id) = ("1".to_string(), "3".to_string());
+        let player_ids = vec![host_id.clone(), "2".to_string()];
+
+        let mut host = UdpApiBackend::bind(&host_id, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let mut spectator =
+            UdpApiBackend::bind(&spectator_id, "127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let input = |frame: u64| PlayerInput {
+            jump: frame % 3 == 1,
+            ..Default::default()
+        };
+
+        let dispatch_frame = |host: &mut UdpApiBackend, frame: u64| {
+            host.dispatch_message(NetworkMessage::SpectatorInputs {
+                player_id: host_id.clone(),
+                start_frame: frame,
+                player_ids: player_ids.clone(),
+                inputs: vec![input(frame), PlayerInput::default()],
+            })
+            .unwrap();
+        };
+
+        // The spectator joins after the first half of the match
+        for frame in 0..FRAME_CNT / 2 {
+            dispatch_frame(&mut host, frame);
+        }
+
+        host.add_spectator(&spectator_id, spectator.local_addr().unwrap());
+        spectator.add_peer(&host_id, host.local_addr().unwrap());
+
+        for frame in FRAME_CNT / 2..FRAME_CNT {
+            dispatch_frame(&mut host, frame);
+        }
+
+        let mut input_buffer = InputBuffer::new(&player_ids, 0);
+        let mut received = Vec::new();
+
+        let start = Instant::now();
+
+        while received.len() < FRAME_CNT as usize {
+            assert!(start.elapsed() < TIMEOUT, "Timed out waiting for input");
+
+            host.next_event();
+
+            while let Some(event) = spectator.next_event() {
+                if let NetworkEvent::PlayerInput {
+                    player_id,
+                    frame,
+                    input,
+                } = event
+                {
+                    input_buffer.insert(&player_id, frame, input);
+                }
+            }
+
+            while let Some(inputs) = input_buffer.advance() {
+                received.push(inputs[&host_id]);
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let expected = (0..FRAME_CNT).map(input).collect::<Vec<_>>();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_dropped_player_rejoins_through_lossy_resync() {
+        let (mut host, mut client) = connected_pair();
+
+        let (host_id, client_id) = (host.local_player_id(), client.local_player_id());
+
+        let input = |frame: u64| PlayerInput {
+            jump: frame % 3 == 1,
+            ..Default::default()
+        };
+
+        let dispatch_input = |backend: &mut UdpApiBackend, frame: u64| {
+            let message = NetworkMessage::UpdatePlayerInput {
+                player_id: backend.local_player_id(),
+                frame,
+                input: input(frame),
+            };
+
+            backend.dispatch_message(message).unwrap();
+        };
+
+        // The client plays for a few frames, before it drops
+        for frame in 0..10 {
+            dispatch_input(&mut client, frame);
+        }
+
+        for _ in 0..10 {
+            wait_for_event(&mut host);
+        }
+
+        host.set_link_conditioner(Some(LinkConditioner::new(0.3, 0.2, 1)));
+        client.set_link_conditioner(Some(LinkConditioner::new(0.3, 0.2, 2)));
+
+        let data = (0..20_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        host.dispatch_message(NetworkMessage::PlayerIdle {
+            player_id: client_id.clone(),
+            start_frame: 10,
+            end_frame: Some(40),
+        })
+        .unwrap();
+
+        host.resync_player(&client_id, 30, &data).unwrap();
+
+        let mut resync = None;
+        let mut idle = None;
+
+        let mut host_received = Vec::new();
+        let mut client_received = Vec::new();
+
+        let start = Instant::now();
+
+        while host_received.len() < 5 || client_received.len() < 5 {
+            assert!(start.elapsed() < TIMEOUT * 5, "Timed out rejoining");
+
+            while let Some(event) = host.next_event() {
+                if let NetworkEvent::PlayerInput { frame, .. } = event {
+                    host_received.push(frame);
+                }
+            }
+
+            while let Some(event) = client.next_event() {
+                match event {
+                    NetworkEvent::Resync { frame, data } => {
+                        assert!(resync.is_none(), "Resync was received twice");
+                        resync = Some((frame, data));
+
+                        // Both players go on from the frames given by the host
+                        for frame in 30..35 {
+                            dispatch_input(&mut host, frame);
+                        }
+
+                        for frame in 40..45 {
+                            dispatch_input(&mut client, frame);
+                        }
+                    }
+                    NetworkEvent::PlayerIdle {
+                        player_id,
+                        start_frame,
+                        end_frame,
+                    } => idle = Some((player_id, start_frame, end_frame)),
+                    NetworkEvent::PlayerInput {
+                        player_id, frame, ..
+                    } => {
+                        assert_eq!(player_id, host_id);
+                        client_received.push(frame);
+                    }
+                    _ => {}
+                }
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(resync, Some((30, data)));
+        assert_eq!(idle, Some((client_id, 10, Some(40))));
+
+        host_received.sort_unstable();
+        client_received.sort_unstable();
+
+        assert_eq!(host_received, (40..45).collect::<Vec<_>>());
+        assert_eq!(client_received, (30..35).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_state_description_is_sent_on_request() {
+        let (mut a, mut b) = connected_pair();
+
+        a.dispatch_message(NetworkMessage::StateDescriptionRequest {
+            player_id: a.local_player_id(),
+            frame: 12,
+        })
+        .unwrap();
+
+        match wait_for_event(&mut b) {
+            NetworkEvent::StateDescriptionRequested { player_id, frame } => {
+                assert_eq!(player_id, "1");
+                assert_eq!(frame, 12);
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+
+        let data = (0..5_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        for chunk in state_description_chunks(&b.local_player_id(), 12, &data) {
+            b.dispatch_message(chunk).unwrap();
+        }
+
+        match wait_for_event(&mut a) {
+            NetworkEvent::StateDescription {
+                player_id,
+                frame,
+                data: received,
+            } => {
+                assert_eq!(player_id, "2");
+                assert_eq!(frame, 12);
+                assert_eq!(received, data);
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
+}
@@ -2,6 +2,7 @@
 // Ported from https://github.com/josephg/noisejs/blob/master/perlin.js
 // This is all magic to me, don't ask me anything about it
 
+#[derive(Clone)]
 pub struct NoiseGenerator {
     grad_p: [(i32, i32, i32); 512],
     perm: [usize; 512],
@@ -0,0 +1,97 @@
+//! A small, seedable, random number generator. Unlike the global macroquad generator, every
+//! instance holds its own state, so it can be stored in the simulation, where it will produce the
+//! same sequence on every peer, given the same seed.
+//! The algorithm is SplitMix64, which is fast and requires no warm-up for any seed, including zero.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value in the range `0.0..1.0`
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns a value in the range `low..high`, mirroring `macroquad::rand::gen_range`
+    pub fn gen_range<T: RandomRange>(&mut self, low: T, high: T) -> T {
+        T::gen_range(self, low, high)
+    }
+}
+
+pub trait RandomRange {
+    fn gen_range(rng: &mut Rng, low: Self, high: Self) -> Self;
+}
+
+impl RandomRange for f32 {
+    fn gen_range(rng: &mut Rng, low: f32, high: f32) -> f32 {
+        low + (high - low) * rng.next_f32()
+    }
+}
+
+macro_rules! impl_random_range {
+    ($($t:ty),*) => {
+        $(impl RandomRange for $t {
+            fn gen_range(rng: &mut Rng, low: $t, high: $t) -> $t {
+                if high <= low {
+                    return low;
+                }
+
+                let range = (high as i128 - low as i128) as u64;
+                (low as i128 + (rng.next_u64() % range) as i128) as $t
+            }
+        })*
+    };
+}
+
+impl_random_range!(i32, u32, i64, u64, usize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_gives_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let mut c = Rng::new(43);
+
+        let a_values = (0..16).map(|_| a.next_u64()).collect::<Vec<_>>();
+        let b_values = (0..16).map(|_| b.next_u64()).collect::<Vec<_>>();
+        let c_values = (0..16).map(|_| c.next_u64()).collect::<Vec<_>>();
+
+        assert_eq!(a_values, b_values);
+        assert_ne!(a_values, c_values);
+    }
+
+    #[test]
+    fn test_gen_range_is_within_bounds() {
+        let mut rng = Rng::new(0);
+
+        for _ in 0..1000 {
+            let i = rng.gen_range(-3, 3);
+            assert!((-3..3).contains(&i));
+
+            let f = rng.gen_range(-1.5, 2.5);
+            assert!((-1.5..2.5).contains(&f));
+        }
+
+        assert_eq!(rng.gen_range(5usize, 5), 5);
+    }
+}
@@ -1,6 +1,6 @@
 use macroquad::prelude::*;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Transform {
     pub position: Vec2,
     pub rotation: f32,
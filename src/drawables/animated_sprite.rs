@@ -411,7 +411,7 @@ pub fn debug_draw_one_animated_sprite(position: Vec2, sprite: &AnimatedSprite) {
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct AnimatedSpriteSet {
     pub draw_order: Vec<String>,
     pub map: HashMap<String, AnimatedSprite>,
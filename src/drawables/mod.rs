@@ -13,6 +13,7 @@ use core::Transform;
 
 /// This is a wrapper type for all the different types of drawable sprites, used so that we can
 /// access them all in one query and draw them, ordered, in one pass, according to `draw_order`.
+#[derive(Clone)]
 pub struct Drawable {
     /// This is used to specify draw order on a sprite
     /// This will be used, primarily, by `Player` to draw equipped items in the right order, relative
@@ -122,6 +123,7 @@ impl Drawable {
     }
 }
 
+#[derive(Clone)]
 pub enum DrawableKind {
     Sprite(Sprite),
     SpriteSet(SpriteSet),
@@ -228,7 +228,7 @@ pub fn debug_draw_one_sprite(position: Vec2, sprite: &Sprite) {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SpriteSet {
     pub draw_order: Vec<String>,
     pub map: HashMap<String, Sprite>,
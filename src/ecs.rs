@@ -3,6 +3,7 @@ use hecs::{Entity, World};
 pub type SystemFn = fn(&mut World);
 
 /// This is used as a component to signify ownership
+#[derive(Clone)]
 pub struct Owner(pub Entity);
 
 /// Placeholder until we implement threading
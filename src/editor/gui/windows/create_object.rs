@@ -7,7 +7,7 @@ use macroquad::{
 use crate::editor::gui::combobox::ComboBoxVec;
 use crate::{
     editor::gui::{ComboBoxBuilder, ComboBoxValue},
-    map::{Map, MapObjectKind},
+    map::{Map, MapObjectKind, CAPTURE_ZONE_ID, FLAG_BASE_ID},
     Resources,
 };
 
@@ -132,7 +132,7 @@ impl Window for CreateObjectWindow {
                 .keys()
                 .map(|k| k.as_str())
                 .collect::<Vec<&str>>(),
-            MapObjectKind::Environment => vec!["sproinger"],
+            MapObjectKind::Environment => vec!["sproinger", CAPTURE_ZONE_ID, FLAG_BASE_ID],
             MapObjectKind::Decoration => resources
                 .decoration
                 .keys()
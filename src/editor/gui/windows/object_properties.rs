@@ -5,7 +5,10 @@ use macroquad::{
 };
 
 use crate::editor::gui::combobox::ComboBoxVec;
-use crate::map::MapObject;
+use crate::map::{
+    capture_zone_size, flag_base_team, MapObject, MapProperty, CAPTURE_ZONE_ID,
+    CAPTURE_ZONE_SIZE_PROPERTY, FLAG_BASE_ID, FLAG_BASE_TEAM_PROPERTY,
+};
 use crate::{
     editor::gui::{ComboBoxBuilder, ComboBoxValue},
     map::{Map, MapObjectKind},
@@ -55,6 +58,7 @@ impl Window for ObjectPropertiesWindow {
                 id: object.id.clone(),
                 kind: object.kind,
                 position: object.position,
+                properties: Some(object.properties.clone()),
             });
 
             action = Some(batch);
@@ -146,7 +150,7 @@ impl Window for ObjectPropertiesWindow {
                 .keys()
                 .map(|k| k.as_str())
                 .collect::<Vec<&str>>(),
-            MapObjectKind::Environment => vec!["sproinger"],
+            MapObjectKind::Environment => vec!["sproinger", CAPTURE_ZONE_ID, FLAG_BASE_ID],
             MapObjectKind::Decoration => resources
                 .decoration
                 .keys()
@@ -171,6 +175,64 @@ impl Window for ObjectPropertiesWindow {
 
         object.id = item_id_value.get_value();
 
+        if object.kind == MapObjectKind::Environment && object.id == CAPTURE_ZONE_ID {
+            let size = vec2(72.0, 28.0);
+
+            let zone_size = capture_zone_size(&object);
+
+            let mut width_str = format!("{:.1}", zone_size.x);
+            let mut height_str = format!("{:.1}", zone_size.y);
+
+            ui.separator();
+
+            ui.label(None, "Size");
+
+            widgets::InputText::new(hash!(id, "size_x_input"))
+                .size(size)
+                .ui(ui, &mut width_str);
+
+            ui.same_line(0.0);
+
+            ui.label(None, "x");
+
+            ui.same_line(0.0);
+
+            widgets::InputText::new(hash!(id, "size_y_input"))
+                .size(size)
+                .ui(ui, &mut height_str);
+
+            let width = width_str.parse::<f32>().unwrap_or(zone_size.x).max(1.0);
+            let height = height_str.parse::<f32>().unwrap_or(zone_size.y).max(1.0);
+
+            object.properties.insert(
+                CAPTURE_ZONE_SIZE_PROPERTY.to_string(),
+                MapProperty::Vec2(vec2(width, height)),
+            );
+        }
+
+        if object.kind == MapObjectKind::Environment && object.id == FLAG_BASE_ID {
+            let team = flag_base_team(&object);
+
+            // Teams are shown starting at `1`, like in the rest of the game
+            let mut team_str = format!("{}", team + 1);
+
+            ui.separator();
+
+            widgets::InputText::new(hash!(id, "team_input"))
+                .ratio(0.8)
+                .label("Team")
+                .ui(ui, &mut team_str);
+
+            let team = team_str
+                .parse::<i32>()
+                .map(|team| (team - 1).max(0))
+                .unwrap_or(team as i32);
+
+            object
+                .properties
+                .insert(FLAG_BASE_TEAM_PROPERTY.to_string(), MapProperty::Int(team));
+        }
+
         self.object = Some(object);
 
         None
@@ -50,8 +50,11 @@ use crate::editor::gui::windows::{
 use crate::editor::input::{collect_editor_input, EditorInput};
 use crate::editor::tools::SpawnPointPlacementTool;
 use crate::gui::SELECTION_HIGHLIGHT_COLOR;
-use crate::map::{MapObject, MapObjectKind};
-use crate::player::IDLE_ANIMATION_ID;
+use crate::map::{
+    capture_zone_size, flag_base_team, MapObject, MapObjectKind, CAPTURE_ZONE_ID, FLAG_BASE_HEIGHT,
+    FLAG_BASE_ID, FLAG_BASE_WIDTH,
+};
+use crate::player::{IDLE_ANIMATION_ID, TEAM_COLORS};
 use macroquad::{
     color,
     experimental::{
@@ -526,8 +529,10 @@ impl Editor {
                 id,
                 kind,
                 position,
+                properties,
             } => {
-                let action = UpdateObjectAction::new(layer_id, index, id, kind, position);
+                let action =
+                    UpdateObjectAction::new(layer_id, index, id, kind, position, properties);
                 res = self
                     .history
                     .apply(Box::new(action), &mut self.map_resource.map);
@@ -1080,6 +1085,7 @@ impl Node for Editor {
                         index,
                         layer_id,
                         position,
+                        properties: None,
                     };
 
                     node.apply_action(action);
@@ -1476,6 +1482,45 @@ impl Node for Editor {
                                                 ..Default::default()
                                             },
                                         );
+                                    } else if object.id == CAPTURE_ZONE_ID {
+                                        let size = capture_zone_size(object);
+
+                                        draw_rectangle(
+                                            object_position.x,
+                                            object_position.y,
+                                            size.x,
+                                            size.y,
+                                            Color::new(1.0, 1.0, 1.0, 0.15),
+                                        );
+
+                                        draw_rectangle_lines(
+                                            object_position.x,
+                                            object_position.y,
+                                            size.x,
+                                            size.y,
+                                            2.0,
+                                            color::WHITE,
+                                        );
+                                    } else if object.id == FLAG_BASE_ID {
+                                        let team = flag_base_team(object);
+                                        let color = TEAM_COLORS[team as usize % TEAM_COLORS.len()];
+
+                                        draw_rectangle(
+                                            object_position.x,
+                                            object_position.y,
+                                            FLAG_BASE_WIDTH,
+                                            FLAG_BASE_HEIGHT,
+                                            Color::new(color.r, color.g, color.b, 0.2),
+                                        );
+
+                                        draw_rectangle_lines(
+                                            object_position.x,
+                                            object_position.y,
+                                            FLAG_BASE_WIDTH,
+                                            FLAG_BASE_HEIGHT,
+                                            2.0,
+                                            color,
+                                        );
                                     } else {
                                         label = Some("INVALID OBJECT ID".to_string());
                                     }
@@ -1607,6 +1652,10 @@ fn get_object_size(object: &MapObject) -> Vec2 {
             if &object.id == "sproinger" {
                 let texture_res = resources.textures.get("sproinger").unwrap();
                 res = texture_res.meta.frame_size;
+            } else if object.id == CAPTURE_ZONE_ID {
+                res = Some(capture_zone_size(object));
+            } else if object.id == FLAG_BASE_ID {
+                res = Some(vec2(FLAG_BASE_WIDTH, FLAG_BASE_HEIGHT));
             } else {
                 label = Some("INVALID OBJECT ID".to_string())
             }
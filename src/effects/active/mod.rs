@@ -20,6 +20,7 @@ pub use triggered::{TriggeredEffectMetadata, TriggeredEffectTrigger};
 
 use crate::effects::active::projectiles::{spawn_projectile, ProjectileParams};
 use crate::effects::active::triggered::{spawn_triggered_effect, TriggeredEffect};
+use crate::game::{can_damage, simulation_gen_range};
 use crate::particles::ParticleEmitterMetadata;
 use crate::player::{on_player_damage, Player};
 use crate::PhysicsBody;
@@ -28,12 +29,14 @@ pub use projectiles::ProjectileKind;
 
 const COLLIDER_DEBUG_DRAW_TTL: f32 = 0.5;
 
-struct CircleCollider {
+#[derive(Clone)]
+pub struct CircleCollider {
     r: f32,
     ttl_timer: f32,
 }
 
-struct RectCollider {
+#[derive(Clone)]
+pub struct RectCollider {
     w: f32,
     h: f32,
     ttl_timer: f32,
@@ -52,9 +55,9 @@ pub fn spawn_active_effect(
 
     if let Some(id) = &params.sound_effect_id {
         let resources = storage::get::<Resources>();
-        let sound = resources.sounds.get(id).unwrap();
-
-        play_sound_once(*sound);
+        if let Some(sound) = resources.sounds.get(id) {
+            play_sound_once(*sound);
+        }
     }
 
     let mut damage = Vec::new();
@@ -82,8 +85,11 @@ pub fn spawn_active_effect(
             for (e, (transform, body)) in world.query::<(&Transform, &PhysicsBody)>().iter() {
                 let other_rect = body.as_rect(transform.position);
                 if circle.overlaps_rect(&other_rect) {
+                    // This has to be checked before the player is borrowed mutably
+                    let is_damage_allowed = can_damage(world, owner, e);
+
                     if let Ok(mut player) = world.get_mut::<Player>(e) {
-                        if is_explosion || e != owner {
+                        if (is_explosion || e != owner) && is_damage_allowed {
                             if is_lethal {
                                 damage.push((owner, e));
                             }
@@ -128,10 +134,17 @@ pub fn spawn_active_effect(
                 ));
             }
 
+            let protected = world
+                .query::<&Player>()
+                .iter()
+                .map(|(e, _)| e)
+                .filter(|e| !can_damage(world, owner, *e))
+                .collect::<Vec<_>>();
+
             for (e, (transform, player, body)) in
                 world.query_mut::<(&Transform, &mut Player, &PhysicsBody)>()
             {
-                if owner != e {
+                if owner != e && !protected.contains(&e) {
                     let other_rect = body.as_rect(transform.position);
                     if rect.overlaps(&other_rect) {
                         if is_lethal {
@@ -167,7 +180,7 @@ pub fn spawn_active_effect(
 
             if spread != 0.0 {
                 let rad = deg_to_rad(spread);
-                let spread = rand::gen_range(-rad, rad);
+                let spread = simulation_gen_range(world, -rad, rad);
 
                 velocity = rotate_vector(velocity, spread);
             }
@@ -9,6 +9,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::effects::active::triggered::TriggeredEffect;
 use crate::effects::TriggeredEffectTrigger;
+use crate::game::can_damage;
 use crate::particles::{ParticleEmitter, ParticleEmitterMetadata};
 use crate::player::{on_player_damage, Player, PlayerState};
 use crate::{CollisionWorld, PhysicsBody, Resources, RigidBody, RigidBodyParams, SpriteMetadata};
@@ -41,6 +42,7 @@ pub enum ProjectileKind {
     },
 }
 
+#[derive(Clone)]
 pub struct Projectile {
     pub kind: ProjectileKind,
     pub owner: Entity,
@@ -234,8 +236,11 @@ pub fn fixed_update_projectiles(world: &mut World) {
         let rect = body.as_rect(transform.position);
         for (other, other_rect) in &bodies {
             if rect.overlaps(other_rect) {
+                // Projectiles pass through players that the owner can not damage
+                let is_damage_allowed = can_damage(world, projectile.owner, *other);
+
                 if let Ok(mut player) = world.get_mut::<Player>(*other) {
-                    if player.state != PlayerState::Dead {
+                    if player.state != PlayerState::Dead && is_damage_allowed {
                         for meta in projectile.passive_effects.clone().into_iter() {
                             let effect_instance = PassiveEffectInstance::new(None, meta);
 
@@ -9,6 +9,7 @@ use core::math::deg_to_rad;
 use core::{Result, Transform};
 
 use crate::effects::active::spawn_active_effect;
+use crate::game::{can_damage, get_simulation_dt};
 use crate::particles::{ParticleEmitter, ParticleEmitterMetadata};
 use crate::physics;
 use crate::player::{Player, PlayerState};
@@ -33,6 +34,7 @@ pub enum TriggeredEffectTrigger {
     Projectile,
 }
 
+#[derive(Clone)]
 pub struct TriggeredEffect {
     pub owner: Entity,
     pub trigger: Vec<TriggeredEffectTrigger>,
@@ -157,7 +159,7 @@ const KICK_FORCE: f32 = 15.0;
 const KICK_DELAY: f32 = 0.22;
 
 pub fn fixed_update_triggered_effects(world: &mut World) {
-    let dt = get_frame_time();
+    let dt = get_simulation_dt(world);
 
     let mut to_trigger = Vec::new();
 
@@ -216,7 +218,13 @@ pub fn fixed_update_triggered_effects(world: &mut World) {
                     || (!can_be_triggered_by_player && !effect.is_kickable);
 
                 'players: for (pe, is_facing_left, position, size) in players.clone() {
-                    if !should_exclude_owner || pe != effect.owner {
+                    // Triggers that only enemies can set off are not set off by players that the
+                    // owner can not damage
+                    let is_protected = !can_be_triggered_by_player
+                        && pe != effect.owner
+                        && !can_damage(world, effect.owner, pe);
+
+                    if (!should_exclude_owner || pe != effect.owner) && !is_protected {
                         let player_collider = Rect::new(position.x, position.y, size.x, size.y);
 
                         if collider.overlaps(&player_collider) {
@@ -42,6 +42,7 @@ pub fn init_passive_effects() {
     );
 }
 
+#[derive(Clone)]
 pub struct PassiveEffectInstance {
     pub name: String,
     pub function: Option<PassiveEffectFn>,
@@ -24,6 +24,8 @@ pub enum ApplicationEvent {
     ReloadResources,
     /// Exit to main menu
     MainMenu,
+    /// The match has ended, and its `MatchResult` has been stored
+    MatchEnded,
     /// Quit to desktop
     Quit,
 }
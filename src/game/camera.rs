@@ -1,8 +1,9 @@
 use macroquad::prelude::*;
-use macroquad::rand::gen_range;
 
 use core::noise::NoiseGenerator;
+use core::random::Rng;
 
+#[derive(Clone)]
 struct Shake {
     direction: (f32, f32),
     kind: ShakeType,
@@ -13,6 +14,7 @@ struct Shake {
     frequency: f32, // 1 is pretty standard, .2 is a punch (with 10 frames of shake it oscillates about max twice). With .5 it's more of a rumble
 }
 
+#[derive(Clone)]
 #[allow(dead_code)]
 enum ShakeType {
     Noise,
@@ -20,21 +22,27 @@ enum ShakeType {
     Rotational,
 }
 
+#[derive(Clone)]
 pub struct GameCamera {
     bounds: Rect,
     follow_buffer: Vec<(Vec2, f32)>,
     shake: Vec<Shake>,
     noisegen: NoiseGenerator,
     noisegen_position: f32,
+    /// This is seeded per match, so that shakes will not advance any other RNG
+    rng: Rng,
 
     pub manual: Option<(Vec2, f32)>,
-    player_rects: Vec<Rect>,
+    /// If this is set, the camera will only follow the player with this index, as long as that
+    /// player is in the game
+    pub followed_player: Option<u8>,
+    player_rects: Vec<(u8, Rect)>,
 }
 
 impl GameCamera {
     const BUFFER_CAPACITY: usize = 20;
 
-    pub fn new(map_size: Vec2) -> GameCamera {
+    pub fn new(map_size: Vec2, seed: u64) -> GameCamera {
         let bounds = Rect::new(0.0, 0.0, map_size.x, map_size.y);
 
         GameCamera {
@@ -42,14 +50,21 @@ impl GameCamera {
             follow_buffer: vec![],
             shake: vec![],
             manual: None,
+            followed_player: None,
             noisegen: NoiseGenerator::new(5),
             noisegen_position: 5.0,
+            rng: Rng::new(seed),
             player_rects: Vec::new(),
         }
     }
 
-    pub fn add_player_rect(&mut self, rect: Rect) {
-        self.player_rects.push(rect);
+    pub fn add_player_rect(&mut self, index: u8, rect: Rect) {
+        self.player_rects.push((index, rect));
+    }
+
+    /// The position and zoom that the camera is currently centered on, before any shake is applied
+    pub fn view(&self) -> Option<(Vec2, f32)> {
+        self.follow_buffer.first().copied()
     }
 }
 
@@ -62,7 +77,7 @@ impl GameCamera {
             magnitude,
             length: length as f32,
             age: 0.0,
-            random_offset: rand::gen_range(1.0, 100.0),
+            random_offset: self.rng.gen_range(1.0, 100.0),
             frequency,
         });
     }
@@ -80,7 +95,7 @@ impl GameCamera {
             magnitude,
             length: length as f32,
             age: 0.0,
-            random_offset: rand::gen_range(1.0, 100.0),
+            random_offset: self.rng.gen_range(1.0, 100.0),
             frequency,
         });
     }
@@ -101,7 +116,7 @@ impl GameCamera {
         self.shake.push(Shake {
             direction: (1.0, 1.0),
             kind: ShakeType::Rotational,
-            magnitude: magnitude * (gen_range(0, 2) as f32 - 0.5) * 2.0,
+            magnitude: magnitude * (self.rng.gen_range(0, 2) as f32 - 0.5) * 2.0,
             length: length as f32,
             age: 0.0,
             random_offset: 0.0,
@@ -174,8 +189,19 @@ impl GameCamera {
             let mut min = vec2(10000.0, 10000.0);
             let mut max = vec2(-10000.0, -10000.0);
 
+            let is_following_one = self
+                .followed_player
+                .map(|index| self.player_rects.iter().any(|(i, _)| *i == index))
+                .unwrap_or_default();
+
+            if is_following_one {
+                let followed_player = self.followed_player;
+                self.player_rects
+                    .retain(|(index, _)| Some(*index) == followed_player);
+            }
+
             let player_cnt = self.player_rects.len();
-            for rect in self.player_rects.drain(0..player_cnt) {
+            for (_, rect) in self.player_rects.drain(0..player_cnt) {
                 let camera_pox_middle = rect.point() + rect.size() / 2.0;
                 //let k = if player.controller_id == 1 { 0.8 } else { 0.2 };
                 middle_point += camera_pox_middle; // * k;
@@ -204,6 +230,13 @@ impl GameCamera {
             if let Some((override_target, override_zoom)) = self.manual {
                 middle_point = override_target;
                 zoom = override_zoom;
+            } else if player_cnt == 0 {
+                // With no players to follow, like when all of them have been eliminated, the
+                // camera stays where it is
+                if let Some((last_target, last_zoom)) = self.view() {
+                    middle_point = last_target;
+                    zoom = last_zoom;
+                }
             }
 
             self.follow_buffer.insert(0, (middle_point, zoom));
@@ -0,0 +1,397 @@
+//! A runner for matches without a window or an audio device, for gameplay tests. The match is
+//! rebuilt from a `Replay`, which holds the input of every player, for every frame, so the input
+//! can be recorded in a match, or scripted with `InputTrack`. Only the fixed updates are run, as
+//! the simulation of a deterministic match is fully contained in them, and everything else, like
+//! animations, particles and the camera, depends on a window.
+//! Resources, the map and the collision world are kept in global storage, so only one headless
+//! game can exist at a time. Creating one will block until any other headless game is dropped,
+//! which keeps tests that run in parallel from sharing the same storage.
+//! This is built for tests, and with the `headless` feature, which replaces the game with a runner
+//! that plays the replays given on the command line, and prints the outcome of each.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+
+use hecs::{Entity, World};
+
+use serde::{Deserialize, Serialize};
+
+use core::{Result, Transform};
+
+use crate::game::{get_match_result, get_match_rules_mut, get_simulation_mut, Game, Replay};
+use crate::items::{Item, Weapon};
+use crate::player::{Player, PlayerInventory};
+use crate::{PhysicsBody, Resources};
+
+static HEADLESS_LOCK: Mutex<()> = Mutex::new(());
+
+/// The assets dir of the repository, which is what gameplay tests should load resources from
+pub fn default_assets_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("assets")
+}
+
+pub struct HeadlessGame {
+    game: Game,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl HeadlessGame {
+    /// Load the resources of the assets dir, without sounds or textures, and rebuild the match of
+    /// the replay. The replay controllers will feed the players their input from frame `0`.
+    pub fn new<P: AsRef<Path>>(assets_dir: P, replay: &Replay) -> Result<Self> {
+        // A test that panics will poison the lock, but it is only used to keep games apart, so
+        // the tests that follow it can still go ahead
+        let guard = HEADLESS_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+
+        let resources = Resources::new_headless(assets_dir)?;
+        storage::store(resources);
+
+        let game = Game::from_replay(replay)?;
+
+        Ok(HeadlessGame {
+            game,
+            _guard: guard,
+        })
+    }
+
+    /// Run a number of fixed updates
+    pub fn run(&mut self, frame_cnt: u64) {
+        for _ in 0..frame_cnt {
+            self.game.fixed_updates.execute(&mut self.game.world);
+        }
+    }
+
+    /// Run fixed updates until the predicate returns `true`, checking it after every update, or
+    /// until `max_frame_cnt` updates have been run. Returns `true` if the predicate was met.
+    pub fn run_until<F>(&mut self, max_frame_cnt: u64, mut f: F) -> bool
+    where
+        F: FnMut(&World) -> bool,
+    {
+        for _ in 0..max_frame_cnt {
+            self.game.fixed_updates.execute(&mut self.game.world);
+
+            if f(&self.game.world) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// The amount of fixed updates that have been run
+    pub fn frame(&self) -> u64 {
+        get_simulation_mut(&self.game.world).frame
+    }
+
+    pub fn world(&self) -> &World {
+        &self.game.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.game.world
+    }
+
+    /// The entity of the player with the given index
+    pub fn player(&self, index: u8) -> Option<Entity> {
+        self.game
+            .world
+            .query::<&Player>()
+            .iter()
+            .find(|(_, player)| player.index == index)
+            .map(|(entity, _)| entity)
+    }
+
+    /// The state of the players and the scores on the current frame
+    pub fn snapshot(&self) -> GameSnapshot {
+        GameSnapshot::capture(&self.game.world)
+    }
+}
+
+/// Play a replay to its final frame, and return the outcome
+pub fn run_headless_replay<P: AsRef<Path>>(assets_dir: P, replay: &Replay) -> Result<GameSnapshot> {
+    let mut game = HeadlessGame::new(assets_dir, replay)?;
+    game.run(replay.frame_cnt());
+
+    Ok(game.snapshot())
+}
+
+/// The outcome of a match, as the state of the players and the scores on its final frame
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameSnapshot {
+    pub frame: u64,
+    pub players: Vec<PlayerSnapshot>,
+    pub scores: Vec<ScoreSnapshot>,
+    pub round: u32,
+    /// The side that won the match, if it is over
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub winner: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    pub index: u8,
+    pub state: String,
+    #[serde(with = "core::json::vec2_def")]
+    pub position: Vec2,
+    #[serde(with = "core::json::vec2_def")]
+    pub velocity: Vec2,
+    pub is_facing_left: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weapon: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScoreSnapshot {
+    pub index: u8,
+    pub kills: u32,
+    pub deaths: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lives: Option<u32>,
+    pub hold_time: f32,
+    pub captures: u32,
+}
+
+impl GameSnapshot {
+    pub fn capture(world: &World) -> Self {
+        let frame = get_simulation_mut(world).frame;
+
+        let mut players = world
+            .query::<(&Player, &Transform, &PhysicsBody, &PlayerInventory)>()
+            .iter()
+            .map(|(_, (player, transform, body, inventory))| {
+                let weapon = inventory.weapon.and_then(|entity| {
+                    world
+                        .get::<Weapon>(entity)
+                        .ok()
+                        .map(|weapon| weapon.id.clone())
+                });
+
+                let items = inventory
+                    .items
+                    .iter()
+                    .filter_map(|entity| {
+                        world.get::<Item>(*entity).ok().map(|item| item.id.clone())
+                    })
+                    .collect();
+
+                PlayerSnapshot {
+                    index: player.index,
+                    state: format!("{:?}", player.state),
+                    position: transform.position,
+                    velocity: body.velocity,
+                    is_facing_left: player.is_facing_left,
+                    weapon,
+                    items,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        players.sort_by_key(|player| player.index);
+
+        let (scores, round) = get_match_rules_mut(world)
+            .map(|rules| {
+                let scores = rules
+                    .scores
+                    .iter()
+                    .map(|(index, score)| ScoreSnapshot {
+                        index: *index,
+                        kills: score.kills,
+                        deaths: score.deaths,
+                        lives: score.lives,
+                        hold_time: score.hold_time,
+                        captures: score.captures,
+                    })
+                    .collect();
+
+                (scores, rules.round)
+            })
+            .unwrap_or_default();
+
+        let winner = get_match_result(world)
+            .and_then(|result| result.winner)
+            .map(|side| format!("{:?}", side));
+
+        GameSnapshot {
+            frame,
+            players,
+            scores,
+            round,
+            winner,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::input::{InputTrack, PlayerInput};
+
+    use crate::effects::active::spawn_active_effect;
+    use crate::effects::active::triggered::TriggeredEffect;
+    use crate::game::{MatchRulesParams, ReplayPlayer, WinCondition};
+    use crate::items::MapItemKind;
+    use crate::network::{describe_world, state_checksum};
+    use crate::player::PlayerState;
+    use crate::Map;
+
+    use super::*;
+
+    const MAP_NAME: &str = "lev01";
+
+    /// The frames that players are given to fall to the ground, after being moved
+    const SETTLE_FRAME_CNT: u64 = 60;
+
+    fn replay(players: Vec<ReplayPlayer>) -> Replay {
+        Replay::from_script(MAP_NAME, 0, MatchRulesParams::default(), players)
+    }
+
+    fn idle_player(index: u8) -> ReplayPlayer {
+        ReplayPlayer::new(index, "pescy", InputTrack::new())
+    }
+
+    /// Move a player to one of the spawn points of the map, as spawn points are picked at random
+    fn move_to_spawn_point(game: &mut HeadlessGame, index: u8, spawn_point: usize) {
+        let position = storage::get::<Map>().spawn_points[spawn_point];
+
+        let entity = game.player(index).unwrap();
+        game.world_mut()
+            .get_mut::<Transform>(entity)
+            .unwrap()
+            .position = position;
+    }
+
+    fn position_of(game: &HeadlessGame, index: u8) -> Vec2 {
+        let entity = game.player(index).unwrap();
+        game.world().get::<Transform>(entity).unwrap().position
+    }
+
+    fn state_of(world: &World, entity: Entity) -> PlayerState {
+        world.get::<Player>(entity).unwrap().state
+    }
+
+    #[test]
+    fn scripted_input_moves_player() {
+        let mut inputs = InputTrack::new();
+        inputs.push_for(PlayerInput::default(), SETTLE_FRAME_CNT);
+        inputs.push_for(
+            PlayerInput {
+                right: true,
+                ..Default::default()
+            },
+            30,
+        );
+
+        let replay = replay(vec![ReplayPlayer::new(0, "pescy", inputs), idle_player(1)]);
+
+        let mut game = HeadlessGame::new(default_assets_dir(), &replay).unwrap();
+
+        move_to_spawn_point(&mut game, 0, 1);
+        move_to_spawn_point(&mut game, 1, 2);
+
+        game.run(SETTLE_FRAME_CNT);
+
+        let start = position_of(&game, 0);
+        let idle_start = position_of(&game, 1);
+
+        game.run(30);
+
+        assert_eq!(game.frame(), SETTLE_FRAME_CNT + 30);
+        assert!(position_of(&game, 0).x > start.x);
+        assert_eq!(position_of(&game, 1).x, idle_start.x);
+    }
+
+    #[test]
+    fn grenade_kills_player_within_radius() {
+        let replay = replay(vec![idle_player(0), idle_player(1), idle_player(2)]);
+
+        let mut game = HeadlessGame::new(default_assets_dir(), &replay).unwrap();
+
+        // The thrower and the bystander are kept well outside of the radius of the explosion
+        move_to_spawn_point(&mut game, 0, 2);
+        move_to_spawn_point(&mut game, 1, 1);
+        move_to_spawn_point(&mut game, 2, 0);
+
+        game.run(SETTLE_FRAME_CNT);
+
+        let effect = {
+            let resources = storage::get::<Resources>();
+            match &resources.items.get("grenades").unwrap().kind {
+                MapItemKind::Weapon { meta } => meta.effects[0].clone(),
+                _ => panic!("Grenades should be a weapon"),
+            }
+        };
+
+        let thrower = game.player(0).unwrap();
+        let target = game.player(1).unwrap();
+        let bystander = game.player(2).unwrap();
+
+        let target_position = position_of(&game, 1);
+        spawn_active_effect(game.world_mut(), thrower, target_position, effect).unwrap();
+
+        // Keep the grenade from being thrown, so that it goes off at the feet of the target
+        for (_, (_, body)) in game
+            .world_mut()
+            .query_mut::<(&TriggeredEffect, &mut PhysicsBody)>()
+        {
+            body.velocity = Vec2::ZERO;
+        }
+
+        let is_killed = game.run_until(240, |world| state_of(world, target) == PlayerState::Dead);
+
+        assert!(is_killed, "The target should be killed by the explosion");
+        assert_ne!(state_of(game.world(), thrower), PlayerState::Dead);
+        assert_ne!(state_of(game.world(), bystander), PlayerState::Dead);
+    }
+
+    #[test]
+    fn same_seed_and_input_give_same_state() {
+        let scripted_player = |index: u8| {
+            let mut inputs = InputTrack::new();
+
+            for i in 0..60 {
+                let n = i + index as u64 * 7;
+
+                let input = PlayerInput {
+                    left: n % 4 == 1,
+                    right: n % 3 == 1,
+                    jump: n % 5 == 2,
+                    fire: n % 6 == 3,
+                    pickup: n % 7 == 4,
+                    ..Default::default()
+                };
+
+                inputs.push_for(input, 5);
+            }
+
+            ReplayPlayer::new(index, "pescy", inputs)
+        };
+
+        let replay = replay(vec![scripted_player(0), scripted_player(1)]);
+
+        let run = || {
+            let mut game = HeadlessGame::new(default_assets_dir(), &replay).unwrap();
+            game.run(replay.frame_cnt());
+
+            state_checksum(&describe_world(game.world()))
+        };
+
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn unsupported_win_condition_is_an_error() {
+        let rules = MatchRulesParams {
+            win_condition: WinCondition::CaptureTheFlag(3),
+            ..Default::default()
+        };
+
+        // Players that are not on a team can not capture flags
+        let replay = Replay::from_script(MAP_NAME, 0, rules, vec![idle_player(0), idle_player(1)]);
+
+        assert!(HeadlessGame::new(default_assets_dir(), &replay).is_err());
+    }
+}
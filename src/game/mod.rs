@@ -1,7 +1,42 @@
 mod camera;
+#[cfg(any(test, feature = "headless"))]
+mod headless;
 mod music;
+#[cfg(test)]
+mod regression;
+mod replay;
+mod rules;
+mod simulation;
+mod spectator;
+mod stats;
 
 pub use camera::GameCamera;
+#[cfg(any(test, feature = "headless"))]
+pub use headless::{
+    default_assets_dir, run_headless_replay, GameSnapshot, HeadlessGame, PlayerSnapshot,
+    ScoreSnapshot,
+};
+pub use replay::{
+    fixed_update_replay_controllers, list_replays, Replay, ReplayPlayback, ReplayPlayer,
+    REPLAYS_DIR_NAME, REPLAY_FILE_EXTENSION, REPLAY_FORMAT_VERSION,
+};
+pub use rules::{
+    can_damage, draw_match_hud, fixed_update_match_rules, get_match_result, get_match_rules_mut,
+    reset_round, spawn_match_rules, MatchResult, MatchRules, MatchRulesParams, PlayerScore, Side,
+    WinCondition, DEFAULT_CAPTURE_CNT, DEFAULT_HOLD_TIME, DEFAULT_KILL_CNT, DEFAULT_LIVES,
+    MATCH_END_DELAY, MAX_CAPTURE_CNT, MAX_HOLD_TIME, MAX_KILL_CNT, MAX_LIVES, ROUND_END_DELAY,
+};
+pub use simulation::{
+    fixed_update_simulation, get_simulation_dt, get_simulation_mut, simulation_gen_range,
+    spawn_simulation, Simulation, FIXED_DELTA_TIME,
+};
+pub use spectator::{
+    draw_eliminated_hud, draw_spectator_hud, update_eliminated_camera, update_spectator_camera,
+};
+pub use stats::{
+    collect_player_stats, record_item_picked_up, record_shot_fired, update_player_stats,
+    PlayerStats,
+};
 
 use fishsticks::{Button, GamepadContext};
 
@@ -12,32 +47,44 @@ use macroquad::ui::root_ui;
 
 use hecs::{Entity, World};
 
+use core::error::ErrorKind;
+use core::history::{MatchHistory, MatchRecord, PlayerRecord};
 use core::input::is_gamepad_btn_pressed;
-use core::Result;
+use core::network::PlayerId;
+use core::{formaterr, Result};
 
 use crate::debug;
 use crate::ecs::Scheduler;
 use crate::gui::{self, GAME_MENU_RESULT_MAIN_MENU, GAME_MENU_RESULT_QUIT};
 use crate::physics::{debug_draw_physics_bodies, fixed_update_physics_bodies};
 use crate::player::{
-    draw_weapons_hud, spawn_player, update_player_animations, update_player_camera_box,
-    update_player_controllers, update_player_events, update_player_inventory,
-    update_player_passive_effects, update_player_states, PlayerParams,
+    debug_draw_ai, draw_weapons_hud, fixed_update_ai_controllers, spawn_player,
+    update_player_animations, update_player_camera_box, update_player_controllers,
+    update_player_events, update_player_inventory, update_player_passive_effects,
+    update_player_states, PlayerParams,
 };
 use crate::{
     create_collision_world, debug_draw_drawables, debug_draw_rigid_bodies, draw_drawables,
-    exit_to_main_menu, fixed_update_rigid_bodies, quit_to_desktop, update_animated_sprites, Map,
-    MapLayerKind, MapObjectKind, Resources,
+    exit_to_main_menu, fixed_update_rigid_bodies, quit_to_desktop, update_animated_sprites,
+    ApplicationEvent, Map, MapLayerKind, MapObjectKind, Resources,
 };
 
 use crate::effects::active::debug_draw_active_effects;
 use crate::effects::active::projectiles::fixed_update_projectiles;
 use crate::effects::active::triggered::fixed_update_triggered_effects;
-use crate::items::spawn_item;
-use crate::map::{fixed_update_sproingers, spawn_decoration, spawn_sproinger};
+use crate::items::{spawn_item, MapItemKind};
+use crate::map::{
+    capture_the_flag_teams, capture_zone_size, draw_capture_zone_hud, draw_capture_zones,
+    draw_flag_bases, fixed_update_capture_zones, fixed_update_flags, fixed_update_sproingers,
+    flag_base_team, has_capture_zones, has_flag_bases, spawn_capture_zone, spawn_decoration,
+    spawn_default_flag_bases, spawn_flag_base, spawn_sproinger, CAPTURE_ZONE_ID, FLAG_BASE_ID,
+};
 use crate::network::{
-    fixed_update_network_client, fixed_update_network_host, update_network_client,
-    update_network_host,
+    advance_rollback_session, debug_draw_network_stats, fixed_update_network_client,
+    fixed_update_network_host, fixed_update_network_spectator, fixed_update_state_history,
+    init_network_session, is_next_frame_ready, is_rollback_session, record_stall,
+    resync_catch_up_frame_cnt, spectator_frame_cnt, take_pending_resync, update_network_client,
+    update_network_host, update_network_spectator,
 };
 use crate::particles::{draw_particles, update_particle_emitters};
 pub use music::{start_music, stop_music};
@@ -47,12 +94,56 @@ pub enum GameMode {
     Local,
     NetworkHost,
     NetworkClient,
+    /// Watch a network game, without taking part in it. All players are network players.
+    NetworkSpectator,
+    /// Play back a replay of a local game. All players are fed their recorded input.
+    Replay,
+}
+
+impl GameMode {
+    pub fn is_network(&self) -> bool {
+        matches!(
+            self,
+            GameMode::NetworkHost | GameMode::NetworkClient | GameMode::NetworkSpectator
+        )
+    }
+}
+
+/// Parameters for a match, that are not tied to the map or the players
+#[derive(Debug, Clone, Default)]
+pub struct GameParams {
+    /// The seed of the simulation RNG. This must be the same for all peers in a network game.
+    pub seed: u64,
+    /// If this is `true`, all simulation systems are run in the fixed updates, with a fixed delta
+    /// time, so that the same input will always produce the same state. Network games are always
+    /// deterministic.
+    pub is_deterministic: bool,
+    /// The id of the player hosting a network game
+    pub host_player_id: Option<PlayerId>,
+    /// How the match is won, and whether players can damage their own team
+    pub rules: MatchRulesParams,
+    /// The name of the map, as it is recorded in the match history
+    pub map_name: String,
+    /// If this is `true`, the input of a local game is recorded, and saved as a replay, when the
+    /// match ends. Recorded games are always deterministic.
+    pub is_recording_replay: bool,
 }
 
 pub struct Game {
+    mode: GameMode,
     world: World,
     #[allow(dead_code)]
     players: Vec<Entity>,
+    /// Kept to rebuild the world when rejoining a network game
+    player_params: Vec<PlayerParams>,
+    rules: MatchRulesParams,
+    map_name: String,
+    /// Set when the match has ended, and the result has been dispatched
+    is_match_over: bool,
+    /// The replay that is being recorded, if recording is enabled
+    replay: Option<Replay>,
+    /// The playback state, when playing back a replay
+    playback: Option<ReplayPlayback>,
     updates: Scheduler,
     fixed_updates: Scheduler,
     draws: Scheduler,
@@ -61,73 +152,147 @@ pub struct Game {
 }
 
 impl Game {
-    pub fn new(mode: GameMode, map: Map, player_params: &[PlayerParams]) -> Result<Game> {
-        let mut world = World::default();
-
-        {
-            let camera = GameCamera::new(map.get_size());
-            storage::store(camera);
+    pub fn new(
+        mode: GameMode,
+        map: Map,
+        player_params: &[PlayerParams],
+        params: GameParams,
+    ) -> Result<Game> {
+        // Some modes could never be won on some maps, or with some players
+        let unsupported_reason = match params.rules.win_condition {
+            WinCondition::KingOfTheHill(_) if !has_capture_zones(&map) => {
+                Some("the map has no capture zones")
+            }
+            WinCondition::CaptureTheFlag(_) if team_cnt(player_params) < 2 => {
+                Some("there are less than two teams")
+            }
+            WinCondition::CaptureTheFlag(_)
+                if !has_flag_bases(&map) && map.spawn_points.len() < 2 =>
+            {
+                Some("the map has no flag bases")
+            }
+            _ => None,
+        };
 
-            let collision_world = create_collision_world(&map);
-            storage::store(collision_world);
+        if let Some(reason) = unsupported_reason {
+            return Err(formaterr!(
+                ErrorKind::General,
+                "Game: {} can not be played on '{}', as {}",
+                params.rules.win_condition.label(),
+                params.map_name,
+                reason
+            ));
         }
 
-        spawn_map_objects(&mut world, &map).unwrap();
+        let is_recording_replay = mode == GameMode::Local && params.is_recording_replay;
 
-        let players = player_params
-            .iter()
-            .cloned()
-            .map(|params| {
-                let position = map.get_random_spawn_point();
-                spawn_player(
-                    &mut world,
-                    params.index,
-                    position,
-                    params.controller,
-                    params.character,
-                )
-            })
-            .collect();
+        let is_deterministic =
+            params.is_deterministic || mode != GameMode::Local || is_recording_replay;
+
+        let replay = if is_recording_replay {
+            Some(Replay::new(&params, player_params))
+        } else {
+            None
+        };
 
-        storage::store(map);
+        let (world, players) = create_world(
+            map,
+            player_params,
+            params.seed,
+            is_deterministic,
+            params.rules,
+        );
+
+        if mode.is_network() {
+            init_network_session(&mode, player_params, &params);
+        }
 
         let mut updates_builder = Scheduler::builder();
 
         let mut fixed_updates_builder = Scheduler::builder();
 
+        // With rollback, the network session drives the fixed updates, so the network systems are
+        // not added to the fixed update scheduler, as that would have them re-run on rollback
+        let is_rollback = mode.is_network() && is_rollback_session();
+
         match mode {
             GameMode::NetworkClient => {
                 updates_builder.add_system(update_network_client);
 
-                fixed_updates_builder.add_system(fixed_update_network_client);
+                if !is_rollback {
+                    fixed_updates_builder.add_system(fixed_update_network_client);
+                }
             }
             GameMode::NetworkHost => {
                 updates_builder.add_system(update_network_host);
 
-                fixed_updates_builder.add_system(fixed_update_network_host);
+                if !is_rollback {
+                    fixed_updates_builder.add_system(fixed_update_network_host);
+                }
+            }
+            GameMode::NetworkSpectator => {
+                updates_builder
+                    .add_system(update_network_spectator)
+                    .add_system(update_spectator_camera);
+
+                fixed_updates_builder.add_system(fixed_update_network_spectator);
+            }
+            GameMode::Replay => {
+                fixed_updates_builder.add_system(fixed_update_replay_controllers);
             }
-            _ => {}
+            // Bots are part of the simulation, so they are advanced in fixed steps, like the rest
+            // of it, and their input is recorded to replays along with that of the local players
+            GameMode::Local => {
+                fixed_updates_builder.add_system(fixed_update_ai_controllers);
+            }
+        }
+
+        // In network games, controllers are fed by the network systems, as all input, local as well
+        // as remote, has to go through the lockstep input buffer
+        if mode == GameMode::Local {
+            updates_builder.add_system(update_player_controllers);
+        }
+
+        if mode != GameMode::NetworkSpectator {
+            updates_builder.add_system(update_eliminated_camera);
         }
 
-        updates_builder
-            .add_system(update_player_controllers)
-            .add_system(update_player_camera_box);
+        updates_builder.add_system(update_player_camera_box);
 
-        if matches!(mode, GameMode::Local | GameMode::NetworkHost) {
-            updates_builder
+        // Every peer runs the full simulation, as only input is exchanged. In deterministic mode,
+        // everything that is part of the simulation must run in the fixed updates, so that it is
+        // advanced in fixed steps, and so that it is re-simulated after a rollback.
+        {
+            let builder = if is_deterministic {
+                &mut fixed_updates_builder
+            } else {
+                &mut updates_builder
+            };
+
+            builder
                 .add_system(update_player_states)
                 .add_system(update_player_inventory)
                 .add_system(update_player_passive_effects)
+                .add_system(update_player_stats)
                 .add_system(update_player_events);
+        }
 
-            fixed_updates_builder
-                .add_system(fixed_update_physics_bodies)
-                .add_system(fixed_update_rigid_bodies)
-                .add_system(fixed_update_projectiles)
-                .add_system(fixed_update_triggered_effects)
-                .add_system(fixed_update_sproingers);
+        fixed_updates_builder
+            .add_system(fixed_update_physics_bodies)
+            .add_system(fixed_update_rigid_bodies)
+            .add_system(fixed_update_projectiles)
+            .add_system(fixed_update_triggered_effects)
+            .add_system(fixed_update_sproingers)
+            .add_system(fixed_update_capture_zones)
+            .add_system(fixed_update_flags)
+            .add_system(fixed_update_match_rules);
+
+        if mode.is_network() {
+            fixed_updates_builder.add_system(fixed_update_state_history);
         }
 
+        fixed_updates_builder.add_system(fixed_update_simulation);
+
         let updates = updates_builder
             .with_system(update_player_animations)
             .with_system(update_animated_sprites)
@@ -136,23 +301,51 @@ impl Game {
 
         let fixed_updates = fixed_updates_builder.build();
 
-        let draws = Scheduler::builder()
-            .with_thread_local(draw_drawables)
-            .with_thread_local(draw_weapons_hud)
-            .with_thread_local(draw_particles)
-            .build();
+        let draws = {
+            let mut builder = Scheduler::builder()
+                .with_thread_local(draw_capture_zones)
+                .with_thread_local(draw_flag_bases)
+                .with_thread_local(draw_drawables)
+                .with_thread_local(draw_weapons_hud)
+                .with_thread_local(draw_particles)
+                .with_thread_local(draw_match_hud)
+                .with_thread_local(draw_capture_zone_hud);
+
+            if mode == GameMode::NetworkSpectator {
+                builder.add_thread_local(draw_spectator_hud);
+            } else {
+                builder.add_thread_local(draw_eliminated_hud);
+            }
+
+            builder.build()
+        };
 
         #[cfg(debug_assertions)]
-        let debug_draws = Scheduler::builder()
-            .with_thread_local(debug_draw_drawables)
-            .with_thread_local(debug_draw_physics_bodies)
-            .with_thread_local(debug_draw_rigid_bodies)
-            .with_thread_local(debug_draw_active_effects)
-            .build();
+        let debug_draws = {
+            let mut builder = Scheduler::builder()
+                .with_thread_local(debug_draw_drawables)
+                .with_thread_local(debug_draw_physics_bodies)
+                .with_thread_local(debug_draw_rigid_bodies)
+                .with_thread_local(debug_draw_active_effects)
+                .with_thread_local(debug_draw_ai);
+
+            if mode.is_network() {
+                builder.add_thread_local(debug_draw_network_stats);
+            }
+
+            builder.build()
+        };
 
         let res = Game {
+            mode,
             world,
             players,
+            player_params: player_params.to_vec(),
+            rules: params.rules,
+            map_name: params.map_name,
+            is_match_over: false,
+            replay,
+            playback: None,
             updates,
             fixed_updates,
             draws,
@@ -163,9 +356,27 @@ impl Game {
         Ok(res)
     }
 
+    /// Rebuild the match of a replay, with the map and characters of the loaded resources, to play
+    /// it back
+    pub fn from_replay(replay: &Replay) -> Result<Game> {
+        let map = replay.map()?;
+        let player_params = replay.player_params()?;
+
+        let mut res = Game::new(GameMode::Replay, map, &player_params, replay.game_params())?;
+        res.playback = Some(ReplayPlayback::new(replay));
+
+        Ok(res)
+    }
+
     fn on_update(&mut self) {
         self.updates.execute(&mut self.world);
 
+        if let Some(playback) = &mut self.playback {
+            if !gui::is_game_menu_open() {
+                playback.update();
+            }
+        }
+
         #[cfg(debug_assertions)]
         if is_key_pressed(macroquad::prelude::KeyCode::U) {
             crate::debug::toggle_debug_draw();
@@ -182,7 +393,149 @@ impl Game {
     }
 
     fn on_fixed_update(&mut self) {
+        // The world is restored to the checkpoint that a resync starts from, and then caught up
+        // with the match over the following fixed updates, with the input that has been put in the
+        // session by the resync
+        if let Some(snapshot) = take_pending_resync() {
+            snapshot.restore(&mut self.world);
+            return;
+        }
+
+        if self.mode.is_network() {
+            let catch_up_frame_cnt = resync_catch_up_frame_cnt(&self.world);
+
+            if catch_up_frame_cnt > 0 {
+                for _ in 0..catch_up_frame_cnt {
+                    if is_rollback_session() {
+                        advance_rollback_session(&mut self.world, &mut self.fixed_updates);
+                    } else {
+                        self.fixed_updates.execute(&mut self.world);
+                    }
+                }
+
+                return;
+            }
+        }
+
+        if self.mode == GameMode::NetworkSpectator {
+            for _ in 0..spectator_frame_cnt() {
+                self.fixed_updates.execute(&mut self.world);
+            }
+
+            return;
+        }
+
+        if let Some(playback) = &mut self.playback {
+            for _ in 0..playback.frames_to_run() {
+                self.fixed_updates.execute(&mut self.world);
+            }
+
+            return;
+        }
+
+        if self.mode.is_network() {
+            if is_rollback_session() {
+                advance_rollback_session(&mut self.world, &mut self.fixed_updates);
+                return;
+            }
+
+            if !is_next_frame_ready() {
+                record_stall();
+                return;
+            }
+        }
+
         self.fixed_updates.execute(&mut self.world);
+
+        // This is recorded after the frame has been simulated, as the input of bots is produced by
+        // the fixed updates, while controllers are left untouched by the rest of them
+        if let Some(replay) = &mut self.replay {
+            replay.record_frame(&self.world);
+        }
+    }
+
+    /// Returns the players and parameters to start another match with, if this is a local game.
+    /// Rematches are not supported in network games.
+    pub fn rematch_params(&self, seed: u64) -> Option<(Vec<PlayerParams>, GameParams)> {
+        if self.mode != GameMode::Local {
+            return None;
+        }
+
+        let params = GameParams {
+            seed,
+            rules: self.rules,
+            map_name: self.map_name.clone(),
+            is_recording_replay: self.replay.is_some(),
+            ..Default::default()
+        };
+
+        Some((self.player_params.clone(), params))
+    }
+
+    /// Store the result, and dispatch `ApplicationEvent::MatchEnded`, once the match is over
+    fn check_match_result(&mut self) {
+        if !self.is_match_over {
+            if let Some(result) = get_match_result(&self.world) {
+                self.is_match_over = true;
+
+                // Replays are not recorded again, as the match was recorded when it was played
+                if self.mode != GameMode::Replay {
+                    self.record_match(&result);
+                }
+
+                if let Some(replay) = &self.replay {
+                    let path = crate::replays_dir().join(replay.file_name());
+
+                    if let Err(err) = replay.save(path) {
+                        #[cfg(debug_assertions)]
+                        println!("WARNING: Unable to save the replay: {}", err);
+                    }
+                }
+
+                storage::store(result);
+                ApplicationEvent::MatchEnded.dispatch();
+            }
+        }
+    }
+
+    /// Append the match to the match history, which is kept next to the config file
+    fn record_match(&self, result: &MatchResult) {
+        let players = self
+            .player_params
+            .iter()
+            .map(|params| {
+                let stats = result.stats.get(&params.index).copied().unwrap_or_default();
+
+                PlayerRecord {
+                    index: params.index,
+                    team: params.team,
+                    character_id: params.character.id.clone(),
+                    character_name: params.character.name.clone(),
+                    is_winner: result.winner == Some(Side::new(params.index, params.team)),
+                    kills: stats.kills,
+                    deaths: stats.deaths,
+                    suicides: stats.suicides,
+                    items_picked_up: stats.items_picked_up,
+                    shots_fired: stats.shots_fired,
+                    hits: stats.hits,
+                }
+            })
+            .collect();
+
+        let record = MatchRecord {
+            timestamp: MatchRecord::now(),
+            duration: result.duration,
+            map: self.map_name.clone(),
+            win_condition: self.rules.win_condition.label(),
+            friendly_fire: self.rules.friendly_fire,
+            winner: result.winner.map(|side| side.label()),
+            players,
+        };
+
+        if let Err(err) = MatchHistory::append(crate::match_history_path(), record) {
+            #[cfg(debug_assertions)]
+            println!("WARNING: Unable to record the match: {}", err);
+        }
     }
 
     fn on_draw(&mut self) {
@@ -201,6 +554,11 @@ impl Game {
             self.debug_draws.execute(&mut self.world);
         }
 
+        if let Some(playback) = &self.playback {
+            let frame = get_simulation_mut(&self.world).frame;
+            playback.draw_hud(frame);
+        }
+
         if gui::is_game_menu_open() {
             if let Some(res) = gui::draw_game_menu(&mut *root_ui()) {
                 match res.into_usize() {
@@ -220,6 +578,7 @@ impl Node for Game {
 
     fn fixed_update(mut node: RefMut<Self>) {
         node.on_fixed_update();
+        node.check_match_result();
     }
 
     fn draw(mut node: RefMut<Self>) {
@@ -227,9 +586,84 @@ impl Node for Game {
     }
 }
 
+/// Create the world of a new match, spawning the match rules, the map objects and the players, and
+/// store the `Map`, as well as the `CollisionWorld` and `GameCamera` created for it, replacing
+/// those of any previous world
+pub fn create_world(
+    map: Map,
+    player_params: &[PlayerParams],
+    seed: u64,
+    is_deterministic: bool,
+    rules: MatchRulesParams,
+) -> (World, Vec<Entity>) {
+    let mut world = World::default();
+
+    spawn_simulation(&mut world, seed, is_deterministic);
+
+    {
+        let players = player_params
+            .iter()
+            .map(|params| (params.index, params.team))
+            .collect::<Vec<_>>();
+
+        spawn_match_rules(&mut world, rules, &players);
+    }
+
+    {
+        let camera = GameCamera::new(map.get_size(), seed);
+        storage::store(camera);
+
+        let collision_world = create_collision_world(&map);
+        storage::store(collision_world);
+    }
+
+    spawn_map_objects(&mut world, &map).unwrap();
+
+    let players = player_params
+        .iter()
+        .cloned()
+        .map(|params| {
+            let position = {
+                let mut simulation = get_simulation_mut(&world);
+                map.get_random_spawn_point(&mut simulation.rng)
+            };
+
+            spawn_player(
+                &mut world,
+                params.index,
+                params.team,
+                position,
+                params.controller,
+                params.character,
+            )
+        })
+        .collect();
+
+    storage::store(map);
+
+    (world, players)
+}
+
+/// The amount of teams among the players
+fn team_cnt(player_params: &[PlayerParams]) -> usize {
+    let mut teams = player_params
+        .iter()
+        .filter_map(|params| params.team)
+        .collect::<Vec<_>>();
+
+    teams.sort_unstable();
+    teams.dedup();
+
+    teams.len()
+}
+
 pub fn spawn_map_objects(world: &mut World, map: &Map) -> Result<Vec<Entity>> {
     let mut objects = Vec::new();
 
+    // Flag bases are only spawned in the capture the flag mode, for the teams that play
+    let flag_teams = capture_the_flag_teams(world);
+    let mut has_spawned_flag_bases = false;
+
     for layer in map.layers.values() {
         if layer.is_visible && layer.kind == MapLayerKind::ObjectLayer {
             for map_object in &layer.objects {
@@ -251,6 +685,16 @@ pub fn spawn_map_objects(world: &mut World, map: &Map) -> Result<Vec<Entity>> {
                         let res = resources.items.get(&map_object.id).cloned();
 
                         if let Some(params) = res {
+                            if matches!(&params.kind, MapItemKind::Item { meta } if meta.is_flag) {
+                                #[cfg(debug_assertions)]
+                                println!(
+                                    "WARNING: The flag item '{}' can only be spawned by flag bases",
+                                    &map_object.id
+                                );
+
+                                continue;
+                            }
+
                             let item = spawn_item(world, map_object.position, params)?;
                             objects.push(item);
                         } else {
@@ -262,6 +706,19 @@ pub fn spawn_map_objects(world: &mut World, map: &Map) -> Result<Vec<Entity>> {
                         if map_object.id == "sproinger" {
                             let sproinger = spawn_sproinger(world, map_object.position)?;
                             objects.push(sproinger);
+                        } else if map_object.id == CAPTURE_ZONE_ID {
+                            let size = capture_zone_size(map_object);
+                            let capture_zone = spawn_capture_zone(world, map_object.position, size);
+                            objects.push(capture_zone);
+                        } else if map_object.id == FLAG_BASE_ID {
+                            let team = flag_base_team(map_object);
+
+                            if flag_teams.contains(&team) {
+                                let flag_base = spawn_flag_base(world, map_object.position, team)?;
+                                objects.push(flag_base);
+
+                                has_spawned_flag_bases = true;
+                            }
                         } else {
                             #[cfg(debug_assertions)]
                             println!("WARNING: Invalid environment item id '{}'", &map_object.id)
@@ -272,5 +729,10 @@ pub fn spawn_map_objects(world: &mut World, map: &Map) -> Result<Vec<Entity>> {
         }
     }
 
+    if !flag_teams.is_empty() && !has_spawned_flag_bases {
+        let flag_bases = spawn_default_flag_bases(world, map, &flag_teams)?;
+        objects.extend(flag_bases);
+    }
+
     Ok(objects)
 }
@@ -0,0 +1,119 @@
+//! Regression tests for gameplay. Every replay in `tests/replays` is run in a `HeadlessGame`, and
+//! the state of the world on its final frame is compared to the golden snapshot of the same name,
+//! in `tests/snapshots`. Anything that changes the outcome of a match, like a change to the
+//! physics or to a weapon, will fail these tests, until the snapshots are updated.
+//! A replay without a snapshot is a failure. To write the snapshots of new replays, or to update
+//! the snapshots of all replays after an intended change to gameplay, run the tests with
+//! `FISHFIGHT_BLESS_SNAPSHOTS` set, and commit the snapshots along with the change.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use core::Result;
+
+use crate::game::{default_assets_dir, list_replays, run_headless_replay, GameSnapshot, Replay};
+
+const REPLAYS_DIR: &str = "tests/replays";
+const SNAPSHOTS_DIR: &str = "tests/snapshots";
+
+const BLESS_ENV_VAR: &str = "FISHFIGHT_BLESS_SNAPSHOTS";
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Compare the outcome of a replay to its golden snapshot, or write the snapshot if `is_blessing`
+/// is set. Returns a description of the mismatch, or of the missing snapshot, if any.
+fn check_replay(path: &Path, is_blessing: bool) -> Result<Option<String>> {
+    let replay = Replay::load(path)?;
+    let snapshot = run_headless_replay(default_assets_dir(), &replay)?;
+
+    let snapshot_path = manifest_dir()
+        .join(SNAPSHOTS_DIR)
+        .join(path.file_name().unwrap());
+
+    let expected = if snapshot_path.exists() {
+        let bytes = fs::read(&snapshot_path)?;
+        Some(serde_json::from_slice::<GameSnapshot>(&bytes)?)
+    } else {
+        None
+    };
+
+    if expected.as_ref() == Some(&snapshot) {
+        return Ok(None);
+    }
+
+    if is_blessing {
+        fs::create_dir_all(snapshot_path.parent().unwrap())?;
+
+        let mut json = serde_json::to_string_pretty(&snapshot)?;
+        json.push('\n');
+        fs::write(&snapshot_path, json)?;
+
+        return Ok(None);
+    }
+
+    let res = match expected {
+        Some(expected) => format!(
+            "The outcome of '{}' does not match its snapshot\nexpected: {:#?}\nactual: {:#?}",
+            path.display(),
+            expected,
+            snapshot,
+        ),
+        None => format!(
+            "'{}' has no snapshot, at '{}'\nactual: {:#?}",
+            path.display(),
+            snapshot_path.display(),
+            snapshot,
+        ),
+    };
+
+    Ok(Some(res))
+}
+
+#[test]
+fn replays_match_snapshots() {
+    let is_blessing = env::var(BLESS_ENV_VAR).is_ok();
+
+    let paths = list_replays(manifest_dir().join(REPLAYS_DIR));
+    assert!(
+        !paths.is_empty(),
+        "No replays were found in '{}'",
+        REPLAYS_DIR
+    );
+
+    let mismatches = paths
+        .iter()
+        .filter_map(|path| {
+            check_replay(path, is_blessing)
+                .unwrap_or_else(|err| panic!("Unable to run '{}': {}", path.display(), err))
+        })
+        .collect::<Vec<_>>();
+
+    assert!(
+        mismatches.is_empty(),
+        "{}\nIf these changes are intended, run the tests again with {} set, to update the snapshots",
+        mismatches.join("\n\n"),
+        BLESS_ENV_VAR
+    );
+}
+
+#[test]
+fn replays_are_deterministic() {
+    let paths = list_replays(manifest_dir().join(REPLAYS_DIR));
+
+    for path in paths {
+        let replay = Replay::load(&path).unwrap();
+
+        let a = run_headless_replay(default_assets_dir(), &replay).unwrap();
+        let b = run_headless_replay(default_assets_dir(), &replay).unwrap();
+
+        assert_eq!(
+            a,
+            b,
+            "'{}' has a different outcome every run",
+            path.display()
+        );
+    }
+}
@@ -0,0 +1,401 @@
+//! Replays of local matches. When recording is enabled in the config, the input of every player is
+//! recorded for every fixed update, along with what is needed to rebuild the world of the match:
+//! the map, the characters, the rules and the seed of the simulation. Recorded matches are run in
+//! deterministic mode, so feeding the recorded input to the players of a rebuilt world, through
+//! `PlayerControllerKind::Replay` controllers, plays the match out exactly as it was played.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use fishsticks::{Button, GamepadContext};
+
+use macroquad::color;
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+
+use hecs::World;
+
+use serde::{Deserialize, Serialize};
+
+use core::error::ErrorKind;
+use core::history::MatchRecord;
+use core::input::{is_gamepad_btn_pressed, InputTrack};
+use core::{formaterr, Result};
+
+use crate::game::{get_simulation_mut, GameParams, MatchRulesParams, FIXED_DELTA_TIME};
+use crate::player::{Player, PlayerController, PlayerControllerKind, PlayerParams};
+use crate::{Map, Resources};
+
+/// The name of the directory that replays are saved to, which is kept in the same directory as
+/// the config file
+pub const REPLAYS_DIR_NAME: &str = "replays";
+
+pub const REPLAY_FILE_EXTENSION: &str = "json";
+
+/// The version of the replay format. Replays of other versions can not be played back.
+pub const REPLAY_FORMAT_VERSION: u32 = 1;
+
+/// The playback speeds that can be selected, as multiples of the speed that the match was played at
+const PLAYBACK_SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const DEFAULT_PLAYBACK_SPEED_INDEX: usize = 2;
+
+const HUD_FONT_SIZE: f32 = 20.0;
+const HUD_MARGIN: f32 = 16.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    /// The time that the match was started, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// The name of the map, which is used to look it up in the loaded resources
+    pub map: String,
+    pub seed: u64,
+    pub rules: MatchRulesParams,
+    pub players: Vec<ReplayPlayer>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayPlayer {
+    pub index: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<u8>,
+    pub character_id: String,
+    /// The input of the player, for every fixed update of the match
+    pub inputs: InputTrack,
+}
+
+impl ReplayPlayer {
+    #[cfg(test)]
+    pub fn new(index: u8, character_id: &str, inputs: InputTrack) -> Self {
+        ReplayPlayer {
+            index,
+            team: None,
+            character_id: character_id.to_string(),
+            inputs,
+        }
+    }
+}
+
+impl Replay {
+    /// Create an empty replay of a match that is about to start
+    pub fn new(params: &GameParams, player_params: &[PlayerParams]) -> Self {
+        let players = player_params
+            .iter()
+            .map(|params| ReplayPlayer {
+                index: params.index,
+                team: params.team,
+                character_id: params.character.id.clone(),
+                inputs: InputTrack::new(),
+            })
+            .collect();
+
+        Replay {
+            version: REPLAY_FORMAT_VERSION,
+            timestamp: MatchRecord::now(),
+            map: params.map_name.clone(),
+            seed: params.seed,
+            rules: params.rules,
+            players,
+        }
+    }
+
+    /// Create a replay from input that has been scripted, in stead of recorded, like the input of
+    /// the players of a gameplay test
+    #[cfg(test)]
+    pub fn from_script(
+        map: &str,
+        seed: u64,
+        rules: MatchRulesParams,
+        players: Vec<ReplayPlayer>,
+    ) -> Self {
+        Replay {
+            version: REPLAY_FORMAT_VERSION,
+            timestamp: 0,
+            map: map.to_string(),
+            seed,
+            rules,
+            players,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let res: Replay = serde_json::from_slice(&bytes)?;
+
+        if res.version != REPLAY_FORMAT_VERSION {
+            return Err(formaterr!(
+                ErrorKind::Parsing,
+                "Replay: Unsupported replay version {} (expected {})",
+                res.version,
+                REPLAY_FORMAT_VERSION,
+            ));
+        }
+
+        Ok(res)
+    }
+
+    /// Save the replay, creating the directory it is saved to, if it does not exist
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let bytes = serde_json::to_vec(self)?;
+        fs::write(path, bytes)?;
+
+        Ok(())
+    }
+
+    /// The file name that the replay is saved as. The name starts with the timestamp, so that
+    /// replays are ordered by the time that they were recorded.
+    pub fn file_name(&self) -> String {
+        format!("replay_{}.{}", self.timestamp, REPLAY_FILE_EXTENSION)
+    }
+
+    /// The amount of fixed updates that have been recorded
+    pub fn frame_cnt(&self) -> u64 {
+        self.players
+            .iter()
+            .map(|player| player.inputs.len())
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// The length of the recording, in seconds
+    pub fn duration(&self) -> f32 {
+        self.frame_cnt() as f32 * FIXED_DELTA_TIME
+    }
+
+    /// Record the input that the controllers of the players hold, for the next fixed update
+    pub fn record_frame(&mut self, world: &World) {
+        for (_, (player, controller)) in world.query::<(&Player, &PlayerController)>().iter() {
+            if let Some(replay_player) = self
+                .players
+                .iter_mut()
+                .find(|replay_player| replay_player.index == player.index)
+            {
+                replay_player.inputs.push(controller.input());
+            }
+        }
+    }
+
+    /// Look up the map of the replay in the loaded resources
+    pub fn map(&self) -> Result<Map> {
+        let resources = storage::get::<Resources>();
+
+        resources
+            .maps
+            .iter()
+            .find(|map_resource| map_resource.meta.name == self.map)
+            .map(|map_resource| map_resource.map.clone())
+            .ok_or_else(|| formaterr!(ErrorKind::General, "Replay: Invalid map '{}'", &self.map))
+    }
+
+    /// The parameters of the players of the replay, with controllers that feed them the recorded
+    /// input. Characters are looked up in the loaded resources.
+    pub fn player_params(&self) -> Result<Vec<PlayerParams>> {
+        let resources = storage::get::<Resources>();
+
+        self.players
+            .iter()
+            .map(|player| {
+                let character = resources
+                    .player_characters
+                    .get(&player.character_id)
+                    .cloned()
+                    .ok_or_else(|| {
+                        formaterr!(
+                            ErrorKind::General,
+                            "Replay: Invalid character id '{}'",
+                            &player.character_id
+                        )
+                    })?;
+
+                Ok(PlayerParams {
+                    index: player.index,
+                    team: player.team,
+                    controller: PlayerControllerKind::Replay(Arc::new(player.inputs.clone())),
+                    character,
+                })
+            })
+            .collect()
+    }
+
+    /// The parameters to rebuild the match with
+    pub fn game_params(&self) -> GameParams {
+        GameParams {
+            seed: self.seed,
+            is_deterministic: true,
+            rules: self.rules,
+            map_name: self.map.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// A description of the replay, like `"Fishtank: Sharky vs Pescy (2:31)"`, for the replays
+    /// menu. Characters that are not loaded are shown by their id.
+    pub fn label(&self) -> String {
+        let resources = storage::get::<Resources>();
+
+        let characters = self
+            .players
+            .iter()
+            .map(|player| {
+                resources
+                    .player_characters
+                    .get(&player.character_id)
+                    .map(|character| character.name.clone())
+                    .unwrap_or_else(|| player.character_id.clone())
+            })
+            .collect::<Vec<_>>();
+
+        let duration = self.duration() as u32;
+
+        format!(
+            "{}: {} ({}:{:02})",
+            self.map,
+            characters.join(" vs "),
+            duration / 60,
+            duration % 60
+        )
+    }
+}
+
+/// The paths of the replays in a directory, with the most recent first
+pub fn list_replays<P: AsRef<Path>>(dir: P) -> Vec<PathBuf> {
+    let mut res = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.extension()
+                        .map(|extension| extension == REPLAY_FILE_EXTENSION)
+                        .unwrap_or_default()
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    res.sort_unstable_by(|a, b| b.cmp(a));
+
+    res
+}
+
+/// Feed the recorded input of the current frame to every player with a replay controller. This
+/// should be the first of the fixed updates, when playing back a replay. Once the recording has
+/// run out, players are given no input.
+pub fn fixed_update_replay_controllers(world: &mut World) {
+    let frame = get_simulation_mut(world).frame;
+
+    for (_, controller) in world.query::<&mut PlayerController>().iter() {
+        if let PlayerControllerKind::Replay(inputs) = &controller.kind {
+            let input = inputs.get(frame).unwrap_or_default();
+            controller.apply_input(input);
+        }
+    }
+}
+
+/// The playback state of a replay. Playback can be paused, slowed down and sped up, by running
+/// fewer, or more, fixed updates for every fixed update of the game loop.
+#[derive(Debug, Clone)]
+pub struct ReplayPlayback {
+    frame_cnt: u64,
+    speed_index: usize,
+    is_paused: bool,
+    /// The fraction of a fixed update that is carried over to the next fixed update of the loop,
+    /// at speeds below `1.0`
+    accumulator: f32,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: &Replay) -> Self {
+        ReplayPlayback {
+            frame_cnt: replay.frame_cnt(),
+            speed_index: DEFAULT_PLAYBACK_SPEED_INDEX,
+            is_paused: false,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        PLAYBACK_SPEEDS[self.speed_index]
+    }
+
+    /// Handle the playback controls. Space, or South on a gamepad, pauses and resumes playback,
+    /// and left and right, on the keyboard or the d-pad, change the playback speed.
+    pub fn update(&mut self) {
+        let gamepad_context = storage::get::<GamepadContext>();
+        let is_pressed = |key_code: KeyCode, btn: Button| {
+            is_key_pressed(key_code) || is_gamepad_btn_pressed(Some(&gamepad_context), btn)
+        };
+
+        if is_pressed(KeyCode::Space, Button::South) {
+            self.is_paused = !self.is_paused;
+        }
+
+        if is_pressed(KeyCode::Left, Button::DPadLeft) && self.speed_index > 0 {
+            self.speed_index -= 1;
+        }
+
+        if is_pressed(KeyCode::Right, Button::DPadRight)
+            && self.speed_index < PLAYBACK_SPEEDS.len() - 1
+        {
+            self.speed_index += 1;
+        }
+    }
+
+    /// The amount of fixed updates to run for this fixed update of the game loop
+    pub fn frames_to_run(&mut self) -> u32 {
+        if self.is_paused {
+            return 0;
+        }
+
+        self.accumulator += self.speed();
+
+        let res = self.accumulator.floor();
+        self.accumulator -= res;
+
+        res as u32
+    }
+
+    /// Draw the playback state, and the controls, at the bottom of the screen
+    pub fn draw_hud(&self, frame: u64) {
+        push_camera_state();
+        set_default_camera();
+
+        let elapsed = (frame.min(self.frame_cnt) as f32 * FIXED_DELTA_TIME) as u32;
+        let duration = (self.frame_cnt as f32 * FIXED_DELTA_TIME) as u32;
+
+        let state = if self.is_paused {
+            "Paused".to_string()
+        } else if frame >= self.frame_cnt {
+            "Ended".to_string()
+        } else {
+            format!("{}x", self.speed())
+        };
+
+        let label = format!(
+            "Replay  -  {}  -  {}:{:02} / {}:{:02}  -  SPACE: Pause  LEFT/RIGHT: Speed",
+            state,
+            elapsed / 60,
+            elapsed % 60,
+            duration / 60,
+            duration % 60
+        );
+
+        let size = measure_text(&label, None, HUD_FONT_SIZE as u16, 1.0);
+
+        draw_text(
+            &label,
+            (screen_width() - size.width) / 2.0,
+            screen_height() - HUD_MARGIN,
+            HUD_FONT_SIZE,
+            color::WHITE,
+        );
+
+        pop_camera_state();
+    }
+}
@@ -0,0 +1,1010 @@
+//! Scoring and win conditions of a match. `MatchRules` is stored as a component on a single entity
+//! in the world, like the `Simulation`, so that the score is captured by snapshots and rolled back
+//! along with the rest of the world.
+
+use std::collections::BTreeMap;
+
+use macroquad::color;
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+
+use hecs::{Entity, RefMut, World};
+
+use serde::{Deserialize, Serialize};
+
+use core::Transform;
+
+use crate::game::{
+    collect_player_stats, get_simulation_dt, get_simulation_mut, spawn_map_objects, PlayerStats,
+    Simulation,
+};
+use crate::player::{Player, PlayerEventQueue, PlayerInventory, PlayerState};
+use crate::{Map, PhysicsBody};
+
+/// The amount of kills needed to win, with the default win condition
+pub const DEFAULT_KILL_CNT: u32 = 10;
+
+/// The maximum amount of kills that can be selected, with the kill count win condition
+pub const MAX_KILL_CNT: u32 = 30;
+
+/// The amount of lives that players start with, in the stocks mode, unless another is selected
+pub const DEFAULT_LIVES: u32 = 3;
+
+/// The maximum amount of lives that can be selected, in the stocks mode
+pub const MAX_LIVES: u32 = 9;
+
+/// The time, in seconds, that a side must hold the capture zones to win, in the king of the hill
+/// mode, unless another is selected
+pub const DEFAULT_HOLD_TIME: u32 = 60;
+
+/// The maximum hold time, in seconds, that can be selected, in the king of the hill mode
+pub const MAX_HOLD_TIME: u32 = 300;
+
+/// The amount of captures needed to win, in the capture the flag mode, unless another is selected
+pub const DEFAULT_CAPTURE_CNT: u32 = 3;
+
+/// The maximum amount of captures that can be selected, in the capture the flag mode
+pub const MAX_CAPTURE_CNT: u32 = 9;
+
+/// The time between the end of a round and the start of the next
+pub const ROUND_END_DELAY: f32 = 3.0;
+
+/// The time that the result is shown, in game, before the match ends
+pub const MATCH_END_DELAY: f32 = 3.0;
+
+const HUD_FONT_SIZE: f32 = 20.0;
+const HUD_BANNER_FONT_SIZE: f32 = 48.0;
+const HUD_MARGIN: f32 = 16.0;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", content = "value", rename_all = "snake_case")]
+pub enum WinCondition {
+    /// The first player to reach this amount of kills wins the match. Players respawn after they
+    /// are killed.
+    KillCnt(u32),
+    /// A round ends when only one player is left alive, and that player wins the round. Players
+    /// are not respawned until the next round. The first player to win this amount of rounds wins
+    /// the match.
+    LastFishStanding(u32),
+    /// Every player starts with this amount of lives, and loses one on every death. A player with
+    /// no lives left is not respawned, and the last player with lives left wins the match.
+    Stocks(u32),
+    /// Players score by being the only side inside a capture zone, and the first side to hold
+    /// the capture zones for this amount of seconds, in total, wins the match. Players respawn
+    /// after they are killed.
+    KingOfTheHill(u32),
+    /// Teams score by carrying the flag of another team to their own flag base, while their own
+    /// flag is at home, and the first team to make this amount of captures wins the match.
+    /// Players respawn after they are killed.
+    CaptureTheFlag(u32),
+}
+
+impl WinCondition {
+    pub fn label(&self) -> String {
+        match self {
+            WinCondition::KillCnt(kill_cnt) => format!("Kills ({})", kill_cnt),
+            WinCondition::LastFishStanding(round_cnt) => {
+                format!("Last Fish Standing ({})", round_cnt)
+            }
+            WinCondition::Stocks(lives) => format!("Stocks ({})", lives),
+            WinCondition::KingOfTheHill(hold_time) => format!("King of the Hill ({})", hold_time),
+            WinCondition::CaptureTheFlag(capture_cnt) => {
+                format!("Capture the Flag ({})", capture_cnt)
+            }
+        }
+    }
+}
+
+impl Default for WinCondition {
+    fn default() -> Self {
+        WinCondition::KillCnt(DEFAULT_KILL_CNT)
+    }
+}
+
+/// Players that are not on a team play for themselves
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Side {
+    Player(u8),
+    Team(u8),
+}
+
+impl Side {
+    pub fn new(index: u8, team: Option<u8>) -> Self {
+        match team {
+            Some(team) => Side::Team(team),
+            None => Side::Player(index),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Side::Player(index) => format!("Player {}", index + 1),
+            Side::Team(team) => format!("Team {}", team + 1),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct PlayerScore {
+    pub team: Option<u8>,
+    pub kills: u32,
+    pub deaths: u32,
+    /// The lives that the player has left, in the stocks mode
+    pub lives: Option<u32>,
+    /// The time, in seconds, that the player has held capture zones, in the king of the hill mode
+    pub hold_time: f32,
+    /// The flags that the player has captured, in the capture the flag mode
+    pub captures: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchResult {
+    /// The side that won, or `None` if the match ended in a draw
+    pub winner: Option<Side>,
+    /// The score of every player, by player index
+    pub scores: BTreeMap<u8, PlayerScore>,
+    /// The amount of rounds won by each side
+    pub round_wins: BTreeMap<Side, u32>,
+    /// The amount of rounds that were played
+    pub round_cnt: u32,
+    /// The time, in seconds, from the start of the match until it ended
+    pub duration: f32,
+    /// The stats of every player, by player index. These are added by `get_match_result`.
+    pub stats: BTreeMap<u8, PlayerStats>,
+}
+
+/// Options for a match. These must be the same for all peers in a network game.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MatchRulesParams {
+    pub win_condition: WinCondition,
+    /// If this is `true`, players can damage players on their own team
+    #[serde(default)]
+    pub friendly_fire: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchRules {
+    pub win_condition: WinCondition,
+    pub friendly_fire: bool,
+    /// The score of every player, by player index
+    pub scores: BTreeMap<u8, PlayerScore>,
+    /// The amount of rounds won by each side
+    pub round_wins: BTreeMap<Side, u32>,
+    /// The current round, starting at 1
+    pub round: u32,
+    /// This is set when a round has ended, and counts up to `ROUND_END_DELAY`
+    pub round_end_timer: Option<f32>,
+    /// This is set when the match has ended, and counts up to `MATCH_END_DELAY`
+    pub match_end_timer: f32,
+    /// The time that has passed since the start of the match, until it has ended
+    pub match_time: f32,
+    pub result: Option<MatchResult>,
+}
+
+impl MatchRules {
+    /// Create the rules of a match between players, given by their index and team
+    pub fn new(params: MatchRulesParams, players: &[(u8, Option<u8>)]) -> Self {
+        let lives = match params.win_condition {
+            WinCondition::Stocks(lives) => Some(lives),
+            _ => None,
+        };
+
+        let scores = players
+            .iter()
+            .map(|&(index, team)| {
+                let score = PlayerScore {
+                    team,
+                    lives,
+                    ..Default::default()
+                };
+
+                (index, score)
+            })
+            .collect();
+
+        MatchRules {
+            win_condition: params.win_condition,
+            friendly_fire: params.friendly_fire,
+            scores,
+            round_wins: BTreeMap::new(),
+            round: 1,
+            round_end_timer: None,
+            match_end_timer: 0.0,
+            match_time: 0.0,
+            result: None,
+        }
+    }
+
+    pub fn side_of(&self, index: u8) -> Side {
+        let team = self.scores.get(&index).and_then(|score| score.team);
+        Side::new(index, team)
+    }
+
+    /// All sides in the match, in order
+    pub fn sides(&self) -> Vec<Side> {
+        let mut sides = self
+            .scores
+            .keys()
+            .map(|index| self.side_of(*index))
+            .collect::<Vec<_>>();
+
+        sides.sort_unstable();
+        sides.dedup();
+
+        sides
+    }
+
+    /// The total kills of all players on a side
+    pub fn side_kills(&self, side: Side) -> u32 {
+        self.scores
+            .keys()
+            .filter(|index| self.side_of(**index) == side)
+            .map(|index| self.scores[index].kills)
+            .sum()
+    }
+
+    /// The total lives left of all players on a side, in the stocks mode
+    pub fn side_lives(&self, side: Side) -> u32 {
+        self.scores
+            .keys()
+            .filter(|index| self.side_of(**index) == side)
+            .filter_map(|index| self.scores[index].lives)
+            .sum()
+    }
+
+    /// The total time, in seconds, that the players on a side have held capture zones
+    pub fn side_hold_time(&self, side: Side) -> f32 {
+        self.scores
+            .keys()
+            .filter(|index| self.side_of(**index) == side)
+            .map(|index| self.scores[index].hold_time)
+            .sum()
+    }
+
+    /// The total flags captured by the players on a side
+    pub fn side_captures(&self, side: Side) -> u32 {
+        self.scores
+            .keys()
+            .filter(|index| self.side_of(**index) == side)
+            .map(|index| self.scores[index].captures)
+            .sum()
+    }
+
+    /// Returns `true` if a dead player should be respawned, within the current round
+    pub fn can_respawn(&self, index: u8) -> bool {
+        if self.result.is_some() {
+            return false;
+        }
+
+        match self.win_condition {
+            WinCondition::KillCnt(_)
+            | WinCondition::KingOfTheHill(_)
+            | WinCondition::CaptureTheFlag(_) => true,
+            WinCondition::LastFishStanding(_) => false,
+            WinCondition::Stocks(_) => !self.is_eliminated(index),
+        }
+    }
+
+    /// Returns `true` if the player has no lives left, in the stocks mode
+    pub fn is_eliminated(&self, index: u8) -> bool {
+        matches!(
+            self.scores.get(&index),
+            Some(PlayerScore { lives: Some(0), .. })
+        )
+    }
+
+    /// The indices of all players that have no lives left
+    pub fn eliminated_players(&self) -> Vec<u8> {
+        self.scores
+            .keys()
+            .copied()
+            .filter(|index| self.is_eliminated(*index))
+            .collect()
+    }
+
+    /// Returns `true` when the result has been shown for `MATCH_END_DELAY`
+    pub fn is_match_over(&self) -> bool {
+        self.result.is_some() && self.match_end_timer >= MATCH_END_DELAY
+    }
+
+    /// Record the death of a player. A kill is credited to the killer, unless it is the same
+    /// player, or a player on the same team. Nothing is recorded once the match, or the current
+    /// round, has ended.
+    pub fn record_death(&mut self, index: u8, killer: Option<u8>) {
+        if self.result.is_some() || self.round_end_timer.is_some() {
+            return;
+        }
+
+        {
+            let score = self.scores.entry(index).or_default();
+            score.deaths += 1;
+
+            if let Some(lives) = &mut score.lives {
+                *lives = lives.saturating_sub(1);
+            }
+        }
+
+        let side = self.side_of(index);
+
+        if let Some(killer) = killer.filter(|killer| self.side_of(*killer) != side) {
+            self.scores.entry(killer).or_default().kills += 1;
+
+            if let WinCondition::KillCnt(kill_cnt) = self.win_condition {
+                let killer_side = self.side_of(killer);
+
+                if self.side_kills(killer_side) >= kill_cnt {
+                    self.end_match(Some(killer_side));
+                }
+            }
+        }
+    }
+
+    /// Record time that a player has held a capture zone, in the king of the hill mode. Nothing is
+    /// recorded in other modes, or once the match has ended.
+    pub fn record_hold_time(&mut self, index: u8, time: f32) {
+        let hold_time = match self.win_condition {
+            WinCondition::KingOfTheHill(hold_time) => hold_time,
+            _ => return,
+        };
+
+        if self.result.is_some() {
+            return;
+        }
+
+        self.scores.entry(index).or_default().hold_time += time;
+
+        let side = self.side_of(index);
+
+        if self.side_hold_time(side) >= hold_time as f32 {
+            self.end_match(Some(side));
+        }
+    }
+
+    /// Record the capture of a flag by a player, in the capture the flag mode. Nothing is recorded
+    /// in other modes, or once the match has ended.
+    pub fn record_capture(&mut self, index: u8) {
+        let capture_cnt = match self.win_condition {
+            WinCondition::CaptureTheFlag(capture_cnt) => capture_cnt,
+            _ => return,
+        };
+
+        if self.result.is_some() {
+            return;
+        }
+
+        self.scores.entry(index).or_default().captures += 1;
+
+        let side = self.side_of(index);
+
+        if self.side_captures(side) >= capture_cnt {
+            self.end_match(Some(side));
+        }
+    }
+
+    /// End the current round, with the side left alive as the winner, if any
+    fn end_round(&mut self, winner: Option<Side>) {
+        self.round_end_timer = Some(0.0);
+
+        if let Some(winner) = winner {
+            let round_wins = self.round_wins.entry(winner).or_default();
+            *round_wins += 1;
+
+            if let WinCondition::LastFishStanding(round_cnt) = self.win_condition {
+                if *round_wins >= round_cnt {
+                    self.end_match(Some(winner));
+                }
+            }
+        }
+    }
+
+    fn end_match(&mut self, winner: Option<Side>) {
+        self.result = Some(MatchResult {
+            winner,
+            scores: self.scores.clone(),
+            round_wins: self.round_wins.clone(),
+            round_cnt: self.round,
+            duration: self.match_time,
+            stats: BTreeMap::new(),
+        });
+    }
+}
+
+pub fn spawn_match_rules(
+    world: &mut World,
+    params: MatchRulesParams,
+    players: &[(u8, Option<u8>)],
+) -> Entity {
+    world.spawn((MatchRules::new(params, players),))
+}
+
+/// Get the match rules of the world, if they have been spawned. As the borrow is dynamic, this can
+/// be held while running queries for other components, as long as these are done with
+/// `World::query` and not `World::query_mut`.
+pub fn get_match_rules_mut(world: &World) -> Option<RefMut<'_, MatchRules>> {
+    let entity = world
+        .query::<&MatchRules>()
+        .iter()
+        .next()
+        .map(|(entity, _)| entity)?;
+
+    world.get_mut::<MatchRules>(entity).ok()
+}
+
+/// Returns `true` if the player `damage_from` can damage the player `damage_to`. Players can
+/// always damage themselves, but they can only damage players on their own team if friendly fire
+/// is enabled.
+pub fn can_damage(world: &World, damage_from: Entity, damage_to: Entity) -> bool {
+    if damage_from == damage_to {
+        return true;
+    }
+
+    let is_friendly_fire = get_match_rules_mut(world)
+        .map(|rules| rules.friendly_fire)
+        .unwrap_or(true);
+
+    if is_friendly_fire {
+        return true;
+    }
+
+    let team_of = |entity: Entity| {
+        world
+            .get::<Player>(entity)
+            .ok()
+            .and_then(|player| player.team)
+    };
+
+    match (team_of(damage_from), team_of(damage_to)) {
+        (Some(team), Some(other_team)) => team != other_team,
+        _ => true,
+    }
+}
+
+/// Returns the result of the match, with the stats of every player, once it has been shown for
+/// `MATCH_END_DELAY`
+pub fn get_match_result(world: &World) -> Option<MatchResult> {
+    let mut result = get_match_rules_mut(world)
+        .filter(|rules| rules.is_match_over())
+        .and_then(|rules| rules.result.clone())?;
+
+    result.stats = collect_player_stats(world);
+
+    Some(result)
+}
+
+/// Ends rounds and the match, when the win condition is met, and starts the next round, once
+/// `ROUND_END_DELAY` has passed
+pub fn fixed_update_match_rules(world: &mut World) {
+    let dt = get_simulation_dt(world);
+
+    let should_reset = {
+        let mut rules = match get_match_rules_mut(world) {
+            Some(rules) => rules,
+            None => return,
+        };
+
+        if rules.result.is_some() {
+            rules.match_end_timer += dt;
+            return;
+        }
+
+        rules.match_time += dt;
+
+        if let Some(timer) = &mut rules.round_end_timer {
+            *timer += dt;
+            *timer >= ROUND_END_DELAY
+        } else {
+            match rules.win_condition {
+                WinCondition::LastFishStanding(_) => {
+                    let mut alive = world
+                        .query::<&Player>()
+                        .iter()
+                        .filter(|(_, player)| player.state != PlayerState::Dead)
+                        .map(|(_, player)| rules.side_of(player.index))
+                        .collect::<Vec<_>>();
+
+                    alive.sort_unstable();
+                    alive.dedup();
+
+                    // A single side can not win against anyone, so the round goes on
+                    if rules.sides().len() > 1 && alive.len() <= 1 {
+                        rules.end_round(alive.first().copied());
+                    }
+                }
+                WinCondition::Stocks(_) => {
+                    // This is checked here, rather than on every death, so that sides that lose
+                    // their last lives on the same frame end the match in a draw
+                    let remaining = rules
+                        .sides()
+                        .into_iter()
+                        .filter(|side| rules.side_lives(*side) > 0)
+                        .collect::<Vec<_>>();
+
+                    if rules.sides().len() > 1 && remaining.len() <= 1 {
+                        rules.end_match(remaining.first().copied());
+                    }
+                }
+                WinCondition::KillCnt(_)
+                | WinCondition::KingOfTheHill(_)
+                | WinCondition::CaptureTheFlag(_) => {}
+            }
+
+            false
+        }
+    };
+
+    if should_reset {
+        reset_round(world);
+    }
+}
+
+/// Start the next round. Everything but the players is despawned, and the map objects are spawned
+/// again, while the players are respawned, at random spawn points, with empty inventories.
+pub fn reset_round(world: &mut World) {
+    let to_despawn = world
+        .iter()
+        .filter(|entity_ref| {
+            !entity_ref.has::<Player>()
+                && !entity_ref.has::<Simulation>()
+                && !entity_ref.has::<MatchRules>()
+        })
+        .map(|entity_ref| entity_ref.entity())
+        .collect::<Vec<_>>();
+
+    for entity in to_despawn {
+        if let Err(err) = world.despawn(entity) {
+            #[cfg(debug_assertions)]
+            println!("WARNING: {}", err);
+        }
+    }
+
+    {
+        let map = storage::get::<Map>();
+
+        if let Err(err) = spawn_map_objects(world, &map) {
+            #[cfg(debug_assertions)]
+            println!("WARNING: {}", err);
+        }
+    }
+
+    {
+        let map = storage::get::<Map>();
+        let mut simulation = get_simulation_mut(world);
+
+        let mut query = world.query::<(
+            &mut Transform,
+            &mut Player,
+            &mut PlayerInventory,
+            &mut PlayerEventQueue,
+            &mut PhysicsBody,
+        )>();
+
+        for (_, (transform, player, inventory, events, body)) in query.iter() {
+            let position = map.get_random_spawn_point(&mut simulation.rng);
+
+            *player = Player::new(player.index, player.team, position);
+
+            transform.position = position;
+            body.velocity = Vec2::ZERO;
+
+            inventory.weapon = None;
+            inventory.items.clear();
+            inventory.hat = None;
+
+            events.queue.clear();
+        }
+    }
+
+    if let Some(mut rules) = get_match_rules_mut(world) {
+        rules.round += 1;
+        rules.round_end_timer = None;
+    }
+}
+
+/// Draws the score of every player at the top of the screen, and the winner, when a round or the
+/// match has ended
+pub fn draw_match_hud(world: &mut World) {
+    let (label, banner) = {
+        let rules = match get_match_rules_mut(world) {
+            Some(rules) => rules,
+            None => return,
+        };
+
+        let scores = rules
+            .sides()
+            .into_iter()
+            .map(|side| {
+                let label = match side {
+                    Side::Player(index) => format!("P{}", index + 1),
+                    Side::Team(team) => format!("Team {}", team + 1),
+                };
+
+                match rules.win_condition {
+                    WinCondition::KillCnt(_) => format!("{}: {}", label, rules.side_kills(side)),
+                    WinCondition::LastFishStanding(_) => {
+                        let round_wins = rules.round_wins.get(&side).copied().unwrap_or_default();
+                        format!("{}: {}", label, round_wins)
+                    }
+                    WinCondition::Stocks(_) => match rules.side_lives(side) {
+                        0 => format!("{}: out", label),
+                        lives => format!("{}: {} lives", label, lives),
+                    },
+                    WinCondition::KingOfTheHill(_) => {
+                        format!("{}: {:.0}s", label, rules.side_hold_time(side).floor())
+                    }
+                    WinCondition::CaptureTheFlag(_) => {
+                        format!("{}: {}", label, rules.side_captures(side))
+                    }
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("   ");
+
+        let label = match rules.win_condition {
+            WinCondition::KillCnt(kill_cnt) => {
+                format!("First to {} kills  -  {}", kill_cnt, scores)
+            }
+            WinCondition::LastFishStanding(round_cnt) => format!(
+                "Round {}, first to {} wins  -  {}",
+                rules.round, round_cnt, scores
+            ),
+            WinCondition::Stocks(_) => format!("Last fish with lives wins  -  {}", scores),
+            WinCondition::KingOfTheHill(hold_time) => {
+                format!("Hold the zone for {}s  -  {}", hold_time, scores)
+            }
+            WinCondition::CaptureTheFlag(capture_cnt) => {
+                format!("First to {} captures  -  {}", capture_cnt, scores)
+            }
+        };
+
+        let banner = if let Some(result) = &rules.result {
+            Some(match result.winner {
+                Some(side) => format!("{} wins the match!", side.label()),
+                None => "The match is a draw!".to_string(),
+            })
+        } else if rules.round_end_timer.is_some() {
+            Some(format!("Round {} is over", rules.round))
+        } else {
+            None
+        };
+
+        (label, banner)
+    };
+
+    push_camera_state();
+    set_default_camera();
+
+    draw_text(
+        &label,
+        HUD_MARGIN,
+        HUD_MARGIN + HUD_FONT_SIZE,
+        HUD_FONT_SIZE,
+        color::WHITE,
+    );
+
+    if let Some(banner) = banner {
+        let size = measure_text(&banner, None, HUD_BANNER_FONT_SIZE as u16, 1.0);
+
+        draw_text(
+            &banner,
+            (screen_width() - size.width) / 2.0,
+            screen_height() / 2.0,
+            HUD_BANNER_FONT_SIZE,
+            color::WHITE,
+        );
+    }
+
+    pop_camera_state();
+}
+
+#[cfg(test)]
+mod tests {
+    use core::input::InputTrack;
+
+    use crate::game::{
+        default_assets_dir, spawn_simulation, HeadlessGame, Replay, ReplayPlayer, FIXED_DELTA_TIME,
+    };
+    use crate::map::{fixed_update_capture_zones, spawn_capture_zone, CaptureZone};
+
+    use super::*;
+
+    fn params(win_condition: WinCondition) -> MatchRulesParams {
+        MatchRulesParams {
+            win_condition,
+            ..Default::default()
+        }
+    }
+
+    /// A world with only the simulation and the match rules, for rules that do not depend on the
+    /// state of the players
+    fn rules_world(win_condition: WinCondition, players: &[(u8, Option<u8>)]) -> World {
+        let mut world = World::default();
+
+        spawn_simulation(&mut world, 0, true);
+        spawn_match_rules(&mut world, params(win_condition), players);
+
+        world
+    }
+
+    fn kill(game: &mut HeadlessGame, index: u8) {
+        let entity = game.player(index).unwrap();
+        game.world_mut().get_mut::<Player>(entity).unwrap().state = PlayerState::Dead;
+    }
+
+    /// A king of the hill match. No map has capture zones, so the match is started in another mode
+    /// and zones are spawned by the tests.
+    fn king_of_the_hill_game(teams: &[Option<u8>], hold_time: u32) -> HeadlessGame {
+        let players = teams
+            .iter()
+            .enumerate()
+            .map(|(index, team)| ReplayPlayer {
+                team: *team,
+                ..ReplayPlayer::new(index as u8, "pescy", InputTrack::new())
+            })
+            .collect();
+
+        let replay = Replay::from_script("lev01", 0, MatchRulesParams::default(), players);
+
+        let game = HeadlessGame::new(default_assets_dir(), &replay).unwrap();
+
+        get_match_rules_mut(game.world()).unwrap().win_condition =
+            WinCondition::KingOfTheHill(hold_time);
+
+        game
+    }
+
+    /// Spawn a capture zone outside of the map, so that players are only in it once they have
+    /// been moved into it
+    fn spawn_zone(game: &mut HeadlessGame) -> Entity {
+        spawn_capture_zone(game.world_mut(), vec2(-1000.0, -1000.0), vec2(128.0, 128.0))
+    }
+
+    fn enter_zone(game: &mut HeadlessGame, index: u8, zone: Entity) {
+        let position = game.world().get::<Transform>(zone).unwrap().position;
+
+        let entity = game.player(index).unwrap();
+        game.world_mut()
+            .get_mut::<Transform>(entity)
+            .unwrap()
+            .position = position;
+    }
+
+    fn update_capture_zones(game: &mut HeadlessGame, frame_cnt: u32) {
+        for _ in 0..frame_cnt {
+            fixed_update_capture_zones(game.world_mut());
+        }
+    }
+
+    fn hold_time(game: &HeadlessGame, side: Side) -> f32 {
+        get_match_rules_mut(game.world())
+            .unwrap()
+            .side_hold_time(side)
+    }
+
+    #[test]
+    fn team_kills_are_not_credited() {
+        let players = [(0, Some(0)), (1, Some(0)), (2, Some(1))];
+        let mut rules = MatchRules::new(params(WinCondition::KillCnt(1)), &players);
+
+        rules.record_death(1, Some(0));
+
+        assert_eq!(rules.scores[&0].kills, 0);
+        assert_eq!(rules.scores[&1].deaths, 1);
+        assert!(rules.result.is_none());
+
+        // Suicides are not credited either
+        rules.record_death(0, Some(0));
+
+        assert_eq!(rules.scores[&0].kills, 0);
+        assert!(rules.result.is_none());
+
+        rules.record_death(2, Some(0));
+
+        assert_eq!(rules.scores[&0].kills, 1);
+        assert_eq!(rules.result.unwrap().winner, Some(Side::Team(0)));
+    }
+
+    #[test]
+    fn first_to_kill_cnt_wins() {
+        let players = [(0, None), (1, None), (2, None)];
+        let mut rules = MatchRules::new(params(WinCondition::KillCnt(2)), &players);
+
+        rules.record_death(1, Some(0));
+        rules.record_death(0, Some(2));
+
+        assert!(rules.result.is_none());
+
+        rules.record_death(2, Some(0));
+
+        let result = rules.result.clone().unwrap();
+        assert_eq!(result.winner, Some(Side::Player(0)));
+        assert_eq!(result.scores[&0].kills, 2);
+
+        // Nothing is recorded once the match has ended
+        rules.record_death(0, Some(2));
+
+        assert_eq!(rules.scores[&0].deaths, 1);
+        assert_eq!(rules.scores[&2].kills, 1);
+    }
+
+    #[test]
+    fn last_fish_standing_ends_round_then_match() {
+        let players = vec![
+            ReplayPlayer::new(0, "pescy", InputTrack::new()),
+            ReplayPlayer::new(1, "sharky", InputTrack::new()),
+        ];
+
+        let replay = Replay::from_script(
+            "lev01",
+            0,
+            params(WinCondition::LastFishStanding(2)),
+            players,
+        );
+
+        let mut game = HeadlessGame::new(default_assets_dir(), &replay).unwrap();
+
+        game.run(1);
+        kill(&mut game, 1);
+        game.run(1);
+
+        {
+            let rules = get_match_rules_mut(game.world()).unwrap();
+
+            assert!(rules.round_end_timer.is_some());
+            assert_eq!(rules.round_wins.get(&Side::Player(0)), Some(&1));
+            assert!(rules.result.is_none());
+        }
+
+        // The dead player is not respawned until the next round has started
+        let round_end_frame_cnt = (ROUND_END_DELAY / FIXED_DELTA_TIME).ceil() as u64 + 1;
+        game.run(round_end_frame_cnt);
+
+        {
+            let rules = get_match_rules_mut(game.world()).unwrap();
+
+            assert_eq!(rules.round, 2);
+            assert!(rules.round_end_timer.is_none());
+        }
+
+        let entity = game.player(1).unwrap();
+        assert_ne!(
+            game.world().get::<Player>(entity).unwrap().state,
+            PlayerState::Dead
+        );
+
+        kill(&mut game, 1);
+        game.run(1);
+
+        let rules = get_match_rules_mut(game.world()).unwrap();
+        let result = rules.result.clone().unwrap();
+
+        assert_eq!(result.winner, Some(Side::Player(0)));
+        assert_eq!(result.round_cnt, 2);
+    }
+
+    #[test]
+    fn stocks_is_a_draw_when_last_lives_are_lost_on_same_frame() {
+        let mut world = rules_world(WinCondition::Stocks(1), &[(0, None), (1, None)]);
+
+        {
+            let mut rules = get_match_rules_mut(&world).unwrap();
+
+            rules.record_death(0, Some(1));
+            rules.record_death(1, Some(0));
+
+            assert!(rules.is_eliminated(0));
+            assert!(rules.is_eliminated(1));
+        }
+
+        fixed_update_match_rules(&mut world);
+
+        let rules = get_match_rules_mut(&world).unwrap();
+        let result = rules.result.clone().unwrap();
+
+        assert_eq!(result.winner, None);
+    }
+
+    #[test]
+    fn king_of_the_hill_is_won_by_total_hold_time() {
+        let players = [(0, Some(0)), (1, Some(0)), (2, Some(1))];
+        let mut rules = MatchRules::new(params(WinCondition::KingOfTheHill(10)), &players);
+
+        rules.record_hold_time(0, 4.0);
+        rules.record_hold_time(2, 8.0);
+
+        assert!(rules.result.is_none());
+
+        // The hold time of a side is the total of its players
+        rules.record_hold_time(1, 6.0);
+
+        assert_eq!(rules.side_hold_time(Side::Team(0)), 10.0);
+        assert_eq!(rules.result.clone().unwrap().winner, Some(Side::Team(0)));
+
+        // Nothing is recorded once the match has ended
+        rules.record_hold_time(2, 4.0);
+
+        assert_eq!(rules.side_hold_time(Side::Team(1)), 8.0);
+
+        // Nor in other modes
+        let mut rules = MatchRules::new(params(WinCondition::KillCnt(1)), &players);
+        rules.record_hold_time(0, 20.0);
+
+        assert_eq!(rules.side_hold_time(Side::Team(0)), 0.0);
+        assert!(rules.result.is_none());
+    }
+
+    #[test]
+    fn capture_zones_are_held_by_the_only_side_inside() {
+        let mut game = king_of_the_hill_game(&[None, None], 2);
+        let zone = spawn_zone(&mut game);
+
+        enter_zone(&mut game, 0, zone);
+        update_capture_zones(&mut game, 60);
+
+        {
+            let zone = game.world().get::<CaptureZone>(zone).unwrap();
+
+            assert_eq!(zone.holder, Some(Side::Player(0)));
+            assert!(!zone.is_contested);
+        }
+
+        assert!((hold_time(&game, Side::Player(0)) - 1.0).abs() < 0.001);
+        assert_eq!(hold_time(&game, Side::Player(1)), 0.0);
+        assert!(get_match_rules_mut(game.world()).unwrap().result.is_none());
+
+        update_capture_zones(&mut game, 61);
+
+        let rules = get_match_rules_mut(game.world()).unwrap();
+        assert_eq!(rules.result.clone().unwrap().winner, Some(Side::Player(0)));
+    }
+
+    #[test]
+    fn contested_capture_zones_are_not_held() {
+        let mut game = king_of_the_hill_game(&[None, None], 10);
+        let zone = spawn_zone(&mut game);
+
+        enter_zone(&mut game, 0, zone);
+        enter_zone(&mut game, 1, zone);
+        update_capture_zones(&mut game, 60);
+
+        {
+            let zone = game.world().get::<CaptureZone>(zone).unwrap();
+
+            assert_eq!(zone.holder, None);
+            assert!(zone.is_contested);
+        }
+
+        assert_eq!(hold_time(&game, Side::Player(0)), 0.0);
+        assert_eq!(hold_time(&game, Side::Player(1)), 0.0);
+
+        // Dead players do not contest zones
+        kill(&mut game, 1);
+        update_capture_zones(&mut game, 1);
+
+        let zone = game.world().get::<CaptureZone>(zone).unwrap();
+
+        assert_eq!(zone.holder, Some(Side::Player(0)));
+        assert!(!zone.is_contested);
+    }
+
+    #[test]
+    fn hold_time_is_shared_between_teammates_in_a_capture_zone() {
+        let mut game = king_of_the_hill_game(&[Some(0), Some(0), Some(1)], 10);
+        let zone = spawn_zone(&mut game);
+
+        enter_zone(&mut game, 0, zone);
+        enter_zone(&mut game, 1, zone);
+        update_capture_zones(&mut game, 60);
+
+        assert_eq!(
+            game.world().get::<CaptureZone>(zone).unwrap().holder,
+            Some(Side::Team(0))
+        );
+
+        // The side is credited the time it held the zone, no matter how many players were in it
+        assert!((hold_time(&game, Side::Team(0)) - 1.0).abs() < 0.001);
+
+        let rules = get_match_rules_mut(game.world()).unwrap();
+        assert!((rules.scores[&0].hold_time - 0.5).abs() < 0.001);
+        assert!((rules.scores[&1].hold_time - 0.5).abs() < 0.001);
+    }
+}
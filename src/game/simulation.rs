@@ -0,0 +1,86 @@
+use macroquad::prelude::*;
+
+use hecs::{Entity, RefMut, World};
+
+use core::random::{RandomRange, Rng};
+
+/// The time step of the fixed updates, used as delta time by all simulation systems when the game
+/// is run in deterministic mode.
+pub const FIXED_DELTA_TIME: f32 = 1.0 / 60.0;
+
+/// This holds the state that is global to the simulation of a match. It is stored as a component
+/// on a single entity in the world, so that it is captured along with the rest of the world, by
+/// snapshots.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    pub is_deterministic: bool,
+    /// The amount of fixed updates that has been run
+    pub frame: u64,
+    pub rng: Rng,
+    /// The RNG of bots. Only the input of bots is recorded, so they are not run when a replay is
+    /// played back, and they draw from this, to leave `rng` in the same state in both cases.
+    pub ai_rng: Rng,
+}
+
+impl Simulation {
+    pub fn new(seed: u64, is_deterministic: bool) -> Self {
+        Simulation {
+            is_deterministic,
+            frame: 0,
+            rng: Rng::new(seed),
+            ai_rng: Rng::new(!seed),
+        }
+    }
+
+    /// The delta time that simulation systems should use for timers
+    pub fn dt(&self) -> f32 {
+        if self.is_deterministic {
+            FIXED_DELTA_TIME
+        } else {
+            get_frame_time()
+        }
+    }
+}
+
+pub fn spawn_simulation(world: &mut World, seed: u64, is_deterministic: bool) -> Entity {
+    world.spawn((Simulation::new(seed, is_deterministic),))
+}
+
+fn simulation_entity(world: &World) -> Option<Entity> {
+    world
+        .query::<&Simulation>()
+        .iter()
+        .next()
+        .map(|(entity, _)| entity)
+}
+
+/// Get the simulation state of the world. This will panic if no `Simulation` has been spawned.
+/// As the borrow is dynamic, this can be held while running queries for other components, as long
+/// as these are done with `World::query` and not `World::query_mut`.
+pub fn get_simulation_mut(world: &World) -> RefMut<'_, Simulation> {
+    let entity = simulation_entity(world).expect("No simulation has been spawned in the world");
+    world.get_mut::<Simulation>(entity).unwrap()
+}
+
+/// Returns the delta time that simulation systems should use for timers. This will fall back to
+/// the frame time if no `Simulation` has been spawned.
+pub fn get_simulation_dt(world: &World) -> f32 {
+    world
+        .query::<&Simulation>()
+        .iter()
+        .next()
+        .map(|(_, simulation)| simulation.dt())
+        .unwrap_or_else(get_frame_time)
+}
+
+/// Generate a random number in the range `low..high`, using the seeded RNG of the simulation
+pub fn simulation_gen_range<T: RandomRange>(world: &World, low: T, high: T) -> T {
+    get_simulation_mut(world).rng.gen_range(low, high)
+}
+
+/// This increments the frame counter and should be the last system of the fixed updates
+pub fn fixed_update_simulation(world: &mut World) {
+    for (_, simulation) in world.query_mut::<&mut Simulation>() {
+        simulation.frame += 1;
+    }
+}
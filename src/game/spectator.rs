@@ -0,0 +1,158 @@
+//! Camera controls and HUD for spectators. By default, the camera follows all players, as it does
+//! for players. `Tab` cycles through following each player on their own, and `F` toggles a free
+//! camera, that is moved with the arrow keys, or WASD, and zoomed with the mouse wheel.
+//!
+//! Players that have been eliminated from a match, and have no other local players left in it, get
+//! the same controls, to spectate the remaining players.
+
+use macroquad::color;
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+
+use hecs::World;
+
+use crate::game::get_match_rules_mut;
+use crate::player::{Player, PlayerController};
+use crate::GameCamera;
+
+/// The speed of the free camera, in screen heights per second
+const FREE_CAMERA_SPEED: f32 = 1.0;
+
+const FREE_CAMERA_ZOOM_STEP: f32 = 1.1;
+const FREE_CAMERA_MIN_ZOOM: f32 = 200.0;
+const FREE_CAMERA_MAX_ZOOM: f32 = 3000.0;
+
+const HUD_FONT_SIZE: f32 = 20.0;
+const HUD_MARGIN: f32 = 16.0;
+
+/// Returns `true` if there are local players in the match, and all of them have been eliminated
+fn is_local_play_over(world: &World) -> bool {
+    let rules = match get_match_rules_mut(world) {
+        Some(rules) => rules,
+        None => return false,
+    };
+
+    let local_players = world
+        .query::<(&Player, &PlayerController)>()
+        .iter()
+        .filter(|(_, (_, controller))| controller.kind.is_local())
+        .map(|(_, (player, _))| player.index)
+        .collect::<Vec<_>>();
+
+    !local_players.is_empty()
+        && local_players
+            .iter()
+            .all(|index| rules.is_eliminated(*index))
+}
+
+/// Gives the spectator camera controls to local players, once they have all been eliminated
+pub fn update_eliminated_camera(world: &mut World) {
+    if is_local_play_over(world) {
+        update_spectator_camera(world);
+    }
+}
+
+pub fn draw_eliminated_hud(world: &mut World) {
+    if is_local_play_over(world) {
+        draw_spectator_hud(world);
+    }
+}
+
+pub fn update_spectator_camera(world: &mut World) {
+    let eliminated = get_match_rules_mut(world)
+        .map(|rules| rules.eliminated_players())
+        .unwrap_or_default();
+
+    let mut indices = world
+        .query::<&Player>()
+        .iter()
+        .map(|(_, player)| player.index)
+        .filter(|index| !eliminated.contains(index))
+        .collect::<Vec<_>>();
+
+    indices.sort_unstable();
+
+    let mut camera = storage::get_mut::<GameCamera>();
+
+    // Cycles from following all players, through each player, and back
+    if is_key_pressed(KeyCode::Tab) {
+        camera.followed_player = match camera.followed_player {
+            Some(current) => indices.iter().copied().find(|index| *index > current),
+            None => indices.first().copied(),
+        };
+
+        camera.manual = None;
+    }
+
+    if is_key_pressed(KeyCode::F) {
+        camera.manual = match camera.manual {
+            Some(_) => None,
+            None => camera.view(),
+        };
+    }
+
+    if let Some((position, zoom)) = &mut camera.manual {
+        let mut direction = Vec2::ZERO;
+
+        if is_key_down(KeyCode::Left) || is_key_down(KeyCode::A) {
+            direction.x -= 1.0;
+        }
+
+        if is_key_down(KeyCode::Right) || is_key_down(KeyCode::D) {
+            direction.x += 1.0;
+        }
+
+        if is_key_down(KeyCode::Up) || is_key_down(KeyCode::W) {
+            direction.y -= 1.0;
+        }
+
+        if is_key_down(KeyCode::Down) || is_key_down(KeyCode::S) {
+            direction.y += 1.0;
+        }
+
+        *position += direction * FREE_CAMERA_SPEED * *zoom * get_frame_time();
+
+        let (_, wheel) = mouse_wheel();
+
+        if wheel > 0.0 {
+            *zoom /= FREE_CAMERA_ZOOM_STEP;
+        } else if wheel < 0.0 {
+            *zoom *= FREE_CAMERA_ZOOM_STEP;
+        }
+
+        *zoom = zoom.clamp(FREE_CAMERA_MIN_ZOOM, FREE_CAMERA_MAX_ZOOM);
+    }
+}
+
+/// Draws the camera mode and controls at the bottom of the screen
+pub fn draw_spectator_hud(_world: &mut World) {
+    let label = {
+        let camera = storage::get::<GameCamera>();
+
+        let mode = if camera.manual.is_some() {
+            "free camera".to_string()
+        } else if let Some(index) = camera.followed_player {
+            format!("following player {}", index + 1)
+        } else {
+            "following all players".to_string()
+        };
+
+        format!(
+            "Spectating, {}  -  [Tab] Follow player  [F] Free camera",
+            mode
+        )
+    };
+
+    push_camera_state();
+    set_default_camera();
+
+    draw_text(
+        &label,
+        HUD_MARGIN,
+        screen_height() - HUD_MARGIN,
+        HUD_FONT_SIZE,
+        color::WHITE,
+    );
+
+    pop_camera_state();
+}
@@ -0,0 +1,193 @@
+//! Statistics of every player in a match, shown on the results screen. These are kept in a
+//! `PlayerStats` component on the player entities, so that they are rolled back along with the
+//! rest of the world, and are not reset between rounds.
+
+use std::collections::BTreeMap;
+
+use hecs::{Entity, World};
+
+use crate::player::{Player, PlayerEvent, PlayerEventQueue, PlayerState};
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct PlayerStats {
+    pub kills: u32,
+    pub deaths: u32,
+    /// Deaths caused by the player itself, or by nothing but the map
+    pub suicides: u32,
+    pub items_picked_up: u32,
+    pub shots_fired: u32,
+    /// The amount of times the player has damaged another player
+    pub hits: u32,
+}
+
+impl PlayerStats {
+    /// The share of shots that hit another player, or `None` if no shots have been fired. As a
+    /// single shot can hit more than one player, this is capped at `1.0`.
+    pub fn accuracy(&self) -> Option<f32> {
+        if self.shots_fired > 0 {
+            Some((self.hits as f32 / self.shots_fired as f32).min(1.0))
+        } else {
+            None
+        }
+    }
+}
+
+/// Count a shot fired by a player. This is called by `fire_weapon`.
+pub fn record_shot_fired(world: &World, player_entity: Entity) {
+    if let Ok(mut stats) = world.get_mut::<PlayerStats>(player_entity) {
+        stats.shots_fired += 1;
+    }
+}
+
+/// Count an item picked up by a player
+pub fn record_item_picked_up(world: &World, player_entity: Entity) {
+    if let Ok(mut stats) = world.get_mut::<PlayerStats>(player_entity) {
+        stats.items_picked_up += 1;
+    }
+}
+
+/// Get the stats of every player in the world, by player index
+pub fn collect_player_stats(world: &World) -> BTreeMap<u8, PlayerStats> {
+    world
+        .query::<(&Player, &PlayerStats)>()
+        .iter()
+        .map(|(_, (player, stats))| (player.index, *stats))
+        .collect()
+}
+
+/// Counts hits, kills and deaths from the `GiveDamage` and `ReceiveDamage` events of the players.
+/// This must run before `update_player_events`, which handles, and clears, the event queues.
+pub fn update_player_stats(world: &mut World) {
+    let mut kills = Vec::new();
+
+    for (entity, (player, events, stats)) in world
+        .query::<(&Player, &PlayerEventQueue, &mut PlayerStats)>()
+        .iter()
+    {
+        for event in &events.queue {
+            if let PlayerEvent::GiveDamage {
+                damage_to: Some(damage_to),
+            } = event
+            {
+                if *damage_to != entity {
+                    stats.hits += 1;
+                }
+            }
+        }
+
+        if player.state != PlayerState::Dead {
+            if let Some(damage_from) = events.fatal_damage() {
+                stats.deaths += 1;
+
+                match damage_from {
+                    Some(killer) if killer != entity => kills.push(killer),
+                    _ => stats.suicides += 1,
+                }
+            }
+        }
+    }
+
+    for killer in kills {
+        if let Ok(mut stats) = world.get_mut::<PlayerStats>(killer) {
+            stats.kills += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::input::InputTrack;
+
+    use crate::game::{
+        default_assets_dir, get_match_rules_mut, HeadlessGame, MatchRulesParams, Replay,
+        ReplayPlayer,
+    };
+
+    use super::*;
+
+    fn stats_game() -> HeadlessGame {
+        let players = ["pescy", "sharky", "fishy"]
+            .iter()
+            .enumerate()
+            .map(|(index, character)| ReplayPlayer::new(index as u8, character, InputTrack::new()))
+            .collect();
+
+        let replay = Replay::from_script("lev01", 0, MatchRulesParams::default(), players);
+
+        HeadlessGame::new(default_assets_dir(), &replay).unwrap()
+    }
+
+    fn push_event(game: &mut HeadlessGame, index: u8, event: PlayerEvent) {
+        let entity = game.player(index).unwrap();
+
+        game.world_mut()
+            .get_mut::<PlayerEventQueue>(entity)
+            .unwrap()
+            .queue
+            .push(event);
+    }
+
+    fn damage(game: &mut HeadlessGame, index: u8, damage_from: Option<u8>) {
+        let damage_from = damage_from.map(|index| game.player(index).unwrap());
+
+        push_event(
+            game,
+            index,
+            PlayerEvent::ReceiveDamage {
+                is_from_left: true,
+                damage_from,
+            },
+        );
+    }
+
+    /// Returns the kills, deaths and suicides in the stats of every player, checking that the
+    /// kills and deaths agree with its score
+    fn accounting(game: &HeadlessGame) -> Vec<(u32, u32, u32)> {
+        let stats = collect_player_stats(game.world());
+        let rules = get_match_rules_mut(game.world()).unwrap();
+
+        stats
+            .iter()
+            .map(|(index, stats)| {
+                let score = &rules.scores[index];
+
+                assert_eq!((score.kills, score.deaths), (stats.kills, stats.deaths));
+
+                (stats.kills, stats.deaths, stats.suicides)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kills_deaths_and_suicides_are_counted() {
+        let mut game = stats_game();
+        game.run(1);
+
+        // Player 0 kills player 1, player 2 kills itself, and player 0 blocks the damage it takes
+        let target = game.player(1);
+        push_event(&mut game, 0, PlayerEvent::GiveDamage { damage_to: target });
+        damage(&mut game, 1, Some(0));
+        damage(&mut game, 2, Some(2));
+        damage(&mut game, 0, Some(2));
+        push_event(
+            &mut game,
+            0,
+            PlayerEvent::DamageBlocked { is_from_left: true },
+        );
+
+        game.run(1);
+
+        assert_eq!(accounting(&game), vec![(1, 0, 0), (0, 1, 0), (0, 1, 1)]);
+
+        let stats = collect_player_stats(game.world());
+        assert_eq!(stats[&0].hits, 1);
+
+        // Dead players are not killed again, and dying to the map counts as a suicide
+        damage(&mut game, 1, Some(0));
+        damage(&mut game, 0, None);
+
+        game.run(1);
+
+        assert_eq!(accounting(&game), vec![(1, 1, 1), (0, 1, 0), (0, 1, 1)]);
+    }
+}
@@ -0,0 +1,128 @@
+use macroquad::{
+    experimental::collections::storage,
+    prelude::*,
+    ui::{hash, root_ui},
+};
+
+use core::history::MatchHistory;
+use core::input::update_gamepad_context;
+
+use super::{draw_main_menu_background, GuiResources, Menu, MenuEntry, MenuPosition, Panel};
+
+const PANEL_WIDTH: f32 = 760.0;
+const PANEL_ROW_HEIGHT: f32 = 32.0;
+const PANEL_MENU_MARGIN: f32 = 16.0;
+
+const MENU_WIDTH: f32 = 300.0;
+
+/// The amount of characters that are shown on the leaderboard
+const MAX_ROWS: usize = 10;
+
+/// The labels and x offsets of the columns of the leaderboard
+const COLUMNS: [(&str, f32); 7] = [
+    ("Character", 0.0),
+    ("Matches", 180.0),
+    ("Wins", 290.0),
+    ("Win Rate", 370.0),
+    ("Kills", 490.0),
+    ("Deaths", 570.0),
+    ("K/D", 670.0),
+];
+
+/// Show the characters with the most wins in the local match history, until the player goes back
+pub async fn show_leaderboard() {
+    let (title, rows) = match MatchHistory::load(crate::match_history_path()) {
+        Ok(history) => {
+            let title = format!("Leaderboard ({} matches)", history.matches.len());
+
+            let rows = history
+                .leaderboard()
+                .into_iter()
+                .take(MAX_ROWS)
+                .map(|entry| {
+                    [
+                        entry.character_name.clone(),
+                        entry.matches.to_string(),
+                        entry.wins.to_string(),
+                        format!("{:.0}%", entry.win_rate() * 100.0),
+                        entry.kills.to_string(),
+                        entry.deaths.to_string(),
+                        format!("{:.2}", entry.kill_death_ratio()),
+                    ]
+                })
+                .collect::<Vec<_>>();
+
+            (title, rows)
+        }
+        Err(err) => {
+            #[cfg(debug_assertions)]
+            println!("WARNING: Unable to load the match history: {}", err);
+
+            (
+                "The match history could not be loaded".to_string(),
+                Vec::new(),
+            )
+        }
+    };
+
+    let mut menu = Menu::new(
+        hash!("leaderboard"),
+        MENU_WIDTH,
+        &[MenuEntry {
+            index: 0,
+            title: "Back".to_string(),
+            ..Default::default()
+        }],
+    );
+
+    // Skip a frame to let the press that opened the leaderboard be unpressed
+    next_frame().await;
+
+    loop {
+        update_gamepad_context(None).unwrap();
+
+        draw_main_menu_background(false);
+
+        // Title, column headers and a row for every character
+        let panel_size = vec2(PANEL_WIDTH, PANEL_ROW_HEIGHT * (rows.len() + 3) as f32);
+        let panel_position = vec2(
+            (screen_width() - panel_size.x) / 2.0,
+            (screen_height() - panel_size.y) / 4.0,
+        );
+
+        Panel::new(hash!("leaderboard", "table"), panel_size, panel_position).ui(
+            &mut root_ui(),
+            |ui, _| {
+                {
+                    let gui_resources = storage::get::<GuiResources>();
+                    ui.push_skin(&gui_resources.skins.menu);
+                }
+
+                ui.label(vec2(0.0, 0.0), &title);
+
+                for (label, x) in COLUMNS {
+                    ui.label(vec2(x, PANEL_ROW_HEIGHT), label);
+                }
+
+                for (i, row) in rows.iter().enumerate() {
+                    let y = PANEL_ROW_HEIGHT * (i + 2) as f32;
+
+                    for (value, (_, x)) in row.iter().zip(COLUMNS) {
+                        ui.label(vec2(x, y), value);
+                    }
+                }
+
+                ui.pop_skin();
+            },
+        );
+
+        let menu_position = panel_position.y + panel_size.y + PANEL_MENU_MARGIN;
+        menu = menu.with_position(MenuPosition::AbsoluteVertical(menu_position));
+
+        if menu.ui(&mut root_ui()).is_some() {
+            return;
+        }
+
+        next_frame().await;
+    }
+}
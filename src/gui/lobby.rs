@@ -0,0 +1,619 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use macroquad::{
+    color,
+    experimental::collections::storage,
+    prelude::*,
+    ui::{hash, root_ui, widgets},
+};
+
+use core::error::{Error, ErrorKind};
+use core::input::GameInputScheme;
+use core::network::{
+    Api, Lobby, LobbyApiBackend, LobbyBackendParams, LobbyPrivacy, LobbyServer, LobbyState,
+    NetworkEvent, PlayerId,
+};
+use core::Result;
+
+use super::{
+    draw_main_menu_background, show_select_map_menu, Checkbox, GuiResources, Menu, MenuEntry,
+    Panel, ELEMENT_MARGIN,
+};
+
+use crate::game::{GameMode, MatchRulesParams};
+use crate::player::{PlayerControllerKind, PlayerParams};
+use crate::{Config, Map, Resources};
+
+const LOBBY_LIST_MENU_WIDTH: f32 = 400.0;
+
+const LOBBY_LIST_OPTION_REFRESH: usize = 9999;
+
+const LOBBY_CAPACITY: i32 = 4;
+
+const LOBBY_MENU_WIDTH: f32 = 500.0;
+const LOBBY_MENU_HEIGHT: f32 = 420.0;
+
+const PLAYER_ROW_HEIGHT: f32 = 32.0;
+
+const NAVIGATION_BTN_WIDTH: f32 = 32.0;
+
+/// The amount of teams that players can be put on, in a lobby
+const LOBBY_TEAM_CNT: u8 = 2;
+
+/// The parameters of a network game, as agreed upon in the lobby
+pub struct NetworkGameParams {
+    pub mode: GameMode,
+    pub map: Map,
+    pub map_name: String,
+    pub players: Vec<PlayerParams>,
+    pub rules: MatchRulesParams,
+    pub seed: u64,
+    pub host_player_id: PlayerId,
+}
+
+/// Select a map and host a new lobby. Returns the parameters of the game when it is started, or
+/// `None` if the player left the lobby, or the lobby server could not be reached.
+pub async fn show_host_game_menu() -> Option<NetworkGameParams> {
+    let map_resource = show_select_map_menu().await;
+
+    let res = host_game(&map_resource.meta.path).await;
+    close_api_unless_started(res).await
+}
+
+/// Select an open lobby and join it, or a running lobby and spectate it. Returns the parameters of
+/// the game when it is started, or `None` if the player cancelled, or the lobby server could not be
+/// reached.
+pub async fn show_join_game_menu() -> Option<NetworkGameParams> {
+    let res = join_game().await;
+    close_api_unless_started(res).await
+}
+
+async fn host_game(map: &str) -> Result<Option<NetworkGameParams>> {
+    connect_to_lobby_server(true).await?;
+    create_lobby(map)?;
+
+    show_lobby_menu().await
+}
+
+async fn join_game() -> Result<Option<NetworkGameParams>> {
+    connect_to_lobby_server(false).await?;
+
+    match show_lobby_list_menu().await? {
+        Some(lobby) if lobby.state == LobbyState::Running => {
+            Api::spectate_lobby(&lobby.id)?;
+            wait_for_spectated_game().await
+        }
+        Some(lobby) => {
+            Api::join_lobby(&lobby.id)?;
+            show_lobby_menu().await
+        }
+        None => Ok(None),
+    }
+}
+
+/// The `Api` is kept for the game, if it was started, and closed otherwise
+async fn close_api_unless_started(
+    res: Result<Option<NetworkGameParams>>,
+) -> Option<NetworkGameParams> {
+    match res {
+        Ok(Some(params)) => return Some(params),
+        Ok(None) => {}
+        Err(err) => {
+            #[cfg(debug_assertions)]
+            println!("WARNING: Network game: {}", err);
+        }
+    }
+
+    if let Err(err) = Api::close().await {
+        #[cfg(debug_assertions)]
+        println!("WARNING: Network game: {}", err);
+    }
+
+    None
+}
+
+/// Connect to the lobby server in the config and initialize the `Api` with a `LobbyApiBackend`.
+/// If `is_host` is `true`, a lobby server will be started in the background first, unless one is
+/// already listening on the configured port.
+async fn connect_to_lobby_server(is_host: bool) -> Result<()> {
+    let config = storage::get::<Config>().network.clone();
+
+    let server_addr = config
+        .lobby_server
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| Error::new_const(ErrorKind::Config, &"Invalid lobby server address"))?;
+
+    // The host connects to the server it started through the loopback interface. The server will
+    // advertise the host to the other players by the address that they reached the server at.
+    let server_addr = if is_host {
+        let bind_addr = SocketAddr::from(([0, 0, 0, 0], server_addr.port()));
+
+        match LobbyServer::spawn(bind_addr) {
+            Ok((local_addr, _)) => SocketAddr::from(([127, 0, 0, 1], local_addr.port())),
+            Err(err) => {
+                #[cfg(debug_assertions)]
+                println!(
+                    "WARNING: Unable to start lobby server, connecting to {} instead: {}",
+                    server_addr, err
+                );
+
+                server_addr
+            }
+        }
+    } else {
+        server_addr
+    };
+
+    let params = LobbyBackendParams {
+        server_addr,
+        username: config.username,
+        game_addr: SocketAddr::from(([0, 0, 0, 0], 0)),
+    };
+
+    Api::init::<LobbyApiBackend>(params).await
+}
+
+/// Create a new lobby, with the selected map, and make the local player its admin
+fn create_lobby(map: &str) -> Result<()> {
+    let name = format!("{}'s game", storage::get::<Config>().network.username);
+
+    Api::create_lobby(&name, LOBBY_CAPACITY, LobbyPrivacy::Public)?;
+    Api::set_lobby_map(map)
+}
+
+fn build_lobby_list_menu(lobbies: &[Lobby]) -> Menu {
+    let mut entries = lobbies
+        .iter()
+        .enumerate()
+        .map(|(i, lobby)| {
+            let title = if lobby.state == LobbyState::Running {
+                format!("{} (spectate)", lobby.name)
+            } else {
+                format!("{} ({}/{})", lobby.name, lobby.player_count, lobby.capacity)
+            };
+
+            MenuEntry {
+                index: i,
+                title,
+                ..Default::default()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    entries.push(MenuEntry {
+        index: LOBBY_LIST_OPTION_REFRESH,
+        title: "Refresh".to_string(),
+        is_pulled_down: true,
+        ..Default::default()
+    });
+
+    Menu::new(
+        hash!("lobby_list", lobbies.len()),
+        LOBBY_LIST_MENU_WIDTH,
+        &entries,
+    )
+    .with_header("Join Game")
+    .with_cancel_button(None)
+}
+
+/// Show the list of open and running lobbies. Returns the selected lobby, or `None` if cancelled.
+async fn show_lobby_list_menu() -> Result<Option<Lobby>> {
+    Api::request_lobby_list()?;
+
+    let mut lobbies = Vec::new();
+    let mut menu = build_lobby_list_menu(&lobbies);
+
+    next_frame().await;
+
+    loop {
+        while let Some(event) = Api::next_event() {
+            if let NetworkEvent::LobbyList { lobbies: list } = event {
+                lobbies = list;
+                menu = build_lobby_list_menu(&lobbies);
+            }
+        }
+
+        draw_main_menu_background(true);
+
+        if let Some(res) = menu.ui(&mut root_ui()) {
+            match res.into_usize() {
+                LOBBY_LIST_OPTION_REFRESH => Api::request_lobby_list()?,
+                Menu::CANCEL_INDEX => return Ok(None),
+                i => return Ok(lobbies.get(i).cloned()),
+            }
+        }
+
+        next_frame().await;
+    }
+}
+
+/// Wait for the server to send the lobby and the seed of the game that is being spectated. Returns
+/// the parameters of the game, or `None` if the request was refused, or cancelled.
+async fn wait_for_spectated_game() -> Result<Option<NetworkGameParams>> {
+    let local_player_id = Api::local_player_id();
+
+    let mut lobby: Option<Lobby> = None;
+
+    loop {
+        while let Some(event) = Api::next_event() {
+            match event {
+                NetworkEvent::LobbyChanged { lobby: changed } => lobby = Some(*changed),
+                NetworkEvent::RequestFailed { status } => {
+                    return Err(Error::new_message(
+                        ErrorKind::Network,
+                        &format!("Unable to spectate: {}", status.as_str()),
+                    ));
+                }
+                NetworkEvent::GameStarted { seed, .. } => {
+                    if let Some(lobby) = &lobby {
+                        return Ok(network_game_params(lobby, &local_player_id, seed));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if is_key_pressed(KeyCode::Escape) {
+            return Ok(None);
+        }
+
+        draw_main_menu_background(true);
+
+        let text = "Joining game as spectator...";
+        let size = measure_text(text, None, 32, 1.0);
+        draw_text(
+            text,
+            (screen_width() - size.width) / 2.0,
+            screen_height() / 2.0,
+            32.0,
+            color::WHITE,
+        );
+
+        next_frame().await;
+    }
+}
+
+/// Show the lobby screen, where players select their characters and mark themselves as ready.
+/// Returns the parameters of the game when it is started, or `None` if the player left.
+async fn show_lobby_menu() -> Result<Option<NetworkGameParams>> {
+    let local_player_id = Api::local_player_id();
+
+    let characters = {
+        let resources = storage::get::<Resources>();
+
+        let mut characters = resources
+            .player_characters
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        characters.sort_by(|a, b| a.id.cmp(&b.id));
+        characters
+    };
+
+    let mut current_character = 0;
+    Api::set_character(&characters[current_character].id)?;
+
+    let mut lobby: Option<Lobby> = None;
+    let mut is_ready = false;
+    let mut team = None;
+    let mut status_message: Option<String> = None;
+
+    next_frame().await;
+
+    loop {
+        while let Some(event) = Api::next_event() {
+            match event {
+                NetworkEvent::LobbyChanged { lobby: changed } => lobby = Some(*changed),
+                NetworkEvent::RequestFailed { status } => {
+                    status_message = Some(format!("Request failed: {}", status.as_str()));
+                }
+                NetworkEvent::GameStarted { seed, .. } => {
+                    if let Some(lobby) = &lobby {
+                        return Ok(network_game_params(lobby, &local_player_id, seed));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        draw_main_menu_background(true);
+
+        let mut should_leave = is_key_pressed(KeyCode::Escape);
+        let mut should_start = false;
+        let mut selection_change = 0;
+        let mut is_ready_checked = is_ready;
+        let mut should_change_team = false;
+        let mut friendly_fire_checked = lobby.as_ref().map(|lobby| lobby.friendly_fire);
+
+        let size = vec2(LOBBY_MENU_WIDTH, LOBBY_MENU_HEIGHT);
+        let position = (vec2(screen_width(), screen_height()) - size) / 2.0;
+
+        let title = lobby
+            .as_ref()
+            .map(|lobby| lobby.name.clone())
+            .unwrap_or_else(|| "Joining lobby...".to_string());
+
+        Panel::new(hash!("lobby"), size, position)
+            .with_title(&title, true)
+            .ui(&mut root_ui(), |ui, inner_size| {
+                {
+                    let gui_resources = storage::get::<GuiResources>();
+                    ui.push_skin(&gui_resources.skins.menu);
+                }
+
+                let mut row = 0.0;
+
+                if let Some(lobby) = &lobby {
+                    let map_label = lobby
+                        .map
+                        .as_deref()
+                        .and_then(map_name)
+                        .unwrap_or_else(|| "-".to_string());
+
+                    ui.label(vec2(0.0, row), &format!("Map: {}", map_label));
+                    row += PLAYER_ROW_HEIGHT * 1.5;
+
+                    for player in &lobby.players {
+                        let character_name = player
+                            .character
+                            .as_ref()
+                            .and_then(|id| characters.iter().find(|c| &c.id == id))
+                            .map(|c| c.name.clone())
+                            .unwrap_or_else(|| "-".to_string());
+
+                        let team_name = player
+                            .team
+                            .map(|team| format!("Team {}", team + 1))
+                            .unwrap_or_else(|| "-".to_string());
+
+                        let mut label = format!(
+                            "{}  {}  {}  {:?}",
+                            player.username, character_name, team_name, player.state
+                        );
+
+                        if player.id == lobby.admin_player_id {
+                            label.push_str("  (host)");
+                        }
+
+                        ui.label(vec2(0.0, row), &label);
+                        row += PLAYER_ROW_HEIGHT;
+                    }
+                }
+
+                let controls_y = inner_size.y - PLAYER_ROW_HEIGHT * 3.0;
+
+                {
+                    let options_y = controls_y - PLAYER_ROW_HEIGHT * 1.5;
+
+                    let team_label = match team {
+                        Some(team) => format!("Team {}", team + 1),
+                        None => "No team".to_string(),
+                    };
+
+                    if widgets::Button::new(team_label.as_str())
+                        .position(vec2(0.0, options_y))
+                        .ui(ui)
+                    {
+                        should_change_team = true;
+                    }
+
+                    let is_admin = lobby
+                        .as_ref()
+                        .map(|lobby| lobby.admin_player_id == local_player_id)
+                        .unwrap_or_default();
+
+                    if let Some(friendly_fire) = friendly_fire_checked.as_mut().filter(|_| is_admin)
+                    {
+                        Checkbox::new(
+                            hash!("lobby", "friendly_fire"),
+                            vec2(inner_size.x / 2.0, options_y),
+                            "Friendly fire",
+                        )
+                        .ui(ui, friendly_fire);
+                    }
+                }
+
+                {
+                    let btn_size = vec2(NAVIGATION_BTN_WIDTH, PLAYER_ROW_HEIGHT);
+
+                    if widgets::Button::new("<")
+                        .size(btn_size)
+                        .position(vec2(0.0, controls_y))
+                        .ui(ui)
+                    {
+                        selection_change = -1;
+                    }
+
+                    let label_x = btn_size.x + ELEMENT_MARGIN;
+                    ui.label(
+                        vec2(label_x, controls_y),
+                        &characters[current_character].name,
+                    );
+
+                    if widgets::Button::new(">")
+                        .size(btn_size)
+                        .position(vec2(label_x + 160.0, controls_y))
+                        .ui(ui)
+                    {
+                        selection_change = 1;
+                    }
+                }
+
+                Checkbox::new(
+                    hash!("lobby", "ready"),
+                    vec2(inner_size.x / 2.0, controls_y),
+                    "Ready",
+                )
+                .ui(ui, &mut is_ready_checked);
+
+                let buttons_y = inner_size.y - PLAYER_ROW_HEIGHT * 1.5;
+
+                if let Some(lobby) = &lobby {
+                    let can_start = lobby.admin_player_id == local_player_id
+                        && lobby.state == LobbyState::Ready
+                        && lobby.map.is_some();
+
+                    if can_start
+                        && widgets::Button::new("Start")
+                            .position(vec2(0.0, buttons_y))
+                            .ui(ui)
+                    {
+                        should_start = true;
+                    }
+                }
+
+                if widgets::Button::new("Leave")
+                    .position(vec2(inner_size.x / 2.0, buttons_y))
+                    .ui(ui)
+                {
+                    should_leave = true;
+                }
+
+                if let Some(message) = &status_message {
+                    ui.label(vec2(0.0, buttons_y - PLAYER_ROW_HEIGHT), message);
+                }
+
+                ui.pop_skin();
+            });
+
+        // The character can not be changed while ready, so that the game is never started with a
+        // selection that the other players have not seen yet
+        if selection_change != 0 && !is_ready {
+            let len = characters.len() as i32;
+            current_character =
+                (current_character as i32 + selection_change).rem_euclid(len) as usize;
+
+            Api::set_character(&characters[current_character].id)?;
+        }
+
+        // The team is locked while ready, for the same reason as the character
+        if should_change_team && !is_ready {
+            team = match team {
+                None => Some(0),
+                Some(team) if team + 1 < LOBBY_TEAM_CNT => Some(team + 1),
+                Some(_) => None,
+            };
+
+            Api::set_team(team)?;
+        }
+
+        if let (Some(lobby), Some(friendly_fire)) = (&lobby, friendly_fire_checked) {
+            if friendly_fire != lobby.friendly_fire {
+                Api::set_friendly_fire(friendly_fire)?;
+            }
+        }
+
+        if is_ready_checked != is_ready {
+            is_ready = is_ready_checked;
+            Api::set_ready(is_ready)?;
+        }
+
+        if should_start {
+            Api::start_game()?;
+        }
+
+        if should_leave {
+            Api::leave_lobby()?;
+            return Ok(None);
+        }
+
+        next_frame().await;
+    }
+}
+
+fn map_name(map_path: &str) -> Option<String> {
+    let resources = storage::get::<Resources>();
+
+    resources
+        .maps
+        .iter()
+        .find(|res| res.meta.path == map_path)
+        .map(|res| res.meta.name.clone())
+}
+
+/// Build the parameters of the game from the final state of the lobby. Player indices are given
+/// by the order of the players in the lobby, which is the same for all peers, and spectators.
+fn network_game_params(
+    lobby: &Lobby,
+    local_player_id: &PlayerId,
+    seed: u64,
+) -> Option<NetworkGameParams> {
+    let resources = storage::get::<Resources>();
+
+    let (map, map_name) = match lobby
+        .map
+        .as_ref()
+        .and_then(|map| resources.maps.iter().find(|res| &res.meta.path == map))
+    {
+        Some(res) => (res.map.clone(), res.meta.name.clone()),
+        None => {
+            #[cfg(debug_assertions)]
+            println!("WARNING: Lobby: The selected map was not found");
+
+            return None;
+        }
+    };
+
+    let mut players = Vec::new();
+
+    for (i, player) in lobby.players.iter().enumerate() {
+        let character = match player
+            .character
+            .as_ref()
+            .and_then(|id| resources.player_characters.get(id))
+        {
+            Some(character) => character.clone(),
+            None => {
+                #[cfg(debug_assertions)]
+                println!(
+                    "WARNING: Lobby: The character of player '{}' was not found",
+                    player.id
+                );
+
+                return None;
+            }
+        };
+
+        let controller = if &player.id == local_player_id {
+            PlayerControllerKind::LocalInput(GameInputScheme::KeyboardLeft)
+        } else {
+            PlayerControllerKind::Network(player.id.clone())
+        };
+
+        players.push(PlayerParams {
+            index: i as u8,
+            team: player.team,
+            controller,
+            character,
+        });
+    }
+
+    let is_spectator = lobby
+        .spectators
+        .iter()
+        .any(|player| &player.id == local_player_id);
+
+    let mode = if is_spectator {
+        GameMode::NetworkSpectator
+    } else if &lobby.admin_player_id == local_player_id {
+        GameMode::NetworkHost
+    } else {
+        GameMode::NetworkClient
+    };
+
+    Some(NetworkGameParams {
+        mode,
+        map,
+        map_name,
+        players,
+        rules: MatchRulesParams {
+            friendly_fire: lobby.friendly_fire,
+            ..Default::default()
+        },
+        seed,
+        host_player_id: lobby.admin_player_id.clone(),
+    })
+}
@@ -8,9 +8,15 @@ use macroquad::{
 
 use fishsticks::{Button, GamepadContext};
 
-use super::{draw_main_menu_background, GuiResources, Menu, MenuEntry, MenuResult, Panel};
+use super::{
+    draw_main_menu_background, GuiResources, Menu, MenuEntry, MenuResult, NetworkGameParams, Panel,
+};
 
-use crate::player::{PlayerControllerKind, PlayerParams};
+use crate::game::{
+    MatchRulesParams, Replay, WinCondition, DEFAULT_CAPTURE_CNT, DEFAULT_HOLD_TIME,
+    DEFAULT_KILL_CNT, DEFAULT_LIVES, MAX_CAPTURE_CNT, MAX_HOLD_TIME, MAX_KILL_CNT, MAX_LIVES,
+};
+use crate::player::{AiProfile, PlayerCharacterMetadata, PlayerControllerKind, PlayerParams};
 use crate::{gui, EditorInputScheme, Map, Resources};
 use core::input::{is_gamepad_btn_pressed, update_gamepad_context, GameInputScheme};
 
@@ -19,13 +25,23 @@ const MENU_WIDTH: f32 = 300.0;
 const HEADER_TEXTURE_ID: &str = "main_menu_header";
 
 const LOCAL_GAME_MENU_WIDTH: f32 = 400.0;
-const LOCAL_GAME_MENU_HEIGHT: f32 = 200.0;
+const LOCAL_GAME_MENU_HEIGHT: f32 = 328.0;
+
+/// The amount of seconds that the hold time of the king of the hill mode is changed by
+const HOLD_TIME_STEP: u32 = 10;
+
+/// The amount of teams that players can be put on, in a local game
+const LOCAL_TEAM_CNT: u8 = 2;
 
 pub enum MainMenuResult {
     LocalGame {
         map: Box<Map>,
+        map_name: String,
         players: Vec<PlayerParams>,
+        rules: MatchRulesParams,
     },
+    NetworkGame(Box<NetworkGameParams>),
+    Replay(Box<Replay>),
     Editor {
         input_scheme: EditorInputScheme,
         is_new_map: bool,
@@ -45,10 +61,14 @@ enum MainMenuState {
 }
 
 const ROOT_OPTION_LOCAL_GAME: usize = 0;
-const ROOT_OPTION_EDITOR: usize = 1;
-const ROOT_OPTION_SETTINGS: usize = 2;
-const ROOT_OPTION_RELOAD_RESOURCES: usize = 3;
-const ROOT_OPTION_CREDITS: usize = 4;
+const ROOT_OPTION_HOST_GAME: usize = 1;
+const ROOT_OPTION_JOIN_GAME: usize = 2;
+const ROOT_OPTION_EDITOR: usize = 3;
+const ROOT_OPTION_LEADERBOARD: usize = 4;
+const ROOT_OPTION_REPLAYS: usize = 5;
+const ROOT_OPTION_SETTINGS: usize = 6;
+const ROOT_OPTION_RELOAD_RESOURCES: usize = 7;
+const ROOT_OPTION_CREDITS: usize = 8;
 
 const LOCAL_GAME_OPTION_SUBMIT: usize = 0;
 
@@ -65,11 +85,31 @@ fn build_main_menu() -> Menu {
                 title: "Local Game".to_string(),
                 ..Default::default()
             },
+            MenuEntry {
+                index: ROOT_OPTION_HOST_GAME,
+                title: "Host Game".to_string(),
+                ..Default::default()
+            },
+            MenuEntry {
+                index: ROOT_OPTION_JOIN_GAME,
+                title: "Join Game".to_string(),
+                ..Default::default()
+            },
             MenuEntry {
                 index: ROOT_OPTION_EDITOR,
                 title: "Editor".to_string(),
                 ..Default::default()
             },
+            MenuEntry {
+                index: ROOT_OPTION_LEADERBOARD,
+                title: "Leaderboard".to_string(),
+                ..Default::default()
+            },
+            MenuEntry {
+                index: ROOT_OPTION_REPLAYS,
+                title: "Replays".to_string(),
+                ..Default::default()
+            },
             MenuEntry {
                 index: ROOT_OPTION_SETTINGS,
                 title: "Settings".to_string(),
@@ -117,6 +157,16 @@ pub async fn show_main_menu() -> MainMenuResult {
 
     let mut player_input = Vec::new();
 
+    // If this is `Some`, the second player is a bot, with the AI profile at this index
+    let mut bot_profile = None;
+
+    // The mode is changed with up and down, and its kill count, lives or hold time with left and
+    // right
+    let mut rules = MatchRulesParams::default();
+
+    // The team of each of the two players, if any
+    let mut teams = [None; 2];
+
     loop {
         update_gamepad_context(None).unwrap();
 
@@ -146,9 +196,35 @@ pub async fn show_main_menu() -> MainMenuResult {
                         ROOT_OPTION_LOCAL_GAME => {
                             menu_state = MainMenuState::LocalGame;
                         }
+                        ROOT_OPTION_HOST_GAME => {
+                            if let Some(params) = gui::show_host_game_menu().await {
+                                return MainMenuResult::NetworkGame(Box::new(params));
+                            }
+
+                            menu_state = MainMenuState::Root(build_main_menu());
+                        }
+                        ROOT_OPTION_JOIN_GAME => {
+                            if let Some(params) = gui::show_join_game_menu().await {
+                                return MainMenuResult::NetworkGame(Box::new(params));
+                            }
+
+                            menu_state = MainMenuState::Root(build_main_menu());
+                        }
                         ROOT_OPTION_EDITOR => {
                             menu_state = MainMenuState::Editor(build_editor_menu());
                         }
+                        ROOT_OPTION_LEADERBOARD => {
+                            gui::show_leaderboard().await;
+
+                            menu_state = MainMenuState::Root(build_main_menu());
+                        }
+                        ROOT_OPTION_REPLAYS => {
+                            if let Some(replay) = gui::show_select_replay_menu().await {
+                                return MainMenuResult::Replay(Box::new(replay));
+                            }
+
+                            menu_state = MainMenuState::Root(build_main_menu());
+                        }
                         ROOT_OPTION_RELOAD_RESOURCES => {
                             return MainMenuResult::ReloadResources;
                         }
@@ -163,15 +239,22 @@ pub async fn show_main_menu() -> MainMenuResult {
                 }
             }
             MainMenuState::LocalGame => {
-                let res = local_game_ui(&mut *root_ui(), &mut player_input);
+                let res = local_game_ui(
+                    &mut *root_ui(),
+                    &mut player_input,
+                    &mut bot_profile,
+                    &mut teams,
+                    &mut rules,
+                );
                 if let Some(res) = res {
                     match res.into_usize() {
                         LOCAL_GAME_OPTION_SUBMIT => {
-                            let player_cnt = player_input.len();
+                            let player_cnt =
+                                player_input.len() + usize::from(bot_profile.is_some());
 
                             assert_eq!(
                                 player_cnt, 2,
-                                "Local Game: There should be two player input schemes for this game mode"
+                                "Local Game: There should be two players for this game mode"
                             );
 
                             let player_characters =
@@ -179,6 +262,15 @@ pub async fn show_main_menu() -> MainMenuResult {
 
                             let map_resource = gui::show_select_map_menu().await;
 
+                            let is_capture_the_flag =
+                                matches!(rules.win_condition, WinCondition::CaptureTheFlag(_));
+
+                            let teams = if is_capture_the_flag {
+                                assign_free_teams(teams)
+                            } else {
+                                teams
+                            };
+
                             let mut players = Vec::new();
 
                             for (i, &input_scheme) in player_input.iter().enumerate() {
@@ -188,6 +280,7 @@ pub async fn show_main_menu() -> MainMenuResult {
 
                                 let params = PlayerParams {
                                     index: i as u8,
+                                    team: teams[i],
                                     controller,
                                     character,
                                 };
@@ -195,9 +288,25 @@ pub async fn show_main_menu() -> MainMenuResult {
                                 players.push(params);
                             }
 
+                            if let Some(profile_index) = bot_profile {
+                                let character = bot_character(&player_characters);
+                                let profile_id = bot_profile_id(profile_index);
+
+                                let params = PlayerParams {
+                                    index: players.len() as u8,
+                                    team: teams[players.len()],
+                                    controller: PlayerControllerKind::Ai(profile_id),
+                                    character,
+                                };
+
+                                players.push(params);
+                            }
+
                             return MainMenuResult::LocalGame {
                                 map: Box::new(map_resource.map),
+                                map_name: map_resource.meta.name,
                                 players,
+                                rules,
                             };
                         }
                         Menu::CANCEL_INDEX => {
@@ -241,20 +350,94 @@ pub async fn show_main_menu() -> MainMenuResult {
     }
 }
 
-fn local_game_ui(ui: &mut ui::Ui, player_input: &mut Vec<GameInputScheme>) -> Option<MenuResult> {
-    if player_input.len() == 2 {
-        return Some(LOCAL_GAME_OPTION_SUBMIT.into());
-    } else {
+fn local_game_ui(
+    ui: &mut ui::Ui,
+    player_input: &mut Vec<GameInputScheme>,
+    bot_profile: &mut Option<usize>,
+    teams: &mut [Option<u8>; 2],
+    rules: &mut MatchRulesParams,
+) -> Option<MenuResult> {
+    let win_condition = &mut rules.win_condition;
+
+    let player_cnt = player_input.len() + usize::from(bot_profile.is_some());
+
+    {
         let gamepad_context = storage::get::<GamepadContext>();
 
+        // This is checked before any players join, so that the press that readies the last player
+        // does not also start the game
+        if player_cnt == 2
+            && (is_key_pressed(KeyCode::Enter)
+                || is_gamepad_btn_pressed(Some(&gamepad_context), Button::Start))
+        {
+            return Some(LOCAL_GAME_OPTION_SUBMIT.into());
+        }
+
         if is_key_pressed(KeyCode::Escape)
             || is_gamepad_btn_pressed(Some(&gamepad_context), Button::East)
         {
             return Some(Menu::CANCEL_INDEX.into());
         }
+
+        if is_key_pressed(KeyCode::Left)
+            || is_gamepad_btn_pressed(Some(&gamepad_context), Button::DPadLeft)
+        {
+            *win_condition = adjust_win_condition(*win_condition, true);
+        }
+
+        if is_key_pressed(KeyCode::Right)
+            || is_gamepad_btn_pressed(Some(&gamepad_context), Button::DPadRight)
+        {
+            *win_condition = adjust_win_condition(*win_condition, false);
+        }
+
+        if is_key_pressed(KeyCode::Up)
+            || is_gamepad_btn_pressed(Some(&gamepad_context), Button::DPadUp)
+        {
+            *win_condition = next_win_condition(*win_condition, true);
+        }
+
+        if is_key_pressed(KeyCode::Down)
+            || is_gamepad_btn_pressed(Some(&gamepad_context), Button::DPadDown)
+        {
+            *win_condition = next_win_condition(*win_condition, false);
+        }
+
+        if is_key_pressed(KeyCode::Key1)
+            || is_gamepad_btn_pressed(Some(&gamepad_context), Button::LeftTrigger)
+        {
+            teams[0] = next_team(teams[0]);
+        }
+
+        if is_key_pressed(KeyCode::Key2)
+            || is_gamepad_btn_pressed(Some(&gamepad_context), Button::RightTrigger)
+        {
+            teams[1] = next_team(teams[1]);
+        }
+
+        if is_key_pressed(KeyCode::F)
+            || is_gamepad_btn_pressed(Some(&gamepad_context), Button::Select)
+        {
+            rules.friendly_fire = !rules.friendly_fire;
+        }
+
+        // The second player can be replaced by a bot, once the first player has joined. Pressing
+        // the button again cycles through the AI profiles, and then removes the bot.
+        if player_input.len() == 1
+            && (is_key_pressed(KeyCode::Tab)
+                || is_gamepad_btn_pressed(Some(&gamepad_context), Button::North))
+        {
+            let profile_cnt = storage::get::<Resources>().ai_profiles.len().max(1);
+
+            *bot_profile = match *bot_profile {
+                None => Some(0),
+                Some(i) if i + 1 < profile_cnt => Some(i + 1),
+                Some(_) => None,
+            };
+        }
     }
 
-    if player_input.len() < 2 {
+    if player_input.len() + usize::from(bot_profile.is_some()) < 2 {
         if is_key_pressed(KeyCode::Enter) {
             if !player_input.contains(&GameInputScheme::KeyboardLeft) {
                 player_input.push(GameInputScheme::KeyboardLeft);
@@ -286,7 +469,8 @@ fn local_game_ui(ui: &mut ui::Ui, player_input: &mut Vec<GameInputScheme>) -> Op
             let position = vec2(12.0, 12.0);
 
             if !player_input.is_empty() {
-                ui.label(position, "Player 1: READY");
+                let label = format!("Player 1: READY  {}", team_label(teams[0]));
+                ui.label(position, &label);
             } else {
                 ui.label(position, "Player 1: press START or ENTER");
             }
@@ -295,17 +479,67 @@ fn local_game_ui(ui: &mut ui::Ui, player_input: &mut Vec<GameInputScheme>) -> Op
         {
             let position = vec2(12.0, 44.0);
 
-            if player_input.len() > 1 {
-                ui.label(position, "Player 2: READY");
+            if let Some(profile_index) = *bot_profile {
+                let label = format!(
+                    "Player 2: BOT ({})  {}",
+                    bot_profile_name(profile_index),
+                    team_label(teams[1])
+                );
+                ui.label(position, &label);
+            } else if player_input.len() > 1 {
+                let label = format!("Player 2: READY  {}", team_label(teams[1]));
+                ui.label(position, &label);
             } else {
                 ui.label(position, "Player 2: press START or ENTER");
             }
         }
 
+        {
+            let position = vec2(12.0, 76.0);
+
+            ui.label(position, &format!("Mode: < {} >", win_condition.label()));
+        }
+
         {
             let position = vec2(12.0, 108.0);
 
-            ui.label(position, "Press B or ESC to cancel");
+            let state = if rules.friendly_fire { "ON" } else { "OFF" };
+            ui.label(position, &format!("Friendly fire: {}", state));
+        }
+
+        {
+            let position = vec2(12.0, 140.0);
+
+            if player_input.len() + usize::from(bot_profile.is_some()) == 2 {
+                ui.label(position, "Press START or ENTER to begin");
+            } else {
+                ui.label(position, "Press B or ESC to cancel");
+            }
+        }
+
+        if player_input.len() == 1 {
+            let position = vec2(12.0, 172.0);
+
+            if bot_profile.is_some() {
+                ui.label(position, "Press Y or TAB to change the bot");
+            } else {
+                ui.label(position, "Press Y or TAB to add a bot");
+            }
+        }
+
+        {
+            let position = vec2(12.0, 204.0);
+            ui.label(position, "Press UP or DOWN to change the mode");
+        }
+
+        {
+            let position = vec2(12.0, 236.0);
+            ui.label(position, "Press 1/2 or LT/RT to change the teams");
+        }
+
+        {
+            let position = vec2(12.0, 268.0);
+            ui.label(position, "Press F or SELECT to toggle friendly fire");
         }
 
         ui.pop_skin();
@@ -313,3 +547,126 @@ fn local_game_ui(ui: &mut ui::Ui, player_input: &mut Vec<GameInputScheme>) -> Op
 
     None
 }
+
+/// The next team in the cycle of no team, followed by every local team
+fn next_team(team: Option<u8>) -> Option<u8> {
+    match team {
+        None => Some(0),
+        Some(team) if team + 1 < LOCAL_TEAM_CNT => Some(team + 1),
+        Some(_) => None,
+    }
+}
+
+fn team_label(team: Option<u8>) -> String {
+    match team {
+        Some(team) => format!("Team {}", team + 1),
+        None => "No team".to_string(),
+    }
+}
+
+/// Put every player without a team on the lowest team that no other player is on, as capture the
+/// flag is played between teams
+fn assign_free_teams(mut teams: [Option<u8>; 2]) -> [Option<u8>; 2] {
+    for i in 0..teams.len() {
+        if teams[i].is_none() {
+            let free_team = (0..=u8::MAX).find(|team| !teams.contains(&Some(*team)));
+            teams[i] = free_team;
+        }
+    }
+
+    teams
+}
+
+/// The next of the modes that can be selected, with its default value, or the previous one, if
+/// `is_reversed` is `true`
+fn next_win_condition(win_condition: WinCondition, is_reversed: bool) -> WinCondition {
+    let modes = [
+        WinCondition::KillCnt(DEFAULT_KILL_CNT),
+        WinCondition::Stocks(DEFAULT_LIVES),
+        WinCondition::KingOfTheHill(DEFAULT_HOLD_TIME),
+        WinCondition::CaptureTheFlag(DEFAULT_CAPTURE_CNT),
+    ];
+
+    let i = modes
+        .iter()
+        .position(|mode| std::mem::discriminant(mode) == std::mem::discriminant(&win_condition))
+        .unwrap_or_default();
+
+    let i = if is_reversed {
+        (i + modes.len() - 1) % modes.len()
+    } else {
+        (i + 1) % modes.len()
+    };
+
+    modes[i]
+}
+
+/// Increase or decrease the value of a win condition by one step, wrapping around at its limits
+fn adjust_win_condition(win_condition: WinCondition, is_decrease: bool) -> WinCondition {
+    let adjust = |value: u32, step: u32, max: u32| {
+        if is_decrease {
+            if value > step {
+                value - step
+            } else {
+                max
+            }
+        } else if value + step > max {
+            step
+        } else {
+            value + step
+        }
+    };
+
+    match win_condition {
+        WinCondition::KillCnt(kill_cnt) => WinCondition::KillCnt(adjust(kill_cnt, 1, MAX_KILL_CNT)),
+        WinCondition::Stocks(lives) => WinCondition::Stocks(adjust(lives, 1, MAX_LIVES)),
+        WinCondition::KingOfTheHill(hold_time) => {
+            WinCondition::KingOfTheHill(adjust(hold_time, HOLD_TIME_STEP, MAX_HOLD_TIME))
+        }
+        WinCondition::CaptureTheFlag(capture_cnt) => {
+            WinCondition::CaptureTheFlag(adjust(capture_cnt, 1, MAX_CAPTURE_CNT))
+        }
+        WinCondition::LastFishStanding(_) => win_condition,
+    }
+}
+
+/// The id of the AI profile at `index`, or the id of the default profile, if there is none
+fn bot_profile_id(index: usize) -> String {
+    let resources = storage::get::<Resources>();
+
+    resources
+        .ai_profiles
+        .get(index)
+        .map(|profile| profile.id.clone())
+        .unwrap_or_else(|| AiProfile::DEFAULT_ID.to_string())
+}
+
+/// The name of the AI profile at `index`, as shown in the local game menu
+fn bot_profile_name(index: usize) -> String {
+    let resources = storage::get::<Resources>();
+
+    resources
+        .ai_profiles
+        .get(index)
+        .map(|profile| profile.name.clone())
+        .unwrap_or_else(|| AiProfile::default().name)
+}
+
+/// Pick a character for a bot, that is not played by any of the other players, if possible
+fn bot_character(player_characters: &[PlayerCharacterMetadata]) -> PlayerCharacterMetadata {
+    let resources = storage::get::<Resources>();
+
+    let mut characters = resources.player_characters.values().collect::<Vec<_>>();
+    characters.sort_by(|a, b| a.id.cmp(&b.id));
+
+    characters
+        .iter()
+        .find(|character| {
+            !player_characters
+                .iter()
+                .any(|selected| selected.id == character.id)
+        })
+        .or_else(|| characters.first())
+        .map(|character| (*character).clone())
+        .unwrap()
+}
@@ -3,9 +3,13 @@ mod checkbox;
 mod create_map;
 mod credits;
 mod game_menu;
+mod leaderboard;
+mod lobby;
 mod main_menu;
 mod menu;
 mod panel;
+mod replays;
+mod results;
 mod select_character;
 mod select_map;
 mod style;
@@ -25,9 +29,13 @@ pub use game_menu::{
     close_game_menu, draw_game_menu, is_game_menu_open, open_game_menu, toggle_game_menu,
     GAME_MENU_RESULT_MAIN_MENU, GAME_MENU_RESULT_QUIT,
 };
+pub use leaderboard::show_leaderboard;
+pub use lobby::{show_host_game_menu, show_join_game_menu, NetworkGameParams};
 pub use main_menu::{show_main_menu, MainMenuResult};
-pub use menu::{Menu, MenuEntry, MenuResult};
+pub use menu::{Menu, MenuEntry, MenuPosition, MenuResult};
 pub use panel::{NewPanel, Panel};
+pub use replays::show_select_replay_menu;
+pub use results::{show_match_results, MatchResultsAction};
 pub use select_character::show_select_characters_menu;
 pub use select_map::show_select_map_menu;
 
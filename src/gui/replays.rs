@@ -0,0 +1,83 @@
+use macroquad::{
+    prelude::*,
+    ui::{hash, root_ui},
+};
+
+use core::input::update_gamepad_context;
+
+use super::{draw_main_menu_background, Menu, MenuEntry};
+
+use crate::game::{list_replays, Replay};
+
+const MENU_WIDTH: f32 = 600.0;
+
+/// The amount of replays that are listed, starting with the most recent
+const MAX_REPLAYS: usize = 10;
+
+/// Show the most recent replays, and return the one that is selected, or `None` if the player goes
+/// back. Replays that can not be loaded are not listed.
+pub async fn show_select_replay_menu() -> Option<Replay> {
+    let mut replays = list_replays(crate::replays_dir())
+        .into_iter()
+        .filter_map(|path| match Replay::load(&path) {
+            Ok(replay) => Some(replay),
+            Err(_err) => {
+                #[cfg(debug_assertions)]
+                println!(
+                    "WARNING: Unable to load the replay '{}': {}",
+                    path.display(),
+                    _err
+                );
+
+                None
+            }
+        })
+        .take(MAX_REPLAYS)
+        .collect::<Vec<_>>();
+
+    let mut entries = replays
+        .iter()
+        .enumerate()
+        .map(|(i, replay)| MenuEntry {
+            index: i,
+            title: replay.label(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    if entries.is_empty() {
+        entries.push(MenuEntry {
+            index: 0,
+            title: "No replays have been recorded".to_string(),
+            is_disabled: true,
+            ..Default::default()
+        });
+    }
+
+    let mut menu = Menu::new(hash!("replays"), MENU_WIDTH, &entries)
+        .with_header("Replays")
+        .with_cancel_button(Some("Back"));
+
+    // Skip a frame to let the press that opened the menu be unpressed
+    next_frame().await;
+
+    loop {
+        update_gamepad_context(None).unwrap();
+
+        draw_main_menu_background(false);
+
+        if let Some(res) = menu.ui(&mut root_ui()) {
+            if res.is_cancel() {
+                return None;
+            }
+
+            let i = res.into_usize();
+
+            if i < replays.len() {
+                return Some(replays.swap_remove(i));
+            }
+        }
+
+        next_frame().await;
+    }
+}
@@ -0,0 +1,157 @@
+use macroquad::{
+    experimental::collections::storage,
+    prelude::*,
+    ui::{hash, root_ui},
+};
+
+use core::input::update_gamepad_context;
+
+use super::{draw_main_menu_background, GuiResources, Menu, MenuEntry, MenuPosition, Panel};
+
+use crate::game::MatchResult;
+
+const PANEL_WIDTH: f32 = 760.0;
+const PANEL_ROW_HEIGHT: f32 = 32.0;
+const PANEL_MENU_MARGIN: f32 = 16.0;
+
+const MENU_WIDTH: f32 = 300.0;
+
+/// The labels and x offsets of the columns of the stats table
+const COLUMNS: [(&str, f32); 7] = [
+    ("Player", 0.0),
+    ("Kills", 160.0),
+    ("Deaths", 250.0),
+    ("Suicides", 350.0),
+    ("Items", 460.0),
+    ("Shots", 540.0),
+    ("Accuracy", 620.0),
+];
+
+const RESULTS_OPTION_REMATCH: usize = 0;
+const RESULTS_OPTION_CHANGE_MAP: usize = 1;
+const RESULTS_OPTION_MAIN_MENU: usize = 2;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MatchResultsAction {
+    /// Play again, with the same players, on the same map
+    Rematch,
+    /// Play again, with the same players, on a map that is selected next
+    ChangeMap,
+    MainMenu,
+}
+
+/// Show the result of a match, with the stats of every player. Rematch and Change Map are disabled
+/// if `can_rematch` is `false`, as they are only supported in local games.
+pub async fn show_match_results(result: &MatchResult, can_rematch: bool) -> MatchResultsAction {
+    let title = match result.winner {
+        Some(side) => format!("{} wins!", side.label()),
+        None => "Draw!".to_string(),
+    };
+
+    let rows = result
+        .stats
+        .iter()
+        .map(|(index, stats)| {
+            let accuracy = stats
+                .accuracy()
+                .map(|accuracy| format!("{:.0}%", accuracy * 100.0))
+                .unwrap_or_else(|| "-".to_string());
+
+            let player = match result.scores.get(index).and_then(|score| score.team) {
+                Some(team) => format!("P{} (Team {})", index + 1, team + 1),
+                None => format!("P{}", index + 1),
+            };
+
+            [
+                player,
+                stats.kills.to_string(),
+                stats.deaths.to_string(),
+                stats.suicides.to_string(),
+                stats.items_picked_up.to_string(),
+                stats.shots_fired.to_string(),
+                accuracy,
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let mut menu = Menu::new(
+        hash!("match_results"),
+        MENU_WIDTH,
+        &[
+            MenuEntry {
+                index: RESULTS_OPTION_REMATCH,
+                title: "Rematch".to_string(),
+                is_disabled: !can_rematch,
+                ..Default::default()
+            },
+            MenuEntry {
+                index: RESULTS_OPTION_CHANGE_MAP,
+                title: "Change Map".to_string(),
+                is_disabled: !can_rematch,
+                ..Default::default()
+            },
+            MenuEntry {
+                index: RESULTS_OPTION_MAIN_MENU,
+                title: "Main Menu".to_string(),
+                ..Default::default()
+            },
+        ],
+    );
+
+    // Skip a frame to let any input from the match be unpressed
+    next_frame().await;
+
+    loop {
+        update_gamepad_context(None).unwrap();
+
+        draw_main_menu_background(false);
+
+        // Title, column headers and a row for every player
+        let panel_size = vec2(PANEL_WIDTH, PANEL_ROW_HEIGHT * (rows.len() + 3) as f32);
+        let panel_position = vec2(
+            (screen_width() - panel_size.x) / 2.0,
+            (screen_height() - panel_size.y) / 4.0,
+        );
+
+        Panel::new(hash!("match_results", "stats"), panel_size, panel_position).ui(
+            &mut root_ui(),
+            |ui, _| {
+                {
+                    let gui_resources = storage::get::<GuiResources>();
+                    ui.push_skin(&gui_resources.skins.menu);
+                }
+
+                ui.label(vec2(0.0, 0.0), &title);
+
+                for (label, x) in COLUMNS {
+                    ui.label(vec2(x, PANEL_ROW_HEIGHT), label);
+                }
+
+                for (i, row) in rows.iter().enumerate() {
+                    let y = PANEL_ROW_HEIGHT * (i + 2) as f32;
+
+                    for (value, (_, x)) in row.iter().zip(COLUMNS) {
+                        ui.label(vec2(x, y), value);
+                    }
+                }
+
+                ui.pop_skin();
+            },
+        );
+
+        let menu_position = panel_position.y + panel_size.y + PANEL_MENU_MARGIN;
+        menu = menu.with_position(MenuPosition::AbsoluteVertical(menu_position));
+
+        if let Some(res) = menu.ui(&mut root_ui()) {
+            match res.into_usize() {
+                // Disabled entries can still be confirmed, when nothing is selected
+                RESULTS_OPTION_REMATCH if can_rematch => return MatchResultsAction::Rematch,
+                RESULTS_OPTION_CHANGE_MAP if can_rematch => return MatchResultsAction::ChangeMap,
+                RESULTS_OPTION_MAIN_MENU => return MatchResultsAction::MainMenu,
+                _ => {}
+            }
+        }
+
+        next_frame().await;
+    }
+}
@@ -16,6 +16,7 @@ use crate::{
 use core::{Result, Transform};
 
 use crate::effects::active::spawn_active_effect;
+use crate::game::record_shot_fired;
 use crate::particles::{ParticleEmitter, ParticleEmitterMetadata};
 use crate::physics::PhysicsBodyParams;
 use crate::player::{Player, PlayerInventory, IDLE_ANIMATION_ID};
@@ -87,8 +88,10 @@ pub struct ItemParams {
     pub drop_behavior: ItemDropBehavior,
     pub deplete_behavior: ItemDepleteBehavior,
     pub is_hat: bool,
+    pub is_flag: bool,
 }
 
+#[derive(Clone)]
 pub struct Item {
     pub id: String,
     pub name: String,
@@ -99,6 +102,7 @@ pub struct Item {
     pub drop_behavior: ItemDropBehavior,
     pub deplete_behavior: ItemDepleteBehavior,
     pub is_hat: bool,
+    pub is_flag: bool,
     pub duration_timer: f32,
     pub use_cnt: u32,
 }
@@ -115,6 +119,7 @@ impl Item {
             drop_behavior: params.drop_behavior,
             deplete_behavior: params.deplete_behavior,
             is_hat: params.is_hat,
+            is_flag: params.is_flag,
             duration_timer: 0.0,
             use_cnt: 0,
         }
@@ -134,6 +139,10 @@ pub struct ItemMetadata {
     /// If this is `true` the item will be treated as a hat
     #[serde(default, rename = "hat", skip_serializing_if = "core::json::is_false")]
     pub is_hat: bool,
+    /// If this is `true` the item will be treated as a capture the flag flag. Flags are only
+    /// spawned by flag bases, which give them a team, and they can not be placed on maps directly.
+    #[serde(default, rename = "flag", skip_serializing_if = "core::json::is_false")]
+    pub is_flag: bool,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -222,6 +231,7 @@ pub fn spawn_item(world: &mut World, position: Vec2, meta: MapItemMetadata) -> R
                 effects,
                 duration,
                 is_hat,
+                is_flag,
             } = meta;
 
             world.insert_one(
@@ -237,6 +247,7 @@ pub fn spawn_item(world: &mut World, position: Vec2, meta: MapItemMetadata) -> R
                         drop_behavior,
                         deplete_behavior,
                         is_hat,
+                        is_flag,
                     },
                 ),
             )?;
@@ -340,6 +351,7 @@ impl Default for WeaponParams {
     }
 }
 
+#[derive(Clone)]
 pub struct Weapon {
     pub id: String,
     pub name: String,
@@ -423,6 +435,8 @@ pub fn fire_weapon(world: &mut World, entity: Entity, owner: Entity) -> Result<(
 
             weapon.use_cnt += 1;
 
+            record_shot_fired(world, owner);
+
             weapon.cooldown_timer = 0.0;
 
             if let Some(sound) = weapon.sound_effect {
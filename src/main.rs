@@ -1,7 +1,11 @@
+// With the `headless` feature, most of the game is left unused by the replay runner
+#![cfg_attr(feature = "headless", allow(dead_code, unused_imports))]
+
 use fishsticks::GamepadContext;
 
 use std::env;
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use macroquad::experimental::collections::storage;
 use macroquad::prelude::*;
@@ -21,6 +25,7 @@ pub mod particles;
 pub mod physics;
 pub mod player;
 pub mod resources;
+pub mod snapshot;
 
 pub mod drawables;
 
@@ -31,6 +36,7 @@ use editor::{Editor, EditorCamera, EditorInputScheme};
 
 use map::{Map, MapLayerKind, MapObjectKind};
 
+use core::history::MATCH_HISTORY_FILE_NAME;
 use core::network::Api;
 use core::Result;
 
@@ -48,7 +54,7 @@ pub use player::PlayerEvent;
 pub use ecs::Owner;
 
 use crate::effects::passive::init_passive_effects;
-use crate::game::GameMode;
+use crate::game::{GameMode, GameParams, MatchResult, REPLAYS_DIR_NAME};
 use crate::particles::Particles;
 use crate::resources::load_resources;
 pub use effects::{
@@ -78,15 +84,31 @@ pub fn reload_resources() {
     ApplicationEvent::ReloadResources.dispatch()
 }
 
-fn window_conf() -> Conf {
-    let path = env::var(CONFIG_FILE_ENV_VAR)
+/// The path of the config file, which can be set with the `FISHFIGHT_CONFIG` env variable
+fn config_path() -> PathBuf {
+    env::var(CONFIG_FILE_ENV_VAR)
         .map(PathBuf::from)
         .unwrap_or_else(|_| {
             #[cfg(debug_assertions)]
             return PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("config.toml");
             #[cfg(not(debug_assertions))]
             return PathBuf::from("./config.toml");
-        });
+        })
+}
+
+/// The path of the match history file, which is kept in the same directory as the config file
+pub fn match_history_path() -> PathBuf {
+    config_path().with_file_name(MATCH_HISTORY_FILE_NAME)
+}
+
+/// The path of the directory that replays are saved to, which is kept in the same directory as the
+/// config file
+pub fn replays_dir() -> PathBuf {
+    config_path().with_file_name(REPLAYS_DIR_NAME)
+}
+
+fn window_conf() -> Conf {
+    let path = config_path();
 
     let config = Config::load(&path).unwrap();
 
@@ -102,14 +124,78 @@ fn window_conf() -> Conf {
     }
 }
 
+/// A seed for the simulation of a new local match. The global rng of macroquad is seeded with a
+/// constant on launch, so it would give every first match the same seed.
+fn new_match_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
 /// Returns `true` if the outer game loop should continue;
-#[cfg(not(feature = "ultimate"))]
 async fn init_game() -> Result<bool> {
     use gui::MainMenuResult;
 
     match gui::show_main_menu().await {
-        MainMenuResult::LocalGame { map, players } => {
-            let game = Game::new(GameMode::Local, *map, &players)?;
+        MainMenuResult::LocalGame {
+            map,
+            map_name,
+            players,
+            rules,
+        } => {
+            let params = GameParams {
+                seed: new_match_seed(),
+                rules,
+                map_name,
+                is_recording_replay: storage::get::<Config>().replays.is_recording,
+                ..Default::default()
+            };
+
+            let game = match Game::new(GameMode::Local, *map, &players, params) {
+                Ok(game) => game,
+                Err(err) => {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: {}", err);
+
+                    return Ok(true);
+                }
+            };
+
+            scene::add_node(game);
+
+            start_music("fish_tide");
+        }
+        MainMenuResult::NetworkGame(network_game) => {
+            let params = GameParams {
+                seed: network_game.seed,
+                rules: network_game.rules,
+                host_player_id: Some(network_game.host_player_id),
+                map_name: network_game.map_name,
+                ..Default::default()
+            };
+
+            let game = match Game::new(
+                network_game.mode,
+                network_game.map,
+                &network_game.players,
+                params,
+            ) {
+                Ok(game) => game,
+                Err(err) => {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: {}", err);
+
+                    return Ok(true);
+                }
+            };
+
+            scene::add_node(game);
+
+            start_music("fish_tide");
+        }
+        MainMenuResult::Replay(replay) => {
+            let game = Game::from_replay(&replay)?;
             scene::add_node(game);
 
             start_music("fish_tide");
@@ -153,51 +239,64 @@ async fn init_game() -> Result<bool> {
     Ok(false)
 }
 
-#[cfg(feature = "ultimate")]
-async fn init_game() -> Result<bool> {
-    use core::input::GameInputScheme;
-    use core::network::Api;
+/// Show the results of the match that has ended, and start the next match, if a rematch, or a
+/// change of map, is selected. Returns `true` if a new match was started.
+async fn show_match_results() -> Result<bool> {
+    use gui::MatchResultsAction;
 
-    use crate::player::{PlayerControllerKind, PlayerParams};
+    let result = storage::get::<MatchResult>().clone();
+    let map = storage::get::<Map>().clone();
 
-    let player_ids = vec!["1".to_string(), "2".to_string()];
+    let rematch =
+        scene::find_node_by_type::<Game>().and_then(|game| game.rematch_params(new_match_seed()));
 
-    Api::init::<ultimate::UltimateApiBackend>(&player_ids[0], true).await?;
+    scene::clear();
 
-    let (map, mut characters) = {
-        let resources = storage::get::<Resources>();
+    stop_music();
 
-        let map = resources.maps.first().map(|res| res.map.clone()).unwrap();
+    let action = gui::show_match_results(&result, rematch.is_some()).await;
 
-        let characters = vec![
-            resources.player_characters.get("pescy").cloned().unwrap(),
-            resources.player_characters.get("sharky").cloned().unwrap(),
-        ];
+    if action == MatchResultsAction::MainMenu {
+        return Ok(false);
+    }
 
-        (map, characters)
-    };
+    if let Some((players, mut params)) = rematch {
+        let map = if action == MatchResultsAction::ChangeMap {
+            let map_resource = gui::show_select_map_menu().await;
+            params.map_name = map_resource.meta.name;
+            map_resource.map
+        } else {
+            map
+        };
 
-    let players = vec![
-        PlayerParams {
-            index: 0,
-            controller: PlayerControllerKind::LocalInput(GameInputScheme::KeyboardLeft).into(),
-            character: characters.pop().unwrap(),
-        },
-        PlayerParams {
-            index: 1,
-            controller: PlayerControllerKind::Network(player_ids[1].clone()).into(),
-            character: characters.pop().unwrap(),
-        },
-    ];
+        let game = Game::new(GameMode::Local, map, &players, params)?;
+        scene::add_node(game);
 
-    let game = Game::new(GameMode::NetworkHost, map, &players)?;
-    scene::add_node(game);
+        start_music("fish_tide");
 
-    start_music("fish_tide");
+        return Ok(true);
+    }
 
     Ok(false)
 }
 
+/// Play the replays given on the command line without a window, and print the outcome of each, as
+/// JSON. The assets are loaded from the same dir as the game would load them from.
+#[cfg(feature = "headless")]
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let assets_dir = env::var(ASSETS_DIR_ENV_VAR).unwrap_or_else(|_| "./assets".to_string());
+
+    for path in env::args().skip(1) {
+        let replay = game::Replay::load(&path)?;
+        let snapshot = game::run_headless_replay(&assets_dir, &replay)?;
+
+        println!("{}", serde_json::to_string_pretty(&snapshot)?);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "headless"))]
 #[macroquad::main(window_conf)]
 async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     use events::iter_events;
@@ -234,6 +333,13 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
                         load_resources(&assets_dir, &mods_dir).await?;
                     }
                     ApplicationEvent::MainMenu => break 'inner,
+                    ApplicationEvent::MatchEnded => {
+                        if show_match_results().await? {
+                            continue 'inner;
+                        }
+
+                        break 'inner;
+                    }
                     ApplicationEvent::Quit => break 'outer,
                 }
             }
@@ -249,6 +355,9 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
         scene::clear();
 
         stop_music();
+
+        // Network games are started from the lobby, so the connection is not reused
+        Api::close().await?;
     }
 
     Api::close().await?;
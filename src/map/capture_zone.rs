@@ -0,0 +1,260 @@
+//! Capture zones are areas of a map that players score by holding, in the king of the hill mode.
+//! A zone is held by a side when the players of that side are the only players inside it, and it
+//! is contested when players of more than one side are inside it. The position of a zone is its
+//! top left corner, and its size is set with the `size` property of its map object.
+
+use macroquad::color;
+use macroquad::prelude::*;
+
+use hecs::{Entity, World};
+
+use core::Transform;
+
+use crate::game::{get_match_rules_mut, get_simulation_dt, Side, WinCondition};
+use crate::map::{Map, MapLayerKind, MapObject, MapObjectKind};
+use crate::player::{Player, PlayerState, TEAM_COLORS};
+use crate::PhysicsBody;
+
+pub const CAPTURE_ZONE_ID: &str = "capture_zone";
+
+/// The map object property that holds the size of a zone, as a `Vec2`
+pub const CAPTURE_ZONE_SIZE_PROPERTY: &str = "size";
+
+const DEFAULT_WIDTH: f32 = 96.0;
+const DEFAULT_HEIGHT: f32 = 64.0;
+
+const FREE_COLOR: Color = Color::new(1.0, 1.0, 1.0, 0.15);
+/// The color of a zone held by a player that is not on a team
+const HELD_COLOR: Color = Color::new(1.0, 0.85, 0.2, 0.3);
+const CONTESTED_COLOR: Color = Color::new(1.0, 0.2, 0.2, 0.3);
+const OUTLINE_THICKNESS: f32 = 2.0;
+
+const HUD_FONT_SIZE: f32 = 20.0;
+const HUD_MARGIN: f32 = 16.0;
+const HUD_METER_WIDTH: f32 = 240.0;
+const HUD_METER_HEIGHT: f32 = 12.0;
+/// The distance from the top of the screen to the meter, which is below the score line
+const HUD_METER_Y: f32 = 48.0;
+
+#[derive(Debug, Clone)]
+pub struct CaptureZone {
+    pub size: Vec2,
+    /// The side that is alone in the zone, if any
+    pub holder: Option<Side>,
+    /// This is `true` if players of more than one side are in the zone
+    pub is_contested: bool,
+}
+
+impl CaptureZone {
+    pub fn new(size: Vec2) -> Self {
+        CaptureZone {
+            size,
+            holder: None,
+            is_contested: false,
+        }
+    }
+
+    pub fn as_rect(&self, position: Vec2) -> Rect {
+        Rect::new(position.x, position.y, self.size.x, self.size.y)
+    }
+}
+
+/// The size of a capture zone, from the properties of its map object, or the default size, if it
+/// has no valid `size` property
+pub fn capture_zone_size(object: &MapObject) -> Vec2 {
+    object
+        .properties
+        .get(CAPTURE_ZONE_SIZE_PROPERTY)
+        .and_then(|prop| prop.get_value::<Vec2>())
+        .copied()
+        .unwrap_or_else(|| vec2(DEFAULT_WIDTH, DEFAULT_HEIGHT))
+}
+
+/// Returns `true` if the map has a capture zone on any of its visible object layers
+pub fn has_capture_zones(map: &Map) -> bool {
+    map.layers
+        .values()
+        .filter(|layer| layer.is_visible && layer.kind == MapLayerKind::ObjectLayer)
+        .flat_map(|layer| &layer.objects)
+        .any(|object| object.kind == MapObjectKind::Environment && object.id == CAPTURE_ZONE_ID)
+}
+
+pub fn spawn_capture_zone(world: &mut World, position: Vec2, size: Vec2) -> Entity {
+    world.spawn((CaptureZone::new(size), Transform::from(position)))
+}
+
+/// Update the holder of every capture zone, and credit hold time to the players of the holding
+/// side. The time is shared between the players inside the zone, so that the hold time of a side
+/// is the time that it has held the zone, no matter how many of its players were in it.
+pub fn fixed_update_capture_zones(world: &mut World) {
+    let dt = get_simulation_dt(world);
+
+    let mut rules = match get_match_rules_mut(world) {
+        Some(rules) => rules,
+        None => return,
+    };
+
+    let players = world
+        .query::<(&Player, &Transform, &PhysicsBody)>()
+        .iter()
+        .filter(|(_, (player, _, _))| player.state != PlayerState::Dead)
+        .map(|(_, (player, transform, body))| (player.index, body.as_rect(transform.position)))
+        .collect::<Vec<_>>();
+
+    for (_, (zone, transform)) in world.query::<(&mut CaptureZone, &Transform)>().iter() {
+        let rect = zone.as_rect(transform.position);
+
+        let inside = players
+            .iter()
+            .filter(|(_, player_rect)| player_rect.overlaps(&rect))
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>();
+
+        let mut sides = inside
+            .iter()
+            .map(|index| rules.side_of(*index))
+            .collect::<Vec<_>>();
+
+        sides.sort_unstable();
+        sides.dedup();
+
+        zone.is_contested = sides.len() > 1;
+        zone.holder = if sides.len() == 1 {
+            sides.first().copied()
+        } else {
+            None
+        };
+
+        if zone.holder.is_some() {
+            let time = dt / inside.len() as f32;
+
+            for index in &inside {
+                rules.record_hold_time(*index, time);
+            }
+        }
+    }
+}
+
+pub fn draw_capture_zones(world: &mut World) {
+    for (_, (zone, transform)) in world.query::<(&CaptureZone, &Transform)>().iter() {
+        let rect = zone.as_rect(transform.position);
+
+        let color = if zone.is_contested {
+            CONTESTED_COLOR
+        } else {
+            match zone.holder {
+                Some(Side::Team(team)) => {
+                    let color = TEAM_COLORS[team as usize % TEAM_COLORS.len()];
+                    Color::new(color.r, color.g, color.b, HELD_COLOR.a)
+                }
+                Some(Side::Player(_)) => HELD_COLOR,
+                None => FREE_COLOR,
+            }
+        };
+
+        draw_rectangle(rect.x, rect.y, rect.w, rect.h, color);
+
+        let outline_color = Color::new(color.r, color.g, color.b, 1.0);
+        draw_rectangle_lines(
+            rect.x,
+            rect.y,
+            rect.w,
+            rect.h,
+            OUTLINE_THICKNESS,
+            outline_color,
+        );
+    }
+}
+
+/// Draw a meter, below the score line, that shows how close the side that holds the capture zones
+/// is to winning, in the king of the hill mode. If no side holds a zone, the leading side is shown.
+pub fn draw_capture_zone_hud(world: &mut World) {
+    let (label, progress, is_contested) = {
+        let rules = match get_match_rules_mut(world) {
+            Some(rules) => rules,
+            None => return,
+        };
+
+        let hold_time = match rules.win_condition {
+            WinCondition::KingOfTheHill(hold_time) => hold_time,
+            _ => return,
+        };
+
+        if rules.result.is_some() {
+            return;
+        }
+
+        let mut holder = None;
+        let mut is_contested = false;
+
+        for (_, zone) in world.query::<&CaptureZone>().iter() {
+            is_contested |= zone.is_contested;
+            holder = holder.or(zone.holder);
+        }
+
+        let side = holder.or_else(|| {
+            rules.sides().into_iter().max_by(|a, b| {
+                rules
+                    .side_hold_time(*a)
+                    .partial_cmp(&rules.side_hold_time(*b))
+                    .unwrap()
+            })
+        });
+
+        let side = match side {
+            Some(side) => side,
+            None => return,
+        };
+
+        let label = if holder.is_some() {
+            format!("{} holds the zone", side.label())
+        } else if is_contested {
+            "The zone is contested!".to_string()
+        } else {
+            format!("{} leads", side.label())
+        };
+
+        let progress = (rules.side_hold_time(side) / hold_time.max(1) as f32).min(1.0);
+
+        (label, progress, is_contested && holder.is_none())
+    };
+
+    push_camera_state();
+    set_default_camera();
+
+    let x = (screen_width() - HUD_METER_WIDTH) / 2.0;
+
+    draw_rectangle(
+        x,
+        HUD_METER_Y,
+        HUD_METER_WIDTH,
+        HUD_METER_HEIGHT,
+        Color::new(0.0, 0.0, 0.0, 0.5),
+    );
+
+    let fill_color = if is_contested {
+        Color::new(CONTESTED_COLOR.r, CONTESTED_COLOR.g, CONTESTED_COLOR.b, 1.0)
+    } else {
+        Color::new(HELD_COLOR.r, HELD_COLOR.g, HELD_COLOR.b, 1.0)
+    };
+
+    draw_rectangle(
+        x,
+        HUD_METER_Y,
+        HUD_METER_WIDTH * progress,
+        HUD_METER_HEIGHT,
+        fill_color,
+    );
+
+    let size = measure_text(&label, None, HUD_FONT_SIZE as u16, 1.0);
+
+    draw_text(
+        &label,
+        (screen_width() - size.width) / 2.0,
+        HUD_METER_Y + HUD_METER_HEIGHT + HUD_MARGIN / 2.0 + HUD_FONT_SIZE,
+        HUD_FONT_SIZE,
+        color::WHITE,
+    );
+
+    pop_camera_state();
+}
@@ -15,6 +15,7 @@ pub struct DecorationMetadata {
     pub sprite: AnimatedSpriteMetadata,
 }
 
+#[derive(Clone)]
 pub struct Decoration {
     pub id: String,
 }
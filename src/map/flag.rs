@@ -0,0 +1,541 @@
+//! Flags and flag bases, for the capture the flag mode. Every flag base spawns the flag of its team,
+//! which is an item with `is_flag` set, so that it is picked up, carried and dropped on death, like
+//! other items, by `update_player_inventory`. Flags can only be picked up by players on other
+//! teams, and a flag that has been dropped returns to its base after `FLAG_RETURN_TIME`, or when a
+//! player on its own team touches it. A team captures a flag by carrying it to its own base, while
+//! its own flag is at home.
+//! On maps without flag bases, bases are placed at the two spawn points that are furthest apart,
+//! so that capture the flag can be played on any map.
+
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+
+use hecs::{Entity, World};
+
+use core::{Result, Transform};
+
+use crate::game::{get_match_rules_mut, get_simulation_dt, WinCondition};
+use crate::items::{spawn_item, Item};
+use crate::map::{Map, MapLayerKind, MapObject, MapObjectKind};
+use crate::player::{Player, PlayerState, TEAM_COLORS};
+use crate::{Drawable, Owner, PhysicsBody, Resources};
+
+pub const FLAG_BASE_ID: &str = "flag_base";
+
+/// The map object property that holds the team of a flag base, as an integer, starting at `0`
+pub const FLAG_BASE_TEAM_PROPERTY: &str = "team";
+
+/// The id of the item that flag bases spawn as their flag
+pub const FLAG_ITEM_ID: &str = "flag";
+
+pub const FLAG_BASE_WIDTH: f32 = 64.0;
+pub const FLAG_BASE_HEIGHT: f32 = 64.0;
+
+/// The time that a flag that has been dropped stays where it is, before it returns to its base
+const FLAG_RETURN_TIME: f32 = 10.0;
+
+const BASE_FILL_ALPHA: f32 = 0.2;
+const BASE_OUTLINE_THICKNESS: f32 = 2.0;
+
+#[derive(Debug, Clone)]
+pub struct Flag {
+    pub team: u8,
+    /// The position that the flag is spawned at, and returned to
+    pub home: Vec2,
+    /// This is `true` until the flag is picked up, and again when it has been returned
+    pub is_at_home: bool,
+    /// This is set when the flag is captured, which makes its carrier drop it, so that it can be
+    /// returned to its base
+    pub is_captured: bool,
+    /// The time that the flag has been dropped, away from its base
+    pub return_timer: f32,
+}
+
+impl Flag {
+    pub fn new(team: u8, home: Vec2) -> Self {
+        Flag {
+            team,
+            home,
+            is_at_home: true,
+            is_captured: false,
+            return_timer: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FlagBase {
+    pub team: u8,
+}
+
+impl FlagBase {
+    pub fn new(team: u8) -> Self {
+        FlagBase { team }
+    }
+
+    pub fn as_rect(&self, position: Vec2) -> Rect {
+        Rect::new(position.x, position.y, FLAG_BASE_WIDTH, FLAG_BASE_HEIGHT)
+    }
+}
+
+/// The team of a flag base, from the properties of its map object, or the first team, if it has
+/// no valid `team` property
+pub fn flag_base_team(object: &MapObject) -> u8 {
+    object
+        .properties
+        .get(FLAG_BASE_TEAM_PROPERTY)
+        .and_then(|prop| {
+            prop.get_value::<i32>()
+                .map(|team| *team as u8)
+                .or_else(|| prop.get_value::<u32>().map(|team| *team as u8))
+                .or_else(|| prop.get_value::<f32>().map(|team| *team as u8))
+        })
+        .unwrap_or_default()
+}
+
+/// Returns `true` if the map has a flag base on any of its visible object layers
+pub fn has_flag_bases(map: &Map) -> bool {
+    map.layers
+        .values()
+        .filter(|layer| layer.is_visible && layer.kind == MapLayerKind::ObjectLayer)
+        .flat_map(|layer| &layer.objects)
+        .any(|object| object.kind == MapObjectKind::Environment && object.id == FLAG_BASE_ID)
+}
+
+/// The teams that play in a capture the flag match, in order, or none, in any other mode
+pub fn capture_the_flag_teams(world: &World) -> Vec<u8> {
+    let rules = match get_match_rules_mut(world) {
+        Some(rules) => rules,
+        None => return Vec::new(),
+    };
+
+    if !matches!(rules.win_condition, WinCondition::CaptureTheFlag(_)) {
+        return Vec::new();
+    }
+
+    let mut teams = rules
+        .scores
+        .values()
+        .filter_map(|score| score.team)
+        .collect::<Vec<_>>();
+
+    teams.sort_unstable();
+    teams.dedup();
+
+    teams
+}
+
+/// Spawn a flag base, along with the flag of its team, which is tinted in the color of the team
+pub fn spawn_flag_base(world: &mut World, position: Vec2, team: u8) -> Result<Entity> {
+    let entity = world.spawn((FlagBase::new(team), Transform::from(position)));
+
+    let meta = storage::get::<Resources>().items.get(FLAG_ITEM_ID).cloned();
+
+    if let Some(meta) = meta {
+        let home = position + vec2((FLAG_BASE_WIDTH - meta.collider_size.x) / 2.0, 0.0);
+
+        let flag = spawn_item(world, home, meta)?;
+        world.insert_one(flag, Flag::new(team, home))?;
+
+        if let Ok(mut drawable) = world.get_mut::<Drawable>(flag) {
+            if let Some(sprite_set) = drawable.get_animated_sprite_set_mut() {
+                for sprite in sprite_set.map.values_mut() {
+                    sprite.tint = TEAM_COLORS[team as usize % TEAM_COLORS.len()];
+                }
+            }
+        }
+    } else {
+        #[cfg(debug_assertions)]
+        println!("WARNING: Invalid flag item id '{}'", FLAG_ITEM_ID);
+    }
+
+    Ok(entity)
+}
+
+/// Spawn flag bases for the first two teams, at the two spawn points of the map that are furthest
+/// apart. This is used on maps that have no flag bases of their own.
+pub fn spawn_default_flag_bases(world: &mut World, map: &Map, teams: &[u8]) -> Result<Vec<Entity>> {
+    let mut furthest = None;
+    let mut max_distance = 0.0;
+
+    for (i, a) in map.spawn_points.iter().enumerate() {
+        for b in map.spawn_points.iter().skip(i + 1) {
+            let distance = a.distance_squared(*b);
+
+            if distance > max_distance {
+                max_distance = distance;
+                furthest = Some((*a, *b));
+            }
+        }
+    }
+
+    let mut res = Vec::new();
+
+    if let Some((a, b)) = furthest {
+        for (spawn_point, team) in [a, b].into_iter().zip(teams) {
+            let position = spawn_point - vec2(FLAG_BASE_WIDTH / 2.0, 0.0);
+            res.push(spawn_flag_base(world, position, *team)?);
+        }
+    } else {
+        #[cfg(debug_assertions)]
+        println!("WARNING: The map needs two spawn points for default flag bases");
+    }
+
+    Ok(res)
+}
+
+/// Capture flags that are carried to the base of their carrier, and return flags that have been
+/// captured, or that have been dropped for `FLAG_RETURN_TIME`, or that are touched by a player on
+/// their own team, to their bases
+pub fn fixed_update_flags(world: &mut World) {
+    let dt = get_simulation_dt(world);
+
+    let players = world
+        .query::<(&Player, &Transform, &PhysicsBody)>()
+        .iter()
+        .filter(|(_, (player, _, _))| player.state != PlayerState::Dead)
+        .map(|(entity, (player, transform, body))| {
+            (
+                entity,
+                player.index,
+                player.team,
+                body.as_rect(transform.position),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let bases = world
+        .query::<(&FlagBase, &Transform)>()
+        .iter()
+        .map(|(_, (base, transform))| (base.team, base.as_rect(transform.position)))
+        .collect::<Vec<_>>();
+
+    let teams_at_home = world
+        .query::<&Flag>()
+        .iter()
+        .filter(|(_, flag)| flag.is_at_home)
+        .map(|(_, flag)| flag.team)
+        .collect::<Vec<_>>();
+
+    let mut captures = Vec::new();
+    let mut to_return = Vec::new();
+
+    for (entity, (flag, transform, body, owner)) in world
+        .query::<(&mut Flag, &Transform, &PhysicsBody, Option<&Owner>)>()
+        .iter()
+    {
+        if let Some(owner) = owner {
+            flag.is_at_home = false;
+            flag.return_timer = 0.0;
+
+            if flag.is_captured {
+                continue;
+            }
+
+            let carrier = players
+                .iter()
+                .find(|(player_entity, _, _, _)| *player_entity == owner.0);
+
+            if let Some(&(_, index, Some(team), rect)) = carrier {
+                let is_at_base = bases
+                    .iter()
+                    .any(|(base_team, base_rect)| *base_team == team && base_rect.overlaps(&rect));
+
+                if is_at_base && teams_at_home.contains(&team) {
+                    flag.is_captured = true;
+                    captures.push(index);
+                }
+            }
+        } else if flag.is_captured {
+            to_return.push(entity);
+        } else if !flag.is_at_home {
+            flag.return_timer += dt;
+
+            let rect = body.as_rect(transform.position);
+
+            let is_touched_by_team = players.iter().any(|(_, _, team, player_rect)| {
+                *team == Some(flag.team) && player_rect.overlaps(&rect)
+            });
+
+            if is_touched_by_team || flag.return_timer >= FLAG_RETURN_TIME {
+                to_return.push(entity);
+            }
+        }
+    }
+
+    if let Some(mut rules) = get_match_rules_mut(world) {
+        for index in captures {
+            rules.record_capture(index);
+        }
+    }
+
+    for entity in to_return {
+        let home = {
+            let mut flag = world.get_mut::<Flag>(entity).unwrap();
+
+            flag.is_at_home = true;
+            flag.is_captured = false;
+            flag.return_timer = 0.0;
+
+            flag.home
+        };
+
+        let mut transform = world.get_mut::<Transform>(entity).unwrap();
+        transform.position = home;
+
+        let mut body = world.get_mut::<PhysicsBody>(entity).unwrap();
+        body.velocity = Vec2::ZERO;
+    }
+}
+
+/// Returns `true` if the item is a flag that can be picked up by the player. Players that are not
+/// on a team can not pick up flags, and players can only carry one flag at a time.
+pub fn can_pickup_flag(
+    world: &World,
+    flag_entity: Entity,
+    player: &Player,
+    items: &[Entity],
+) -> bool {
+    let flag = match world.get::<Flag>(flag_entity) {
+        Ok(flag) => flag,
+        Err(_) => return false,
+    };
+
+    let is_carrying_flag = items.iter().any(|item_entity| {
+        world
+            .get::<Item>(*item_entity)
+            .map(|item| item.is_flag)
+            .unwrap_or_default()
+    });
+
+    !flag.is_captured
+        && !is_carrying_flag
+        && player.team.is_some()
+        && player.team != Some(flag.team)
+}
+
+/// Returns `true` if the item is a flag that has been captured, and should be dropped
+pub fn is_flag_captured(world: &World, flag_entity: Entity) -> bool {
+    world
+        .get::<Flag>(flag_entity)
+        .map(|flag| flag.is_captured)
+        .unwrap_or_default()
+}
+
+pub fn draw_flag_bases(world: &mut World) {
+    for (_, (base, transform)) in world.query::<(&FlagBase, &Transform)>().iter() {
+        let rect = base.as_rect(transform.position);
+        let color = TEAM_COLORS[base.team as usize % TEAM_COLORS.len()];
+
+        draw_rectangle(
+            rect.x,
+            rect.y,
+            rect.w,
+            rect.h,
+            Color::new(color.r, color.g, color.b, BASE_FILL_ALPHA),
+        );
+
+        draw_rectangle_lines(
+            rect.x,
+            rect.y,
+            rect.w,
+            rect.h,
+            BASE_OUTLINE_THICKNESS,
+            color,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::input::InputTrack;
+
+    use crate::game::{
+        default_assets_dir, HeadlessGame, MatchRulesParams, Replay, ReplayPlayer, Side,
+        FIXED_DELTA_TIME,
+    };
+
+    use super::*;
+
+    /// A capture the flag match between the first player, on team `0`, and the second player, on
+    /// team `1`. The map has no flag bases, so default bases are spawned.
+    fn ctf_game(capture_cnt: u32) -> HeadlessGame {
+        let players = [("pescy", 0), ("sharky", 1)]
+            .iter()
+            .enumerate()
+            .map(|(index, (character, team))| ReplayPlayer {
+                team: Some(*team),
+                ..ReplayPlayer::new(index as u8, character, InputTrack::new())
+            })
+            .collect();
+
+        let params = MatchRulesParams {
+            win_condition: WinCondition::CaptureTheFlag(capture_cnt),
+            ..Default::default()
+        };
+
+        let replay = Replay::from_script("lev01", 0, params, players);
+
+        let mut game = HeadlessGame::new(default_assets_dir(), &replay).unwrap();
+        game.run(1);
+        game
+    }
+
+    fn flag_of(game: &HeadlessGame, team: u8) -> Entity {
+        game.world()
+            .query::<&Flag>()
+            .iter()
+            .find(|(_, flag)| flag.team == team)
+            .map(|(entity, _)| entity)
+            .unwrap()
+    }
+
+    fn base_of(game: &HeadlessGame, team: u8) -> Entity {
+        game.world()
+            .query::<&FlagBase>()
+            .iter()
+            .find(|(_, base)| base.team == team)
+            .map(|(entity, _)| entity)
+            .unwrap()
+    }
+
+    fn carrier_of(game: &HeadlessGame, flag: Entity) -> Option<Entity> {
+        game.world().get::<Owner>(flag).ok().map(|owner| owner.0)
+    }
+
+    fn flag_state(game: &HeadlessGame, flag: Entity) -> Flag {
+        Flag::clone(&game.world().get::<Flag>(flag).unwrap())
+    }
+
+    /// Move a player to the position of an entity, and run a frame
+    fn move_to(game: &mut HeadlessGame, index: u8, entity: Entity) {
+        let position = game.world().get::<Transform>(entity).unwrap().position;
+
+        let player = game.player(index).unwrap();
+        game.world_mut()
+            .get_mut::<Transform>(player)
+            .unwrap()
+            .position = position;
+
+        game.run(1);
+    }
+
+    fn kill(game: &mut HeadlessGame, index: u8) {
+        let entity = game.player(index).unwrap();
+        game.world_mut().get_mut::<Player>(entity).unwrap().state = PlayerState::Dead;
+    }
+
+    /// Run only the flag updates, so that players are not moved, or respawned
+    fn update_flags(game: &mut HeadlessGame, frame_cnt: u32) {
+        for _ in 0..frame_cnt {
+            fixed_update_flags(game.world_mut());
+        }
+    }
+
+    fn captures(game: &HeadlessGame, team: u8) -> u32 {
+        get_match_rules_mut(game.world())
+            .unwrap()
+            .side_captures(Side::Team(team))
+    }
+
+    #[test]
+    fn flags_are_only_picked_up_by_other_teams() {
+        let mut game = ctf_game(3);
+        let flag = flag_of(&game, 0);
+
+        move_to(&mut game, 0, flag);
+
+        assert_eq!(carrier_of(&game, flag), None);
+
+        move_to(&mut game, 1, flag);
+
+        assert_eq!(carrier_of(&game, flag), game.player(1));
+        assert!(!flag_state(&game, flag).is_at_home);
+    }
+
+    #[test]
+    fn dropped_flags_are_returned_after_a_time() {
+        let mut game = ctf_game(3);
+        let flag = flag_of(&game, 0);
+        let home = flag_state(&game, flag).home;
+
+        move_to(&mut game, 1, flag);
+        kill(&mut game, 1);
+        game.run(1);
+
+        // The flag is dropped where its carrier died
+        assert_eq!(carrier_of(&game, flag), None);
+        assert!(!flag_state(&game, flag).is_at_home);
+
+        let return_frame_cnt = (FLAG_RETURN_TIME / FIXED_DELTA_TIME).ceil() as u32;
+        update_flags(&mut game, return_frame_cnt - 2);
+
+        assert!(!flag_state(&game, flag).is_at_home);
+
+        update_flags(&mut game, 2);
+
+        assert!(flag_state(&game, flag).is_at_home);
+        assert_eq!(game.world().get::<Transform>(flag).unwrap().position, home);
+    }
+
+    #[test]
+    fn dropped_flags_are_returned_when_touched_by_their_team() {
+        let mut game = ctf_game(3);
+        let flag = flag_of(&game, 0);
+
+        move_to(&mut game, 1, flag);
+        kill(&mut game, 1);
+        game.run(1);
+
+        assert!(!flag_state(&game, flag).is_at_home);
+
+        move_to(&mut game, 0, flag);
+
+        assert!(flag_state(&game, flag).is_at_home);
+        assert_eq!(carrier_of(&game, flag), None);
+    }
+
+    #[test]
+    fn flags_are_captured_at_the_base_of_the_carrier() {
+        let mut game = ctf_game(2);
+        let flag = flag_of(&game, 0);
+        let base = base_of(&game, 1);
+
+        move_to(&mut game, 1, flag);
+        move_to(&mut game, 1, base);
+
+        assert_eq!(captures(&game, 1), 1);
+        assert!(get_match_rules_mut(game.world()).unwrap().result.is_none());
+
+        // The captured flag is dropped and returned to its base
+        game.run(2);
+
+        assert_eq!(carrier_of(&game, flag), None);
+        assert!(flag_state(&game, flag).is_at_home);
+
+        // Flags can not be captured while the flag of the carrier is away from its base
+        let own_flag = flag_of(&game, 1);
+
+        move_to(&mut game, 0, own_flag);
+        move_to(&mut game, 1, flag);
+        move_to(&mut game, 1, base);
+
+        assert_eq!(carrier_of(&game, own_flag), game.player(0));
+        assert_eq!(captures(&game, 1), 1);
+
+        kill(&mut game, 0);
+        game.run(1);
+
+        let return_frame_cnt = (FLAG_RETURN_TIME / FIXED_DELTA_TIME).ceil() as u32;
+        update_flags(&mut game, return_frame_cnt);
+
+        assert!(flag_state(&game, own_flag).is_at_home);
+
+        update_flags(&mut game, 1);
+
+        assert_eq!(captures(&game, 1), 2);
+
+        let rules = get_match_rules_mut(game.world()).unwrap();
+        assert_eq!(rules.result.clone().unwrap().winner, Some(Side::Team(1)));
+    }
+}
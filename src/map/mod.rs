@@ -4,13 +4,20 @@ use macroquad::{color, experimental::collections::storage, prelude::*};
 
 use serde::{Deserialize, Serialize};
 
+mod capture_zone;
 mod decoration;
+mod flag;
+mod navigation;
 mod sproinger;
 
+pub use capture_zone::*;
 pub use decoration::*;
+pub use flag::*;
+pub use navigation::*;
 pub use sproinger::*;
 
 use core::math::URect;
+use core::random::Rng;
 use core::text::ToStringHelper;
 use core::Result;
 
@@ -385,8 +392,8 @@ impl Map {
         Ok(())
     }
 
-    pub fn get_random_spawn_point(&self) -> Vec2 {
-        let i = rand::gen_range(0, self.spawn_points.len()) as usize;
+    pub fn get_random_spawn_point(&self, rng: &mut Rng) -> Vec2 {
+        let i = rng.gen_range(0, self.spawn_points.len());
         self.spawn_points[i]
     }
 }
@@ -0,0 +1,663 @@
+//! A navigation graph for bots, built from the collision layers of a map. Nodes are the tiles that
+//! a player can stand in, and edges are the moves between them: walking to a neighbouring tile,
+//! dropping off a ledge, or through a platform, and jumping. Which jumps are possible depends on
+//! the jump force, move speed and gravity of the player character, so a graph is built for a
+//! specific character.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use macroquad::color;
+use macroquad::prelude::*;
+
+use macroquad_platformer::Tile;
+
+use crate::player::PlayerCharacterMetadata;
+use crate::{get_collision_tiles, Map, TERMINAL_VELOCITY};
+
+/// The maximum amount of frames that a jump is simulated for
+const MAX_JUMP_FRAMES: u32 = 180;
+/// Jumps cost more than walking the same distance, so that bots only jump when it saves time
+const JUMP_COST_FACTOR: f32 = 1.5;
+
+const DEBUG_NODE_RADIUS: f32 = 3.0;
+const DEBUG_EDGE_THICKNESS: f32 = 1.0;
+const DEBUG_PATH_THICKNESS: f32 = 3.0;
+
+/// The movement attributes of the character that a graph is built for
+#[derive(Debug, Copy, Clone)]
+pub struct NavGraphParams {
+    pub jump_force: f32,
+    pub move_speed: f32,
+    pub gravity: f32,
+    pub collider_size: Vec2,
+}
+
+impl From<&PlayerCharacterMetadata> for NavGraphParams {
+    fn from(character: &PlayerCharacterMetadata) -> Self {
+        NavGraphParams {
+            jump_force: character.jump_force,
+            move_speed: character.move_speed,
+            gravity: character.gravity,
+            collider_size: character.collider_size,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NavEdgeKind {
+    /// Walk to a neighbouring tile, on the same level
+    Walk,
+    /// Fall off a ledge, or through a platform, to the tile below
+    Drop,
+    /// Jump to a tile that can not be reached by walking or dropping
+    Jump,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct NavNode {
+    /// The grid coordinates of the tile that a player stands in
+    pub coords: UVec2,
+    /// The position of the feet of a player standing in the middle of the tile
+    pub position: Vec2,
+    /// This is `true` if the node is on top of a platform, that can be dropped through
+    pub is_on_platform: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct NavEdge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: NavEdgeKind,
+    pub cost: f32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NavGraph {
+    nodes: Vec<NavNode>,
+    edges: Vec<Vec<NavEdge>>,
+    /// The index of the node in each tile, if there is one, by tile index
+    node_indices: Vec<Option<usize>>,
+}
+
+impl NavGraph {
+    pub fn new(map: &Map, params: NavGraphParams) -> Self {
+        let tiles = get_collision_tiles(map);
+
+        let mut graph = NavGraph {
+            node_indices: vec![None; tiles.len()],
+            ..Default::default()
+        };
+
+        let grid = Grid {
+            tiles: &tiles,
+            size: map.grid_size,
+        };
+
+        let body_height = (params.collider_size.y / map.tile_size.y).ceil().max(1.0) as u32;
+
+        for y in 0..map.grid_size.y {
+            for x in 0..map.grid_size.x {
+                if grid.is_standable(x, y, body_height) {
+                    let coords = uvec2(x, y);
+
+                    let position =
+                        map.to_position(uvec2(x, y + 1)) + vec2(map.tile_size.x / 2.0, 0.0);
+
+                    graph.node_indices[map.to_index(coords)] = Some(graph.nodes.len());
+
+                    graph.nodes.push(NavNode {
+                        coords,
+                        position,
+                        is_on_platform: grid.is_platform(x, y + 1),
+                    });
+                }
+            }
+        }
+
+        graph.edges = (0..graph.nodes.len())
+            .map(|i| graph.find_edges(i, map, &grid, &params))
+            .collect();
+
+        graph
+    }
+
+    pub fn nodes(&self) -> &[NavNode] {
+        &self.nodes
+    }
+
+    /// The edges going out of a node
+    pub fn edges(&self, node: usize) -> &[NavEdge] {
+        &self.edges[node]
+    }
+
+    /// The node closest to the feet of a player at `position`
+    pub fn nearest_node(&self, position: Vec2) -> Option<usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                a.position
+                    .distance_squared(position)
+                    .partial_cmp(&b.position.distance_squared(position))
+                    .unwrap()
+            })
+            .map(|(i, _)| i)
+    }
+
+    /// Find the cheapest path between two nodes, with A*. The path is returned as the edges to
+    /// follow, in order, and is empty if `from` and `to` are the same node.
+    pub fn find_path(&self, from: usize, to: usize) -> Option<Vec<NavEdge>> {
+        let goal = self.nodes[to].position;
+
+        let mut open = BinaryHeap::new();
+        let mut costs = vec![f32::INFINITY; self.nodes.len()];
+        let mut came_from: Vec<Option<NavEdge>> = vec![None; self.nodes.len()];
+
+        costs[from] = 0.0;
+
+        open.push(OpenNode {
+            node: from,
+            estimate: self.nodes[from].position.distance(goal),
+        });
+
+        while let Some(OpenNode { node, .. }) = open.pop() {
+            if node == to {
+                let mut path = Vec::new();
+
+                let mut current = to;
+                while let Some(edge) = came_from[current] {
+                    path.push(edge);
+                    current = edge.from;
+                }
+
+                path.reverse();
+
+                return Some(path);
+            }
+
+            for edge in &self.edges[node] {
+                let cost = costs[node] + edge.cost;
+
+                if cost < costs[edge.to] {
+                    costs[edge.to] = cost;
+                    came_from[edge.to] = Some(*edge);
+
+                    open.push(OpenNode {
+                        node: edge.to,
+                        estimate: cost + self.nodes[edge.to].position.distance(goal),
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Draw all nodes and edges, and highlight a path, if one is given
+    pub fn debug_draw(&self, path: &[NavEdge]) {
+        for (node, edges) in self.nodes.iter().zip(&self.edges) {
+            for edge in edges {
+                let to = self.nodes[edge.to].position;
+
+                let color = match edge.kind {
+                    NavEdgeKind::Walk => color::GREEN,
+                    NavEdgeKind::Drop => color::ORANGE,
+                    NavEdgeKind::Jump => Color::new(0.0, 0.6, 1.0, 0.3),
+                };
+
+                draw_line(
+                    node.position.x,
+                    node.position.y,
+                    to.x,
+                    to.y,
+                    DEBUG_EDGE_THICKNESS,
+                    color,
+                );
+            }
+
+            draw_circle(
+                node.position.x,
+                node.position.y,
+                DEBUG_NODE_RADIUS,
+                color::YELLOW,
+            );
+        }
+
+        for edge in path {
+            let from = self.nodes[edge.from].position;
+            let to = self.nodes[edge.to].position;
+
+            draw_line(from.x, from.y, to.x, to.y, DEBUG_PATH_THICKNESS, color::RED);
+        }
+    }
+
+    fn find_edges(
+        &self,
+        from: usize,
+        map: &Map,
+        grid: &Grid,
+        params: &NavGraphParams,
+    ) -> Vec<NavEdge> {
+        let mut edges = Vec::new();
+
+        let node = self.nodes[from];
+        let (x, y) = (node.coords.x, node.coords.y);
+
+        let mut add_edge = |to: usize, kind: NavEdgeKind| {
+            let mut cost = node.position.distance(self.nodes[to].position);
+
+            if kind == NavEdgeKind::Jump {
+                cost = cost * JUMP_COST_FACTOR + map.tile_size.x;
+            }
+
+            edges.push(NavEdge {
+                from,
+                to,
+                kind,
+                cost,
+            });
+        };
+
+        for dx in [-1i32, 1] {
+            let neighbor_x = x as i32 + dx;
+            if neighbor_x < 0 || neighbor_x >= map.grid_size.x as i32 {
+                continue;
+            }
+
+            let neighbor_x = neighbor_x as u32;
+
+            if let Some(to) = self.node_at(map, neighbor_x, y) {
+                add_edge(to, NavEdgeKind::Walk);
+            } else if !grid.is_solid(neighbor_x, y) {
+                // Walking off a ledge lands on the first node below it
+                if let Some(to) = self.find_node_below(map, grid, neighbor_x, y) {
+                    add_edge(to, NavEdgeKind::Drop);
+                }
+            }
+        }
+
+        if node.is_on_platform {
+            if let Some(to) = self.find_node_below(map, grid, x, y + 1) {
+                add_edge(to, NavEdgeKind::Drop);
+            }
+        }
+
+        // Jumps are only added to nodes that are not neighbours, on the same level, as those can
+        // be walked to. Nodes that are lower than the jump height are left to drops.
+        let max_height = jump_height(params);
+        let max_distance = landing_frame(params, max_height)
+            .map(|frame| frame as f32 * params.move_speed)
+            .unwrap_or_default();
+
+        for (to, target) in self.nodes.iter().enumerate() {
+            let offset = target.position - node.position;
+
+            let is_neighbor =
+                target.coords.y == y && (target.coords.x as i32 - x as i32).abs() <= 1;
+
+            if is_neighbor || offset.y.abs() > max_height || offset.x.abs() > max_distance {
+                continue;
+            }
+
+            if can_jump(map, grid, params, node.position, target.position) {
+                add_edge(to, NavEdgeKind::Jump);
+            }
+        }
+
+        edges
+    }
+
+    fn node_at(&self, map: &Map, x: u32, y: u32) -> Option<usize> {
+        self.node_indices[map.to_index(uvec2(x, y))]
+    }
+
+    /// Find the first node in a column, below the tile at `x`, `y`, unless there is a solid tile in
+    /// the way
+    fn find_node_below(&self, map: &Map, grid: &Grid, x: u32, y: u32) -> Option<usize> {
+        for y in (y + 1)..grid.size.y {
+            if let Some(node) = self.node_at(map, x, y) {
+                return Some(node);
+            }
+
+            if grid.is_solid(x, y) {
+                break;
+            }
+        }
+
+        None
+    }
+}
+
+/// The collision tiles of a map, with helpers to check them by coordinates
+struct Grid<'a> {
+    tiles: &'a [Tile],
+    size: UVec2,
+}
+
+impl Grid<'_> {
+    fn get(&self, x: u32, y: u32) -> Option<&Tile> {
+        if x < self.size.x && y < self.size.y {
+            self.tiles.get((y * self.size.x + x) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn is_solid(&self, x: u32, y: u32) -> bool {
+        matches!(self.get(x, y), Some(Tile::Solid))
+    }
+
+    fn is_platform(&self, x: u32, y: u32) -> bool {
+        matches!(self.get(x, y), Some(Tile::JumpThrough))
+    }
+
+    /// A player can stand in a tile that has ground below it, and enough room above it
+    fn is_standable(&self, x: u32, y: u32, body_height: u32) -> bool {
+        let has_ground = self.is_solid(x, y + 1) || self.is_platform(x, y + 1);
+        let has_room = (0..body_height).all(|i| y >= i && !self.is_solid(x, y - i));
+
+        has_ground && has_room
+    }
+}
+
+/// The height of a jump, without floating
+fn jump_height(params: &NavGraphParams) -> f32 {
+    let mut height = 0.0;
+    let mut velocity = params.jump_force;
+
+    for _ in 0..MAX_JUMP_FRAMES {
+        if velocity <= 0.0 {
+            break;
+        }
+
+        height += velocity;
+        velocity -= params.gravity;
+    }
+
+    height
+}
+
+/// The frame that a jump lands on a target `offset_y` below the start of the jump, which is
+/// negative for targets above it, or `None` if the target is out of reach
+fn landing_frame(params: &NavGraphParams, offset_y: f32) -> Option<u32> {
+    let mut y = 0.0;
+    let mut velocity = -params.jump_force;
+    let mut has_reached_height = offset_y >= 0.0;
+
+    for frame in 1..=MAX_JUMP_FRAMES {
+        y += velocity;
+        velocity = (velocity + params.gravity).min(TERMINAL_VELOCITY);
+
+        has_reached_height |= y <= offset_y;
+
+        if has_reached_height && velocity > 0.0 && y >= offset_y {
+            return Some(frame);
+        }
+    }
+
+    None
+}
+
+/// Simulate a jump from one position to another, frame by frame, the same way that the physics of
+/// a player body does, and check that the body does not hit any solid tiles on the way. The
+/// horizontal speed is the speed that lands the jump exactly on the target, which can not be
+/// more than the move speed.
+fn can_jump(map: &Map, grid: &Grid, params: &NavGraphParams, from: Vec2, to: Vec2) -> bool {
+    let landing_frame = match landing_frame(params, to.y - from.y) {
+        Some(frame) => frame,
+        None => return false,
+    };
+
+    let horizontal_speed = (to.x - from.x) / landing_frame as f32;
+
+    if horizontal_speed.abs() > params.move_speed {
+        return false;
+    }
+
+    let mut position = from;
+    let mut velocity = -params.jump_force;
+
+    // The body can fall past the target on the landing frame, where it is stopped by the ground, so
+    // only the frames before it are checked
+    for _ in 1..landing_frame {
+        position += vec2(horizontal_speed, velocity);
+        velocity = (velocity + params.gravity).min(TERMINAL_VELOCITY);
+
+        // Check both the head and the feet, as the body is only blocked by solid tiles
+        let head = position - vec2(0.0, params.collider_size.y);
+        let feet = position - vec2(0.0, 1.0);
+
+        for point in [head, feet] {
+            if !map.contains(point) {
+                return false;
+            }
+
+            let coords = map.to_coords(point);
+
+            if grid.is_solid(coords.x, coords.y) {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// An entry in the open set of A*, ordered so that the lowest estimate is popped first
+struct OpenNode {
+    node: usize,
+    estimate: f32,
+}
+
+impl PartialEq for OpenNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl Eq for OpenNode {}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .partial_cmp(&self.estimate)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::{MapLayer, MapLayerKind, MapTile};
+
+    use super::*;
+
+    const TILE_SIZE: f32 = 16.0;
+
+    const FLOOR_Y: u32 = 15;
+    /// A platform that can be jumped onto from the floor
+    const PLATFORM_Y: u32 = 12;
+    /// A platform that is out of reach of a jump from the floor
+    const HIGH_PLATFORM_Y: u32 = 6;
+    /// A solid block on the floor, that can be walked off of
+    const LEDGE_Y: u32 = 14;
+
+    fn tile(attributes: &[&str]) -> Option<MapTile> {
+        Some(MapTile {
+            tile_id: 0,
+            tileset_id: "test".to_string(),
+            texture_id: "test".to_string(),
+            texture_coords: Vec2::ZERO,
+            attributes: attributes.iter().map(|attr| attr.to_string()).collect(),
+        })
+    }
+
+    fn test_map() -> Map {
+        let grid_size = uvec2(24, 16);
+
+        let mut map = Map::new(vec2(TILE_SIZE, TILE_SIZE), grid_size);
+        let mut layer = MapLayer::new("collision", MapLayerKind::TileLayer, true, grid_size);
+
+        let mut set_tile = |x: u32, y: u32, tile: Option<MapTile>| {
+            layer.tiles[(y * grid_size.x + x) as usize] = tile;
+        };
+
+        for x in 0..grid_size.x {
+            set_tile(x, FLOOR_Y, tile(&[]));
+        }
+
+        for x in 4..8 {
+            set_tile(x, PLATFORM_Y, tile(&[Map::PLATFORM_TILE_ATTRIBUTE]));
+        }
+
+        for x in 10..13 {
+            set_tile(x, HIGH_PLATFORM_Y, tile(&[Map::PLATFORM_TILE_ATTRIBUTE]));
+        }
+
+        for x in 16..20 {
+            set_tile(x, LEDGE_Y, tile(&[]));
+        }
+
+        map.layers.insert(layer.id.clone(), layer);
+        map.draw_order.push("collision".to_string());
+
+        map
+    }
+
+    fn default_params() -> NavGraphParams {
+        NavGraphParams {
+            jump_force: PlayerCharacterMetadata::default_jump_force(),
+            move_speed: PlayerCharacterMetadata::default_move_speed(),
+            gravity: PlayerCharacterMetadata::default_gravity(),
+            collider_size: PlayerCharacterMetadata::default_collider_size(),
+        }
+    }
+
+    /// The node that a player stands in, on top of the tile at `x`, `y`
+    fn node_on(graph: &NavGraph, x: u32, y: u32) -> usize {
+        graph
+            .nodes()
+            .iter()
+            .position(|node| node.coords == uvec2(x, y - 1))
+            .unwrap_or_else(|| panic!("There should be a node on top of {}, {}", x, y))
+    }
+
+    fn has_edge(graph: &NavGraph, from: usize, to: usize, kind: NavEdgeKind) -> bool {
+        graph
+            .edges(from)
+            .iter()
+            .any(|edge| edge.to == to && edge.kind == kind)
+    }
+
+    #[test]
+    fn nodes_are_on_top_of_ground() {
+        let graph = NavGraph::new(&test_map(), default_params());
+
+        let platform = graph.nodes()[node_on(&graph, 5, PLATFORM_Y)];
+        assert!(platform.is_on_platform);
+        assert_eq!(platform.position, vec2(5.5, PLATFORM_Y as f32) * TILE_SIZE);
+
+        let floor = graph.nodes()[node_on(&graph, 0, FLOOR_Y)];
+        assert!(!floor.is_on_platform);
+
+        // There is no room to stand between the floor and a platform, or in a solid tile
+        assert!(graph
+            .nodes()
+            .iter()
+            .all(|node| node.coords.y != FLOOR_Y && node.coords.y != PLATFORM_Y));
+    }
+
+    #[test]
+    fn walk_drop_and_jump_edges() {
+        let graph = NavGraph::new(&test_map(), default_params());
+
+        let floor = node_on(&graph, 1, FLOOR_Y);
+        let next_to_floor = node_on(&graph, 2, FLOOR_Y);
+
+        assert!(has_edge(&graph, floor, next_to_floor, NavEdgeKind::Walk));
+        assert!(has_edge(&graph, next_to_floor, floor, NavEdgeKind::Walk));
+
+        // Dropping through a platform lands on the floor below it
+        let platform = node_on(&graph, 5, PLATFORM_Y);
+        let below_platform = node_on(&graph, 5, FLOOR_Y);
+
+        assert!(has_edge(
+            &graph,
+            platform,
+            below_platform,
+            NavEdgeKind::Drop
+        ));
+
+        // Walking off a ledge lands on the floor next to it
+        let ledge = node_on(&graph, 16, LEDGE_Y);
+        let below_ledge = node_on(&graph, 15, FLOOR_Y);
+
+        assert!(has_edge(&graph, ledge, below_ledge, NavEdgeKind::Drop));
+        assert!(has_edge(&graph, below_ledge, ledge, NavEdgeKind::Jump));
+
+        // The floor under a platform is not a platform, so it can not be dropped through
+        assert!(graph
+            .edges(below_platform)
+            .iter()
+            .all(|edge| edge.kind != NavEdgeKind::Drop));
+
+        assert!(graph
+            .edges(below_platform)
+            .iter()
+            .any(|edge| edge.kind == NavEdgeKind::Jump
+                && graph.nodes()[edge.to].coords.y == PLATFORM_Y - 1));
+    }
+
+    #[test]
+    fn raised_platform_is_reachable() {
+        let graph = NavGraph::new(&test_map(), default_params());
+
+        let from = node_on(&graph, 0, FLOOR_Y);
+        let to = node_on(&graph, 6, PLATFORM_Y);
+
+        let path = graph.find_path(from, to).unwrap();
+
+        assert_eq!(path.first().unwrap().from, from);
+        assert_eq!(path.last().unwrap().to, to);
+        assert!(path.windows(2).all(|edges| edges[0].to == edges[1].from));
+        assert!(path.iter().any(|edge| edge.kind == NavEdgeKind::Jump));
+
+        // The way back down is shorter by dropping than by jumping
+        let path = graph.find_path(to, from).unwrap();
+        assert!(path.iter().all(|edge| edge.kind != NavEdgeKind::Jump));
+
+        assert_eq!(graph.find_path(from, from).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn platform_out_of_jump_height_is_unreachable() {
+        let map = test_map();
+
+        let graph = NavGraph::new(&map, default_params());
+
+        let from = node_on(&graph, 0, FLOOR_Y);
+        let to = node_on(&graph, 11, HIGH_PLATFORM_Y);
+
+        assert!(graph.find_path(from, to).is_none());
+
+        // A character that jumps high enough can reach it
+        let params = NavGraphParams {
+            jump_force: 17.0,
+            ..default_params()
+        };
+
+        let graph = NavGraph::new(&map, params);
+
+        let from = node_on(&graph, 0, FLOOR_Y);
+        let to = node_on(&graph, 11, HIGH_PLATFORM_Y);
+
+        assert!(graph.find_path(from, to).is_some());
+    }
+}
@@ -8,6 +8,7 @@ use hecs::{Entity, World};
 use core::Result;
 use core::Transform;
 
+use crate::game::get_simulation_dt;
 use crate::{Animation, Drawable, PhysicsBody, QueuedAnimationAction, Resources};
 
 const SPROINGER_DRAW_ORDER: u32 = 2;
@@ -27,7 +28,7 @@ const TRIGGER_HEIGHT: f32 = 8.0;
 
 const FORCE: f32 = 25.0;
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Sproinger {
     pub cooldown_timer: f32,
 }
@@ -83,7 +84,7 @@ pub fn spawn_sproinger(world: &mut World, position: Vec2) -> Result<Entity> {
 }
 
 pub fn fixed_update_sproingers(world: &mut World) {
-    let dt = get_frame_time();
+    let dt = get_simulation_dt(world);
 
     let bodies = world
         .query::<(&Transform, &PhysicsBody)>()
@@ -106,7 +107,7 @@ pub fn fixed_update_sproingers(world: &mut World) {
 
         let sound = {
             let resources = storage::get::<Resources>();
-            resources.sounds[SOUND_EFFECT_ID]
+            resources.sounds.get(SOUND_EFFECT_ID).copied()
         };
 
         if sproinger.cooldown_timer >= COOLDOWN {
@@ -128,7 +129,9 @@ pub fn fixed_update_sproingers(world: &mut World) {
                         CONTRACT_ANIMATION_ID.to_string(),
                     ));
 
-                    play_sound_once(sound);
+                    if let Some(sound) = sound {
+                        play_sound_once(sound);
+                    }
 
                     continue 'sproingers;
                 }
@@ -0,0 +1,110 @@
+use macroquad::color;
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+
+use hecs::World;
+
+use super::{NetworkSession, NetworkSessionKind};
+
+const FONT_SIZE: f32 = 18.0;
+const LINE_HEIGHT: f32 = 20.0;
+const MARGIN: f32 = 16.0;
+
+/// Draws the state of the network session, like input delay and round trip times, in the top
+/// left corner of the screen
+pub fn debug_draw_network_stats(_world: &mut World) {
+    let session = match storage::try_get::<NetworkSession>() {
+        Some(session) => session,
+        None => return,
+    };
+
+    let mut lines = Vec::new();
+
+    match &session.kind {
+        NetworkSessionKind::DelayedLockstep(input_buffer) => {
+            lines.push(format!(
+                "Delayed lockstep, frame {}",
+                input_buffer.current_frame()
+            ));
+
+            let mut delay_line = format!("Input delay: {} frames", input_buffer.delay());
+
+            if let Some(controller) = &session.delay_controller {
+                delay_line.push_str(&format!(
+                    " (adaptive, min slack: {})",
+                    controller
+                        .min_slack()
+                        .map(|slack| slack.to_string())
+                        .unwrap_or_else(|| "-".to_string())
+                ));
+            }
+
+            lines.push(delay_line);
+            lines.push(format!("Stalls: {}", session.stall_cnt));
+
+            let idle_players = input_buffer
+                .player_ids()
+                .iter()
+                .filter(|id| input_buffer.is_idle(id, input_buffer.current_frame()))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if !idle_players.is_empty() {
+                lines.push(format!("Idle players: {}", idle_players.join(", ")));
+            }
+
+            if session.is_reconnecting {
+                lines.push("Reconnecting to the host".to_string());
+            }
+        }
+        NetworkSessionKind::Rollback(rollback) => {
+            lines.push(format!(
+                "Rollback, frame {} (confirmed {})",
+                rollback.current_frame(),
+                rollback.confirmed_frame()
+            ));
+
+            lines.push(format!(
+                "Rollbacks: {} (last {} frames)",
+                rollback.rollback_cnt(),
+                rollback.last_rollback_len()
+            ));
+        }
+        NetworkSessionKind::Spectate(input_buffer) => {
+            let buffered = input_buffer
+                .player_ids()
+                .iter()
+                .map(|id| input_buffer.buffered_frames(id))
+                .min()
+                .unwrap_or_default();
+
+            lines.push(format!(
+                "Spectating, frame {} ({} frames buffered)",
+                input_buffer.current_frame(),
+                buffered
+            ));
+        }
+    }
+
+    let mut peers = session.peer_latency.iter().collect::<Vec<_>>();
+    peers.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (player_id, estimator) in peers {
+        lines.push(format!(
+            "Player {}: RTT {:.1} ms, jitter {:.1} ms",
+            player_id,
+            estimator.rtt() * 1000.0,
+            estimator.jitter() * 1000.0
+        ));
+    }
+
+    push_camera_state();
+    set_default_camera();
+
+    for (i, line) in lines.iter().enumerate() {
+        let y = MARGIN + FONT_SIZE + LINE_HEIGHT * i as f32;
+        draw_text(line, MARGIN, y, FONT_SIZE, color::WHITE);
+    }
+
+    pop_camera_state();
+}
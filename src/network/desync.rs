@@ -0,0 +1,483 @@
+//! Desync detection. After every fixed update in a network game, the gameplay relevant state of
+//! the world is described, one line per entity, and a checksum is calculated from these lines.
+//! Checksums of final frames are dispatched to the other peers and compared by the `ApiBackend`,
+//! which will emit a `NetworkEvent::Desync`, if they differ. As all peers compare checksums, each
+//! of them will dump its own description of the frame to disk, so the dumps can be compared. The
+//! dump names the files that the peers of the desynced players will have written for the frame.
+//!
+//! The peer that detected the desync also requests the descriptions of the frame from the desynced
+//! players, and writes them next to its own, along with a diff of the two, so that the cause can
+//! be found on a single machine. This is only done for the first desynced frame of each player, as
+//! the state of every frame that follows it will usually differ as well.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use macroquad::experimental::collections::storage;
+use macroquad::time::get_time;
+
+use hecs::{Entity, World};
+
+use core::network::{state_description_chunks, Api, NetworkMessage, PlayerId};
+use core::Transform;
+
+use crate::effects::active::projectiles::Projectile;
+use crate::effects::active::triggered::TriggeredEffect;
+use crate::game::get_simulation_mut;
+use crate::items::Weapon;
+use crate::player::Player;
+use crate::{Item, PhysicsBody};
+
+/// The amount of final frames that state descriptions are kept for, so that they can be dumped if
+/// a desync is detected when the remote checksum arrives
+const STATE_HISTORY_LEN: u64 = 300;
+
+const DUMP_DIR: &str = "desync";
+
+/// The interval, in seconds, between requests for the description of a desynced frame, while
+/// the description has not been received from all of the desynced players
+const DESCRIPTION_REQUEST_INTERVAL: f64 = 1.0;
+
+/// A description is no longer requested after this many attempts
+const MAX_DESCRIPTION_REQUEST_CNT: u32 = 10;
+
+#[derive(Clone)]
+struct FrameState {
+    checksum: u64,
+    lines: Vec<String>,
+}
+
+/// A request for the descriptions of a desynced frame, from the players that it desynced with
+struct DescriptionRequest {
+    /// The local state of the frame, which may no longer be in the history when the remote
+    /// descriptions arrive
+    state: FrameState,
+    player_ids: Vec<PlayerId>,
+    last_request: f64,
+    request_cnt: u32,
+}
+
+#[derive(Default)]
+pub struct StateHistory {
+    states: BTreeMap<u64, FrameState>,
+    /// Checksums have been dispatched for all frames before this
+    next_checksum_frame: u64,
+    description_requests: BTreeMap<u64, DescriptionRequest>,
+    /// The players that a desync has been detected with
+    desynced_player_ids: HashSet<PlayerId>,
+}
+
+/// Describe the gameplay relevant components of an entity. Entity handles and collision world
+/// actors are left out, as these may differ between peers, even if the simulation does not.
+fn describe_entity(world: &World, entity: Entity) -> Option<String> {
+    let mut parts = Vec::new();
+
+    if let Ok(transform) = world.get::<Transform>(entity) {
+        parts.push(format!(
+            "Transform {{ position: {:?}, rotation: {:?} }}",
+            transform.position, transform.rotation
+        ));
+    }
+
+    if let Ok(body) = world.get::<PhysicsBody>(entity) {
+        parts.push(format!(
+            "PhysicsBody {{ offset: {:?}, size: {:?}, velocity: {:?}, is_on_ground: {}, was_on_ground: {}, is_on_platform: {}, has_mass: {}, has_friction: {}, is_deactivated: {} }}",
+            body.offset,
+            body.size,
+            body.velocity,
+            body.is_on_ground,
+            body.was_on_ground,
+            body.is_on_platform,
+            body.has_mass,
+            body.has_friction,
+            body.is_deactivated,
+        ));
+    }
+
+    if let Ok(player) = world.get::<Player>(entity) {
+        let passive_effects = player
+            .passive_effects
+            .iter()
+            .map(|effect| effect.name.as_str())
+            .collect::<Vec<_>>();
+
+        parts.push(format!(
+            "Player {{ index: {}, state: {:?}, is_facing_left: {}, is_upside_down: {}, is_attacking: {}, jump_frame_counter: {}, pickup_grace_timer: {:?}, incapacitation_timer: {:?}, attack_timer: {:?}, respawn_timer: {:?}, passive_effects: {:?} }}",
+            player.index,
+            player.state,
+            player.is_facing_left,
+            player.is_upside_down,
+            player.is_attacking,
+            player.jump_frame_counter,
+            player.pickup_grace_timer,
+            player.incapacitation_timer,
+            player.attack_timer,
+            player.respawn_timer,
+            passive_effects,
+        ));
+    }
+
+    if let Ok(weapon) = world.get::<Weapon>(entity) {
+        parts.push(format!(
+            "Weapon {{ id: {:?}, cooldown_timer: {:?}, use_cnt: {} }}",
+            weapon.id, weapon.cooldown_timer, weapon.use_cnt
+        ));
+    }
+
+    if let Ok(item) = world.get::<Item>(entity) {
+        parts.push(format!(
+            "Item {{ id: {:?}, duration_timer: {:?}, use_cnt: {} }}",
+            item.id, item.duration_timer, item.use_cnt
+        ));
+    }
+
+    if let Ok(effect) = world.get::<TriggeredEffect>(entity) {
+        parts.push(format!(
+            "TriggeredEffect {{ is_triggered: {}, should_override_delay: {}, kick_delay_timer: {:?}, activation_timer: {:?}, trigger_delay_timer: {:?}, timed_trigger_timer: {:?} }}",
+            effect.is_triggered,
+            effect.should_override_delay,
+            effect.kick_delay_timer,
+            effect.activation_timer,
+            effect.trigger_delay_timer,
+            effect.timed_trigger_timer,
+        ));
+    }
+
+    if let Ok(projectile) = world.get::<Projectile>(entity) {
+        parts.push(format!(
+            "Projectile {{ kind: {:?}, origin: {:?}, range: {:?}, is_lethal: {} }}",
+            projectile.kind, projectile.origin, projectile.range, projectile.is_lethal
+        ));
+    }
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(", "))
+    }
+}
+
+/// Describe the gameplay relevant state of the world, one line per entity. The lines are sorted,
+/// so that descriptions can be compared regardless of the order entities are stored in.
+pub fn describe_world(world: &World) -> Vec<String> {
+    let mut lines = world
+        .iter()
+        .filter_map(|entity_ref| describe_entity(world, entity_ref.entity()))
+        .collect::<Vec<_>>();
+
+    lines.sort();
+
+    lines
+}
+
+/// This uses FNV-1a, as the checksum must be stable between builds and platforms
+fn hash_line(line: &str) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+
+    for byte in line.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash
+}
+
+/// Calculate the checksum of a world description. The line hashes are summed, so the result does
+/// not depend on the order of the lines.
+pub fn state_checksum(lines: &[String]) -> u64 {
+    lines.iter().fold(0u64, |checksum, line| {
+        checksum.wrapping_add(hash_line(line))
+    })
+}
+
+/// This records the state of the frame that was just simulated and must be run after all other
+/// simulation systems, but before `fixed_update_simulation` increments the frame counter. When
+/// rolling back, the state of re-simulated frames is overwritten.
+pub fn fixed_update_state_history(world: &mut World) {
+    let frame = get_simulation_mut(world).frame;
+
+    let lines = describe_world(world);
+    let checksum = state_checksum(&lines);
+
+    let mut history = storage::get_mut::<StateHistory>();
+    history.states.insert(frame, FrameState { checksum, lines });
+}
+
+/// Dispatch the checksums of all frames before `final_frame` that have not yet been dispatched.
+/// All frames before `final_frame` must have been simulated with confirmed input only.
+pub fn dispatch_state_checksums(player_id: &PlayerId, final_frame: u64) {
+    let mut history = storage::get_mut::<StateHistory>();
+
+    for (frame, state) in history
+        .states
+        .range(history.next_checksum_frame..final_frame)
+    {
+        let message = NetworkMessage::StateChecksum {
+            player_id: player_id.clone(),
+            frame: *frame,
+            checksum: state.checksum,
+        };
+
+        if let Err(err) = Api::dispatch_message(message) {
+            #[cfg(debug_assertions)]
+            println!("WARNING: {}", err);
+        }
+    }
+
+    history.next_checksum_frame = history.next_checksum_frame.max(final_frame);
+
+    let oldest_frame = final_frame.saturating_sub(STATE_HISTORY_LEN);
+    history.states = history.states.split_off(&oldest_frame);
+}
+
+fn dump_file_name(player_id: &PlayerId, frame: u64) -> String {
+    format!("frame_{}_player_{}.txt", frame, player_id)
+}
+
+fn diff_file_name(player_id: &PlayerId, remote_player_id: &PlayerId, frame: u64) -> String {
+    format!(
+        "frame_{}_player_{}_diff_{}.txt",
+        frame, player_id, remote_player_id
+    )
+}
+
+/// Mark the checksums of all frames before `final_frame` as dispatched, without dispatching them.
+/// This is used for frames that are re-simulated to catch up with the other peers, which have
+/// already compared their checksums.
+pub fn skip_state_checksums(final_frame: u64) {
+    let mut history = storage::get_mut::<StateHistory>();
+
+    history.next_checksum_frame = history.next_checksum_frame.max(final_frame);
+
+    let oldest_frame = final_frame.saturating_sub(STATE_HISTORY_LEN);
+    history.states = history.states.split_off(&oldest_frame);
+}
+
+/// Write the recorded description of `frame` to the desync dump directory. `remote_player_ids`
+/// are the players whose checksums did not match the local one. Their descriptions of the frame
+/// are requested, if this is the first frame that they have desynced on.
+pub fn dump_state(player_id: &PlayerId, remote_player_ids: &[PlayerId], frame: u64) {
+    let mut history = storage::get_mut::<StateHistory>();
+
+    let state = match history.states.get(&frame) {
+        Some(state) => state.clone(),
+        None => {
+            #[cfg(debug_assertions)]
+            println!("WARNING: No state recorded for desynced frame {}", frame);
+
+            return;
+        }
+    };
+
+    let header = remote_player_ids
+        .iter()
+        .map(|remote_player_id| {
+            format!("desynced with: {}", dump_file_name(remote_player_id, frame))
+        })
+        .collect::<Vec<_>>();
+
+    write_dump(&dump_file_name(player_id, frame), &state, &header);
+
+    let player_ids = remote_player_ids
+        .iter()
+        .filter(|remote_player_id| {
+            history
+                .desynced_player_ids
+                .insert((*remote_player_id).clone())
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if !player_ids.is_empty() {
+        dispatch_description_request(player_id, frame);
+
+        let request = DescriptionRequest {
+            state,
+            player_ids,
+            last_request: get_time(),
+            request_cnt: 1,
+        };
+
+        history.description_requests.insert(frame, request);
+    }
+}
+
+/// Request the descriptions of desynced frames again, until they have been received from all the
+/// desynced players, or `MAX_DESCRIPTION_REQUEST_CNT` requests have been made
+pub fn update_state_description_requests(player_id: &PlayerId) {
+    let mut history = storage::get_mut::<StateHistory>();
+
+    let now = get_time();
+
+    history.description_requests.retain(|_, request| {
+        request.request_cnt < MAX_DESCRIPTION_REQUEST_CNT
+            || now - request.last_request < DESCRIPTION_REQUEST_INTERVAL
+    });
+
+    for (frame, request) in &mut history.description_requests {
+        if now - request.last_request >= DESCRIPTION_REQUEST_INTERVAL {
+            request.last_request = now;
+            request.request_cnt += 1;
+
+            dispatch_description_request(player_id, *frame);
+        }
+    }
+}
+
+/// Answer a request for the description of `frame`, from a player that has detected a desync
+pub fn dispatch_state_description(player_id: &PlayerId, frame: u64) {
+    let history = storage::get::<StateHistory>();
+
+    if let Some(state) = history.states.get(&frame) {
+        let data = state.lines.join("\n").into_bytes();
+
+        for message in state_description_chunks(player_id, frame, &data) {
+            if let Err(err) = Api::dispatch_message(message) {
+                #[cfg(debug_assertions)]
+                println!("WARNING: {}", err);
+            }
+        }
+    } else {
+        #[cfg(debug_assertions)]
+        println!(
+            "WARNING: No state recorded for frame {}, requested after a desync",
+            frame
+        );
+    }
+}
+
+/// Write a description of `frame`, received from a desynced player, to the desync dump directory,
+/// along with a diff of it and the local description. Descriptions that have not been requested
+/// are ignored, as the other players will also receive them.
+pub fn dump_remote_state(
+    player_id: &PlayerId,
+    remote_player_id: &PlayerId,
+    frame: u64,
+    data: &[u8],
+) {
+    let mut history = storage::get_mut::<StateHistory>();
+
+    let request = match history.description_requests.get_mut(&frame) {
+        Some(request) if request.player_ids.contains(remote_player_id) => request,
+        _ => return,
+    };
+
+    request.player_ids.retain(|id| id != remote_player_id);
+
+    let lines = String::from_utf8_lossy(data)
+        .lines()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+
+    let remote_state = FrameState {
+        checksum: state_checksum(&lines),
+        lines,
+    };
+
+    let remote_file_name = dump_file_name(remote_player_id, frame);
+    let header = vec![format!("received by: {}", dump_file_name(player_id, frame))];
+
+    write_dump(&remote_file_name, &remote_state, &header);
+
+    let mut diff = vec![
+        format!("--- {}", dump_file_name(player_id, frame)),
+        format!("+++ {}", remote_file_name),
+    ];
+
+    diff.extend(diff_lines(&request.state.lines, &remote_state.lines));
+
+    let path = Path::new(DUMP_DIR).join(diff_file_name(player_id, remote_player_id, frame));
+
+    if let Err(err) = fs::write(&path, diff.join("\n") + "\n") {
+        #[cfg(debug_assertions)]
+        println!("WARNING: Unable to write desync diff: {}", err);
+    }
+
+    if request.player_ids.is_empty() {
+        history.description_requests.remove(&frame);
+    }
+}
+
+/// Compare two sorted descriptions. The lines that are only in the local description are returned
+/// prefixed with `-`, and the lines that are only in the remote description are prefixed with `+`.
+fn diff_lines(local: &[String], remote: &[String]) -> Vec<String> {
+    let mut diff = Vec::new();
+
+    let mut local = local.iter().peekable();
+    let mut remote = remote.iter().peekable();
+
+    loop {
+        match (local.peek(), remote.peek()) {
+            (Some(a), Some(b)) if a == b => {
+                local.next();
+                remote.next();
+            }
+            (Some(a), Some(b)) if a < b => {
+                diff.push(format!("- {}", a));
+                local.next();
+            }
+            (_, Some(b)) => {
+                diff.push(format!("+ {}", b));
+                remote.next();
+            }
+            (Some(a), None) => {
+                diff.push(format!("- {}", a));
+                local.next();
+            }
+            (None, None) => break,
+        }
+    }
+
+    diff
+}
+
+fn write_dump(file_name: &str, state: &FrameState, header: &[String]) {
+    let mut contents = format!("checksum: {}\n", state.checksum);
+
+    for line in header.iter().chain(&state.lines) {
+        contents.push_str(line);
+        contents.push('\n');
+    }
+
+    let path = Path::new(DUMP_DIR).join(file_name);
+
+    if let Err(err) = fs::create_dir_all(DUMP_DIR).and_then(|_| fs::write(&path, contents)) {
+        #[cfg(debug_assertions)]
+        println!("WARNING: Unable to write desync dump: {}", err);
+    }
+}
+
+fn dispatch_description_request(player_id: &PlayerId, frame: u64) {
+    let message = NetworkMessage::StateDescriptionRequest {
+        player_id: player_id.clone(),
+        frame,
+    };
+
+    if let Err(err) = Api::dispatch_message(message) {
+        #[cfg(debug_assertions)]
+        println!("WARNING: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn diff_holds_only_differing_lines() {
+        let local = lines(&["a", "b", "d", "e"]);
+        let remote = lines(&["a", "c", "d", "f"]);
+
+        assert_eq!(
+            diff_lines(&local, &remote),
+            lines(&["- b", "+ c", "- e", "+ f"])
+        );
+
+        assert!(diff_lines(&local, &local).is_empty());
+    }
+}
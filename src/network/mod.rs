@@ -0,0 +1,1001 @@
+//! This module holds the networking core, used to synchronize the simulation in network games.
+//! Two kinds of netcode are supported, selected in the `network` section of the config:
+//!
+//! With delayed lockstep, local input is dispatched to all peers, scheduled `delay` frames into
+//! the future, and the simulation is advanced when the input of all players is available for the
+//! next frame.
+//!
+//! With rollback, remote input is predicted and the simulation runs ahead, saving a snapshot of
+//! the world every frame. When remote input arrives that differs from the prediction, the world
+//! is restored from the snapshot of that frame and re-simulated.
+//!
+//! The round trip time to all peers is measured continuously and, with delayed lockstep, the input
+//! delay can be adjusted to it at runtime, by enabling `adaptive-input-delay`.
+//!
+//! The host also dispatches the confirmed input of every frame, which is sent to spectators. A
+//! spectator runs the simulation like a lockstep peer without any local player, from the first
+//! frame of the match, simulating several frames per update until it has caught up.
+//!
+//! A player that drops is made idle by the host, and can rejoin the match by requesting a resync,
+//! as described in the `resync` module.
+
+mod debug;
+mod desync;
+mod resync;
+
+pub use debug::debug_draw_network_stats;
+pub use resync::Checkpoints;
+
+pub use desync::{
+    describe_world, dispatch_state_checksums, dispatch_state_description, dump_remote_state,
+    dump_state, fixed_update_state_history, skip_state_checksums, state_checksum,
+    update_state_description_requests, StateHistory,
+};
+
+use std::collections::HashMap;
+
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+
+use hecs::World;
+
+use core::config::NetcodeKind;
+use core::input::{collect_local_input, PlayerInput};
+use core::network::{
+    pack_input, Api, InputBuffer, InputDelayController, NetworkEvent, NetworkMessage, PlayerId,
+    RollbackSession, RollbackState, RttEstimator, MIN_INPUT_DELAY,
+};
+
+use crate::ecs::Scheduler;
+use crate::game::{get_simulation_mut, GameMode, GameParams, FIXED_DELTA_TIME};
+use crate::player::{PlayerController, PlayerControllerKind, PlayerParams};
+use crate::snapshot::WorldSnapshot;
+use crate::Config;
+
+/// The interval, in seconds, between pings to measure the round trip time to the other peers
+const PING_INTERVAL: f64 = 0.5;
+
+/// The interval, in seconds, between requests for a resync, while reconnecting
+const RESYNC_REQUEST_INTERVAL: f64 = 1.0;
+
+/// The amount of frames of input that a spectator keeps buffered, to absorb the batching of the
+/// input sent by the host
+const SPECTATOR_BUFFER_FRAMES: u64 = 10;
+
+/// The maximum amount of frames a spectator will simulate in a single fixed update, when it is
+/// catching up
+const MAX_SPECTATOR_CATCH_UP_FRAMES: u64 = 10;
+
+/// The maximum amount of frames a rejoining player will simulate in a single fixed update, when it
+/// is catching up after a resync
+const MAX_RESYNC_CATCH_UP_FRAMES: u64 = 60;
+
+pub enum NetworkSessionKind {
+    DelayedLockstep(InputBuffer),
+    Rollback(RollbackSession<WorldSnapshot>),
+    /// A spectator has no local player, and simulates frames as the confirmed input of all
+    /// players is received from the host
+    Spectate(InputBuffer),
+}
+
+pub struct NetworkSession {
+    pub local_player_id: PlayerId,
+    /// The players of the match, in the same order as the player params, which is the same for
+    /// all peers
+    pub player_ids: Vec<PlayerId>,
+    /// If this is `true`, the confirmed input of every frame is dispatched, for spectators, and
+    /// players that drop are made idle, and resynchronized when they rejoin
+    pub is_host: bool,
+    /// A client will request a resync from the host, if the connection to it is lost
+    pub host_player_id: Option<PlayerId>,
+    pub kind: NetworkSessionKind,
+    /// The round trip time to every remote player
+    pub peer_latency: HashMap<PlayerId, RttEstimator>,
+    /// This will be `Some` if the input delay of a lockstep session is adaptive
+    pub delay_controller: Option<InputDelayController>,
+    /// The amount of fixed updates that have been skipped, waiting for remote input
+    pub stall_cnt: u64,
+    /// This is `true` while the connection to the host is lost, and a resync is being requested
+    pub is_reconnecting: bool,
+    /// The confirmed input of every frame, packed with `pack_input`, kept by the host to
+    /// resynchronize players that rejoin
+    input_history: Vec<u8>,
+    /// The frames that rejoining players have been told to send input from, by the host
+    rejoin_frames: HashMap<PlayerId, u64>,
+    /// Kept by players, to resynchronize from, if they drop
+    checkpoints: Checkpoints,
+    /// The checkpoint that a received resync starts from, which the world should be restored to
+    pending_resync: Option<WorldSnapshot>,
+    /// After a resync, the frames before this are re-simulated with confirmed input only
+    catch_up_frame: u64,
+    /// After a resync, the local player is idle until this frame, and frames are simulated as
+    /// fast as the input of the other players arrives, until it is reached
+    rejoin_frame: u64,
+    last_resync_request: f64,
+    /// Local input is sampled every frame and merged until the next fixed update, so that button
+    /// presses are not lost on frames where no fixed update is run
+    pending_input: PlayerInput,
+    last_ping_time: f64,
+}
+
+impl NetworkSession {
+    pub fn new(
+        local_player_id: &PlayerId,
+        player_ids: &[PlayerId],
+        is_host: bool,
+        kind: NetworkSessionKind,
+    ) -> Self {
+        NetworkSession {
+            local_player_id: local_player_id.clone(),
+            player_ids: player_ids.to_vec(),
+            is_host,
+            host_player_id: None,
+            kind,
+            peer_latency: HashMap::new(),
+            delay_controller: None,
+            stall_cnt: 0,
+            is_reconnecting: false,
+            input_history: Vec::new(),
+            rejoin_frames: HashMap::new(),
+            checkpoints: Checkpoints::default(),
+            pending_resync: None,
+            catch_up_frame: 0,
+            rejoin_frame: 0,
+            last_resync_request: 0.0,
+            pending_input: PlayerInput::default(),
+            last_ping_time: 0.0,
+        }
+    }
+
+    /// The one-way latency to the slowest peer, with a margin for jitter, in frames
+    pub fn latency_frames(&self) -> u64 {
+        self.peer_latency
+            .values()
+            .map(|estimator| {
+                let latency = estimator.rtt() / 2.0 + estimator.jitter() * 2.0;
+                (latency / FIXED_DELTA_TIME).ceil() as u64
+            })
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// Create the session for a network game and store it, replacing any session left over from a
+/// previous game.
+pub fn init_network_session(mode: &GameMode, player_params: &[PlayerParams], params: &GameParams) {
+    let local_player_id = Api::local_player_id();
+
+    let player_ids = player_params
+        .iter()
+        .map(|params| match &params.controller {
+            PlayerControllerKind::LocalInput(_) => local_player_id.clone(),
+            PlayerControllerKind::Network(player_id) => player_id.clone(),
+            PlayerControllerKind::Ai(_) => unreachable!("Bots are not supported in network games"),
+            PlayerControllerKind::Replay(_) => {
+                unreachable!("Replays are not supported in network games")
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let config = storage::get::<Config>().network.clone();
+
+    let kind = if *mode == GameMode::NetworkSpectator {
+        // Spectators need no input delay, as the host only sends input once it is confirmed
+        NetworkSessionKind::Spectate(InputBuffer::new(&player_ids, 0))
+    } else {
+        match config.netcode {
+            NetcodeKind::DelayedLockstep => {
+                let input_buffer = InputBuffer::new(&player_ids, config.input_delay);
+                NetworkSessionKind::DelayedLockstep(input_buffer)
+            }
+            NetcodeKind::Rollback => {
+                let session =
+                    RollbackSession::new(&local_player_id, &player_ids, config.max_rollback);
+                NetworkSessionKind::Rollback(session)
+            }
+        }
+    };
+
+    let is_host = *mode == GameMode::NetworkHost;
+    let mut session = NetworkSession::new(&local_player_id, &player_ids, is_host, kind);
+
+    session.host_player_id = params.host_player_id.clone();
+
+    let is_lockstep = matches!(session.kind, NetworkSessionKind::DelayedLockstep(_));
+
+    if config.is_input_delay_adaptive && is_lockstep {
+        let controller =
+            InputDelayController::new(config.input_delay, MIN_INPUT_DELAY, config.max_input_delay);
+
+        session.delay_controller = Some(controller);
+    }
+
+    storage::store(session);
+    storage::store(StateHistory::default());
+}
+
+/// Returns the checkpoint that a resync, received since the last call, starts from, if any. The
+/// world should then be restored to it, and caught up with the match, by running the fixed updates
+/// as many times as `resync_catch_up_frame_cnt` returns.
+pub fn take_pending_resync() -> Option<WorldSnapshot> {
+    storage::try_get_mut::<NetworkSession>().and_then(|mut session| session.pending_resync.take())
+}
+
+/// Returns the amount of frames that should be simulated in the current fixed update, after a
+/// resync. These are the frames that have been missed, and then any frames that the input of all
+/// players has arrived for, until the frame that the local player rejoins on. This will be zero
+/// once the world has caught up with the match.
+pub fn resync_catch_up_frame_cnt(world: &World) -> u64 {
+    let frame = get_simulation_mut(world).frame;
+
+    storage::try_get::<NetworkSession>()
+        .map(|session| {
+            if frame < session.catch_up_frame {
+                return (session.catch_up_frame - frame).min(MAX_RESYNC_CATCH_UP_FRAMES);
+            }
+
+            let frame_cnt = session
+                .rejoin_frame
+                .saturating_sub(frame)
+                .min(MAX_RESYNC_CATCH_UP_FRAMES);
+
+            match &session.kind {
+                NetworkSessionKind::DelayedLockstep(input_buffer) => input_buffer
+                    .player_ids()
+                    .iter()
+                    .map(|id| input_buffer.buffered_frames(id))
+                    .min()
+                    .unwrap_or_default()
+                    .min(frame_cnt),
+                NetworkSessionKind::Rollback(rollback) => (frame..frame + frame_cnt)
+                    .take_while(|frame| rollback.is_frame_confirmed(*frame))
+                    .count() as u64,
+                NetworkSessionKind::Spectate(_) => 0,
+            }
+        })
+        .unwrap_or_default()
+}
+
+/// Returns `true` if the stored session uses rollback. If so, the fixed updates should be run
+/// through `advance_rollback_session`, instead of being executed directly.
+pub fn is_rollback_session() -> bool {
+    storage::try_get::<NetworkSession>()
+        .map(|session| matches!(session.kind, NetworkSessionKind::Rollback(_)))
+        .unwrap_or_default()
+}
+
+/// This should be called when a fixed update is skipped, because `is_next_frame_ready` returned
+/// `false`, so that the input delay can be adjusted
+pub fn record_stall() {
+    if let Some(mut session) = storage::try_get_mut::<NetworkSession>() {
+        session.stall_cnt += 1;
+
+        if let Some(controller) = &mut session.delay_controller {
+            controller.add_stall();
+        }
+    }
+}
+
+/// Returns `true` if the input of all players has been received for the next simulation frame.
+/// If not, the fixed update should be skipped, stalling the simulation until the input arrives.
+pub fn is_next_frame_ready() -> bool {
+    storage::try_get::<NetworkSession>()
+        .map(|session| match &session.kind {
+            NetworkSessionKind::DelayedLockstep(input_buffer)
+            | NetworkSessionKind::Spectate(input_buffer) => input_buffer.is_frame_ready(),
+            NetworkSessionKind::Rollback(_) => true,
+        })
+        .unwrap_or(true)
+}
+
+pub fn update_network_client(world: &mut World) {
+    update_network_common(world);
+}
+
+pub fn fixed_update_network_client(world: &mut World) {
+    fixed_update_network_common(world);
+}
+
+pub fn update_network_host(world: &mut World) {
+    update_network_common(world);
+}
+
+pub fn fixed_update_network_host(world: &mut World) {
+    fixed_update_network_common(world);
+}
+
+pub fn update_network_spectator(world: &mut World) {
+    update_network_common(world);
+}
+
+/// Simulate the next frame with the input received from the host. This should only be executed
+/// when `spectator_frame_cnt` is greater than zero.
+pub fn fixed_update_network_spectator(world: &mut World) {
+    let mut session = storage::get_mut::<NetworkSession>();
+    let local_player_id = session.local_player_id.clone();
+
+    if let NetworkSessionKind::Spectate(input_buffer) = &mut session.kind {
+        if let Some(inputs) = input_buffer.advance() {
+            apply_inputs(world, &local_player_id, &inputs);
+        }
+    }
+}
+
+/// Returns the amount of frames that a spectator should simulate in the current fixed update.
+/// This is normally one frame, if input is available, but a spectator that is behind, which will
+/// be the case after joining a running game, will simulate several frames, to catch up.
+pub fn spectator_frame_cnt() -> u64 {
+    storage::try_get::<NetworkSession>()
+        .map(|session| match &session.kind {
+            NetworkSessionKind::Spectate(input_buffer) => {
+                let buffered = input_buffer
+                    .player_ids()
+                    .iter()
+                    .map(|id| input_buffer.buffered_frames(id))
+                    .min()
+                    .unwrap_or_default();
+
+                buffered
+                    .saturating_sub(SPECTATOR_BUFFER_FRAMES)
+                    .clamp(buffered.min(1), MAX_SPECTATOR_CATCH_UP_FRAMES)
+            }
+            _ => 0,
+        })
+        .unwrap_or_default()
+}
+
+fn update_network_common(world: &mut World) {
+    let mut session = storage::get_mut::<NetworkSession>();
+
+    for (_, controller) in world.query_mut::<&PlayerController>() {
+        if let PlayerControllerKind::LocalInput(input_scheme) = controller.kind {
+            let input = collect_local_input(input_scheme);
+            session.pending_input = merge_input(session.pending_input, input);
+        }
+    }
+
+    let now = get_time();
+
+    if session.is_reconnecting && now - session.last_resync_request >= RESYNC_REQUEST_INTERVAL {
+        session.last_resync_request = now;
+
+        let message = NetworkMessage::ResyncRequest {
+            player_id: session.local_player_id.clone(),
+            checkpoint_frames: session.checkpoints.frames(),
+        };
+
+        if let Err(err) = Api::dispatch_message(message) {
+            #[cfg(debug_assertions)]
+            println!("WARNING: {}", err);
+        }
+    }
+
+    if now - session.last_ping_time >= PING_INTERVAL {
+        session.last_ping_time = now;
+
+        let message = NetworkMessage::Ping {
+            player_id: session.local_player_id.clone(),
+            timestamp: time_to_timestamp(now),
+        };
+
+        if let Err(err) = Api::dispatch_message(message) {
+            #[cfg(debug_assertions)]
+            println!("WARNING: {}", err);
+        }
+    }
+
+    update_state_description_requests(&session.local_player_id);
+
+    while let Some(event) = Api::next_event() {
+        match event {
+            NetworkEvent::PlayerInput {
+                player_id,
+                frame,
+                input,
+            } => match &mut session.kind {
+                NetworkSessionKind::DelayedLockstep(input_buffer)
+                | NetworkSessionKind::Spectate(input_buffer) => {
+                    input_buffer.insert(&player_id, frame, input);
+                }
+                NetworkSessionKind::Rollback(rollback) => {
+                    rollback.add_remote_input(&player_id, frame, input);
+                }
+            },
+            NetworkEvent::Pong {
+                player_id,
+                timestamp,
+            } => {
+                let rtt = time_to_timestamp(now).saturating_sub(timestamp) as f32 / 1_000_000.0;
+
+                session
+                    .peer_latency
+                    .entry(player_id)
+                    .or_default()
+                    .add_sample(rtt);
+            }
+            NetworkEvent::PlayerLeft { player_id } => {
+                #[cfg(debug_assertions)]
+                println!("WARNING: Player '{}' left the game", player_id);
+            }
+            NetworkEvent::PlayerReconnecting { player_id } => {
+                let is_spectator = matches!(session.kind, NetworkSessionKind::Spectate(_));
+
+                if session.is_host {
+                    if session.player_ids.contains(&player_id) {
+                        resync::drop_player(&mut session, &player_id);
+                    }
+                } else if !is_spectator && session.host_player_id.as_ref() == Some(&player_id) {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: Lost the connection to the host, reconnecting");
+
+                    session.is_reconnecting = true;
+                }
+            }
+            NetworkEvent::ResyncRequested {
+                player_id,
+                checkpoint_frames,
+            } if session.is_host && session.player_ids.contains(&player_id) => {
+                if let Err(err) =
+                    resync::resync_player(&mut session, &player_id, &checkpoint_frames)
+                {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: {}", err);
+                }
+            }
+            NetworkEvent::PlayerIdle {
+                player_id,
+                start_frame,
+                end_frame,
+            } => {
+                session.kind.set_idle(&player_id, start_frame, end_frame);
+            }
+            NetworkEvent::Resync { data, .. } => match resync::apply_resync(&mut session, &data) {
+                Ok(snapshot) => session.pending_resync = Some(snapshot),
+                Err(err) => {
+                    #[cfg(debug_assertions)]
+                    println!("WARNING: Resync failed: {}", err);
+                }
+            },
+            NetworkEvent::Desync { frame, player_ids } => {
+                #[cfg(debug_assertions)]
+                println!(
+                    "WARNING: Desync detected on frame {}, with players {:?}",
+                    frame, player_ids
+                );
+
+                dump_state(&session.local_player_id, &player_ids, frame);
+            }
+            NetworkEvent::StateDescriptionRequested { frame, .. } => {
+                dispatch_state_description(&session.local_player_id, frame);
+            }
+            NetworkEvent::StateDescription {
+                player_id,
+                frame,
+                data,
+            } => {
+                dump_remote_state(&session.local_player_id, &player_id, frame, &data);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn fixed_update_network_common(world: &mut World) {
+    let mut session = storage::get_mut::<NetworkSession>();
+
+    let latency_frames = session.latency_frames();
+    let local_player_id = session.local_player_id.clone();
+
+    // With lockstep, all frames that have been simulated are final
+    let final_frame = get_simulation_mut(world).frame;
+
+    if !session.is_host && session.checkpoints.is_due(final_frame) {
+        session
+            .checkpoints
+            .push(final_frame, WorldSnapshot::capture(world));
+    }
+
+    if final_frame < session.catch_up_frame {
+        catch_up_frame(world, &mut session, final_frame);
+        return;
+    }
+
+    dispatch_state_checksums(&local_player_id, final_frame);
+
+    let session = &mut *session;
+
+    if let NetworkSessionKind::DelayedLockstep(input_buffer) = &mut session.kind {
+        if let Some(controller) = &mut session.delay_controller {
+            let delay = controller.update(latency_frames);
+            input_buffer.set_delay(delay);
+        }
+
+        // While the delay is shrinking, there might not be any frame to schedule local input for,
+        // in which case it is kept, and merged with the input of the next fixed update
+        if !input_buffer.local_input_frames().is_empty() {
+            let input = session.pending_input;
+            session.pending_input = PlayerInput::default();
+
+            for frame in input_buffer.insert_local(&local_player_id, input) {
+                dispatch_input(&local_player_id, frame, input);
+            }
+        }
+
+        if let Some(controller) = &mut session.delay_controller {
+            let slack = input_buffer
+                .player_ids()
+                .iter()
+                .filter(|id| **id != local_player_id)
+                .map(|id| input_buffer.buffered_frames(id).saturating_sub(1))
+                .min()
+                .unwrap_or_default();
+
+            controller.add_frame(slack);
+        }
+
+        let frame = input_buffer.current_frame();
+
+        if let Some(inputs) = input_buffer.advance() {
+            apply_inputs(world, &local_player_id, &inputs);
+
+            if session.is_host {
+                record_confirmed_inputs(
+                    &session.player_ids,
+                    &mut session.input_history,
+                    frame,
+                    &inputs,
+                );
+            }
+        }
+    }
+}
+
+/// Re-simulate a frame that was missed before a resync, with the confirmed input in the input
+/// buffer. These frames have already been played by the other peers, so no local input is
+/// scheduled, no checksums are dispatched and the input delay is not adjusted to them.
+fn catch_up_frame(world: &mut World, session: &mut NetworkSession, frame: u64) {
+    session.pending_input = PlayerInput::default();
+
+    skip_state_checksums(frame + 1);
+
+    let local_player_id = session.local_player_id.clone();
+
+    if let NetworkSessionKind::DelayedLockstep(input_buffer) = &mut session.kind {
+        if let Some(inputs) = input_buffer.advance() {
+            apply_inputs(world, &local_player_id, &inputs);
+        }
+    }
+}
+
+/// This drives the simulation when using rollback. Local input is added for the current frame
+/// and the session is advanced, executing `fixed_updates` once for every frame that is simulated,
+/// including any frames that are re-simulated after a rollback.
+pub fn advance_rollback_session(world: &mut World, fixed_updates: &mut Scheduler) {
+    let mut session = storage::get_mut::<NetworkSession>();
+    let session = &mut *session;
+
+    if let NetworkSessionKind::Rollback(rollback) = &mut session.kind {
+        let mut state = RollbackWorld {
+            world,
+            fixed_updates,
+            local_player_id: &session.local_player_id,
+        };
+
+        rollback.synchronize(&mut state);
+
+        if rollback.can_advance() {
+            let input = session.pending_input;
+            session.pending_input = PlayerInput::default();
+
+            // Before the frame that the local player rejoins on, after a resync, its input is
+            // either idle or has been confirmed by the host already
+            let frame = rollback.add_local_input(input);
+            if frame >= session.rejoin_frame {
+                dispatch_input(&session.local_player_id, frame, input);
+            }
+
+            rollback.advance(&mut state);
+        }
+
+        let confirmed_frame = rollback.confirmed_frame();
+
+        if !session.is_host && session.checkpoints.is_due(confirmed_frame) {
+            let snapshot = match rollback.snapshot(confirmed_frame) {
+                Some(snapshot) => snapshot.clone(),
+                None => WorldSnapshot::capture(state.world),
+            };
+
+            session.checkpoints.push(confirmed_frame, snapshot);
+        }
+
+        for (frame, inputs) in rollback.take_confirmed_inputs() {
+            if session.is_host {
+                record_confirmed_inputs(
+                    &session.player_ids,
+                    &mut session.input_history,
+                    frame,
+                    &inputs,
+                );
+            }
+        }
+
+        dispatch_state_checksums(&session.local_player_id, rollback.confirmed_frame());
+    }
+}
+
+/// The game world, as it is driven by a `RollbackSession`
+struct RollbackWorld<'a> {
+    world: &'a mut World,
+    fixed_updates: &'a mut Scheduler,
+    local_player_id: &'a PlayerId,
+}
+
+impl<'a> RollbackState for RollbackWorld<'a> {
+    type Snapshot = WorldSnapshot;
+
+    fn save_snapshot(&mut self) -> WorldSnapshot {
+        WorldSnapshot::capture(self.world)
+    }
+
+    fn load_snapshot(&mut self, snapshot: &WorldSnapshot) {
+        snapshot.restore(self.world);
+    }
+
+    fn advance_frame(&mut self, inputs: &HashMap<PlayerId, PlayerInput>) {
+        apply_inputs(self.world, self.local_player_id, inputs);
+        self.fixed_updates.execute(self.world);
+    }
+}
+
+fn dispatch_input(player_id: &PlayerId, frame: u64, input: PlayerInput) {
+    let message = NetworkMessage::UpdatePlayerInput {
+        player_id: player_id.clone(),
+        frame,
+        input,
+    };
+
+    if let Err(err) = Api::dispatch_message(message) {
+        #[cfg(debug_assertions)]
+        println!("WARNING: {}", err);
+    }
+}
+
+/// Add the confirmed input of a frame to the input history, for players that rejoin, and
+/// dispatch it, to be sent to spectators
+fn record_confirmed_inputs(
+    player_ids: &[PlayerId],
+    input_history: &mut Vec<u8>,
+    frame: u64,
+    inputs: &HashMap<PlayerId, PlayerInput>,
+) {
+    let inputs = player_ids
+        .iter()
+        .map(|id| inputs.get(id).copied().unwrap_or_default())
+        .collect::<Vec<_>>();
+
+    input_history.extend(inputs.iter().map(pack_input));
+
+    let message = NetworkMessage::SpectatorInputs {
+        player_id: Api::local_player_id(),
+        start_frame: frame,
+        player_ids: player_ids.to_vec(),
+        inputs,
+    };
+
+    if let Err(err) = Api::dispatch_message(message) {
+        #[cfg(debug_assertions)]
+        println!("WARNING: {}", err);
+    }
+}
+
+fn apply_inputs(
+    world: &mut World,
+    local_player_id: &PlayerId,
+    inputs: &HashMap<PlayerId, PlayerInput>,
+) {
+    for (_, controller) in world.query_mut::<&mut PlayerController>() {
+        let player_id = match &controller.kind {
+            PlayerControllerKind::LocalInput(_) => local_player_id,
+            PlayerControllerKind::Network(player_id) => player_id,
+            PlayerControllerKind::Ai(_) | PlayerControllerKind::Replay(_) => continue,
+        };
+
+        let input = inputs.get(player_id).copied().unwrap_or_default();
+
+        controller.apply_input(input);
+    }
+}
+
+/// Convert a time, as returned by `get_time`, to the timestamp of a ping, in microseconds
+fn time_to_timestamp(time: f64) -> u64 {
+    (time * 1_000_000.0) as u64
+}
+
+/// Merge two inputs, keeping every button that is active in either of them
+fn merge_input(a: PlayerInput, b: PlayerInput) -> PlayerInput {
+    PlayerInput {
+        left: a.left || b.left,
+        right: a.right || b.right,
+        fire: a.fire || b.fire,
+        jump: a.jump || b.jump,
+        pickup: a.pickup || b.pickup,
+        float: a.float || b.float,
+        crouch: a.crouch || b.crouch,
+        slide: a.slide || b.slide,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use core::input::InputTrack;
+    use core::network::{ResyncState, DEFAULT_MAX_ROLLBACK};
+
+    use crate::game::{default_assets_dir, HeadlessGame, MatchRulesParams, Replay, ReplayPlayer};
+    use crate::player::Player;
+
+    use super::*;
+
+    const FRAME_CNT: u64 = 300;
+
+    /// The frame that player "1" drops on, in the resync tests
+    const DROP_FRAME: u64 = 150;
+
+    /// The frame that player "1" rejoins on, in the resync tests
+    const REJOIN_FRAME: u64 = 250;
+
+    /// The game world of a `HeadlessGame`, driven by a `RollbackSession`
+    struct RollbackGame<'a> {
+        game: &'a mut HeadlessGame,
+        local_player_id: PlayerId,
+    }
+
+    impl<'a> RollbackState for RollbackGame<'a> {
+        type Snapshot = WorldSnapshot;
+
+        fn save_snapshot(&mut self) -> WorldSnapshot {
+            WorldSnapshot::capture(self.game.world())
+        }
+
+        fn load_snapshot(&mut self, snapshot: &WorldSnapshot) {
+            snapshot.restore(self.game.world_mut());
+        }
+
+        fn advance_frame(&mut self, inputs: &HashMap<PlayerId, PlayerInput>) {
+            apply_inputs(self.game.world_mut(), &self.local_player_id, inputs);
+            self.game.run(1);
+        }
+    }
+
+    fn player_ids() -> Vec<PlayerId> {
+        vec!["0".to_string(), "1".to_string()]
+    }
+
+    fn test_input(player: u64, frame: u64) -> PlayerInput {
+        let n = frame / 4 + player * 5;
+
+        PlayerInput {
+            left: n % 5 == 1,
+            right: n % 3 == 1,
+            jump: n % 7 == 2,
+            fire: n % 11 == 4,
+            pickup: n % 13 == 6,
+            crouch: n % 17 == 8,
+            ..Default::default()
+        }
+    }
+
+    /// Create a game where the players are controlled by input from the network, with the index
+    /// of a player as its id
+    fn network_game() -> HeadlessGame {
+        let players = (0..2)
+            .map(|index| ReplayPlayer::new(index, "pescy", InputTrack::new()))
+            .collect();
+
+        let replay = Replay::from_script("lev01", 0, MatchRulesParams::default(), players);
+
+        let mut game = HeadlessGame::new(default_assets_dir(), &replay).unwrap();
+
+        for (_, (player, controller)) in game
+            .world_mut()
+            .query_mut::<(&Player, &mut PlayerController)>()
+        {
+            controller.kind = PlayerControllerKind::Network(player.index.to_string());
+        }
+
+        game
+    }
+
+    fn frame_inputs(frame: u64) -> HashMap<PlayerId, PlayerInput> {
+        player_ids()
+            .into_iter()
+            .enumerate()
+            .map(|(i, player_id)| (player_id, test_input(i as u64, frame)))
+            .collect()
+    }
+
+    #[test]
+    fn rollback_matches_run_without_rollback() {
+        let expected = {
+            let mut game = network_game();
+
+            for frame in 0..FRAME_CNT {
+                apply_inputs(game.world_mut(), &"0".to_string(), &frame_inputs(frame));
+                game.run(1);
+            }
+
+            describe_world(game.world())
+        };
+
+        let player_ids = player_ids();
+        let latency = DEFAULT_MAX_ROLLBACK - 2;
+
+        let mut game = network_game();
+        let mut session = RollbackSession::new(&player_ids[0], &player_ids, DEFAULT_MAX_ROLLBACK);
+
+        let mut state = RollbackGame {
+            game: &mut game,
+            local_player_id: player_ids[0].clone(),
+        };
+
+        let mut in_flight = VecDeque::new();
+
+        for frame in 0..FRAME_CNT {
+            in_flight.push_back((frame + latency, frame, test_input(1, frame)));
+
+            while let Some((arrival, frame, input)) = in_flight.front().cloned() {
+                if arrival > session.current_frame() {
+                    break;
+                }
+
+                session.add_remote_input(&player_ids[1], frame, input);
+                in_flight.pop_front();
+            }
+
+            session.add_local_input(test_input(0, frame));
+            assert!(session.advance(&mut state));
+        }
+
+        for (_, frame, input) in in_flight {
+            session.add_remote_input(&player_ids[1], frame, input);
+        }
+
+        session.synchronize(&mut state);
+
+        assert!(session.rollback_cnt() > 0);
+        assert_eq!(describe_world(game.world()), expected);
+    }
+
+    /// The input of every frame, as the host confirmed it, where player "1" is idle from the frame
+    /// it dropped on, until it rejoins
+    fn host_inputs(frame: u64) -> HashMap<PlayerId, PlayerInput> {
+        let mut inputs = frame_inputs(frame);
+
+        if (DROP_FRAME..REJOIN_FRAME).contains(&frame) {
+            inputs.insert("1".to_string(), PlayerInput::default());
+        }
+
+        inputs
+    }
+
+    /// Play as player "1", keeping checkpoints, until some time after it dropped, and then resync
+    /// it with the input confirmed by the host. The world is described once the player has played
+    /// the rest of the match.
+    fn rejoin(kind: NetworkSessionKind) -> Vec<String> {
+        let player_ids = player_ids();
+        let local_player_id = player_ids[1].clone();
+
+        let mut game = network_game();
+        let mut session = NetworkSession::new(&local_player_id, &player_ids, false, kind);
+
+        // Its input never reached the host after it dropped, but the player went on with it
+        for frame in 0..DROP_FRAME + 40 {
+            if session.checkpoints.is_due(frame) {
+                let snapshot = WorldSnapshot::capture(game.world());
+                session.checkpoints.push(frame, snapshot);
+            }
+
+            apply_inputs(game.world_mut(), &local_player_id, &frame_inputs(frame));
+            game.run(1);
+        }
+
+        let confirmed_frame = REJOIN_FRAME - 20;
+
+        let snapshot_frame = session
+            .checkpoints
+            .frames()
+            .into_iter()
+            .filter(|frame| *frame <= DROP_FRAME)
+            .max()
+            .unwrap();
+
+        let mut inputs = Vec::new();
+
+        for frame in snapshot_frame..confirmed_frame {
+            let frame_inputs = host_inputs(frame);
+            inputs.extend(player_ids.iter().map(|id| pack_input(&frame_inputs[id])));
+        }
+
+        let state = ResyncState {
+            snapshot_frame,
+            frame: confirmed_frame,
+            rejoin_frame: REJOIN_FRAME,
+            player_ids: player_ids.clone(),
+            inputs,
+            pending_inputs: Vec::new(),
+            idle_periods: vec![(local_player_id.clone(), DROP_FRAME, Some(REJOIN_FRAME))],
+        };
+
+        let snapshot = resync::apply_resync(&mut session, &state.encode()).unwrap();
+        snapshot.restore(game.world_mut());
+
+        assert_eq!(get_simulation_mut(game.world()).frame, snapshot_frame);
+
+        // The input of the host arrives after the resync, and the player sends its own input again
+        // from the frame it rejoins on
+        for frame in snapshot_frame..FRAME_CNT {
+            let inputs = frame_inputs(frame);
+
+            match &mut session.kind {
+                NetworkSessionKind::DelayedLockstep(input_buffer) => {
+                    if frame >= confirmed_frame {
+                        input_buffer.insert(&player_ids[0], frame, inputs[&player_ids[0]]);
+                    }
+
+                    if frame >= REJOIN_FRAME {
+                        input_buffer.insert(&local_player_id, frame, inputs[&local_player_id]);
+                    }
+
+                    let inputs = input_buffer.advance().unwrap();
+
+                    apply_inputs(game.world_mut(), &local_player_id, &inputs);
+                    game.run(1);
+                }
+                NetworkSessionKind::Rollback(rollback) => {
+                    if frame >= confirmed_frame {
+                        rollback.add_remote_input(&player_ids[0], frame, inputs[&player_ids[0]]);
+                    }
+
+                    rollback.add_local_input(inputs[&local_player_id]);
+
+                    let mut state = RollbackGame {
+                        game: &mut game,
+                        local_player_id: local_player_id.clone(),
+                    };
+
+                    assert!(rollback.advance(&mut state));
+                }
+                NetworkSessionKind::Spectate(_) => unreachable!(),
+            }
+        }
+
+        describe_world(game.world())
+    }
+
+    #[test]
+    fn resync_from_checkpoint_matches_host() {
+        let expected = {
+            let mut game = network_game();
+
+            for frame in 0..FRAME_CNT {
+                apply_inputs(game.world_mut(), &"0".to_string(), &host_inputs(frame));
+                game.run(1);
+            }
+
+            describe_world(game.world())
+        };
+
+        let player_ids = player_ids();
+
+        let input_buffer = InputBuffer::new(&player_ids, 2);
+        let lockstep = rejoin(NetworkSessionKind::DelayedLockstep(input_buffer));
+
+        assert_eq!(lockstep, expected);
+
+        let rollback = RollbackSession::new(&player_ids[1], &player_ids, DEFAULT_MAX_ROLLBACK);
+        let rollback = rejoin(NetworkSessionKind::Rollback(rollback));
+
+        assert_eq!(rollback, expected);
+    }
+}
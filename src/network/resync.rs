@@ -0,0 +1,391 @@
+//! Rejoining a running match, with either kind of netcode. When a player drops, the host makes it
+//! idle, from the first frame that its input is missing for, and notifies the other players, so
+//! that the match can go on without it. When the player has reconnected, and requests a resync,
+//! the host picks the frame that the player will send input from again, and sends it a
+//! `ResyncState`.
+//!
+//! Every player keeps checkpoints of its world, which are snapshots of frames that the input of all
+//! players had been confirmed for, taken every `CHECKPOINT_INTERVAL` frames. Its world was the same
+//! as that of the other peers on those frames, so a request for a resync lists the frames of its
+//! checkpoints, and the host picks the latest of them that is from before the player dropped. As
+//! the simulation is deterministic, restoring that checkpoint and re-simulating it with the
+//! confirmed input of every frame since rebuilds the world exactly as it is on the other peers. The
+//! host only has to send the input of those frames, instead of the world, which can not be sent,
+//! as it holds textures, sounds and other resources that are local to every peer.
+//!
+//! The rejoining player re-simulates the frames it has missed over the following fixed updates, a
+//! limited amount at a time, so that the game does not freeze while it catches up. The network
+//! systems only apply the confirmed input to these frames, as the other peers have already played
+//! them. It then keeps simulating several frames per fixed update, as long as the input of the
+//! other players has arrived, until it reaches the frame that it rejoins on. That frame is picked
+//! far enough ahead for the player to get there before the other peers, so that nobody stalls.
+
+use std::collections::VecDeque;
+
+use macroquad::experimental::collections::storage;
+
+use core::error::ErrorKind;
+use core::input::PlayerInput;
+use core::network::{
+    pack_input, unpack_input, Api, InputBuffer, NetworkMessage, PlayerId, ResyncState,
+    RollbackSession,
+};
+use core::{formaterr, Result};
+
+use crate::snapshot::WorldSnapshot;
+
+use super::{
+    skip_state_checksums, NetworkSession, NetworkSessionKind, StateHistory,
+    MAX_RESYNC_CATCH_UP_FRAMES,
+};
+
+/// The interval, in frames, between the checkpoints of the world that a player keeps
+const CHECKPOINT_INTERVAL: u64 = 60;
+
+/// The amount of checkpoints that a player keeps. Only checkpoints from before the player dropped
+/// can be resynchronized from, which will usually be one of the last two.
+const MAX_CHECKPOINTS: usize = 5;
+
+/// The amount of frames, on top of the time it takes to catch up, that the host gives a rejoining
+/// player to receive the resync, which might take a few attempts if chunks are lost
+const RESYNC_MARGIN_FRAMES: u64 = 30;
+
+/// Snapshots of the world, on frames that the input of all players had been confirmed for
+#[derive(Default)]
+pub struct Checkpoints {
+    snapshots: VecDeque<(u64, WorldSnapshot)>,
+}
+
+impl Checkpoints {
+    /// Returns `true` if a checkpoint should be kept of the world before `frame` is simulated
+    pub fn is_due(&self, frame: u64) -> bool {
+        self.snapshots
+            .back()
+            .map(|(last_frame, _)| frame >= last_frame + CHECKPOINT_INTERVAL)
+            .unwrap_or(true)
+    }
+
+    /// Keep a checkpoint of the world before `frame` is simulated. The input of all the frames
+    /// before it must have been confirmed.
+    pub fn push(&mut self, frame: u64, snapshot: WorldSnapshot) {
+        if self.snapshots.len() >= MAX_CHECKPOINTS {
+            self.snapshots.pop_front();
+        }
+
+        self.snapshots.push_back((frame, snapshot));
+    }
+
+    pub fn frames(&self) -> Vec<u64> {
+        self.snapshots.iter().map(|(frame, _)| *frame).collect()
+    }
+
+    /// Returns the checkpoint of `frame`, dropping those of later frames, as the world of the
+    /// player is about to be restored to it, and they no longer match it
+    fn rewind(&mut self, frame: u64) -> Option<WorldSnapshot> {
+        while let Some((last_frame, _)) = self.snapshots.back() {
+            if *last_frame <= frame {
+                break;
+            }
+
+            self.snapshots.pop_back();
+        }
+
+        self.snapshots
+            .back()
+            .filter(|(last_frame, _)| *last_frame == frame)
+            .map(|(_, snapshot)| snapshot.clone())
+    }
+}
+
+impl NetworkSessionKind {
+    pub(super) fn set_idle(
+        &mut self,
+        player_id: &PlayerId,
+        start_frame: u64,
+        end_frame: Option<u64>,
+    ) {
+        match self {
+            NetworkSessionKind::DelayedLockstep(input_buffer) => {
+                input_buffer.set_idle(player_id, start_frame, end_frame)
+            }
+            NetworkSessionKind::Rollback(rollback) => {
+                rollback.set_idle(player_id, start_frame, end_frame)
+            }
+            NetworkSessionKind::Spectate(_) => {}
+        }
+    }
+
+    fn idle_periods(&self) -> Vec<(PlayerId, u64, Option<u64>)> {
+        match self {
+            NetworkSessionKind::DelayedLockstep(input_buffer) => input_buffer.idle_periods(),
+            NetworkSessionKind::Rollback(rollback) => rollback.idle_periods(),
+            NetworkSessionKind::Spectate(_) => Vec::new(),
+        }
+    }
+
+    fn input(&self, player_id: &PlayerId, frame: u64) -> Option<PlayerInput> {
+        match self {
+            NetworkSessionKind::DelayedLockstep(input_buffer) => {
+                input_buffer.input(player_id, frame)
+            }
+            NetworkSessionKind::Rollback(rollback) => rollback.input(player_id, frame),
+            NetworkSessionKind::Spectate(_) => None,
+        }
+    }
+
+    /// The first frame that the input of all players has not been confirmed for. All frames before
+    /// it are in the input history of the host.
+    fn confirmed_frame(&self) -> u64 {
+        match self {
+            NetworkSessionKind::DelayedLockstep(input_buffer)
+            | NetworkSessionKind::Spectate(input_buffer) => input_buffer.current_frame(),
+            NetworkSessionKind::Rollback(rollback) => rollback.confirmed_frame(),
+        }
+    }
+
+    /// The first frame that the input of a player is missing for
+    fn first_missing_frame(&self, player_id: &PlayerId) -> u64 {
+        match self {
+            NetworkSessionKind::DelayedLockstep(input_buffer)
+            | NetworkSessionKind::Spectate(input_buffer) => {
+                input_buffer.current_frame() + input_buffer.buffered_frames(player_id)
+            }
+            NetworkSessionKind::Rollback(rollback) => {
+                rollback.confirmed_frame() + rollback.buffered_frames(player_id)
+            }
+        }
+    }
+
+    /// No peer can have confirmed a frame that the host has not dispatched its input for yet, so
+    /// the first of those is the earliest frame that all peers can agree on
+    fn first_undispatched_frame(&self) -> u64 {
+        match self {
+            NetworkSessionKind::DelayedLockstep(input_buffer)
+            | NetworkSessionKind::Spectate(input_buffer) => input_buffer.local_input_frames().start,
+            NetworkSessionKind::Rollback(rollback) => rollback.current_frame(),
+        }
+    }
+}
+
+/// Make a player idle from the first frame that the host does not have its input for, and notify
+/// the other players. This should only be called by the host.
+pub fn drop_player(session: &mut NetworkSession, player_id: &PlayerId) {
+    let start_frame = session.kind.first_missing_frame(player_id);
+
+    session.kind.set_idle(player_id, start_frame, None);
+
+    dispatch_idle(player_id, start_frame, None);
+
+    #[cfg(debug_assertions)]
+    println!(
+        "WARNING: Player '{}' dropped, and is idle from frame {}",
+        player_id, start_frame
+    );
+}
+
+/// End the idle period of a player that has requested a resync, and send it the `ResyncState`,
+/// starting from the latest of its checkpoints that it can be resynchronized from. This should
+/// only be called by the host.
+pub fn resync_player(
+    session: &mut NetworkSession,
+    player_id: &PlayerId,
+    checkpoint_frames: &[u64],
+) -> Result<()> {
+    // A player that has already been sent a resync will keep requesting one until it arrives
+    if let Some(rejoin_frame) = session.rejoin_frames.get(player_id) {
+        if session.kind.confirmed_frame() < *rejoin_frame {
+            return Ok(());
+        }
+    }
+
+    // The player might have noticed that it lost the connection before the host did
+    let open_period = session
+        .kind
+        .idle_periods()
+        .into_iter()
+        .find(|(id, _, end_frame)| id == player_id && end_frame.is_none());
+
+    let start_frame = match open_period {
+        Some((_, start_frame, _)) => start_frame,
+        None => {
+            let start_frame = session.kind.first_missing_frame(player_id);
+            session.kind.set_idle(player_id, start_frame, None);
+
+            start_frame
+        }
+    };
+
+    let frame = session.kind.confirmed_frame();
+
+    // The world of the player differs from that of the other peers from the frame it was made
+    // idle on, as it went on with its own input
+    let snapshot_frame = checkpoint_frames
+        .iter()
+        .copied()
+        .filter(|checkpoint_frame| *checkpoint_frame <= start_frame.min(frame))
+        .max()
+        .ok_or_else(|| {
+            formaterr!(
+                ErrorKind::Network,
+                "Resync: Player '{}' has no checkpoint from before frame {}",
+                player_id,
+                start_frame.min(frame)
+            )
+        })?;
+
+    let catch_up_frame_cnt = frame - snapshot_frame;
+
+    let rejoin_frame = session.kind.first_undispatched_frame().max(start_frame)
+        + catch_up_frame_cnt / (MAX_RESYNC_CATCH_UP_FRAMES - 1)
+        + session.latency_frames() * 2
+        + RESYNC_MARGIN_FRAMES;
+
+    session
+        .kind
+        .set_idle(player_id, start_frame, Some(rejoin_frame));
+
+    dispatch_idle(player_id, start_frame, Some(rejoin_frame));
+
+    let player_cnt = session.player_ids.len();
+    let history_range = snapshot_frame as usize * player_cnt..frame as usize * player_cnt;
+
+    let inputs = session
+        .input_history
+        .get(history_range)
+        .ok_or_else(|| {
+            formaterr!(
+                ErrorKind::Network,
+                "Resync: The input history does not reach frame {}",
+                frame
+            )
+        })?
+        .to_vec();
+
+    let state = ResyncState {
+        snapshot_frame,
+        frame,
+        rejoin_frame,
+        player_ids: session.player_ids.clone(),
+        inputs,
+        pending_inputs: (frame..start_frame)
+            .filter_map(|frame| {
+                session
+                    .kind
+                    .input(player_id, frame)
+                    .map(|input| (frame, pack_input(&input)))
+            })
+            .collect(),
+        idle_periods: session.kind.idle_periods(),
+    };
+
+    Api::resync_player(player_id, frame, &state.encode())?;
+
+    session
+        .rejoin_frames
+        .insert(player_id.clone(), rejoin_frame);
+
+    Ok(())
+}
+
+/// Replace the input buffer, or rollback session, of the session with one that starts on the
+/// frame of the checkpoint that the resync starts from, holding all the confirmed input since.
+/// The returned checkpoint should be restored, and re-simulated up to the frame of the resync.
+pub fn apply_resync(session: &mut NetworkSession, data: &[u8]) -> Result<WorldSnapshot> {
+    let state = ResyncState::decode(data)?;
+
+    let snapshot = session
+        .checkpoints
+        .rewind(state.snapshot_frame)
+        .ok_or_else(|| {
+            formaterr!(
+                ErrorKind::Network,
+                "Resync: No checkpoint of frame {}",
+                state.snapshot_frame
+            )
+        })?;
+
+    let mut inputs = Vec::new();
+
+    if !state.player_ids.is_empty() {
+        for (i, frame_inputs) in state
+            .inputs
+            .chunks_exact(state.player_ids.len())
+            .enumerate()
+        {
+            let frame = state.snapshot_frame + i as u64;
+
+            for (player_id, bits) in state.player_ids.iter().zip(frame_inputs) {
+                inputs.push((player_id, frame, unpack_input(*bits)));
+            }
+        }
+    }
+
+    for (frame, bits) in &state.pending_inputs {
+        inputs.push((&session.local_player_id, *frame, unpack_input(*bits)));
+    }
+
+    session.kind = match &session.kind {
+        NetworkSessionKind::Rollback(rollback) => {
+            let mut rollback = RollbackSession::new_at_frame(
+                &session.local_player_id,
+                &state.player_ids,
+                rollback.max_rollback(),
+                state.snapshot_frame,
+            );
+
+            for (player_id, frame, input) in inputs {
+                rollback.add_remote_input(player_id, frame, input);
+            }
+
+            for (player_id, start_frame, end_frame) in &state.idle_periods {
+                rollback.set_idle(player_id, *start_frame, *end_frame);
+            }
+
+            NetworkSessionKind::Rollback(rollback)
+        }
+        kind => {
+            let delay = match kind {
+                NetworkSessionKind::DelayedLockstep(input_buffer) => input_buffer.delay(),
+                _ => 0,
+            };
+
+            let mut input_buffer =
+                InputBuffer::new_at_frame(&state.player_ids, delay, state.snapshot_frame);
+
+            for (player_id, frame, input) in inputs {
+                input_buffer.insert(player_id, frame, input);
+            }
+
+            for (player_id, start_frame, end_frame) in &state.idle_periods {
+                input_buffer.set_idle(player_id, *start_frame, *end_frame);
+            }
+
+            input_buffer.resume_local_input(state.rejoin_frame);
+
+            NetworkSessionKind::DelayedLockstep(input_buffer)
+        }
+    };
+
+    session.is_reconnecting = false;
+    session.catch_up_frame = state.frame;
+    session.rejoin_frame = state.rejoin_frame;
+
+    // The other peers have compared the checksums of the missed frames already
+    storage::store(StateHistory::default());
+    skip_state_checksums(state.frame);
+
+    Ok(snapshot)
+}
+
+fn dispatch_idle(player_id: &PlayerId, start_frame: u64, end_frame: Option<u64>) {
+    let message = NetworkMessage::PlayerIdle {
+        player_id: player_id.clone(),
+        start_frame,
+        end_frame,
+    };
+
+    if let Err(err) = Api::dispatch_message(message) {
+        #[cfg(debug_assertions)]
+        println!("WARNING: {}", err);
+    }
+}
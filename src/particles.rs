@@ -57,6 +57,7 @@ impl Default for ParticleEmitterMetadata {
     }
 }
 
+#[derive(Clone)]
 pub struct ParticleEmitter {
     pub particle_effect_id: String,
     pub offset: Vec2,
@@ -15,6 +15,22 @@ pub const GRAVITY: f32 = 2.5;
 pub const TERMINAL_VELOCITY: f32 = 10.0;
 
 pub fn create_collision_world(map: &Map) -> CollisionWorld {
+    let static_colliders = get_collision_tiles(map);
+
+    let mut collision_world = CollisionWorld::new();
+    collision_world.add_static_tiled_layer(
+        static_colliders,
+        map.tile_size.x,
+        map.tile_size.y,
+        map.grid_size.x as usize,
+        1,
+    );
+
+    collision_world
+}
+
+/// Get the static colliders of all map layers with collision, as a grid of tiles
+pub fn get_collision_tiles(map: &Map) -> Vec<Tile> {
     let tile_cnt = (map.grid_size.x * map.grid_size.y) as usize;
     let mut static_colliders = Vec::with_capacity(tile_cnt);
     for _ in 0..tile_cnt {
@@ -39,16 +55,7 @@ pub fn create_collision_world(map: &Map) -> CollisionWorld {
         }
     }
 
-    let mut collision_world = CollisionWorld::new();
-    collision_world.add_static_tiled_layer(
-        static_colliders,
-        map.tile_size.x,
-        map.tile_size.y,
-        map.grid_size.x as usize,
-        1,
-    );
-
-    collision_world
+    static_colliders
 }
 
 const FRICTION_LERP: f32 = 0.96;
@@ -82,6 +89,7 @@ impl Default for PhysicsBodyParams {
 /// Regular simulated physics bodies.
 /// Note that rotation is abstract, only set on the transform to be used for draws. The colliders
 /// are axis-aligned and will not be affected by rotation.
+#[derive(Clone)]
 pub struct PhysicsBody {
     pub actor: Actor,
     pub offset: Vec2,
@@ -230,6 +238,7 @@ impl Default for RigidBodyParams {
 /// Simple physics bodies that has a velocity and optional rotation.
 /// Note that rotation is abstract, only set on the transform to be used for draws. The colliders
 /// are axis-aligned and will not be affected by rotation.
+#[derive(Clone)]
 pub struct RigidBody {
     pub offset: Vec2,
     pub size: Vec2,
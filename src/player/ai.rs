@@ -1,133 +1,691 @@
-use crate::player::GameInput;
+//! A bot that controls a player, for practicing alone. Every frame, it produces the same kind of
+//! `PlayerInput` that a local player would, from the state of the world: it goes for a weapon if
+//! it has none, chases the nearest opponent, and fires when its opponent is in range. Targets are
+//! reached by following paths through the navigation graph of the map, and, where there is no
+//! path, by heading straight for them, jumping over obstacles and gaps on the way.
+//! How well a bot plays is determined by its `AiProfile`.
 
+use std::sync::Arc;
+
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+
+use hecs::{Entity, With, Without, World};
+
+use core::input::PlayerInput;
+use core::Transform;
+
+use crate::game::{get_simulation_dt, get_simulation_mut};
+use crate::items::Weapon;
+use crate::map::{NavEdge, NavEdgeKind, NavGraph, NavGraphParams};
+use crate::player::{
+    AiProfile, Player, PlayerAttributes, PlayerController, PlayerInventory, PlayerState,
+    PICKUP_GRACE_TIME,
+};
+use crate::{CollisionWorld, Map, Owner, PhysicsBody};
+
+/// The horizontal distance that a bot will fire at its opponent from
+const FIRE_RANGE: f32 = 280.0;
+/// A bot without a weapon will go for weapons that are closer than this, before its opponent.
+/// This is scaled by the preference of the bot for each weapon.
+const WEAPON_SEEK_RANGE: f32 = 480.0;
+/// A bot with a weapon will stop approaching its opponent at this distance, which is scaled by
+/// its aggressiveness, so that it is this distance at the default aggressiveness of `0.5`
+const MIN_DISTANCE: f32 = 64.0;
+/// The distance ahead of a bot that is checked for obstacles
+const OBSTACLE_LOOKAHEAD: f32 = 16.0;
+/// A bot will jump to reach a target that is this much higher than itself, and drop through
+/// platforms to reach a target that is this much lower
+const HEIGHT_THRESHOLD: f32 = 48.0;
+const JUMP_COOLDOWN: f32 = 0.4;
+/// The delay of a jump that is mistimed
+const MISTIMED_JUMP_DELAY: f32 = 0.15;
+/// A bot that has not moved for this long, while trying to, will jump
+const STUCK_DURATION: f32 = 0.5;
+const STUCK_THRESHOLD: f32 = 0.5;
+/// The interval that the path to the current target is updated at
+const REPATH_INTERVAL: f32 = 0.5;
+/// A node of a path is reached when the feet of a bot are this close to it
+const NODE_REACHED_DISTANCE: f32 = 8.0;
+const NODE_REACHED_HEIGHT: f32 = 16.0;
+
+/// The state of a bot, which is kept on players with a `PlayerControllerKind::Ai` controller
+#[derive(Debug, Clone, Default)]
 pub struct Ai {
+    profile: AiProfile,
+    /// The target that the bot is going for. This is only updated when the reaction timer runs
+    /// out, so the bot is going for where its target was, when it last noticed it.
+    target: Option<AiTarget>,
+    reaction_timer: f32,
     jump_cooldown: f32,
-    throw_cooldown: f32,
-    keep_direction_until_event: bool,
-    keep_direction_timeout: f32,
-    fix_direction: i32,
+    /// If this is `Some`, a jump that was mistimed will be started when it runs out
+    jump_delay_timer: Option<f32>,
+    stuck_timer: f32,
+    last_position: Vec2,
+    /// The navigation graph for the character of the bot, which is built on its first update
+    nav_graph: Option<Arc<NavGraph>>,
+    /// The edges that are left to follow, to reach the current target
+    path: Vec<NavEdge>,
+    /// The node that the current path leads to
+    path_target: Option<usize>,
+    repath_timer: f32,
+}
+
+/// What a bot is currently going for
+#[derive(Debug, Copy, Clone)]
+enum AiTarget {
+    Weapon(Rect),
+    Opponent(Rect),
 }
 
 impl Ai {
-    pub fn new() -> Ai {
+    pub fn new(profile: AiProfile) -> Self {
         Ai {
-            jump_cooldown: 0.,
-            keep_direction_until_event: false,
-            keep_direction_timeout: 0.,
-            fix_direction: 0,
-            throw_cooldown: 0.,
+            profile,
+            ..Default::default()
         }
     }
 
-    pub fn update(&mut self, _player: &mut OldPlayer) -> GameInput {
-        let input = GameInput {
-            right: self.fix_direction == 1,
-            left: self.fix_direction == -1,
-            ..Default::default()
-        };
+    pub fn update(&mut self, world: &World, entity: Entity, dt: f32) -> PlayerInput {
+        let mut input = PlayerInput::default();
+
+        let player = world.get::<Player>(entity).unwrap();
+
+        if player.state == PlayerState::Dead {
+            self.stuck_timer = 0.0;
+            self.target = None;
+            self.reaction_timer = 0.0;
+            self.jump_delay_timer = None;
+            self.path.clear();
+            return input;
+        }
+
+        let transform = world.get::<Transform>(entity).unwrap();
+        let body = world.get::<PhysicsBody>(entity).unwrap();
+        let inventory = world.get::<PlayerInventory>(entity).unwrap();
+
+        self.jump_cooldown = (self.jump_cooldown - dt).max(0.0);
+        self.repath_timer -= dt;
+        self.reaction_timer -= dt;
+
+        let rect = body.as_rect(transform.position);
+        let position = rect_center(&rect);
+
+        let (has_weapon, is_depleted) = inventory
+            .weapon
+            .and_then(|weapon_entity| world.get::<Weapon>(weapon_entity).ok())
+            .map(|weapon| {
+                let is_depleted = weapon
+                    .uses
+                    .map(|uses| weapon.use_cnt >= uses)
+                    .unwrap_or_default();
+
+                (true, is_depleted)
+            })
+            .unwrap_or_default();
+
+        // Weapons that can not be used anymore are thrown away, to make room for a new one
+        if is_depleted {
+            input.pickup = true;
+        }
 
-        /*
-        let foe = scene::find_nodes_by_type::<OldPlayer>().next().unwrap();
+        if self.reaction_timer <= 0.0 {
+            let opponent = nearest_opponent(world, entity, &player, position);
 
-        let mut following_horiz = false;
+            self.target = if has_weapon && !is_depleted {
+                opponent.map(AiTarget::Opponent)
+            } else {
+                preferred_weapon(world, position, &self.profile)
+                    .map(AiTarget::Weapon)
+                    .or_else(|| opponent.map(AiTarget::Opponent))
+            };
 
-        if (player.body.position.x - foe.body.position.x).abs() >= 50. {
-            //
-            if !self.keep_direction_until_event {
-                following_horiz = true;
-                if player.body.position.x > foe.body.position.x {
-                    input.left = true;
+            self.reaction_timer = self.profile.reaction_time;
+        }
+
+        let target = self.target;
+
+        let min_distance = MIN_DISTANCE * 2.0 * (1.0 - self.profile.aggressiveness.clamp(0.0, 1.0));
+
+        let mut direction = 0.0;
+        let mut target_offset = Vec2::ZERO;
+        let mut is_firing = false;
+
+        match target {
+            Some(AiTarget::Weapon(weapon_rect)) => {
+                target_offset = rect_center(&weapon_rect) - position;
+
+                if rect.overlaps(&weapon_rect) {
+                    // The target might be outdated, so this checks that the bot is not already
+                    // holding a weapon, to keep it from swapping it
+                    if !has_weapon && player.pickup_grace_timer >= PICKUP_GRACE_TIME {
+                        input.pickup = true;
+                    }
                 } else {
-                    input.right = true;
+                    direction = target_offset.x.signum();
                 }
             }
-        }
+            Some(AiTarget::Opponent(opponent_rect)) => {
+                target_offset = rect_center(&opponent_rect) - position;
+
+                let is_in_range = has_weapon
+                    && target_offset.x.abs() <= FIRE_RANGE
+                    && target_offset.y.abs() <= self.profile.aim_tolerance;
+
+                let is_facing_opponent = (target_offset.x < 0.0) == player.is_facing_left;
 
-        if !self.keep_direction_until_event
-            && (player.body.position.y - foe.body.position.y).abs() >= 50.
-            && !following_horiz
-        {
-            self.fix_direction = if rand::gen_range(0, 2) == 0 { 1 } else { -1 };
-            self.keep_direction_until_event = true;
+                if is_in_range && is_facing_opponent {
+                    input.fire = true;
+                    is_firing = true;
+                } else if is_in_range || !has_weapon || target_offset.x.abs() > min_distance {
+                    direction = target_offset.x.signum();
+                }
+            }
+            None => {}
         }
 
-        let dir = if input.left {
-            -1.
-        } else if input.right {
-            1.
-        } else {
-            0.
-        };
+        let feet = vec2(position.x, rect.y + rect.h);
+
+        // Follow a path through the navigation graph, to targets that are not on the same node
+        let next_edge = {
+            let target_feet = target.map(|target| match target {
+                AiTarget::Weapon(rect) | AiTarget::Opponent(rect) => {
+                    vec2(rect.x + rect.w / 2.0, rect.y + rect.h)
+                }
+            });
+
+            let attributes = world.get::<PlayerAttributes>(entity).unwrap();
 
-        {
-            let collision_world = &mut storage::get_mut::<GameWorld>().collision_world;
-
-            let obstacle_soon = collision_world.collide_check(
-                player.body.collider,
-                player.body.position + vec2(15. * dir, 0.),
-            );
-            let cliff_soon = !collision_world.collide_check(
-                player.body.collider,
-                player.body.position + vec2(5. * dir, 5.),
-            );
-            let wants_descent = player.body.position.y < foe.body.position.y;
-
-            if (cliff_soon || obstacle_soon) && self.keep_direction_timeout <= 0. {
-                self.keep_direction_until_event = false;
-                self.fix_direction = 0;
-                self.keep_direction_timeout = 1.;
+            let graph = self.nav_graph.get_or_insert_with(|| {
+                let params = NavGraphParams {
+                    jump_force: attributes.jump_force,
+                    move_speed: attributes.move_speed,
+                    gravity: body.gravity,
+                    collider_size: body.size,
+                };
+
+                let map = storage::get::<Map>();
+                Arc::new(NavGraph::new(&map, params))
+            });
+
+            match target_feet {
+                Some(target_feet) => {
+                    let path_target = graph.nearest_node(target_feet);
+
+                    if body.is_on_ground
+                        && (self.repath_timer <= 0.0 || path_target != self.path_target)
+                    {
+                        self.path = graph
+                            .nearest_node(feet)
+                            .zip(path_target)
+                            .and_then(|(from, to)| graph.find_path(from, to))
+                            .unwrap_or_default();
+
+                        self.path_target = path_target;
+                        self.repath_timer = REPATH_INTERVAL;
+                    }
+                }
+                None => self.path.clear(),
             }
 
-            if (obstacle_soon || (!wants_descent && cliff_soon))
-                && player.body.is_on_ground
-                && self.jump_cooldown <= 0.
-            {
-                input.jump = true;
-                self.jump_cooldown = 0.2;
+            // Edges are followed until the bot is on the node that they lead to
+            while let Some(edge) = self.path.first() {
+                let offset = graph.nodes()[edge.to].position - feet;
+
+                if body.is_on_ground
+                    && offset.x.abs() <= NODE_REACHED_DISTANCE
+                    && offset.y.abs() <= NODE_REACHED_HEIGHT
+                {
+                    self.path.remove(0);
+                } else {
+                    break;
+                }
             }
-        }
 
-        if rand::gen_range(0, 200) == 5 {
-            self.fix_direction = if rand::gen_range(0, 2) == 0 { 1 } else { -1 };
-            self.keep_direction_until_event = true;
+            self.path
+                .first()
+                .map(|edge| (*edge, graph.nodes()[edge.from], graph.nodes()[edge.to]))
+        };
+
+        match next_edge {
+            Some((edge, from, to)) if !is_firing => {
+                let offset = to.position - feet;
+
+                direction = if offset.x.abs() > NODE_REACHED_DISTANCE / 2.0 {
+                    offset.x.signum()
+                } else {
+                    0.0
+                };
+
+                let is_at_start = (from.position.x - feet.x).abs() <= NODE_REACHED_DISTANCE;
+                let is_drop_through =
+                    edge.kind == NavEdgeKind::Drop && from.coords.x == to.coords.x;
+
+                if body.is_on_ground && self.jump_cooldown <= 0.0 {
+                    if is_drop_through && body.is_on_platform {
+                        input.crouch = true;
+                        input.jump = true;
+                    } else if edge.kind == NavEdgeKind::Jump && is_at_start {
+                        input.jump = true;
+                    }
+
+                    if input.jump {
+                        self.jump_cooldown = JUMP_COOLDOWN;
+                    }
+                }
+
+                if !body.is_on_ground {
+                    input.crouch = is_drop_through;
+                }
+            }
+            None if !is_firing => {
+                // Without a path, the bot heads straight for its target, and tries to jump over, or
+                // drop down to, whatever is in the way
+                self.steer_without_path(
+                    &mut input,
+                    &body,
+                    transform.position,
+                    direction,
+                    target_offset,
+                );
+            }
+            _ => {}
         }
 
-        if rand::gen_range(0, 800) == 5 {
-            input.pickup = true;
-            self.throw_cooldown = 1.;
+        // Jumps are mistimed by starting them late. Drops through platforms are not affected.
+        // Only the input of bots is recorded in replays, so this draws from the RNG of the
+        // simulation that is kept for bots, which leaves the main one untouched.
+        if input.jump && !input.crouch && self.profile.jump_accuracy < 1.0 {
+            let roll: f32 = get_simulation_mut(world).ai_rng.gen_range(0.0, 1.0);
+
+            if roll >= self.profile.jump_accuracy {
+                input.jump = false;
+                self.jump_delay_timer = Some(MISTIMED_JUMP_DELAY);
+            }
         }
 
-        if player.body.position.distance(foe.body.position) <= 100. || rand::gen_range(0, 180) == 5
-        {
-            //
-            if player.state_machine.state() == OldPlayer::ST_NORMAL && player.weapon.is_some() {
-                player.state_machine.set_state(OldPlayer::ST_ATTACK);
+        if let Some(timer) = self.jump_delay_timer {
+            let timer = timer - dt;
+
+            if timer <= 0.0 {
+                input.jump = body.is_on_ground;
+                self.jump_delay_timer = None;
+            } else {
+                self.jump_delay_timer = Some(timer);
             }
         }
 
-        if self.jump_cooldown >= 0. {
-            self.jump_cooldown -= get_frame_time();
+        input.left = direction < 0.0;
+        input.right = direction > 0.0;
+
+        if direction != 0.0 && (position.x - self.last_position.x).abs() < STUCK_THRESHOLD {
+            self.stuck_timer += dt;
+        } else {
+            self.stuck_timer = 0.0;
         }
-        if self.throw_cooldown >= 0. {
-            self.throw_cooldown -= get_frame_time();
+
+        self.last_position = position;
+
+        input
+    }
+
+    fn steer_without_path(
+        &mut self,
+        input: &mut PlayerInput,
+        body: &PhysicsBody,
+        position: Vec2,
+        direction: f32,
+        target_offset: Vec2,
+    ) {
+        let wants_up = target_offset.y < -HEIGHT_THRESHOLD;
+        let wants_down = target_offset.y > HEIGHT_THRESHOLD;
+
+        if body.is_on_ground && self.jump_cooldown <= 0.0 {
+            let collision_world = storage::get::<CollisionWorld>();
+            let actor_position = position + body.offset;
+
+            let is_obstacle_ahead = direction != 0.0
+                && collision_world.collide_check(
+                    body.actor,
+                    actor_position + vec2(OBSTACLE_LOOKAHEAD * direction, 0.0),
+                );
+
+            // This checks for ground under the front half of the body, so that the bot jumps
+            // before it walks off a ledge
+            let is_gap_ahead = direction != 0.0
+                && !collision_world.collide_check(
+                    body.actor,
+                    actor_position + vec2(body.size.x / 2.0 * direction, 1.0),
+                );
+
+            let is_stuck = self.stuck_timer >= STUCK_DURATION;
+
+            if wants_down && body.is_on_platform {
+                input.crouch = true;
+                input.jump = true;
+            } else if wants_up || is_obstacle_ahead || is_stuck || (is_gap_ahead && !wants_down) {
+                input.jump = true;
+            }
+
+            if input.jump {
+                self.jump_cooldown = JUMP_COOLDOWN;
+                self.stuck_timer = 0.0;
+            }
         }
 
-        if self.keep_direction_timeout >= 0. {
-            self.keep_direction_timeout -= get_frame_time();
+        if !body.is_on_ground {
+            // Floating extends jumps, so it is held while the target is above, and crouching in
+            // the air drops through platforms, so it is held while the target is below
+            input.float = target_offset.y < 0.0;
+            input.crouch = wants_down;
         }
+    }
+}
 
-        if self.throw_cooldown <= 0.0 {
-            for item in scene::find_nodes_by_type::<MapItem>() {
-                let item_collider = item.body.get_collider_rect();
-                if item_collider.point().distance(player.body.position) <= 80. {
-                    input.pickup = true;
-                }
+/// The collider of the nearest player that is alive, and not on the same team
+fn nearest_opponent(
+    world: &World,
+    entity: Entity,
+    player: &Player,
+    position: Vec2,
+) -> Option<Rect> {
+    world
+        .query::<(&Player, &Transform, &PhysicsBody)>()
+        .iter()
+        .filter(|(other_entity, (other, _, _))| {
+            *other_entity != entity
+                && other.state != PlayerState::Dead
+                && (player.team.is_none() || other.team != player.team)
+        })
+        .map(|(_, (_, transform, body))| body.as_rect(transform.position))
+        .min_by(|a, b| {
+            rect_center(a)
+                .distance_squared(position)
+                .total_cmp(&rect_center(b).distance_squared(position))
+        })
+}
+
+/// The collider of the weapon, that is not held by a player, with the highest preference relative
+/// to its distance. Weapons are only considered within `WEAPON_SEEK_RANGE`, scaled by preference.
+fn preferred_weapon(world: &World, position: Vec2, profile: &AiProfile) -> Option<Rect> {
+    world
+        .query::<Without<Owner, (&Weapon, &Transform, &PhysicsBody)>>()
+        .iter()
+        .filter(|(_, (_, _, body))| !body.is_deactivated)
+        .filter_map(|(_, (weapon, transform, body))| {
+            let preference = profile.item_preference(&weapon.id);
+            let rect = body.as_rect(transform.position);
+            let distance = rect_center(&rect).distance(position);
+
+            if preference > 0.0 && distance <= WEAPON_SEEK_RANGE * preference {
+                Some((rect, preference / distance.max(1.0)))
+            } else {
+                None
             }
-            self.throw_cooldown = 1.;
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(rect, _)| rect)
+}
+
+fn rect_center(rect: &Rect) -> Vec2 {
+    rect.point() + rect.size() / 2.0
+}
+
+/// Produce the input of all bots, for the frame that is about to be simulated. This should run
+/// before any other fixed update.
+pub fn fixed_update_ai_controllers(world: &mut World) {
+    let dt = get_simulation_dt(world);
+
+    let bots = world
+        .query::<With<PlayerController, &Ai>>()
+        .iter()
+        .map(|(entity, _)| entity)
+        .collect::<Vec<_>>();
+
+    for entity in bots {
+        let input = {
+            let mut ai = world.get_mut::<Ai>(entity).unwrap();
+            ai.update(world, entity, dt)
+        };
+
+        let mut controller = world.get_mut::<PlayerController>(entity).unwrap();
+        controller.apply_input(input);
+    }
+}
+
+/// Draw the navigation graph of every bot, along with the path that it is following
+pub fn debug_draw_ai(world: &mut World) {
+    for (_, ai) in world.query::<&Ai>().iter() {
+        if let Some(nav_graph) = &ai.nav_graph {
+            nav_graph.debug_draw(&ai.path);
         }
+    }
+}
 
-         */
+#[cfg(test)]
+mod tests {
+    use core::data::deserialize_json_bytes;
+    use core::input::InputTrack;
 
-        input
+    use crate::game::{
+        default_assets_dir, HeadlessGame, MatchRulesParams, Replay, ReplayPlayer, FIXED_DELTA_TIME,
+    };
+    use crate::Resources;
+
+    use super::*;
+
+    /// A match between a bot, as the first player, and its opponent, once both have landed
+    fn ai_game() -> HeadlessGame {
+        let players = vec![
+            ReplayPlayer::new(0, "pescy", InputTrack::new()),
+            ReplayPlayer::new(1, "sharky", InputTrack::new()),
+        ];
+
+        let replay = Replay::from_script("lev01", 0, MatchRulesParams::default(), players);
+
+        let mut game = HeadlessGame::new(default_assets_dir(), &replay).unwrap();
+        game.run(60);
+        game
+    }
+
+    fn update(ai: &mut Ai, game: &HeadlessGame, frame_cnt: usize) -> PlayerInput {
+        let entity = game.player(0).unwrap();
+
+        (0..frame_cnt)
+            .map(|_| ai.update(game.world(), entity, FIXED_DELTA_TIME))
+            .last()
+            .unwrap()
+    }
+
+    fn collider(game: &HeadlessGame, entity: Entity) -> Rect {
+        let world = game.world();
+        let transform = world.get::<Transform>(entity).unwrap();
+        let body = world.get::<PhysicsBody>(entity).unwrap();
+
+        body.as_rect(transform.position)
+    }
+
+    /// Place the opponent at an offset from the center of the bot
+    fn move_opponent(game: &mut HeadlessGame, offset: Vec2) {
+        let bot = rect_center(&collider(game, game.player(0).unwrap()));
+
+        let opponent = game.player(1).unwrap();
+        let size = collider(game, opponent).size();
+        let body_offset = game.world().get::<PhysicsBody>(opponent).unwrap().offset;
+
+        game.world_mut()
+            .get_mut::<Transform>(opponent)
+            .unwrap()
+            .position = bot + offset - size / 2.0 - body_offset;
+    }
+
+    fn despawn_weapons(game: &mut HeadlessGame) {
+        let weapons = game
+            .world()
+            .query::<&Weapon>()
+            .iter()
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+
+        for entity in weapons {
+            game.world_mut().despawn(entity).unwrap();
+        }
+    }
+
+    /// The id of the weapon that the bot is going for, if any
+    fn target_weapon(ai: &Ai, game: &HeadlessGame) -> Option<String> {
+        let rect = match ai.target {
+            Some(AiTarget::Weapon(rect)) => rect,
+            _ => return None,
+        };
+
+        game.world()
+            .query::<&Weapon>()
+            .iter()
+            .find(|(entity, _)| collider(game, *entity) == rect)
+            .map(|(_, weapon)| weapon.id.clone())
+    }
+
+    #[test]
+    fn profiles_are_loaded() {
+        let _game = ai_game();
+
+        let resources = storage::get::<Resources>();
+
+        let ids = resources
+            .ai_profiles
+            .iter()
+            .map(|profile| profile.id.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, ["easy", AiProfile::DEFAULT_ID, "hard"]);
+
+        let hard = &resources.ai_profiles[2];
+        assert_eq!(hard.item_preference("sniper_rifle"), 1.5);
+        assert_eq!(hard.item_preference("sword"), 1.0);
+
+        let profiles: Vec<AiProfile> =
+            deserialize_json_bytes(br#"[{ "id": "custom", "name": "Custom" }]"#).unwrap();
+
+        let defaults = AiProfile::default();
+        assert_eq!(profiles[0].reaction_time, defaults.reaction_time);
+        assert_eq!(profiles[0].aim_tolerance, defaults.aim_tolerance);
+        assert_eq!(profiles[0].aggressiveness, defaults.aggressiveness);
+        assert_eq!(profiles[0].jump_accuracy, defaults.jump_accuracy);
+    }
+
+    #[test]
+    fn item_preferences_pick_the_weapon() {
+        let game = ai_game();
+
+        let mut ai = Ai::new(AiProfile::default());
+        update(&mut ai, &game, 1);
+        assert_eq!(target_weapon(&ai, &game).as_deref(), Some("sword"));
+
+        let mut profile = AiProfile::default();
+        profile.item_preferences.insert("cannon".to_string(), 10.0);
+
+        let mut ai = Ai::new(profile.clone());
+        update(&mut ai, &game, 1);
+        assert_eq!(target_weapon(&ai, &game).as_deref(), Some("cannon"));
+
+        profile.item_preferences.insert("cannon".to_string(), 0.0);
+        profile.item_preferences.insert("sword".to_string(), 0.0);
+
+        let mut ai = Ai::new(profile);
+        update(&mut ai, &game, 1);
+        assert_eq!(target_weapon(&ai, &game).as_deref(), Some("musket"));
+    }
+
+    #[test]
+    fn reaction_time_delays_noticing_targets() {
+        let mut game = ai_game();
+        despawn_weapons(&mut game);
+
+        let opponent = game.player(1).unwrap();
+
+        let profile = AiProfile {
+            reaction_time: 0.5,
+            ..Default::default()
+        };
+
+        let mut ai = Ai::new(profile);
+        update(&mut ai, &game, 1);
+
+        let noticed = collider(&game, opponent);
+        assert!(matches!(ai.target, Some(AiTarget::Opponent(rect)) if rect == noticed));
+
+        move_opponent(&mut game, vec2(100.0, 0.0));
+
+        update(&mut ai, &game, 20);
+        assert!(matches!(ai.target, Some(AiTarget::Opponent(rect)) if rect == noticed));
+
+        update(&mut ai, &game, 15);
+        let moved = collider(&game, opponent);
+        assert!(matches!(ai.target, Some(AiTarget::Opponent(rect)) if rect == moved));
+    }
+
+    #[test]
+    fn aim_tolerance_limits_firing() {
+        let mut game = ai_game();
+
+        let bot = game.player(0).unwrap();
+        let weapon = game
+            .world()
+            .query::<&Weapon>()
+            .iter()
+            .next()
+            .map(|(entity, _)| entity)
+            .unwrap();
+
+        game.world_mut()
+            .get_mut::<PlayerInventory>(bot)
+            .unwrap()
+            .weapon = Some(weapon);
+
+        // The bot is facing right
+        move_opponent(&mut game, vec2(100.0, -40.0));
+
+        let fires = |aim_tolerance| {
+            let profile = AiProfile {
+                aim_tolerance,
+                ..Default::default()
+            };
+
+            update(&mut Ai::new(profile), &game, 1).fire
+        };
+
+        assert!(fires(48.0));
+        assert!(!fires(16.0));
+    }
+
+    #[test]
+    fn mistimed_jumps_are_started_late() {
+        let mut game = ai_game();
+        despawn_weapons(&mut game);
+
+        // The bot will jump for an opponent that is right above it
+        move_opponent(&mut game, vec2(0.0, -150.0));
+
+        let rng = get_simulation_mut(game.world()).rng.clone();
+
+        assert!(update(&mut Ai::new(AiProfile::default()), &game, 1).jump);
+
+        let profile = AiProfile {
+            jump_accuracy: 0.0,
+            ..Default::default()
+        };
+
+        let mut ai = Ai::new(profile);
+        let jumps = (0..12)
+            .map(|_| update(&mut ai, &game, 1).jump)
+            .collect::<Vec<_>>();
+
+        // The jump is started once `MISTIMED_JUMP_DELAY` has passed
+        assert!(matches!(jumps.iter().position(|jump| *jump), Some(8..=9)));
+
+        // Bots do not draw from the RNG that the rest of the simulation uses
+        let simulation = get_simulation_mut(game.world());
+        assert_eq!(simulation.rng.clone().next_u64(), rng.clone().next_u64());
     }
 }
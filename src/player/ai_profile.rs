@@ -0,0 +1,75 @@
+//! This implements `AiProfile`, which is a declaration of how a bot plays, loaded from the
+//! `ai_profiles.json` file. Profiles make it possible to tune the difficulty of bots, and to add
+//! new kinds of bots, in mods, without recompiling the game.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiProfile {
+    /// This is the id of the profile. This should be unique, or it will either overwrite or be
+    /// overwritten, depending on load order, if not.
+    pub id: String,
+    /// This is the name of the profile, as shown when adding a bot to a game
+    pub name: String,
+    /// This is the time, in seconds, that it takes a bot to notice where its targets have moved to
+    #[serde(default = "AiProfile::default_reaction_time")]
+    pub reaction_time: f32,
+    /// This is the vertical distance to its opponent that a bot will fire from
+    #[serde(default = "AiProfile::default_aim_tolerance")]
+    pub aim_tolerance: f32,
+    /// This holds a preference for items, by item id. When a bot picks a weapon to go for, the
+    /// range it looks within is scaled by the preference for each weapon, and the weapon with the
+    /// highest preference, relative to its distance, is picked. Items that are not in this map
+    /// have a preference of `1.0`, and items with a preference of `0.0`, or below, are ignored.
+    #[serde(default)]
+    pub item_preferences: HashMap<String, f32>,
+    /// This is a value between `0.0` and `1.0`. Aggressive bots get closer to their opponents
+    /// before they stop approaching them, while cautious bots keep their distance.
+    #[serde(default = "AiProfile::default_aggressiveness")]
+    pub aggressiveness: f32,
+    /// This is the chance, between `0.0` and `1.0`, that a bot times a jump correctly. A jump that
+    /// is mistimed is started late, which might cause the bot to miss the platform it jumps for.
+    #[serde(default = "AiProfile::default_jump_accuracy")]
+    pub jump_accuracy: f32,
+}
+
+impl AiProfile {
+    /// The id of the profile that is used for bots, if no other profile is selected
+    pub const DEFAULT_ID: &'static str = "normal";
+
+    pub fn item_preference(&self, item_id: &str) -> f32 {
+        self.item_preferences.get(item_id).copied().unwrap_or(1.0)
+    }
+
+    pub fn default_reaction_time() -> f32 {
+        0.2
+    }
+
+    pub fn default_aim_tolerance() -> f32 {
+        32.0
+    }
+
+    pub fn default_aggressiveness() -> f32 {
+        0.5
+    }
+
+    pub fn default_jump_accuracy() -> f32 {
+        1.0
+    }
+}
+
+impl Default for AiProfile {
+    fn default() -> Self {
+        AiProfile {
+            id: Self::DEFAULT_ID.to_string(),
+            name: "Normal".to_string(),
+            reaction_time: Self::default_reaction_time(),
+            aim_tolerance: Self::default_aim_tolerance(),
+            item_preferences: HashMap::new(),
+            aggressiveness: Self::default_aggressiveness(),
+            jump_accuracy: Self::default_jump_accuracy(),
+        }
+    }
+}
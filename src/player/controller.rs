@@ -1,15 +1,22 @@
+use std::sync::Arc;
+
 use hecs::World;
 
 use macroquad::prelude::*;
 
 use core::network::PlayerId;
 
-use core::input::{collect_local_input, GameInputScheme, PlayerInput};
+use core::input::{collect_local_input, GameInputScheme, InputTrack, PlayerInput};
 
 #[derive(Debug, Clone)]
 pub enum PlayerControllerKind {
     LocalInput(GameInputScheme),
     Network(PlayerId),
+    /// A bot, which is only supported in local games. This holds the id of its `AiProfile`.
+    Ai(String),
+    /// Input that has been recorded to a replay, which is fed to the controller, frame by frame,
+    /// by `fixed_update_replay_controllers`
+    Replay(Arc<InputTrack>),
 }
 
 impl PlayerControllerKind {
@@ -18,6 +25,7 @@ impl PlayerControllerKind {
     }
 }
 
+#[derive(Clone)]
 pub struct PlayerController {
     pub kind: PlayerControllerKind,
 
@@ -76,15 +84,29 @@ impl PlayerController {
         self.should_attack = input.fire;
         self.should_slide = input.slide;
     }
+
+    /// The input that the controller currently holds, as it would have been applied with
+    /// `apply_input`. This is what is recorded to replays.
+    pub fn input(&self) -> PlayerInput {
+        PlayerInput {
+            left: self.move_direction.x < 0.0,
+            right: self.move_direction.x > 0.0,
+            fire: self.should_attack,
+            jump: self.should_jump,
+            pickup: self.should_pickup,
+            float: self.should_float,
+            crouch: self.should_crouch,
+            slide: self.should_slide,
+        }
+    }
 }
 
 pub fn update_player_controllers(world: &mut World) {
     for (_, controller) in world.query_mut::<&mut PlayerController>() {
-        let input = match &controller.kind {
-            PlayerControllerKind::LocalInput(input_scheme) => collect_local_input(*input_scheme),
-            PlayerControllerKind::Network(_player_id) => PlayerInput::default(),
-        };
-
-        controller.apply_input(input);
+        // Network controllers are updated by the network systems, when their input is received
+        if let PlayerControllerKind::LocalInput(input_scheme) = &controller.kind {
+            let input = collect_local_input(*input_scheme);
+            controller.apply_input(input);
+        }
     }
 }
@@ -1,10 +1,10 @@
 use hecs::{Entity, World};
-use macroquad::time::get_frame_time;
 
+use crate::game::{get_match_rules_mut, get_simulation_dt};
 use crate::player::{Player, PlayerState};
 use serde::{Deserialize, Serialize};
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct PlayerEventQueue {
     pub queue: Vec<PlayerEvent>,
 }
@@ -13,6 +13,29 @@ impl PlayerEventQueue {
     pub fn new() -> Self {
         PlayerEventQueue { queue: Vec::new() }
     }
+
+    /// Returns `true` if damage from the given side is blocked by a `DamageBlocked` event
+    pub fn is_damage_blocked(&self, is_from_left: bool) -> bool {
+        self.queue.iter().any(|event| {
+            matches!(
+                event,
+                PlayerEvent::DamageBlocked { is_from_left: blocked_from_left }
+                    if *blocked_from_left == is_from_left
+            )
+        })
+    }
+
+    /// Returns the source of the damage that the player will be killed by, when the queue is
+    /// handled, if any damage in it is not blocked
+    pub fn fatal_damage(&self) -> Option<Option<Entity>> {
+        self.queue.iter().rev().find_map(|event| match event {
+            PlayerEvent::ReceiveDamage {
+                is_from_left,
+                damage_from,
+            } if !self.is_damage_blocked(*is_from_left) => Some(*damage_from),
+            _ => None,
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -67,21 +90,22 @@ impl From<&PlayerEvent> for PlayerEventKind {
 }
 
 pub fn update_player_events(world: &mut World) {
-    for (_, (player, events)) in world.query_mut::<(&mut Player, &mut PlayerEventQueue)>() {
-        let dt = get_frame_time();
+    let dt = get_simulation_dt(world);
 
-        events.queue.push(PlayerEvent::Update { dt });
+    let mut deaths = Vec::new();
 
-        let mut damage_blocked_left = false;
-        let mut damage_blocked_right = false;
+    for (_, (player, events)) in world.query_mut::<(&mut Player, &mut PlayerEventQueue)>() {
+        events.queue.push(PlayerEvent::Update { dt });
 
-        for event in events.queue.iter() {
-            if let &PlayerEvent::DamageBlocked { is_from_left } = event {
-                damage_blocked_left = damage_blocked_left || is_from_left;
-                damage_blocked_right = damage_blocked_right || !is_from_left;
+        if player.state != PlayerState::Dead {
+            if let Some(damage_from) = events.fatal_damage() {
+                deaths.push((player.index, damage_from));
             }
         }
 
+        let damage_blocked_left = events.is_damage_blocked(true);
+        let damage_blocked_right = events.is_damage_blocked(false);
+
         while let Some(event) = events.queue.pop() {
             if let PlayerEvent::ReceiveDamage { is_from_left, .. } = event {
                 if (is_from_left && !damage_blocked_left)
@@ -93,4 +117,14 @@ pub fn update_player_events(world: &mut World) {
             }
         }
     }
+
+    if let Some(mut rules) = get_match_rules_mut(world) {
+        for (index, damage_from) in deaths {
+            let killer = damage_from
+                .and_then(|entity| world.get::<Player>(entity).ok())
+                .map(|player| player.index);
+
+            rules.record_death(index, killer);
+        }
+    }
 }
@@ -4,17 +4,19 @@ use hecs::{Entity, With, Without, World};
 
 use core::Transform;
 
+use crate::game::{get_simulation_dt, record_item_picked_up};
 use crate::items::{
     fire_weapon, ItemDepleteBehavior, ItemDropBehavior, Weapon, EFFECT_ANIMATED_SPRITE_ID,
     GROUND_ANIMATION_ID, ITEMS_DRAW_ORDER, SPRITE_ANIMATED_SPRITE_ID,
 };
+use crate::map::{can_pickup_flag, is_flag_captured};
 use crate::particles::ParticleEmitter;
 use crate::player::{Player, PlayerController, PlayerState, IDLE_ANIMATION_ID, PICKUP_GRACE_TIME};
 use crate::{Drawable, Item, Owner, PassiveEffectInstance, PhysicsBody};
 
 const THROW_FORCE: f32 = 5.0;
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct PlayerInventory {
     pub weapon_mount: Vec2,
     pub weapon_mount_offset: Vec2,
@@ -53,6 +55,8 @@ impl PlayerInventory {
 }
 
 pub fn update_player_inventory(world: &mut World) {
+    let dt = get_simulation_dt(world);
+
     let mut item_colliders = world
         .query::<With<Item, Without<Owner, (&Transform, &PhysicsBody)>>>()
         .iter()
@@ -99,6 +103,14 @@ pub fn update_player_inventory(world: &mut World) {
                 if player_rect.overlaps(&rect) {
                     let item = world.get::<Item>(item_entity).unwrap();
 
+                    if item.is_flag
+                        && !can_pickup_flag(world, item_entity, player, &inventory.items)
+                    {
+                        i += 1;
+
+                        continue;
+                    }
+
                     if item.is_hat {
                         if player.pickup_grace_timer < PICKUP_GRACE_TIME {
                             i += 1;
@@ -172,7 +184,7 @@ pub fn update_player_inventory(world: &mut World) {
             if let Some(weapon_entity) = inventory.weapon {
                 let mut weapon = world.get_mut::<Weapon>(weapon_entity).unwrap();
 
-                weapon.cooldown_timer += get_frame_time();
+                weapon.cooldown_timer += dt;
 
                 let mut weapon_transform = world.get_mut::<Transform>(weapon_entity).unwrap();
 
@@ -249,7 +261,7 @@ pub fn update_player_inventory(world: &mut World) {
 
                 let mut item = world.get_mut::<Item>(item_entity).unwrap();
 
-                item.duration_timer += get_frame_time();
+                item.duration_timer += dt;
 
                 let mut is_depleted = false;
 
@@ -261,6 +273,11 @@ pub fn update_player_inventory(world: &mut World) {
                     is_depleted = is_depleted || item.duration_timer >= duration;
                 }
 
+                // A flag that has been captured is dropped, so that it can be returned to its base
+                if item.is_flag {
+                    is_depleted = is_depleted || is_flag_captured(world, item_entity);
+                }
+
                 if is_depleted {
                     res = true;
 
@@ -337,6 +354,8 @@ pub fn update_player_inventory(world: &mut World) {
     for (player_entity, item_entity) in picked_up {
         world.insert_one(item_entity, Owner(player_entity)).unwrap();
 
+        record_item_picked_up(world, player_entity);
+
         let player_draw_order = world
             .get::<Drawable>(player_entity)
             .map(|drawable| drawable.draw_order)
@@ -10,6 +10,8 @@ use crate::{
     GameCamera, PassiveEffectInstance, PhysicsBody, Resources,
 };
 
+mod ai;
+mod ai_profile;
 mod animation;
 mod character;
 mod controller;
@@ -17,6 +19,8 @@ mod events;
 mod inventory;
 mod state;
 
+pub use ai::*;
+pub use ai_profile::*;
 pub use animation::*;
 pub use character::*;
 pub use controller::*;
@@ -24,6 +28,7 @@ pub use events::*;
 pub use inventory::*;
 pub use state::*;
 
+use crate::game::{get_match_rules_mut, PlayerStats};
 use crate::physics::PhysicsBodyParams;
 
 pub const BODY_ANIMATED_SPRITE_ID: &str = "body";
@@ -49,15 +54,28 @@ pub const LAND_SOUND_ID: &str = "land";
 pub const RESPAWN_DELAY: f32 = 2.5;
 pub const PICKUP_GRACE_TIME: f32 = 0.25;
 
+/// The tint of the players on each team
+pub const TEAM_COLORS: [Color; 4] = [
+    Color::new(1.0, 0.6, 0.6, 1.0),
+    Color::new(0.6, 0.7, 1.0, 1.0),
+    Color::new(0.6, 1.0, 0.6, 1.0),
+    Color::new(1.0, 1.0, 0.5, 1.0),
+];
+
 #[derive(Debug, Clone)]
 pub struct PlayerParams {
     pub index: u8,
+    /// Players on the same team score together, and can only damage each other if friendly fire
+    /// is enabled
+    pub team: Option<u8>,
     pub controller: PlayerControllerKind,
     pub character: PlayerCharacterMetadata,
 }
 
+#[derive(Clone)]
 pub struct Player {
     pub index: u8,
+    pub team: Option<u8>,
     pub state: PlayerState,
     pub damage_from_left: bool,
     pub is_facing_left: bool,
@@ -73,11 +91,12 @@ pub struct Player {
 }
 
 impl Player {
-    pub fn new(index: u8, position: Vec2) -> Self {
+    pub fn new(index: u8, team: Option<u8>, position: Vec2) -> Self {
         let camera_box = Rect::new(position.x - 30.0, position.y - 150.0, 100.0, 210.0);
 
         Player {
             index,
+            team,
             state: PlayerState::None,
             damage_from_left: false,
             is_facing_left: false,
@@ -95,6 +114,12 @@ impl Player {
 }
 
 pub fn update_player_camera_box(world: &mut World) {
+    // Players that have been eliminated are not followed by the camera, so that it stays on the
+    // players that are still in the match
+    let eliminated = get_match_rules_mut(world)
+        .map(|rules| rules.eliminated_players())
+        .unwrap_or_default();
+
     for (_, (transform, player)) in world.query_mut::<(&Transform, &mut Player)>() {
         let rect = Rect::new(transform.position.x, transform.position.y, 32.0, 60.0);
 
@@ -114,8 +139,10 @@ pub fn update_player_camera_box(world: &mut World) {
             player.camera_box.y = rect.y + rect.h - player.camera_box.h;
         }
 
-        let mut camera = storage::get_mut::<GameCamera>();
-        camera.add_player_rect(player.camera_box);
+        if !eliminated.contains(&player.index) {
+            let mut camera = storage::get_mut::<GameCamera>();
+            camera.add_player_rect(player.index, player.camera_box);
+        }
     }
 }
 
@@ -155,6 +182,7 @@ impl From<PlayerCharacterMetadata> for PlayerAttributes {
 pub fn spawn_player(
     world: &mut World,
     index: u8,
+    team: Option<u8>,
     position: Vec2,
     controller: PlayerControllerKind,
     character: PlayerCharacterMetadata,
@@ -186,10 +214,16 @@ pub fn spawn_player(
     let params = {
         let meta: AnimatedSpriteMetadata = character.sprite.clone().into();
 
-        AnimatedSpriteParams {
+        let mut params = AnimatedSpriteParams {
             offset,
             ..meta.into()
+        };
+
+        if let Some(team) = team {
+            params.tint = TEAM_COLORS[team as usize % TEAM_COLORS.len()];
         }
+
+        params
     };
 
     let sprites = vec![(
@@ -199,6 +233,26 @@ pub fn spawn_player(
 
     let draw_order = (index as u32 + 1) * 10;
 
+    let ai_profile = match &controller {
+        PlayerControllerKind::Ai(profile_id) => {
+            let resources = storage::get::<Resources>();
+
+            let profile = resources
+                .ai_profiles
+                .iter()
+                .find(|profile| &profile.id == profile_id)
+                .cloned();
+
+            if profile.is_none() {
+                #[cfg(debug_assertions)]
+                println!("WARNING: The AI profile '{}' was not found", profile_id);
+            }
+
+            Some(profile.unwrap_or_default())
+        }
+        _ => None,
+    };
+
     let size = character.collider_size.as_i32();
     let actor = storage::get_mut::<CollisionWorld>().add_actor(position, size.x, size.y);
 
@@ -211,14 +265,21 @@ pub fn spawn_player(
         ..Default::default()
     };
 
-    world.spawn((
-        Player::new(index, position),
+    let entity = world.spawn((
+        Player::new(index, team, position),
         Transform::from(position),
         PlayerController::from(controller),
         PlayerAttributes::from(&character),
         PlayerInventory::new(weapon_mount, item_mount, hat_mount),
         PlayerEventQueue::new(),
+        PlayerStats::default(),
         Drawable::new_animated_sprite_set(draw_order, &sprites),
         PhysicsBody::new(actor, None, body_params),
-    ))
+    ));
+
+    if let Some(profile) = ai_profile {
+        world.insert_one(entity, Ai::new(profile)).unwrap();
+    }
+
+    entity
 }
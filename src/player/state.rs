@@ -6,6 +6,7 @@ use hecs::{Entity, World};
 
 use core::Transform;
 
+use crate::game::{get_match_rules_mut, get_simulation_dt, get_simulation_mut};
 use crate::player::{
     Player, PlayerAttributes, PlayerController, PlayerEventQueue, JUMP_SOUND_ID, LAND_SOUND_ID,
     RESPAWN_DELAY,
@@ -34,17 +35,20 @@ impl Default for PlayerState {
 }
 
 pub fn update_player_states(world: &mut World) {
-    let query = world.query_mut::<(
+    let dt = get_simulation_dt(world);
+
+    let mut simulation = get_simulation_mut(world);
+    let rules = get_match_rules_mut(world);
+
+    let mut query = world.query::<(
         &mut Transform,
         &mut Player,
         &PlayerController,
         &PlayerAttributes,
         &mut PhysicsBody,
     )>();
-    for (_, (transform, player, controller, attributes, body)) in query {
+    for (_, (transform, player, controller, attributes, body)) in query.iter() {
         // Timers
-        let dt = get_frame_time();
-
         player.attack_timer -= dt;
         if player.attack_timer <= 0.0 {
             player.attack_timer = 0.0;
@@ -63,12 +67,17 @@ pub fn update_player_states(world: &mut World) {
 
             player.passive_effects.clear();
 
-            if player.respawn_timer >= RESPAWN_DELAY {
+            let can_respawn = rules
+                .as_ref()
+                .map(|rules| rules.can_respawn(player.index))
+                .unwrap_or(true);
+
+            if can_respawn && player.respawn_timer >= RESPAWN_DELAY {
                 player.state = PlayerState::None;
                 player.respawn_timer = 0.0;
 
                 let map = storage::get::<Map>();
-                transform.position = map.get_random_spawn_point();
+                transform.position = map.get_random_spawn_point(&mut simulation.rng);
             }
         } else if player.state == PlayerState::Incapacitated {
             player.incapacitation_timer += dt;
@@ -145,9 +154,9 @@ pub fn update_player_states(world: &mut World) {
                     player.state = PlayerState::Jumping;
 
                     let resources = storage::get::<Resources>();
-                    let sound = resources.sounds[JUMP_SOUND_ID];
-
-                    play_sound_once(sound);
+                    if let Some(sound) = resources.sounds.get(JUMP_SOUND_ID) {
+                        play_sound_once(*sound);
+                    }
                 } else if player.state == PlayerState::Jumping {
                     player.jump_frame_counter += 1;
 
@@ -182,9 +191,9 @@ pub fn update_player_states(world: &mut World) {
                 body.has_mass = true;
 
                 let resources = storage::get::<Resources>();
-                let sound = resources.sounds[LAND_SOUND_ID];
-
-                play_sound_once(sound);
+                if let Some(sound) = resources.sounds.get(LAND_SOUND_ID) {
+                    play_sound_once(*sound);
+                }
             }
         }
     }
@@ -193,9 +202,9 @@ pub fn update_player_states(world: &mut World) {
 pub fn update_player_passive_effects(world: &mut World) {
     let mut function_calls = Vec::new();
 
-    for (entity, (player, events)) in world.query::<(&mut Player, &mut PlayerEventQueue)>().iter() {
-        let dt = get_frame_time();
+    let dt = get_simulation_dt(world);
 
+    for (entity, (player, events)) in world.query::<(&mut Player, &mut PlayerEventQueue)>().iter() {
         for effect in &mut player.passive_effects {
             effect.duration_timer += dt;
         }
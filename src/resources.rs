@@ -8,6 +8,7 @@ use macroquad::{
 
 use ff_particles::EmitterConfig;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use core::data::{deserialize_json_bytes, deserialize_json_file};
@@ -16,9 +17,10 @@ use core::text::ToStringHelper;
 use core::{formaterr, Result};
 
 use crate::gui::GuiResources;
+use crate::json::TiledMap;
 use crate::map::DecorationMetadata;
 
-use crate::player::PlayerCharacterMetadata;
+use crate::player::{AiProfile, PlayerCharacterMetadata};
 use crate::{items::MapItemMetadata, map::Map};
 
 const PARTICLE_EFFECTS_DIR: &str = "particle_effects";
@@ -30,6 +32,7 @@ const MAPS_FILE: &str = "maps";
 const DECORATION_FILE: &str = "decoration";
 const ITEMS_FILE: &str = "items";
 const PLAYER_CHARACTERS_FILE: &str = "player_characters";
+const AI_PROFILES_FILE: &str = "ai_profiles";
 
 const RESOURCE_FILES_EXTENSION: &str = "json";
 
@@ -157,10 +160,9 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
 
             for meta in metadata {
                 let file_path = path.join(&meta.path);
+                let bytes = load_file(&file_path.to_string_helper()).await?;
 
-                let cfg: EmitterConfig = deserialize_json_file(&file_path).await?;
-
-                resources.particle_effects.insert(meta.id, cfg);
+                add_particle_effect(resources, meta, &file_path, &bytes)?;
             }
         }
     }
@@ -217,24 +219,7 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
 
                 let size = vec2(texture.width(), texture.height());
 
-                let key = meta.id.clone();
-
-                let meta = TextureMetadata { size, ..meta };
-
-                #[cfg(debug_assertions)]
-                if meta.frame_size.is_none()
-                    && meta.kind.is_some()
-                    && meta.kind.unwrap() == TextureKind::Spritesheet
-                {
-                    println!(
-                        "WARNING: The texture '{}' is a spritesheet but no frame size has been set",
-                        &meta.id
-                    );
-                }
-
-                let res = TextureResource { texture, meta };
-
-                resources.textures.insert(key, res);
+                add_texture(resources, meta, texture, size);
             }
         }
     }
@@ -278,17 +263,11 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
                 let map_path = path.join(&meta.path);
                 let preview_path = path.join(&meta.preview_path);
 
-                let map = if meta.is_tiled_map {
-                    Map::load_tiled(map_path, None).await?
-                } else {
-                    Map::load(map_path).await?
-                };
+                let bytes = load_file(&map_path.to_string_helper()).await?;
 
                 let preview = load_texture(&preview_path.to_string_helper()).await?;
 
-                let res = MapResource { map, preview, meta };
-
-                resources.maps.push(res)
+                add_map(resources, meta, &map_path, &bytes, preview)?;
             }
         }
     }
@@ -303,10 +282,9 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
 
             for decoration_path in decoration_paths {
                 let path = path.join(&decoration_path);
+                let bytes = load_file(&path.to_string_helper()).await?;
 
-                let params: DecorationMetadata = deserialize_json_file(&path).await?;
-
-                resources.decoration.insert(params.id.clone(), params);
+                add_decoration(resources, &path, &bytes)?;
             }
         }
     }
@@ -321,10 +299,9 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
 
             for item_path in item_paths {
                 let path = path.join(&item_path);
+                let bytes = load_file(&path.to_string_helper()).await?;
 
-                let params: MapItemMetadata = deserialize_json_file(&path).await?;
-
-                resources.items.insert(params.id.clone(), params);
+                add_item(resources, &path, &bytes)?;
             }
         }
     }
@@ -335,17 +312,238 @@ async fn load_resources_from<P: AsRef<Path>>(path: P, resources: &mut Resources)
             .with_extension(RESOURCE_FILES_EXTENSION);
 
         if let Ok(bytes) = load_file(&path.to_string_helper()).await {
-            let metadata: Vec<PlayerCharacterMetadata> = deserialize_json_bytes(&bytes)?;
+            add_player_characters(resources, &bytes)?;
+        }
+    };
 
-            for meta in metadata {
-                resources.player_characters.insert(meta.id.clone(), meta);
-            }
+    {
+        let path = path
+            .join(AI_PROFILES_FILE)
+            .with_extension(RESOURCE_FILES_EXTENSION);
+
+        if let Ok(bytes) = load_file(&path.to_string_helper()).await {
+            add_ai_profiles(resources, &bytes)?;
         }
+    }
+
+    Ok(())
+}
+
+// The parsing of the files of a resource directory is shared by `load_resources_from` and
+// `load_headless_resources_from`, so that the loaders only differ in how files are read, and in
+// how textures and sounds are created.
+
+/// Deserialize the bytes of a JSON file, with the path of the file in the error, if this fails
+fn parse_json_file<T: DeserializeOwned>(path: &Path, bytes: &[u8]) -> Result<T> {
+    match serde_json::from_slice(bytes) {
+        Err(err) => Err(core::data::Error::new(&path.to_string_helper(), err).into()),
+        Ok(res) => Ok(res),
+    }
+}
+
+fn add_particle_effect(
+    resources: &mut Resources,
+    meta: ParticleEffectMetadata,
+    path: &Path,
+    bytes: &[u8],
+) -> Result<()> {
+    let cfg: EmitterConfig = parse_json_file(path, bytes)?;
+
+    resources.particle_effects.insert(meta.id, cfg);
+
+    Ok(())
+}
+
+/// Add a texture, with its size, as that is not known until the texture has been created
+fn add_texture(resources: &mut Resources, meta: TextureMetadata, texture: Texture2D, size: Vec2) {
+    let key = meta.id.clone();
+
+    let meta = TextureMetadata { size, ..meta };
+
+    #[cfg(debug_assertions)]
+    if meta.frame_size.is_none()
+        && meta.kind.is_some()
+        && meta.kind.unwrap() == TextureKind::Spritesheet
+    {
+        println!(
+            "WARNING: The texture '{}' is a spritesheet but no frame size has been set",
+            &meta.id
+        );
+    }
+
+    let res = TextureResource { texture, meta };
+
+    resources.textures.insert(key, res);
+}
+
+fn add_map(
+    resources: &mut Resources,
+    meta: MapMetadata,
+    path: &Path,
+    bytes: &[u8],
+    preview: Texture2D,
+) -> Result<()> {
+    let map = if meta.is_tiled_map {
+        let tiled_map: TiledMap = parse_json_file(path, bytes)?;
+        tiled_map.into_map()
+    } else {
+        parse_json_file(path, bytes)?
     };
 
+    let res = MapResource { map, preview, meta };
+
+    resources.maps.push(res);
+
+    Ok(())
+}
+
+fn add_decoration(resources: &mut Resources, path: &Path, bytes: &[u8]) -> Result<()> {
+    let params: DecorationMetadata = parse_json_file(path, bytes)?;
+
+    resources.decoration.insert(params.id.clone(), params);
+
+    Ok(())
+}
+
+fn add_item(resources: &mut Resources, path: &Path, bytes: &[u8]) -> Result<()> {
+    let params: MapItemMetadata = parse_json_file(path, bytes)?;
+
+    resources.items.insert(params.id.clone(), params);
+
+    Ok(())
+}
+
+fn add_player_characters(resources: &mut Resources, bytes: &[u8]) -> Result<()> {
+    let metadata: Vec<PlayerCharacterMetadata> = deserialize_json_bytes(bytes)?;
+
+    for meta in metadata {
+        resources.player_characters.insert(meta.id.clone(), meta);
+    }
+
+    Ok(())
+}
+
+/// Profiles are kept in load order, as that is the order they are selected in, so a profile that
+/// overwrites another, with the same id, takes its place
+fn add_ai_profiles(resources: &mut Resources, bytes: &[u8]) -> Result<()> {
+    let profiles: Vec<AiProfile> = deserialize_json_bytes(bytes)?;
+
+    for profile in profiles {
+        match resources
+            .ai_profiles
+            .iter_mut()
+            .find(|p| p.id == profile.id)
+        {
+            Some(existing) => *existing = profile,
+            None => resources.ai_profiles.push(profile),
+        }
+    }
+
     Ok(())
 }
 
+/// Read one of the resource files of a directory, or `None` if it does not exist
+#[cfg(any(test, feature = "headless"))]
+fn read_resource_file(path: &Path, name: &str) -> Option<Vec<u8>> {
+    fs::read(path.join(name).with_extension(RESOURCE_FILES_EXTENSION)).ok()
+}
+
+/// Load the resources of a directory, for a headless game. This is done synchronously, with the
+/// standard library, as the file loading of macroquad requires a window. Sounds, music and images
+/// are not loaded, and textures are given an empty `Texture2D`, with the size read from the header
+/// of their PNG file, so that sprites get the same frame size that they would in a window.
+#[cfg(any(test, feature = "headless"))]
+fn load_headless_resources_from(path: &Path, resources: &mut Resources) -> Result<()> {
+    if let Some(bytes) = read_resource_file(path, PARTICLE_EFFECTS_DIR) {
+        let metadata: Vec<ParticleEffectMetadata> = deserialize_json_bytes(&bytes)?;
+
+        for meta in metadata {
+            let file_path = path.join(&meta.path);
+            let bytes = fs::read(&file_path)?;
+
+            add_particle_effect(resources, meta, &file_path, &bytes)?;
+        }
+    }
+
+    if let Some(bytes) = read_resource_file(path, TEXTURES_FILE) {
+        let metadata: Vec<TextureMetadata> = deserialize_json_bytes(&bytes)?;
+
+        for meta in metadata {
+            let bytes = fs::read(path.join(&meta.path))?;
+
+            let size = png_size(&bytes).ok_or_else(|| {
+                formaterr!(
+                    ErrorKind::Parsing,
+                    "Resources: The texture '{}' is not a PNG file",
+                    &meta.path
+                )
+            })?;
+
+            add_texture(resources, meta, Texture2D::empty(), size);
+        }
+    }
+
+    if let Some(bytes) = read_resource_file(path, MAPS_FILE) {
+        let metadata: Vec<MapMetadata> = deserialize_json_bytes(&bytes)?;
+
+        for meta in metadata {
+            let map_path = path.join(&meta.path);
+            let bytes = fs::read(&map_path)?;
+
+            add_map(resources, meta, &map_path, &bytes, Texture2D::empty())?;
+        }
+    }
+
+    if let Some(bytes) = read_resource_file(path, DECORATION_FILE) {
+        let decoration_paths: Vec<String> = deserialize_json_bytes(&bytes)?;
+
+        for decoration_path in decoration_paths {
+            let path = path.join(&decoration_path);
+            let bytes = fs::read(&path)?;
+
+            add_decoration(resources, &path, &bytes)?;
+        }
+    }
+
+    if let Some(bytes) = read_resource_file(path, ITEMS_FILE) {
+        let item_paths: Vec<String> = deserialize_json_bytes(&bytes)?;
+
+        for item_path in item_paths {
+            let path = path.join(&item_path);
+            let bytes = fs::read(&path)?;
+
+            add_item(resources, &path, &bytes)?;
+        }
+    }
+
+    if let Some(bytes) = read_resource_file(path, PLAYER_CHARACTERS_FILE) {
+        add_player_characters(resources, &bytes)?;
+    }
+
+    if let Some(bytes) = read_resource_file(path, AI_PROFILES_FILE) {
+        add_ai_profiles(resources, &bytes)?;
+    }
+
+    Ok(())
+}
+
+/// The size of a PNG image, read from its header, or `None` if the bytes are not a PNG file
+#[cfg(any(test, feature = "headless"))]
+fn png_size(bytes: &[u8]) -> Option<Vec2> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    // The signature is followed by the length and the type of the first chunk, which is always
+    // the header, holding the width and the height, as big endian integers
+    if bytes.len() < 24 || !bytes.starts_with(SIGNATURE) || &bytes[12..16] != b"IHDR" {
+        return None;
+    }
+
+    let width = u32::from_be_bytes(bytes[16..20].try_into().ok()?);
+    let height = u32::from_be_bytes(bytes[20..24].try_into().ok()?);
+
+    Some(vec2(width as f32, height as f32))
+}
+
 pub struct Resources {
     pub assets_dir: String,
     pub mods_dir: String,
@@ -361,6 +559,7 @@ pub struct Resources {
     pub decoration: HashMap<String, DecorationMetadata>,
     pub items: HashMap<String, MapItemMetadata>,
     pub player_characters: HashMap<String, PlayerCharacterMetadata>,
+    pub ai_profiles: Vec<AiProfile>,
 }
 
 impl Resources {
@@ -368,7 +567,31 @@ impl Resources {
         let assets_dir = assets_dir.as_ref();
         let mods_dir = mods_dir.as_ref();
 
-        let mut resources = Resources {
+        let mut resources = Resources::empty(assets_dir, mods_dir);
+
+        load_resources_from(assets_dir, &mut resources).await?;
+
+        load_mods(mods_dir, &mut resources).await?;
+
+        Ok(resources)
+    }
+
+    /// Load the resources of the assets dir for a headless game, which is run without a window or
+    /// an audio device. See `load_headless_resources_from` for what is left out. Mods are not
+    /// loaded, so that headless games only depend on the assets of the game itself.
+    #[cfg(any(test, feature = "headless"))]
+    pub fn new_headless<P: AsRef<Path>>(assets_dir: P) -> Result<Resources> {
+        let assets_dir = assets_dir.as_ref();
+
+        let mut resources = Resources::empty(assets_dir, Path::new(""));
+
+        load_headless_resources_from(assets_dir, &mut resources)?;
+
+        Ok(resources)
+    }
+
+    fn empty(assets_dir: &Path, mods_dir: &Path) -> Resources {
+        Resources {
             assets_dir: assets_dir.to_string_helper(),
             mods_dir: mods_dir.to_string_helper(),
             loaded_mods: Vec::new(),
@@ -381,13 +604,8 @@ impl Resources {
             maps: Vec::new(),
             items: HashMap::new(),
             player_characters: HashMap::new(),
-        };
-
-        load_resources_from(assets_dir, &mut resources).await?;
-
-        load_mods(mods_dir, &mut resources).await?;
-
-        Ok(resources)
+            ai_profiles: Vec::new(),
+        }
     }
 
     pub fn create_map(
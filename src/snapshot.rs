@@ -0,0 +1,347 @@
+//! Snapshots of the simulation state, used to roll back the game world in network sessions.
+//! Only components that are part of the simulation are captured, so any new component type that
+//! holds simulation state must be added to the list below, or it will be lost on rollback.
+//! The `CollisionWorld` is rebuilt on restore, as actors can not be removed from it, so the actors
+//! that were added after a snapshot was taken would otherwise be kept around after every rollback.
+
+use std::collections::HashMap;
+
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+
+use hecs::{Entity, EntityBuilder, World};
+
+use macroquad_platformer::{Actor, Tile};
+
+use core::Transform;
+
+use crate::effects::active::projectiles::Projectile;
+use crate::effects::active::triggered::TriggeredEffect;
+use crate::effects::active::{CircleCollider, RectCollider};
+use crate::game::{MatchRules, PlayerStats, Simulation};
+use crate::items::{Item, Weapon};
+use crate::map::{CaptureZone, Decoration, Flag, FlagBase, Sproinger};
+use crate::particles::ParticleEmitter;
+use crate::physics::{create_collision_world, get_collision_tiles};
+use crate::player::{
+    Ai, Player, PlayerAttributes, PlayerController, PlayerEventQueue, PlayerInventory,
+};
+use crate::{CollisionWorld, Drawable, GameCamera, Map, Owner, PhysicsBody, RigidBody};
+
+macro_rules! entity_snapshot {
+    ($($field:ident: $component:ty),* $(,)?) => {
+        /// This holds clones of all the simulation components of an entity
+        #[derive(Clone)]
+        struct EntitySnapshot {
+            entity: Entity,
+            $($field: Option<$component>,)*
+        }
+
+        impl EntitySnapshot {
+            fn capture(world: &World, entity: Entity) -> Self {
+                EntitySnapshot {
+                    entity,
+                    $($field: world.get::<$component>(entity).ok().map(|c| (*c).clone()),)*
+                }
+            }
+
+            fn restore(&self, world: &mut World) {
+                let mut builder = EntityBuilder::new();
+
+                $(if let Some(component) = &self.$field {
+                    builder.add(component.clone());
+                })*
+
+                world.spawn_at(self.entity, builder.build());
+            }
+        }
+    };
+}
+
+entity_snapshot! {
+    simulation: Simulation,
+    match_rules: MatchRules,
+    transform: Transform,
+    physics_body: PhysicsBody,
+    rigid_body: RigidBody,
+    drawable: Drawable,
+    owner: Owner,
+    player: Player,
+    player_attributes: PlayerAttributes,
+    player_controller: PlayerController,
+    player_inventory: PlayerInventory,
+    player_event_queue: PlayerEventQueue,
+    player_stats: PlayerStats,
+    ai: Ai,
+    item: Item,
+    weapon: Weapon,
+    projectile: Projectile,
+    triggered_effect: TriggeredEffect,
+    particle_emitters: Vec<ParticleEmitter>,
+    sproinger: Sproinger,
+    capture_zone: CaptureZone,
+    flag: Flag,
+    flag_base: FlagBase,
+    decoration: Decoration,
+    circle_collider: CircleCollider,
+    rect_collider: RectCollider,
+}
+
+/// An actor of the `CollisionWorld` that belongs to a `PhysicsBody`
+#[derive(Clone)]
+struct ActorSnapshot {
+    position: Vec2,
+    width: i32,
+    height: i32,
+    /// This is set if the actor is dropping through platforms
+    is_descending: bool,
+}
+
+/// Get the positions of all the platform tiles of the map
+fn get_platform_tiles(map: &Map) -> Vec<Vec2> {
+    get_collision_tiles(map)
+        .into_iter()
+        .enumerate()
+        .filter(|(_, tile)| *tile == Tile::JumpThrough)
+        .map(|(i, _)| {
+            let x = i as u32 % map.grid_size.x;
+            let y = i as u32 / map.grid_size.x;
+            vec2(x as f32, y as f32) * map.tile_size
+        })
+        .collect()
+}
+
+/// The `CollisionWorld` does not expose whether an actor is dropping through platforms, but it
+/// will only ignore a platform, when checking for collisions, if it is. This finds a position,
+/// for an actor of the specified size, that overlaps platform tiles but no solid tiles, so that
+/// this can be read by checking for collisions there.
+fn find_platform_probe(
+    collision_world: &CollisionWorld,
+    map: &Map,
+    platform_tiles: &[Vec2],
+    width: i32,
+    height: i32,
+) -> Option<Vec2> {
+    let size = vec2(width as f32, height as f32);
+    let corners = [
+        vec2(1.0, 1.0) - size,
+        vec2(map.tile_size.x - 1.0, 1.0 - size.y),
+        vec2(1.0 - size.x, map.tile_size.y - 1.0),
+        map.tile_size - vec2(1.0, 1.0),
+    ];
+
+    platform_tiles.iter().find_map(|tile_position| {
+        corners
+            .iter()
+            .map(|corner| *tile_position + *corner)
+            .find(|position| {
+                collision_world.collide_solids(*position, width, height) == Tile::JumpThrough
+            })
+    })
+}
+
+/// A snapshot of the game world, the actors of the `CollisionWorld` and the `GameCamera`
+#[derive(Clone)]
+pub struct WorldSnapshot {
+    entities: Vec<EntitySnapshot>,
+    actors: HashMap<Actor, ActorSnapshot>,
+    camera: GameCamera,
+}
+
+impl WorldSnapshot {
+    pub fn capture(world: &World) -> Self {
+        let entities = world
+            .iter()
+            .map(|entity_ref| EntitySnapshot::capture(world, entity_ref.entity()))
+            .collect();
+
+        let actors = {
+            let map = storage::get::<Map>();
+            let collision_world = storage::get::<CollisionWorld>();
+
+            let platform_tiles = get_platform_tiles(&map);
+
+            let mut probes = HashMap::new();
+
+            world
+                .query::<&PhysicsBody>()
+                .iter()
+                .map(|(_, body)| {
+                    let size = body.size.as_i32();
+
+                    let probe = *probes.entry((size.x, size.y)).or_insert_with(|| {
+                        find_platform_probe(&collision_world, &map, &platform_tiles, size.x, size.y)
+                    });
+
+                    // If there are no platforms, this will not make a difference
+                    let is_descending = probe
+                        .map(|position| !collision_world.collide_check(body.actor, position))
+                        .unwrap_or(false);
+
+                    let actor = ActorSnapshot {
+                        position: collision_world.actor_pos(body.actor),
+                        width: size.x,
+                        height: size.y,
+                        is_descending,
+                    };
+
+                    (body.actor, actor)
+                })
+                .collect()
+        };
+
+        let camera = storage::get::<GameCamera>().clone();
+
+        WorldSnapshot {
+            entities,
+            actors,
+            camera,
+        }
+    }
+
+    /// Replace the contents of the world with the snapshot. Entity handles are preserved, so any
+    /// references between entities, like `Owner`, will remain valid.
+    pub fn restore(&self, world: &mut World) {
+        world.clear();
+
+        for entity in &self.entities {
+            entity.restore(world);
+        }
+
+        let collision_world = self.rebuild_collision_world();
+        storage::store(collision_world);
+
+        *storage::get_mut::<GameCamera>() = self.camera.clone();
+    }
+
+    /// Create a new `CollisionWorld` for the map, holding only the actors of the snapshot.
+    /// Actors are handed out in the order that they are added, so the handles held by the physics
+    /// bodies are reproduced by adding actors in the same order, with placeholders in the place of
+    /// actors that no longer belonged to a body when the snapshot was taken.
+    /// A newly added actor will be dropping through platforms if it overlaps one, so actors are
+    /// added outside of the map and then moved into place, before this is set from the snapshot.
+    fn rebuild_collision_world(&self) -> CollisionWorld {
+        let mut collision_world = create_collision_world(&storage::get::<Map>());
+
+        // A position where an actor will not overlap any tiles
+        let outside_of_map = vec2(0.0, -1_000_000.0);
+
+        // Only used to get the handles in the order that they will be handed out
+        let mut handles = CollisionWorld::new();
+
+        let mut remaining = self.actors.len();
+        while remaining > 0 {
+            let handle = handles.add_actor(Vec2::ZERO, 0, 0);
+
+            if let Some(actor) = self.actors.get(&handle) {
+                let added = collision_world.add_actor(outside_of_map, actor.width, actor.height);
+                collision_world.set_actor_position(added, actor.position);
+
+                if actor.is_descending {
+                    collision_world.descent(added);
+                }
+
+                remaining -= 1;
+            } else {
+                collision_world.add_actor(Vec2::ZERO, 0, 0);
+            }
+        }
+
+        collision_world
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::input::{InputTrack, PlayerInput};
+
+    use crate::game::{default_assets_dir, HeadlessGame, MatchRulesParams, Replay, ReplayPlayer};
+
+    use super::*;
+
+    fn walking_player_replay() -> Replay {
+        let mut inputs = InputTrack::new();
+        inputs.push_for(
+            PlayerInput {
+                right: true,
+                ..Default::default()
+            },
+            60,
+        );
+
+        let players = vec![ReplayPlayer::new(0, "pescy", inputs)];
+
+        Replay::from_script("lev01", 0, MatchRulesParams::default(), players)
+    }
+
+    fn actor_of(game: &HeadlessGame, index: u8) -> Actor {
+        let entity = game.player(index).unwrap();
+        game.world().get::<PhysicsBody>(entity).unwrap().actor
+    }
+
+    #[test]
+    fn restore_rebuilds_collision_world() {
+        let mut game = HeadlessGame::new(default_assets_dir(), &walking_player_replay()).unwrap();
+
+        game.run(10);
+
+        let snapshot = WorldSnapshot::capture(game.world());
+
+        let actor = actor_of(&game, 0);
+        let position = storage::get::<CollisionWorld>().actor_pos(actor);
+
+        game.run(30);
+
+        let added = storage::get_mut::<CollisionWorld>().add_actor(Vec2::ZERO, 8, 8);
+
+        snapshot.restore(game.world_mut());
+
+        assert_eq!(actor_of(&game, 0), actor);
+        assert_eq!(storage::get::<CollisionWorld>().actor_pos(actor), position);
+
+        // The actor that was added after the snapshot was taken should be gone, so its handle
+        // is handed out again
+        let readded = storage::get_mut::<CollisionWorld>().add_actor(Vec2::ZERO, 8, 8);
+        assert_eq!(readded, added);
+    }
+
+    #[test]
+    fn restore_keeps_actors_dropping_through_platforms() {
+        let mut game = HeadlessGame::new(default_assets_dir(), &walking_player_replay()).unwrap();
+
+        game.run(10);
+
+        let actor = actor_of(&game, 0);
+
+        let probe = {
+            let entity = game.player(0).unwrap();
+            let size = game
+                .world()
+                .get::<PhysicsBody>(entity)
+                .unwrap()
+                .size
+                .as_i32();
+
+            let map = storage::get::<Map>();
+            let platform_tiles = get_platform_tiles(&map);
+            let collision_world = storage::get::<CollisionWorld>();
+
+            find_platform_probe(&collision_world, &map, &platform_tiles, size.x, size.y).unwrap()
+        };
+
+        let is_descending = || !storage::get::<CollisionWorld>().collide_check(actor, probe);
+
+        assert!(!is_descending());
+
+        let snapshot = WorldSnapshot::capture(game.world());
+
+        storage::get_mut::<CollisionWorld>().descent(actor);
+        let descending_snapshot = WorldSnapshot::capture(game.world());
+
+        descending_snapshot.restore(game.world_mut());
+        assert!(is_descending());
+
+        snapshot.restore(game.world_mut());
+        assert!(!is_descending());
+    }
+}